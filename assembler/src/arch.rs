@@ -0,0 +1,190 @@
+//! The target architecture the assembler accepts instructions for, chosen with `--arch`.
+//! RV64 accepts everything the assembler knows. RV32 accepts the RV32I base and, with M,
+//! the multiply and divide instructions; RV64-only mnemonics such as `ld`, `addw` or a
+//! shift by 32 or more are rejected.
+
+use crate::encoder::parse_immediate;
+use crate::types::AssemblerErrorKind;
+use std::fmt;
+use std::str::FromStr;
+
+/// The RV32I instructions and the pseudo-instructions that expand to them.
+const RV32I: &[&str] = &[
+    "lui",
+    "auipc",
+    "jal",
+    "jalr",
+    "beq",
+    "bne",
+    "blt",
+    "bge",
+    "bltu",
+    "bgeu",
+    "lb",
+    "lh",
+    "lw",
+    "lbu",
+    "lhu",
+    "sb",
+    "sh",
+    "sw",
+    "addi",
+    "slti",
+    "sltiu",
+    "xori",
+    "ori",
+    "andi",
+    "slli",
+    "srli",
+    "srai",
+    "add",
+    "sub",
+    "sll",
+    "slt",
+    "sltu",
+    "xor",
+    "srl",
+    "sra",
+    "or",
+    "and",
+    "fence",
+    "fence.i",
+    "ecall",
+    "ebreak",
+    "mret",
+    "sret",
+    "wfi",
+    "sfence.vma",
+    "csrrw",
+    "csrrs",
+    "csrrc",
+    "csrrwi",
+    "csrrsi",
+    "csrrci",
+    "nop",
+    "ret",
+    "j",
+    "li",
+    "la",
+];
+
+const RV32M: &[&str] = &[
+    "mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Arch {
+    #[default]
+    Rv64,
+    /// RV32I, with the M extension when `m` is set.
+    Rv32 { m: bool },
+}
+
+impl Arch {
+    /// Whether `.option rvc` and the `c.*` mnemonics may be used.
+    pub fn has_compressed(self) -> bool {
+        self == Arch::Rv64
+    }
+
+    /// Checks that `instruction` exists on this architecture, including that a shift
+    /// amount fits in XLEN.
+    pub fn check(self, instruction: &str, operands: &[&str]) -> Result<(), AssemblerErrorKind> {
+        let Arch::Rv32 { m } = self else {
+            return Ok(());
+        };
+        let supported = RV32I.contains(&instruction) || (m && RV32M.contains(&instruction));
+        if !supported {
+            return Err(AssemblerErrorKind::UnsupportedInstruction(format!(
+                "{} on {}",
+                instruction, self
+            )));
+        }
+        if matches!(instruction, "slli" | "srli" | "srai")
+            && let Some(shamt) = operands.get(2)
+        {
+            let shamt = parse_immediate(shamt)?;
+            if !(0..32).contains(&shamt) {
+                return Err(AssemblerErrorKind::ImmediateOutOfRange(format!(
+                    "shift amount {} for {} on {}",
+                    shamt, instruction, self
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "rv32i" => Ok(Arch::Rv32 { m: false }),
+            "rv32im" => Ok(Arch::Rv32 { m: true }),
+            other if other.starts_with("rv64") => Ok(Arch::Rv64),
+            _ => Err(format!(
+                "unsupported architecture '{}', expected rv32i, rv32im or rv64",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arch::Rv64 => write!(f, "rv64"),
+            Arch::Rv32 { m: false } => write!(f, "rv32i"),
+            Arch::Rv32 { m: true } => write!(f, "rv32im"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program_for;
+
+    #[test]
+    fn test_rv32_rejects_rv64_only_instructions() {
+        let rv32im: Arch = "rv32im".parse().unwrap();
+        assert!(rv32im.check("mul", &["a0,", "a0,", "a1"]).is_ok());
+        assert!(rv32im.check("slli", &["a0,", "a0,", "31"]).is_ok());
+        for (instruction, operands) in [
+            ("ld", &["a0,", "0(a1)"][..]),
+            ("addw", &["a0,", "a0,", "a1"]),
+            ("lr.w", &["a0,", "(a1)"]),
+            ("c.addi", &["a0,", "1"]),
+        ] {
+            assert!(matches!(
+                rv32im.check(instruction, operands),
+                Err(AssemblerErrorKind::UnsupportedInstruction(_))
+            ));
+        }
+        assert!(matches!(
+            rv32im.check("srai", &["a0,", "a0,", "32"]),
+            Err(AssemblerErrorKind::ImmediateOutOfRange(_))
+        ));
+        assert!(Arch::Rv32 { m: false }
+            .check("div", &["a0,", "a0,", "a1"])
+            .is_err());
+        assert!(Arch::Rv64.check("ld", &["a0,", "0(a1)"]).is_ok());
+        assert!("rv128i".parse::<Arch>().is_err());
+    }
+
+    #[test]
+    fn test_rv32_program_reports_the_offending_line() {
+        let source = "_start:\n    addi a0, zero, 1\n    sd a0, 0(sp)\n";
+        let error = parse_program_for(source, "rv32im".parse().unwrap()).unwrap_err();
+        assert_eq!(error.line, 3);
+
+        let rvc = ".option rvc\n    addi a0, a0, 1\n";
+        assert_eq!(
+            parse_program_for(rvc, Arch::Rv32 { m: true })
+                .unwrap_err()
+                .line,
+            1
+        );
+        assert!(parse_program_for(rvc, Arch::Rv64).is_ok());
+    }
+}
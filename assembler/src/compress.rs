@@ -0,0 +1,695 @@
+//! Compression of 32-bit instructions into their RVC forms, for `.option rvc` and the
+//! explicit `c.*` mnemonics.
+
+use crate::encoder::encode_instruction;
+use crate::types::AssemblerErrorKind;
+use riscv_core::{funct3, funct7, opcodes};
+use std::collections::HashMap;
+
+/// The fields of a 32-bit instruction that the compressed forms are matched against.
+struct Fields {
+    opcode: u32,
+    rd: u32,
+    rs1: u32,
+    rs2: u32,
+    funct3: u32,
+    funct7: u32,
+    i_imm: i32,
+    s_imm: i32,
+    b_imm: i32,
+    j_imm: i32,
+    u_imm: i32,
+}
+
+impl Fields {
+    fn decode(word: u32) -> Self {
+        let b_imm = (((word >> 31) & 1) << 12)
+            | (((word >> 7) & 1) << 11)
+            | (((word >> 25) & 0x3F) << 5)
+            | (((word >> 8) & 0xF) << 1);
+        let j_imm = (((word >> 31) & 1) << 20)
+            | (((word >> 12) & 0xFF) << 12)
+            | (((word >> 20) & 1) << 11)
+            | (((word >> 21) & 0x3FF) << 1);
+        Self {
+            opcode: word & 0x7F,
+            rd: (word >> 7) & 0x1F,
+            rs1: (word >> 15) & 0x1F,
+            rs2: (word >> 20) & 0x1F,
+            funct3: (word >> 12) & 0x7,
+            funct7: word >> 25,
+            i_imm: word as i32 >> 20,
+            s_imm: ((word as i32 >> 25) << 5) | ((word >> 7) & 0x1F) as i32,
+            b_imm: ((b_imm as i32) << 19) >> 19,
+            j_imm: ((j_imm as i32) << 11) >> 11,
+            u_imm: word as i32 >> 12,
+        }
+    }
+}
+
+type Form = fn(&Fields) -> Option<u16>;
+
+/// Every compressed form, in the order auto-compression tries them.
+const FORMS: [(&str, Form); 37] = [
+    ("c.nop", c_nop),
+    ("c.addi", c_addi),
+    ("c.addi4spn", c_addi4spn),
+    ("c.addi16sp", c_addi16sp),
+    ("c.li", c_li),
+    ("c.mv", c_mv),
+    ("c.addiw", c_addiw),
+    ("c.lui", c_lui),
+    ("c.slli", c_slli),
+    ("c.srli", c_srli),
+    ("c.srai", c_srai),
+    ("c.andi", c_andi),
+    ("c.add", c_add),
+    ("c.sub", c_sub),
+    ("c.xor", c_xor),
+    ("c.or", c_or),
+    ("c.and", c_and),
+    ("c.subw", c_subw),
+    ("c.addw", c_addw),
+    ("c.lw", c_lw),
+    ("c.ld", c_ld),
+    ("c.fld", c_fld),
+    ("c.sw", c_sw),
+    ("c.sd", c_sd),
+    ("c.fsd", c_fsd),
+    ("c.j", c_j),
+    ("c.beqz", c_beqz),
+    ("c.bnez", c_bnez),
+    ("c.ebreak", c_ebreak),
+    ("c.lwsp", c_lwsp),
+    ("c.ldsp", c_ldsp),
+    ("c.fldsp", c_fldsp),
+    ("c.swsp", c_swsp),
+    ("c.sdsp", c_sdsp),
+    ("c.fsdsp", c_fsdsp),
+    ("c.jr", c_jr),
+    ("c.jalr", c_jalr),
+];
+
+/// Returns the compressed form of `word`, if it has one.
+pub fn compress(word: u32) -> Option<u16> {
+    let fields = Fields::decode(word);
+    FORMS.iter().find_map(|(_, form)| form(&fields))
+}
+
+/// Returns `word` encoded as the compressed instruction `mnemonic`, if that form can
+/// represent it.
+pub fn compress_as(word: u32, mnemonic: &str) -> Option<u16> {
+    let fields = Fields::decode(word);
+    FORMS
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .and_then(|(_, form)| form(&fields))
+}
+
+/// The mnemonic of the compressed instruction `half`, if it is one of the forms the
+/// assembler produces. Reserved encodings and hints have none.
+pub fn compressed_mnemonic(half: u16) -> Option<&'static str> {
+    let fields = Fields::decode(riscv_core::compressed::expand(half)?);
+    FORMS
+        .iter()
+        .find(|(_, form)| form(&fields) == Some(half))
+        .map(|(name, _)| *name)
+}
+
+/// Whether the encoding of `mnemonic` depends on label addresses. These are never
+/// auto-compressed, so an instruction's size is known before labels are resolved.
+pub(crate) fn refers_to_labels(mnemonic: &str) -> bool {
+    matches!(
+        mnemonic,
+        "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "jal" | "j" | "la"
+    )
+}
+
+/// Assembles an explicit `c.*` mnemonic by rewriting it as the 32-bit instruction it
+/// abbreviates and compressing that into the requested form.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode_compressed(
+    instruction: &str,
+    operands: &[&str],
+    current_address: u64,
+    text_labels: &HashMap<String, u64>,
+    data_labels: &HashMap<String, u64>,
+    bss_labels: &HashMap<String, u64>,
+    text_size: u64,
+    data_size: u64,
+) -> Result<u16, AssemblerErrorKind> {
+    let operand = |index: usize| {
+        operands
+            .get(index)
+            .map(|s| s.trim_end_matches(','))
+            .ok_or_else(|| {
+                AssemblerErrorKind::ParseError(format!("{} is missing operands", instruction))
+            })
+    };
+    let jump_target;
+    let (base, base_operands): (&str, Vec<&str>) = match instruction {
+        "c.nop" => ("addi", vec!["zero", "zero", "0"]),
+        "c.ebreak" => ("ebreak", vec![]),
+        "c.addi" | "c.addiw" | "c.slli" | "c.srli" | "c.srai" | "c.andi" | "c.add" | "c.sub"
+        | "c.xor" | "c.or" | "c.and" | "c.subw" | "c.addw" => (
+            &instruction[2..],
+            vec![operand(0)?, operand(0)?, operand(1)?],
+        ),
+        "c.addi16sp" => ("addi", vec!["sp", "sp", operand(1)?]),
+        "c.addi4spn" => ("addi", vec![operand(0)?, "sp", operand(2)?]),
+        "c.li" => ("addi", vec![operand(0)?, "zero", operand(1)?]),
+        "c.mv" => ("add", vec![operand(0)?, "zero", operand(1)?]),
+        "c.lui" => ("lui", vec![operand(0)?, operand(1)?]),
+        "c.lw" | "c.ld" | "c.fld" | "c.sw" | "c.sd" | "c.fsd" => {
+            (&instruction[2..], vec![operand(0)?, operand(1)?])
+        }
+        "c.lwsp" | "c.ldsp" | "c.fldsp" | "c.swsp" | "c.sdsp" | "c.fsdsp" => {
+            let base = &instruction[2..instruction.len() - 2];
+            (base, vec![operand(0)?, operand(1)?])
+        }
+        "c.j" => ("jal", vec!["zero", operand(0)?]),
+        "c.jr" | "c.jalr" => {
+            jump_target = format!("0({})", operand(0)?);
+            let link = if instruction == "c.jr" { "zero" } else { "ra" };
+            ("jalr", vec![link, jump_target.as_str()])
+        }
+        "c.beqz" => ("beq", vec![operand(0)?, "zero", operand(1)?]),
+        "c.bnez" => ("bne", vec![operand(0)?, "zero", operand(1)?]),
+        _ => {
+            return Err(AssemblerErrorKind::UnknownInstruction(
+                instruction.to_string(),
+            ))
+        }
+    };
+    let words = encode_instruction(
+        base,
+        &base_operands,
+        current_address,
+        text_labels,
+        data_labels,
+        bss_labels,
+        text_size,
+        data_size,
+    )?;
+    compress_as(words[0], instruction).ok_or_else(|| {
+        AssemblerErrorKind::ValueOutOfRange(format!(
+            "'{} {}' has no {} encoding",
+            instruction,
+            operands.join(" "),
+            instruction
+        ))
+    })
+}
+
+/// The register number within `x8..x15` that the 3-bit register fields encode.
+fn creg(reg: u32) -> Option<u16> {
+    (8..16).contains(&reg).then(|| (reg - 8) as u16)
+}
+
+/// Moves `value[hi:lo]` to start at bit `to` of the compressed instruction.
+fn bits(value: i32, hi: u32, lo: u32, to: u32) -> u16 {
+    (((value as u32 >> lo) & ((1 << (hi - lo + 1)) - 1)) << to) as u16
+}
+
+fn fits_signed(value: i32, width: u32) -> bool {
+    let limit = 1 << (width - 1);
+    (-limit..limit).contains(&value)
+}
+
+fn scaled(value: i32, scale: i32, max: i32) -> bool {
+    value >= 0 && value <= max && value % scale == 0
+}
+
+fn is_addi(f: &Fields) -> bool {
+    f.opcode == opcodes::OP_IMM && f.funct3 == funct3::ADD_SUB
+}
+
+/// The quadrant 1 forms with a 6-bit immediate and a full destination register.
+fn ci(funct3: u16, rd: u32, imm: i32) -> u16 {
+    (funct3 << 13) | bits(imm, 5, 5, 12) | ((rd as u16) << 7) | bits(imm, 4, 0, 2) | 0b01
+}
+
+fn c_nop(f: &Fields) -> Option<u16> {
+    (is_addi(f) && f.rd == 0 && f.rs1 == 0 && f.i_imm == 0).then_some(0x0001)
+}
+
+fn c_addi(f: &Fields) -> Option<u16> {
+    (is_addi(f) && f.rd != 0 && f.rd == f.rs1 && f.i_imm != 0 && fits_signed(f.i_imm, 6))
+        .then_some(ci(0b000, f.rd, f.i_imm))
+}
+
+fn c_addiw(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_IMM_32
+        && f.funct3 == funct3::ADD_SUB
+        && f.rd != 0
+        && f.rd == f.rs1
+        && fits_signed(f.i_imm, 6))
+    .then(|| ci(0b001, f.rd, f.i_imm))
+}
+
+fn c_li(f: &Fields) -> Option<u16> {
+    (is_addi(f) && f.rd != 0 && f.rs1 == 0 && fits_signed(f.i_imm, 6))
+        .then_some(ci(0b010, f.rd, f.i_imm))
+}
+
+fn c_addi16sp(f: &Fields) -> Option<u16> {
+    let imm = f.i_imm;
+    (is_addi(f) && f.rd == 2 && f.rs1 == 2 && imm != 0 && imm % 16 == 0 && fits_signed(imm, 10))
+        .then(|| {
+            (0b011 << 13)
+                | bits(imm, 9, 9, 12)
+                | (2 << 7)
+                | bits(imm, 4, 4, 6)
+                | bits(imm, 6, 6, 5)
+                | bits(imm, 8, 7, 3)
+                | bits(imm, 5, 5, 2)
+                | 0b01
+        })
+}
+
+fn c_addi4spn(f: &Fields) -> Option<u16> {
+    let rd = creg(f.rd)?;
+    let imm = f.i_imm;
+    (is_addi(f) && f.rs1 == 2 && imm != 0 && scaled(imm, 4, 1020)).then(|| {
+        bits(imm, 5, 4, 11)
+            | bits(imm, 9, 6, 7)
+            | bits(imm, 2, 2, 6)
+            | bits(imm, 3, 3, 5)
+            | (rd << 2)
+    })
+}
+
+fn c_lui(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LUI
+        && f.rd != 0
+        && f.rd != 2
+        && f.u_imm != 0
+        && fits_signed(f.u_imm, 6))
+    .then(|| ci(0b011, f.rd, f.u_imm))
+}
+
+/// `c.srli`, `c.srai` and `c.andi`, which share quadrant 1's funct3 100.
+fn cb_alu(f: &Fields, funct2: u16, imm: i32) -> Option<u16> {
+    let rd = creg(f.rd)?;
+    (f.rd == f.rs1).then(|| {
+        (0b100 << 13) | bits(imm, 5, 5, 12) | (funct2 << 10) | (rd << 7) | bits(imm, 4, 0, 2) | 0b01
+    })
+}
+
+fn shamt(f: &Fields) -> i32 {
+    f.i_imm & 0x3F
+}
+
+fn c_srli(f: &Fields) -> Option<u16> {
+    let matches = f.opcode == opcodes::OP_IMM
+        && f.funct3 == funct3::SRL_SRA
+        && f.funct7 >> 1 == 0
+        && shamt(f) != 0;
+    matches.then(|| cb_alu(f, 0b00, shamt(f))).flatten()
+}
+
+fn c_srai(f: &Fields) -> Option<u16> {
+    let matches = f.opcode == opcodes::OP_IMM
+        && f.funct3 == funct3::SRL_SRA
+        && f.funct7 >> 1 == funct7::SRA >> 1
+        && shamt(f) != 0;
+    matches.then(|| cb_alu(f, 0b01, shamt(f))).flatten()
+}
+
+fn c_andi(f: &Fields) -> Option<u16> {
+    let matches = f.opcode == opcodes::OP_IMM && f.funct3 == funct3::AND && fits_signed(f.i_imm, 6);
+    matches.then(|| cb_alu(f, 0b10, f.i_imm)).flatten()
+}
+
+fn c_slli(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_IMM
+        && f.funct3 == funct3::SLL
+        && f.funct7 >> 1 == 0
+        && f.rd != 0
+        && f.rd == f.rs1
+        && shamt(f) != 0)
+        .then_some(bits(shamt(f), 5, 5, 12) | ((f.rd as u16) << 7) | bits(shamt(f), 4, 0, 2) | 0b10)
+}
+
+/// `c.sub` through `c.addw`: two 3-bit registers, with `rd` also the first source.
+fn ca(f: &Fields, opcode: u32, funct7: u32, funct3: u32, bit12: u16, funct2: u16) -> Option<u16> {
+    let rd = creg(f.rd)?;
+    let rs2 = creg(f.rs2)?;
+    (f.opcode == opcode && f.funct7 == funct7 && f.funct3 == funct3 && f.rd == f.rs1).then_some(
+        (0b100 << 13)
+            | (bit12 << 12)
+            | (0b11 << 10)
+            | (rd << 7)
+            | (funct2 << 5)
+            | (rs2 << 2)
+            | 0b01,
+    )
+}
+
+fn c_sub(f: &Fields) -> Option<u16> {
+    ca(f, opcodes::OP_REG, funct7::SUB, funct3::ADD_SUB, 0, 0b00)
+}
+
+fn c_xor(f: &Fields) -> Option<u16> {
+    ca(f, opcodes::OP_REG, funct7::DEFAULT, funct3::XOR, 0, 0b01)
+}
+
+fn c_or(f: &Fields) -> Option<u16> {
+    ca(f, opcodes::OP_REG, funct7::DEFAULT, funct3::OR, 0, 0b10)
+}
+
+fn c_and(f: &Fields) -> Option<u16> {
+    ca(f, opcodes::OP_REG, funct7::DEFAULT, funct3::AND, 0, 0b11)
+}
+
+fn c_subw(f: &Fields) -> Option<u16> {
+    ca(
+        f,
+        opcodes::OP_REG_32,
+        funct7::SUBW,
+        funct3::ADD_SUB,
+        1,
+        0b00,
+    )
+}
+
+fn c_addw(f: &Fields) -> Option<u16> {
+    ca(
+        f,
+        opcodes::OP_REG_32,
+        funct7::ADDW,
+        funct3::ADD_SUB,
+        1,
+        0b01,
+    )
+}
+
+fn is_add(f: &Fields) -> bool {
+    f.opcode == opcodes::OP_REG && f.funct7 == funct7::DEFAULT && f.funct3 == funct3::ADD_SUB
+}
+
+fn c_mv(f: &Fields) -> Option<u16> {
+    // `addi rd, rs, 0` is also a move, and is what `mv` usually assembles to.
+    let source = if is_add(f) && f.rs1 == 0 {
+        f.rs2
+    } else if is_addi(f) && f.i_imm == 0 {
+        f.rs1
+    } else {
+        return None;
+    };
+    (f.rd != 0 && source != 0)
+        .then_some((0b100 << 13) | ((f.rd as u16) << 7) | ((source as u16) << 2) | 0b10)
+}
+
+fn c_add(f: &Fields) -> Option<u16> {
+    (is_add(f) && f.rd != 0 && f.rd == f.rs1 && f.rs2 != 0)
+        .then_some((0b100 << 13) | (1 << 12) | ((f.rd as u16) << 7) | ((f.rs2 as u16) << 2) | 0b10)
+}
+
+fn c_jr(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_JALR && f.rd == 0 && f.rs1 != 0 && f.i_imm == 0)
+        .then_some((0b100 << 13) | ((f.rs1 as u16) << 7) | 0b10)
+}
+
+fn c_jalr(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_JALR && f.rd == 1 && f.rs1 != 0 && f.i_imm == 0)
+        .then_some((0b100 << 13) | (1 << 12) | ((f.rs1 as u16) << 7) | 0b10)
+}
+
+fn c_ebreak(f: &Fields) -> Option<u16> {
+    let is_ebreak = f.opcode == opcodes::OP_SYSTEM
+        && f.i_imm == riscv_core::system::FUNCT12_EBREAK as i32
+        && f.rd == 0
+        && f.rs1 == 0
+        && f.funct3 == 0;
+    is_ebreak.then_some(0x9002)
+}
+
+/// `c.lw`, `c.ld`, `c.fld` and their stores: 3-bit registers and a scaled 5-bit offset.
+fn cl_cs(f: &Fields, funct3: u16, data_reg: u32, imm: i32, is_word: bool) -> Option<u16> {
+    let data = creg(data_reg)?;
+    let base = creg(f.rs1)?;
+    let offset = if is_word {
+        if !scaled(imm, 4, 124) {
+            return None;
+        }
+        bits(imm, 5, 3, 10) | bits(imm, 2, 2, 6) | bits(imm, 6, 6, 5)
+    } else {
+        if !scaled(imm, 8, 248) {
+            return None;
+        }
+        bits(imm, 5, 3, 10) | bits(imm, 7, 6, 5)
+    };
+    Some((funct3 << 13) | offset | (base << 7) | (data << 2))
+}
+
+fn c_lw(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD && f.funct3 == funct3::LW)
+        .then(|| cl_cs(f, 0b010, f.rd, f.i_imm, true))
+        .flatten()
+}
+
+fn c_ld(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD && f.funct3 == funct3::LD)
+        .then(|| cl_cs(f, 0b011, f.rd, f.i_imm, false))
+        .flatten()
+}
+
+fn c_fld(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD_FP && f.funct3 == funct3::FLD)
+        .then(|| cl_cs(f, 0b001, f.rd, f.i_imm, false))
+        .flatten()
+}
+
+fn c_sw(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE && f.funct3 == funct3::SW)
+        .then(|| cl_cs(f, 0b110, f.rs2, f.s_imm, true))
+        .flatten()
+}
+
+fn c_sd(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE && f.funct3 == funct3::SD)
+        .then(|| cl_cs(f, 0b111, f.rs2, f.s_imm, false))
+        .flatten()
+}
+
+fn c_fsd(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE_FP && f.funct3 == funct3::FSD)
+        .then(|| cl_cs(f, 0b101, f.rs2, f.s_imm, false))
+        .flatten()
+}
+
+/// The `sp`-relative loads: a full destination register and a scaled 6-bit offset.
+fn ci_sp_load(f: &Fields, funct3: u16, is_word: bool) -> Option<u16> {
+    let imm = f.i_imm;
+    if f.rs1 != 2 {
+        return None;
+    }
+    let offset = if is_word {
+        if !scaled(imm, 4, 252) {
+            return None;
+        }
+        bits(imm, 5, 5, 12) | bits(imm, 4, 2, 4) | bits(imm, 7, 6, 2)
+    } else {
+        if !scaled(imm, 8, 504) {
+            return None;
+        }
+        bits(imm, 5, 5, 12) | bits(imm, 4, 3, 5) | bits(imm, 8, 6, 2)
+    };
+    Some((funct3 << 13) | offset | ((f.rd as u16) << 7) | 0b10)
+}
+
+fn c_lwsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD && f.funct3 == funct3::LW && f.rd != 0)
+        .then(|| ci_sp_load(f, 0b010, true))
+        .flatten()
+}
+
+fn c_ldsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD && f.funct3 == funct3::LD && f.rd != 0)
+        .then(|| ci_sp_load(f, 0b011, false))
+        .flatten()
+}
+
+fn c_fldsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_LOAD_FP && f.funct3 == funct3::FLD)
+        .then(|| ci_sp_load(f, 0b001, false))
+        .flatten()
+}
+
+/// The `sp`-relative stores: a full source register and a scaled 6-bit offset.
+fn css(f: &Fields, funct3: u16, is_word: bool) -> Option<u16> {
+    let imm = f.s_imm;
+    if f.rs1 != 2 {
+        return None;
+    }
+    let offset = if is_word {
+        if !scaled(imm, 4, 252) {
+            return None;
+        }
+        bits(imm, 5, 2, 9) | bits(imm, 7, 6, 7)
+    } else {
+        if !scaled(imm, 8, 504) {
+            return None;
+        }
+        bits(imm, 5, 3, 10) | bits(imm, 8, 6, 7)
+    };
+    Some((funct3 << 13) | offset | ((f.rs2 as u16) << 2) | 0b10)
+}
+
+fn c_swsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE && f.funct3 == funct3::SW)
+        .then(|| css(f, 0b110, true))
+        .flatten()
+}
+
+fn c_sdsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE && f.funct3 == funct3::SD)
+        .then(|| css(f, 0b111, false))
+        .flatten()
+}
+
+fn c_fsdsp(f: &Fields) -> Option<u16> {
+    (f.opcode == opcodes::OP_STORE_FP && f.funct3 == funct3::FSD)
+        .then(|| css(f, 0b101, false))
+        .flatten()
+}
+
+fn c_j(f: &Fields) -> Option<u16> {
+    let imm = f.j_imm;
+    (f.opcode == opcodes::OP_JAL && f.rd == 0 && fits_signed(imm, 12)).then(|| {
+        (0b101 << 13)
+            | bits(imm, 11, 11, 12)
+            | bits(imm, 4, 4, 11)
+            | bits(imm, 9, 8, 9)
+            | bits(imm, 10, 10, 8)
+            | bits(imm, 6, 6, 7)
+            | bits(imm, 7, 7, 6)
+            | bits(imm, 3, 1, 3)
+            | bits(imm, 5, 5, 2)
+            | 0b01
+    })
+}
+
+/// `c.beqz` and `c.bnez`: a comparison of a 3-bit register against zero.
+fn cb_branch(f: &Fields, branch_funct3: u32, funct3: u16) -> Option<u16> {
+    let rs1 = creg(f.rs1)?;
+    let imm = f.b_imm;
+    (f.opcode == opcodes::OP_BRANCH
+        && f.funct3 == branch_funct3
+        && f.rs2 == 0
+        && fits_signed(imm, 9))
+    .then(|| {
+        (funct3 << 13)
+            | bits(imm, 8, 8, 12)
+            | bits(imm, 4, 3, 10)
+            | (rs1 << 7)
+            | bits(imm, 7, 6, 5)
+            | bits(imm, 2, 1, 3)
+            | bits(imm, 5, 5, 2)
+            | 0b01
+    })
+}
+
+fn c_beqz(f: &Fields) -> Option<u16> {
+    cb_branch(f, funct3::BEQ, 0b110)
+}
+
+fn c_bnez(f: &Fields) -> Option<u16> {
+    cb_branch(f, funct3::BNE, 0b111)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv_core::compressed::expand;
+
+    #[test]
+    fn test_compress_round_trips_through_expand() {
+        let words = [
+            0x00000013, // nop
+            0x00150513, // addi a0, a0, 1
+            0xfff00513, // li a0, -1
+            0x00810513, // addi a0, sp, 8
+            0xfd010113, // addi sp, sp, -48
+            0x00001537, // lui a0, 1
+            0x0005b503, // ld a0, 0(a1)
+            0x00a5a023, // sw a0, 0(a1)
+            0x40b50533, // sub a0, a0, a1
+            0x00b5053b, // addw a0, a0, a1
+            0x00b00533, // add a0, zero, a1
+            0x00b50533, // add a0, a0, a1
+            0x00008067, // jalr zero, 0(ra)
+            0x00100073, // ebreak
+            0x00113423, // sd ra, 8(sp)
+            0x00813083, // ld ra, 8(sp)
+            0x00351513, // slli a0, a0, 3
+            0x4035d593, // srai a1, a1, 3
+            0xfe051de3, // bne a0, zero, -6
+            0xffdff06f, // jal zero, -4
+        ];
+        for word in words {
+            let compressed = compress(word).unwrap_or_else(|| panic!("{:#010x}", word));
+            assert_eq!(expand(compressed), Some(word), "{:#010x}", word);
+        }
+    }
+
+    #[test]
+    fn test_uncompressible_instructions() {
+        assert_eq!(compress(0x00150593), None); // addi a1, a0, 1: rd differs from rs1
+        assert_eq!(compress(0x02050513), None); // addi a0, a0, 32: immediate too wide
+        assert_eq!(compress(0x0005b583 | (1 << 20)), None); // ld a1, 1(a1): misaligned
+        assert_eq!(compress(0x00050533), None); // add a0, a0, zero
+    }
+
+    #[test]
+    fn test_option_rvc_compresses_all_but_label_references() {
+        let program = "
+            .option rvc
+            start:
+                addi a0, a0, 1
+                addi a1, zero, 5
+            loop:
+                addi a1, a1, -1
+                bne a1, zero, loop
+                c.j start
+                .option norvc
+                addi a0, a0, 1
+        ";
+        let text = crate::parse_program(program).unwrap().text;
+        let half = |offset: usize| u16::from_le_bytes([text[offset], text[offset + 1]]);
+        let word = |offset: usize| u32::from_le_bytes(text[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(half(0), 0x0505); // c.addi a0, 1
+        assert_eq!(half(2), 0x4595); // c.li a1, 5
+        assert_eq!(half(4), 0x15fd); // c.addi a1, -1
+        assert_eq!(word(6), 0xfe059fe3); // bne a1, zero, -2
+        assert_eq!(expand(half(10)), Some(0xff7ff06f)); // c.j -10
+        assert_eq!(word(12), 0x00150513);
+        assert_eq!(text.len(), 16);
+    }
+
+    #[test]
+    fn test_explicit_compressed_mnemonics() {
+        let text = crate::parse_program("c.addi16sp sp, -48\nc.lwsp a0, 12(sp)\nc.jr ra")
+            .unwrap()
+            .text;
+        assert_eq!(&text[..6], &[0x79, 0x71, 0x32, 0x45, 0x82, 0x80]);
+        assert!(crate::parse_program("c.addi a0, 64").is_err());
+        assert!(crate::parse_program("c.lw a0, 0(sp)").is_err());
+    }
+
+    #[test]
+    fn test_compress_as_picks_the_requested_form() {
+        let addi_sp_16 = 0x01010113; // addi sp, sp, 16
+        assert_eq!(
+            expand(compress_as(addi_sp_16, "c.addi").unwrap()),
+            Some(addi_sp_16)
+        );
+        assert_eq!(
+            expand(compress_as(addi_sp_16, "c.addi16sp").unwrap()),
+            Some(addi_sp_16)
+        );
+        assert_eq!(compress_as(addi_sp_16, "c.li"), None);
+    }
+}
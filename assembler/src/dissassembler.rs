@@ -1,6 +1,9 @@
-use riscv_core::{funct3, funct7, opcodes, system};
+use crate::compress::compressed_mnemonic;
+use crate::vector::disassemble_vector;
+use riscv_core::compressed::{self, UNCOMPRESSED_MASK};
+use riscv_core::{bitmanip, crypto, funct3, funct7, opcodes, system};
 
-fn abi_to_string(reg: u32) -> String {
+pub(crate) fn abi_to_string(reg: u32) -> String {
     match reg {
         0 => "zero",
         1 => "ra",
@@ -39,9 +42,39 @@ fn abi_to_string(reg: u32) -> String {
     .to_string()
 }
 
+fn fp_abi_to_string(reg: u32) -> String {
+    const NAMES: [&str; 32] = [
+        "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+        "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+        "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+    ];
+    NAMES
+        .get(reg as usize)
+        .copied()
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// The assembler syntax for a static rounding mode, with its leading comma, or nothing for
+/// `dyn`.
+fn rm_suffix(rm: u32) -> &'static str {
+    match rm {
+        0b000 => ", rne",
+        0b001 => ", rtz",
+        0b010 => ", rdn",
+        0b011 => ", rup",
+        0b100 => ", rmm",
+        _ => "",
+    }
+}
+
 fn csr_to_string(csr: u32) -> String {
-    let csr_str = match csr {
+    match csr {
         0x000 => "ustatus",
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0x015 => "seed",
         0x004 => "uie",
         0x005 => "utvec",
         0x040 => "uscratch",
@@ -72,19 +105,43 @@ fn csr_to_string(csr: u32) -> String {
         0x304 => "mie",
         0x305 => "mtvec",
         0x306 => "mcounteren",
+        0x320 => "mcountinhibit",
+        0x310 => "mstatush",
         0x340 => "mscratch",
         0x341 => "mepc",
         0x342 => "mcause",
         0x343 => "mtval",
         0x344 => "mip",
+        0x747 => "mseccfg",
+        0x3A0..=0x3AF => return format!("pmpcfg{}", csr - 0x3A0),
+        0x3B0..=0x3EF => return format!("pmpaddr{}", csr - 0x3B0),
+        0x323..=0x33F => return format!("mhpmevent{}", csr - 0x320),
+        0x723..=0x73F => return format!("mhpmevent{}h", csr - 0x720),
+        0xB03..=0xB1F => return format!("mhpmcounter{}", csr - 0xB00),
+        0xB83..=0xB9F => return format!("mhpmcounter{}h", csr - 0xB80),
+        0xC03..=0xC1F => return format!("hpmcounter{}", csr - 0xC00),
+        0xC83..=0xC9F => return format!("hpmcounter{}h", csr - 0xC80),
+        0xDA0 => "scountovf",
+        0xB00 => "mcycle",
+        0xB02 => "minstret",
+        0xB80 => "mcycleh",
+        0xB82 => "minstreth",
+        0xC00 => "cycle",
+        0xC01 => "time",
+        0xC02 => "instret",
+        0xC80 => "cycleh",
+        0xC81 => "timeh",
+        0xC82 => "instreth",
         _ => "extra",
     }
-    .to_string();
-
-    csr_str
+    .to_string()
 }
 
 pub fn disassemble(word: u32, pc: u64) -> String {
+    if word & UNCOMPRESSED_MASK != UNCOMPRESSED_MASK {
+        return disassemble_compressed(word as u16, pc);
+    }
+
     let opcode = word & 0x7f;
     let rd = (word >> 7) & 0x1f;
     let rs1 = (word >> 15) & 0x1f;
@@ -96,6 +153,13 @@ pub fn disassemble(word: u32, pc: u64) -> String {
     let rs1_str = abi_to_string(rs1);
     let rs2_str = abi_to_string(rs2);
 
+    if let Some(text) = disassemble_bitmanip(word)
+        .or_else(|| disassemble_crypto(word))
+        .or_else(|| disassemble_vector(word))
+    {
+        return text;
+    }
+
     match opcode {
         opcodes::OP_LUI => format!("lui {}, {:#x}", rd_str, (word & 0xfffff000) as i32 >> 12),
 
@@ -258,6 +322,98 @@ pub fn disassemble(word: u32, pc: u64) -> String {
             };
             format!("{} {}, {}, {}", mnemonic, rd_str, rs1_str, rs2_str)
         }
+        opcodes::OP_AMO => {
+            let width = match funct3 {
+                funct3::AMO_W => "w",
+                funct3::AMO_D => "d",
+                _ => return "unknown_amo".to_string(),
+            };
+            let name = match funct7 >> 2 {
+                funct7::LR => "lr",
+                funct7::SC => "sc",
+                funct7::AMOSWAP => "amoswap",
+                funct7::AMOADD => "amoadd",
+                funct7::AMOXOR => "amoxor",
+                funct7::AMOAND => "amoand",
+                funct7::AMOOR => "amoor",
+                funct7::AMOMIN => "amomin",
+                funct7::AMOMAX => "amomax",
+                funct7::AMOMINU => "amominu",
+                funct7::AMOMAXU => "amomaxu",
+                _ => return "unknown_amo".to_string(),
+            };
+            let ordering = match funct7 & 0b11 {
+                0b01 => ".rl",
+                0b10 => ".aq",
+                0b11 => ".aqrl",
+                _ => "",
+            };
+            if name == "lr" {
+                format!("lr.{}{} {}, ({})", width, ordering, rd_str, rs1_str)
+            } else {
+                format!(
+                    "{}.{}{} {}, {}, ({})",
+                    name, width, ordering, rd_str, rs2_str, rs1_str
+                )
+            }
+        }
+        opcodes::OP_LOAD_FP => {
+            let imm = (word as i32 >> 20) as i64;
+            let mnemonic = match funct3 {
+                funct3::FLW => "flw",
+                funct3::FLD => "fld",
+                _ => "unknown_load_fp",
+            };
+            format!(
+                "{} {}, {}({})",
+                mnemonic,
+                fp_abi_to_string(rd),
+                imm,
+                rs1_str
+            )
+        }
+        opcodes::OP_STORE_FP => {
+            let imm11_5 = (word >> 25) & 0x7f;
+            let imm4_0 = (word >> 7) & 0x1f;
+            let imm = (imm11_5 << 5) | imm4_0;
+            let signed_imm = ((imm as i32) << 20) >> 20;
+            let mnemonic = match funct3 {
+                funct3::FSW => "fsw",
+                funct3::FSD => "fsd",
+                _ => "unknown_store_fp",
+            };
+            format!(
+                "{} {}, {}({})",
+                mnemonic,
+                fp_abi_to_string(rs2),
+                signed_imm,
+                rs1_str
+            )
+        }
+        opcodes::OP_MADD | opcodes::OP_MSUB | opcodes::OP_NMSUB | opcodes::OP_NMADD => {
+            let name = match opcode {
+                opcodes::OP_MADD => "fmadd",
+                opcodes::OP_MSUB => "fmsub",
+                opcodes::OP_NMSUB => "fnmsub",
+                _ => "fnmadd",
+            };
+            let fmt = match funct7 & 0b11 {
+                funct7::FMT_S => "s",
+                funct7::FMT_D => "d",
+                _ => return "unknown_fma".to_string(),
+            };
+            format!(
+                "{}.{} {}, {}, {}, {}{}",
+                name,
+                fmt,
+                fp_abi_to_string(rd),
+                fp_abi_to_string(rs1),
+                fp_abi_to_string(rs2),
+                fp_abi_to_string(word >> 27),
+                rm_suffix(funct3)
+            )
+        }
+        opcodes::OP_FP => disassemble_fp(word),
         opcodes::OP_MISC_MEM => match funct3 {
             funct3::FENCE => "fence".to_string(),
             funct3::FENCE_I => "fence.i".to_string(),
@@ -266,11 +422,19 @@ pub fn disassemble(word: u32, pc: u64) -> String {
         opcodes::OP_SYSTEM => {
             let csr = word >> 20;
             match funct3 {
+                0 if word >> 25 == funct7::SFENCE_VMA => {
+                    format!(
+                        "sfence.vma {}, {}",
+                        rs1_str,
+                        abi_to_string((word >> 20) & 0x1f)
+                    )
+                }
                 0 => match csr {
                     system::FUNCT12_ECALL => "ecall".to_string(),
                     system::FUNCT12_EBREAK => "ebreak".to_string(),
                     system::FUNCT12_SRET => "sret".to_string(),
                     system::FUNCT12_MRET => "mret".to_string(),
+                    system::FUNCT12_WFI => "wfi".to_string(),
                     _ => "unknown_system".to_string(),
                 },
                 funct3::CSRRW => format!("csrrw {}, {}, {}", rd_str, csr_to_string(csr), rs1_str),
@@ -285,3 +449,318 @@ pub fn disassemble(word: u32, pc: u64) -> String {
         _ => format!("unimplemented {:#010x}", word),
     }
 }
+
+fn disassemble_fp(word: u32) -> String {
+    let rd = (word >> 7) & 0x1f;
+    let rs1 = (word >> 15) & 0x1f;
+    let rs2 = (word >> 20) & 0x1f;
+    let funct3 = (word >> 12) & 0x7;
+    let funct5 = word >> 27;
+    let fmt = match (word >> 25) & 0b11 {
+        funct7::FMT_S => "s",
+        funct7::FMT_D => "d",
+        _ => return "unknown_op_fp".to_string(),
+    };
+    let int_type = match rs2 {
+        0 => "w",
+        1 => "wu",
+        2 => "l",
+        _ => "lu",
+    };
+
+    let (frd, frs1, frs2) = (
+        fp_abi_to_string(rd),
+        fp_abi_to_string(rs1),
+        fp_abi_to_string(rs2),
+    );
+    let rm = rm_suffix(funct3);
+    match funct5 {
+        funct7::FADD | funct7::FSUB | funct7::FMUL | funct7::FDIV => {
+            let name = match funct5 {
+                funct7::FADD => "fadd",
+                funct7::FSUB => "fsub",
+                funct7::FMUL => "fmul",
+                _ => "fdiv",
+            };
+            format!("{}.{} {}, {}, {}{}", name, fmt, frd, frs1, frs2, rm)
+        }
+        funct7::FSQRT => format!("fsqrt.{} {}, {}{}", fmt, frd, frs1, rm),
+        funct7::FSGNJ => {
+            let (name, pseudo) = match funct3 {
+                0b000 => ("fsgnj", "fmv"),
+                0b001 => ("fsgnjn", "fneg"),
+                0b010 => ("fsgnjx", "fabs"),
+                _ => return "unknown_op_fp".to_string(),
+            };
+            if rs1 == rs2 {
+                format!("{}.{} {}, {}", pseudo, fmt, frd, frs1)
+            } else {
+                format!("{}.{} {}, {}, {}", name, fmt, frd, frs1, frs2)
+            }
+        }
+        funct7::FMIN_MAX => {
+            let name = if funct3 == 0 { "fmin" } else { "fmax" };
+            format!("{}.{} {}, {}, {}", name, fmt, frd, frs1, frs2)
+        }
+        funct7::FCVT_FMT_FMT => {
+            let from = if rs2 == 0 { "s" } else { "d" };
+            format!("fcvt.{}.{} {}, {}{}", fmt, from, frd, frs1, rm)
+        }
+        funct7::FCMP => {
+            let name = match funct3 {
+                0b010 => "feq",
+                0b001 => "flt",
+                _ => "fle",
+            };
+            format!("{}.{} {}, {}, {}", name, fmt, abi_to_string(rd), frs1, frs2)
+        }
+        funct7::FCVT_INT_FMT => {
+            format!(
+                "fcvt.{}.{} {}, {}{}",
+                int_type,
+                fmt,
+                abi_to_string(rd),
+                frs1,
+                rm
+            )
+        }
+        funct7::FCVT_FMT_INT => {
+            format!(
+                "fcvt.{}.{} {}, {}{}",
+                fmt,
+                int_type,
+                frd,
+                abi_to_string(rs1),
+                rm
+            )
+        }
+        funct7::FMV_X_FMT_FCLASS if funct3 == 0b001 => {
+            format!("fclass.{} {}, {}", fmt, abi_to_string(rd), frs1)
+        }
+        funct7::FMV_X_FMT_FCLASS => {
+            let width = if fmt == "s" { "w" } else { "d" };
+            format!("fmv.x.{} {}, {}", width, abi_to_string(rd), frs1)
+        }
+        funct7::FMV_FMT_X => {
+            let width = if fmt == "s" { "w" } else { "d" };
+            format!("fmv.{}.x {}, {}", width, frd, abi_to_string(rs1))
+        }
+        _ => "unknown_op_fp".to_string(),
+    }
+}
+
+/// Disassembles a Zba, Zbb, Zbc, Zbs, Zbkb or Zbkx instruction, or returns `None` if `word`
+/// is not one.
+fn disassemble_bitmanip(word: u32) -> Option<String> {
+    let opcode = word & 0x7f;
+    let rd = abi_to_string((word >> 7) & 0x1f);
+    let rs1 = abi_to_string((word >> 15) & 0x1f);
+    let rs2 = abi_to_string((word >> 20) & 0x1f);
+    let funct3 = (word >> 12) & 0x7;
+    let funct7 = (word >> 25) & 0x7f;
+    let funct12 = word >> 20;
+    let funct6 = (word >> 26) << 1;
+    let shamt = (word >> 20) & 0x3f;
+
+    let register = |mnemonic: &str| Some(format!("{} {}, {}, {}", mnemonic, rd, rs1, rs2));
+    let unary = |mnemonic: &str| Some(format!("{} {}, {}", mnemonic, rd, rs1));
+    let immediate =
+        |mnemonic: &str, shamt: u32| Some(format!("{} {}, {}, {}", mnemonic, rd, rs1, shamt));
+
+    match opcode {
+        opcodes::OP_REG => match (funct7, funct3) {
+            (funct7::SHADD, funct3::SH1ADD) => register("sh1add"),
+            (funct7::SHADD, funct3::SH2ADD) => register("sh2add"),
+            (funct7::SHADD, funct3::SH3ADD) => register("sh3add"),
+            (funct7::ANDN_ORN_XNOR, funct3::AND) => register("andn"),
+            (funct7::ANDN_ORN_XNOR, funct3::OR) => register("orn"),
+            (funct7::ANDN_ORN_XNOR, funct3::XOR) => register("xnor"),
+            (funct7::MINMAX_CLMUL, funct3::MIN) => register("min"),
+            (funct7::MINMAX_CLMUL, funct3::MINU) => register("minu"),
+            (funct7::MINMAX_CLMUL, funct3::MAX) => register("max"),
+            (funct7::MINMAX_CLMUL, funct3::MAXU) => register("maxu"),
+            (funct7::MINMAX_CLMUL, funct3::CLMUL) => register("clmul"),
+            (funct7::MINMAX_CLMUL, funct3::CLMULR) => register("clmulr"),
+            (funct7::MINMAX_CLMUL, funct3::CLMULH) => register("clmulh"),
+            (funct7::ROTATE, funct3::SLL) => register("rol"),
+            (funct7::ROTATE, funct3::SRL_SRA) => register("ror"),
+            (funct7::BSET, funct3::SLL) => register("bset"),
+            (funct7::BCLR_BEXT, funct3::SLL) => register("bclr"),
+            (funct7::BINV, funct3::SLL) => register("binv"),
+            (funct7::BCLR_BEXT, funct3::SRL_SRA) => register("bext"),
+            (funct7::PACK, funct3::PACK) => register("pack"),
+            (funct7::PACK, funct3::PACKH) => register("packh"),
+            (funct7::XPERM, funct3::XPERM4) => register("xperm4"),
+            (funct7::XPERM, funct3::XPERM8) => register("xperm8"),
+            _ => None,
+        },
+        opcodes::OP_REG_32 => match (funct7, funct3) {
+            (funct7::ADD_UW, funct3::ADD_SUB) if funct12 & 0x1f == 0 => unary("zext.w"),
+            (funct7::ADD_UW, funct3::ADD_SUB) => register("add.uw"),
+            (funct7::SHADD, funct3::SH1ADD) => register("sh1add.uw"),
+            (funct7::SHADD, funct3::SH2ADD) => register("sh2add.uw"),
+            (funct7::SHADD, funct3::SH3ADD) => register("sh3add.uw"),
+            (funct7::ADD_UW, funct3::ZEXT_H) if funct12 == bitmanip::FUNCT12_ZEXT_H => {
+                unary("zext.h")
+            }
+            (funct7::PACK, funct3::PACK) => register("packw"),
+            (funct7::ROTATE, funct3::SLL) => register("rolw"),
+            (funct7::ROTATE, funct3::SRL_SRA) => register("rorw"),
+            _ => None,
+        },
+        opcodes::OP_IMM => match (funct3, funct12) {
+            (funct3::SLL, bitmanip::FUNCT12_CLZ) => unary("clz"),
+            (funct3::SLL, bitmanip::FUNCT12_CTZ) => unary("ctz"),
+            (funct3::SLL, bitmanip::FUNCT12_CPOP) => unary("cpop"),
+            (funct3::SLL, bitmanip::FUNCT12_SEXT_B) => unary("sext.b"),
+            (funct3::SLL, bitmanip::FUNCT12_SEXT_H) => unary("sext.h"),
+            (funct3::SRL_SRA, bitmanip::FUNCT12_REV8) => unary("rev8"),
+            (funct3::SRL_SRA, bitmanip::FUNCT12_ORC_B) => unary("orc.b"),
+            (funct3::SRL_SRA, bitmanip::FUNCT12_BREV8) => unary("brev8"),
+            _ => match (funct6, funct3) {
+                (funct7::ROTATE, funct3::SRL_SRA) => immediate("rori", shamt),
+                (funct7::BSET, funct3::SLL) => immediate("bseti", shamt),
+                (funct7::BCLR_BEXT, funct3::SLL) => immediate("bclri", shamt),
+                (funct7::BINV, funct3::SLL) => immediate("binvi", shamt),
+                (funct7::BCLR_BEXT, funct3::SRL_SRA) => immediate("bexti", shamt),
+                _ => None,
+            },
+        },
+        opcodes::OP_IMM_32 => match (funct3, funct12) {
+            (funct3::SLL, bitmanip::FUNCT12_CLZ) => unary("clzw"),
+            (funct3::SLL, bitmanip::FUNCT12_CTZ) => unary("ctzw"),
+            (funct3::SLL, bitmanip::FUNCT12_CPOP) => unary("cpopw"),
+            _ if funct6 == funct7::ADD_UW && funct3 == funct3::SLL => immediate("slli.uw", shamt),
+            _ if funct7 == funct7::ROTATE && funct3 == funct3::SRL_SRA => {
+                immediate("roriw", shamt & 0x1f)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Disassembles a scalar cryptography instruction, or returns `None` if `word` is not one.
+fn disassemble_crypto(word: u32) -> Option<String> {
+    let opcode = word & 0x7f;
+    let rd = abi_to_string((word >> 7) & 0x1f);
+    let rs1 = abi_to_string((word >> 15) & 0x1f);
+    let rs2 = abi_to_string((word >> 20) & 0x1f);
+    let funct3 = (word >> 12) & 0x7;
+    let funct7 = (word >> 25) & 0x7f;
+    let funct12 = word >> 20;
+
+    match (opcode, funct3) {
+        (opcodes::OP_REG, funct3::ADD_SUB) => {
+            let mnemonic = match funct7 {
+                crypto::FUNCT7_AES64ES => "aes64es",
+                crypto::FUNCT7_AES64ESM => "aes64esm",
+                crypto::FUNCT7_AES64DS => "aes64ds",
+                crypto::FUNCT7_AES64DSM => "aes64dsm",
+                crypto::FUNCT7_AES64KS2 => "aes64ks2",
+                _ => {
+                    let mnemonic = match funct7 & 0x1f {
+                        crypto::FUNCT5_SM4ED => "sm4ed",
+                        crypto::FUNCT5_SM4KS => "sm4ks",
+                        _ => return None,
+                    };
+                    return Some(format!(
+                        "{} {}, {}, {}, {}",
+                        mnemonic,
+                        rd,
+                        rs1,
+                        rs2,
+                        funct7 >> 5
+                    ));
+                }
+            };
+            Some(format!("{} {}, {}, {}", mnemonic, rd, rs1, rs2))
+        }
+        (opcodes::OP_IMM, funct3::SLL) => {
+            if funct12 >> 4 == crypto::FUNCT8_AES64KS1I {
+                return Some(format!("aes64ks1i {}, {}, {}", rd, rs1, funct12 & 0xf));
+            }
+            let mnemonic = match funct12 {
+                crypto::FUNCT12_AES64IM => "aes64im",
+                crypto::FUNCT12_SHA256SIG0 => "sha256sig0",
+                crypto::FUNCT12_SHA256SIG1 => "sha256sig1",
+                crypto::FUNCT12_SHA256SUM0 => "sha256sum0",
+                crypto::FUNCT12_SHA256SUM1 => "sha256sum1",
+                crypto::FUNCT12_SHA512SIG0 => "sha512sig0",
+                crypto::FUNCT12_SHA512SIG1 => "sha512sig1",
+                crypto::FUNCT12_SHA512SUM0 => "sha512sum0",
+                crypto::FUNCT12_SHA512SUM1 => "sha512sum1",
+                crypto::FUNCT12_SM3P0 => "sm3p0",
+                crypto::FUNCT12_SM3P1 => "sm3p1",
+                _ => return None,
+            };
+            Some(format!("{} {}, {}", mnemonic, rd, rs1))
+        }
+        _ => None,
+    }
+}
+
+/// Disassembles a 16-bit instruction from the fields of its 32-bit expansion.
+fn disassemble_compressed(half: u16, pc: u64) -> String {
+    let Some(word) = compressed::expand(half) else {
+        return format!("unimplemented {:#06x}", half);
+    };
+    // Hints have no mnemonic of their own; show what they expand to.
+    let Some(name) = compressed_mnemonic(half) else {
+        return disassemble(word, pc);
+    };
+
+    let rd = (word >> 7) & 0x1f;
+    let rs1 = abi_to_string((word >> 15) & 0x1f);
+    let rs2 = (word >> 20) & 0x1f;
+    let i_imm = word as i32 >> 20;
+    let s_imm = ((word as i32 >> 25) << 5) | ((word >> 7) & 0x1f) as i32;
+
+    match name {
+        "c.nop" | "c.ebreak" => name.to_string(),
+        "c.addi" | "c.addiw" | "c.li" | "c.andi" => {
+            format!("{} {}, {}", name, abi_to_string(rd), i_imm)
+        }
+        "c.slli" | "c.srli" | "c.srai" => {
+            format!("{} {}, {}", name, abi_to_string(rd), i_imm & 0x3f)
+        }
+        "c.addi16sp" => format!("c.addi16sp sp, {}", i_imm),
+        "c.addi4spn" => format!("c.addi4spn {}, sp, {}", abi_to_string(rd), i_imm),
+        "c.lui" => format!(
+            "c.lui {}, {:#x}",
+            abi_to_string(rd),
+            (word & 0xfffff000) as i32 >> 12
+        ),
+        "c.mv" | "c.add" | "c.sub" | "c.xor" | "c.or" | "c.and" | "c.subw" | "c.addw" => {
+            format!("{} {}, {}", name, abi_to_string(rd), abi_to_string(rs2))
+        }
+        "c.lw" | "c.ld" | "c.lwsp" | "c.ldsp" => {
+            format!("{} {}, {}({})", name, abi_to_string(rd), i_imm, rs1)
+        }
+        "c.fld" | "c.fldsp" => format!("{} {}, {}({})", name, fp_abi_to_string(rd), i_imm, rs1),
+        "c.sw" | "c.sd" | "c.swsp" | "c.sdsp" => {
+            format!("{} {}, {}({})", name, abi_to_string(rs2), s_imm, rs1)
+        }
+        "c.fsd" | "c.fsdsp" => {
+            format!("{} {}, {}({})", name, fp_abi_to_string(rs2), s_imm, rs1)
+        }
+        "c.jr" | "c.jalr" => format!("{} {}", name, rs1),
+        "c.j" => {
+            let imm = (((word >> 31) & 1) << 20)
+                | (((word >> 12) & 0xff) << 12)
+                | (((word >> 20) & 1) << 11)
+                | (((word >> 21) & 0x3ff) << 1);
+            let target = pc.wrapping_add((((imm as i32) << 11) >> 11) as i64 as u64);
+            format!("c.j {:#x}", target)
+        }
+        _ => {
+            // c.beqz and c.bnez
+            let imm = (((word >> 31) & 1) << 12)
+                | (((word >> 7) & 1) << 11)
+                | (((word >> 25) & 0x3f) << 5)
+                | (((word >> 8) & 0xf) << 1);
+            let target = pc.wrapping_add((((imm as i32) << 19) >> 19) as i64 as u64);
+            format!("{} {}, {:#x}", name, rs1, target)
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use crate::types::{AssemblerErrorKind, BASE_ADDRESS};
-use riscv_core::{funct3, funct7, opcodes, system};
+use crate::vector::encode_vector;
+use riscv_core::{bitmanip, crypto, funct3, funct7, opcodes, system};
 use std::collections::HashMap;
 
-fn parse_immediate(imm_str: &str) -> Result<i64, AssemblerErrorKind> {
+pub(crate) fn parse_immediate(imm_str: &str) -> Result<i64, AssemblerErrorKind> {
     let s = imm_str.trim_end_matches(',');
-    if s.starts_with("0x") {
-        i64::from_str_radix(&s[2..], 16)
+    if let Some(hex) = s.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
             .map_err(|_| AssemblerErrorKind::InvalidImmediateValue(s.to_string()))
-    } else if s.starts_with("0b") {
-        i64::from_str_radix(&s[2..], 2)
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
             .map_err(|_| AssemblerErrorKind::InvalidImmediateValue(s.to_string()))
     } else {
         s.parse::<i64>()
@@ -16,6 +17,7 @@ fn parse_immediate(imm_str: &str) -> Result<i64, AssemblerErrorKind> {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn encode_instruction(
     instruction: &str,
     operands: &[&str],
@@ -235,7 +237,7 @@ pub(crate) fn encode_instruction(
             let rd = parse_register(operands[0])?;
             let imm = parse_immediate(operands[1])?;
 
-            if imm >= -2048 && imm <= 2047 {
+            if (-2048..=2047).contains(&imm) {
                 let addi = encode_i_type(imm as u32, 0, funct3::ADD_SUB, rd, opcodes::OP_IMM);
                 return Ok(vec![addi]);
             }
@@ -296,6 +298,26 @@ pub(crate) fn encode_instruction(
             0,
             opcodes::OP_SYSTEM,
         )),
+        "wfi" => Ok(encode_i_type(
+            system::FUNCT12_WFI,
+            0,
+            0,
+            0,
+            opcodes::OP_SYSTEM,
+        )),
+        // Either operand may be left out, standing for x0: every address or every ASID.
+        "sfence.vma" => {
+            let rs1 = operands.first().map_or(Ok(0), |op| parse_register(op))?;
+            let rs2 = operands.get(1).map_or(Ok(0), |op| parse_register(op))?;
+            Ok(encode_r_type(
+                funct7::SFENCE_VMA,
+                rs2,
+                rs1,
+                0,
+                0,
+                opcodes::OP_SYSTEM,
+            ))
+        }
         "fence" => {
             let pred = 0b1000;
             let succ = 0b1000;
@@ -344,6 +366,27 @@ pub(crate) fn encode_instruction(
 
             Ok((csr << 20) | (rs1_field << 15) | (funct3 << 12) | (rd << 7) | opcodes::OP_SYSTEM)
         }
+        "sh1add" | "sh2add" | "sh3add" | "add.uw" | "sh1add.uw" | "sh2add.uw" | "sh3add.uw"
+        | "slli.uw" | "zext.w" | "andn" | "orn" | "xnor" | "clz" | "ctz" | "cpop" | "clzw"
+        | "ctzw" | "cpopw" | "min" | "minu" | "max" | "maxu" | "sext.b" | "sext.h" | "zext.h"
+        | "rev8" | "orc.b" | "rol" | "ror" | "rori" | "rolw" | "rorw" | "roriw" | "clmul"
+        | "clmulh" | "clmulr" | "bset" | "bclr" | "binv" | "bext" | "bseti" | "bclri" | "binvi"
+        | "bexti" | "pack" | "packh" | "packw" | "brev8" | "xperm4" | "xperm8" => {
+            encode_bitmanip(instruction, operands)
+        }
+        "aes64es" | "aes64esm" | "aes64ds" | "aes64dsm" | "aes64ks1i" | "aes64ks2" | "aes64im"
+        | "sha256sig0" | "sha256sig1" | "sha256sum0" | "sha256sum1" | "sha512sig0"
+        | "sha512sig1" | "sha512sum0" | "sha512sum1" | "sm3p0" | "sm3p1" | "sm4ed" | "sm4ks" => {
+            encode_crypto(instruction, operands)
+        }
+        _ if instruction.starts_with("lr.")
+            || instruction.starts_with("sc.")
+            || instruction.starts_with("amo") =>
+        {
+            encode_atomic(instruction, operands)
+        }
+        _ if instruction.starts_with('f') => encode_float(instruction, operands),
+        _ if instruction.starts_with('v') => encode_vector(instruction, operands),
         _ => Err(AssemblerErrorKind::UnknownInstruction(
             instruction.to_string(),
         )),
@@ -351,6 +394,532 @@ pub(crate) fn encode_instruction(
     Ok(vec![single_instr])
 }
 
+/// Encodes an A-extension instruction: `lr.w rd, (rs1)`, `sc.w rd, rs2, (rs1)` or
+/// `amoadd.w rd, rs2, (rs1)`, with an optional `.aq`, `.rl` or `.aqrl` ordering suffix.
+fn encode_atomic(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let unknown = || AssemblerErrorKind::UnknownInstruction(instruction.to_string());
+    let mut parts = instruction.split('.');
+    let (Some(name), Some(width)) = (parts.next(), parts.next()) else {
+        return Err(unknown());
+    };
+    let ordering = match parts.next() {
+        None => 0b00,
+        Some("rl") => 0b01,
+        Some("aq") => 0b10,
+        Some("aqrl") => 0b11,
+        Some(_) => return Err(unknown()),
+    };
+    if parts.next().is_some() {
+        return Err(unknown());
+    }
+    let funct3 = match width {
+        "w" => funct3::AMO_W,
+        "d" => funct3::AMO_D,
+        _ => return Err(unknown()),
+    };
+    let funct5 = match name {
+        "lr" => funct7::LR,
+        "sc" => funct7::SC,
+        "amoswap" => funct7::AMOSWAP,
+        "amoadd" => funct7::AMOADD,
+        "amoxor" => funct7::AMOXOR,
+        "amoand" => funct7::AMOAND,
+        "amoor" => funct7::AMOOR,
+        "amomin" => funct7::AMOMIN,
+        "amomax" => funct7::AMOMAX,
+        "amominu" => funct7::AMOMINU,
+        "amomaxu" => funct7::AMOMAXU,
+        _ => return Err(unknown()),
+    };
+
+    let expected_operands = if funct5 == funct7::LR { 2 } else { 3 };
+    if operands.len() != expected_operands {
+        return Err(AssemblerErrorKind::ParseError(format!(
+            "{} expects {} operands",
+            instruction, expected_operands
+        )));
+    }
+    let rd = parse_register(operands[0])?;
+    let rs2 = if funct5 == funct7::LR {
+        0
+    } else {
+        parse_register(operands[1])?
+    };
+    let address = operands[expected_operands - 1];
+    let (offset, rs1) = parse_memory_operand(address)?;
+    if offset != 0 {
+        return Err(AssemblerErrorKind::InvalidMemoryOperand(
+            address.to_string(),
+        ));
+    }
+
+    let funct7 = (funct5 << 2) | ordering;
+    Ok(encode_r_type(funct7, rs2, rs1, funct3, rd, opcodes::OP_AMO))
+}
+
+/// Encodes a Zba, Zbb, Zbc, Zbs, Zbkb or Zbkx instruction, or the `zext.w`
+/// pseudo-instruction.
+fn encode_bitmanip(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let unary = match instruction {
+        "clz" => Some((bitmanip::FUNCT12_CLZ, funct3::SLL, opcodes::OP_IMM)),
+        "ctz" => Some((bitmanip::FUNCT12_CTZ, funct3::SLL, opcodes::OP_IMM)),
+        "cpop" => Some((bitmanip::FUNCT12_CPOP, funct3::SLL, opcodes::OP_IMM)),
+        "clzw" => Some((bitmanip::FUNCT12_CLZ, funct3::SLL, opcodes::OP_IMM_32)),
+        "ctzw" => Some((bitmanip::FUNCT12_CTZ, funct3::SLL, opcodes::OP_IMM_32)),
+        "cpopw" => Some((bitmanip::FUNCT12_CPOP, funct3::SLL, opcodes::OP_IMM_32)),
+        "sext.b" => Some((bitmanip::FUNCT12_SEXT_B, funct3::SLL, opcodes::OP_IMM)),
+        "sext.h" => Some((bitmanip::FUNCT12_SEXT_H, funct3::SLL, opcodes::OP_IMM)),
+        "zext.h" => Some((bitmanip::FUNCT12_ZEXT_H, funct3::ZEXT_H, opcodes::OP_REG_32)),
+        "rev8" => Some((bitmanip::FUNCT12_REV8, funct3::SRL_SRA, opcodes::OP_IMM)),
+        "orc.b" => Some((bitmanip::FUNCT12_ORC_B, funct3::SRL_SRA, opcodes::OP_IMM)),
+        "brev8" => Some((bitmanip::FUNCT12_BREV8, funct3::SRL_SRA, opcodes::OP_IMM)),
+        _ => None,
+    };
+    let expected_operands = if unary.is_some() || instruction == "zext.w" {
+        2
+    } else {
+        3
+    };
+    if operands.len() != expected_operands {
+        return Err(AssemblerErrorKind::ParseError(format!(
+            "{} expects {} operands",
+            instruction, expected_operands
+        )));
+    }
+    let rd = parse_register(operands[0])?;
+    let rs1 = parse_register(operands[1])?;
+    if let Some((funct12, funct3, opcode)) = unary {
+        return Ok(encode_i_type(funct12, rs1, funct3, rd, opcode));
+    }
+    if instruction == "zext.w" {
+        return Ok(encode_r_type(
+            funct7::ADD_UW,
+            0,
+            rs1,
+            funct3::ADD_SUB,
+            rd,
+            opcodes::OP_REG_32,
+        ));
+    }
+
+    // The shift-immediate forms: funct7 in bits 31:25 and a 5- or 6-bit shift amount.
+    let shift_immediate = match instruction {
+        "slli.uw" => Some((funct7::ADD_UW, funct3::SLL, opcodes::OP_IMM_32, 0x3F)),
+        "rori" => Some((funct7::ROTATE, funct3::SRL_SRA, opcodes::OP_IMM, 0x3F)),
+        "roriw" => Some((funct7::ROTATE, funct3::SRL_SRA, opcodes::OP_IMM_32, 0x1F)),
+        "bseti" => Some((funct7::BSET, funct3::SLL, opcodes::OP_IMM, 0x3F)),
+        "bclri" => Some((funct7::BCLR_BEXT, funct3::SLL, opcodes::OP_IMM, 0x3F)),
+        "binvi" => Some((funct7::BINV, funct3::SLL, opcodes::OP_IMM, 0x3F)),
+        "bexti" => Some((funct7::BCLR_BEXT, funct3::SRL_SRA, opcodes::OP_IMM, 0x3F)),
+        _ => None,
+    };
+    if let Some((funct7, funct3, opcode, shamt_mask)) = shift_immediate {
+        let shamt = parse_immediate(operands[2])?;
+        if !(0..=shamt_mask).contains(&shamt) {
+            return Err(AssemblerErrorKind::ValueOutOfRange(format!(
+                "shift amount {} for {}",
+                shamt, instruction
+            )));
+        }
+        let imm = (funct7 << 5) | shamt as u32;
+        return Ok(encode_i_type(imm, rs1, funct3, rd, opcode));
+    }
+
+    let rs2 = parse_register(operands[2])?;
+    let (funct7, funct3, opcode) = match instruction {
+        "sh1add" => (funct7::SHADD, funct3::SH1ADD, opcodes::OP_REG),
+        "sh2add" => (funct7::SHADD, funct3::SH2ADD, opcodes::OP_REG),
+        "sh3add" => (funct7::SHADD, funct3::SH3ADD, opcodes::OP_REG),
+        "add.uw" => (funct7::ADD_UW, funct3::ADD_SUB, opcodes::OP_REG_32),
+        "sh1add.uw" => (funct7::SHADD, funct3::SH1ADD, opcodes::OP_REG_32),
+        "sh2add.uw" => (funct7::SHADD, funct3::SH2ADD, opcodes::OP_REG_32),
+        "sh3add.uw" => (funct7::SHADD, funct3::SH3ADD, opcodes::OP_REG_32),
+
+        "andn" => (funct7::ANDN_ORN_XNOR, funct3::AND, opcodes::OP_REG),
+        "orn" => (funct7::ANDN_ORN_XNOR, funct3::OR, opcodes::OP_REG),
+        "xnor" => (funct7::ANDN_ORN_XNOR, funct3::XOR, opcodes::OP_REG),
+        "min" => (funct7::MINMAX_CLMUL, funct3::MIN, opcodes::OP_REG),
+        "minu" => (funct7::MINMAX_CLMUL, funct3::MINU, opcodes::OP_REG),
+        "max" => (funct7::MINMAX_CLMUL, funct3::MAX, opcodes::OP_REG),
+        "maxu" => (funct7::MINMAX_CLMUL, funct3::MAXU, opcodes::OP_REG),
+        "rol" => (funct7::ROTATE, funct3::SLL, opcodes::OP_REG),
+        "ror" => (funct7::ROTATE, funct3::SRL_SRA, opcodes::OP_REG),
+        "rolw" => (funct7::ROTATE, funct3::SLL, opcodes::OP_REG_32),
+        "rorw" => (funct7::ROTATE, funct3::SRL_SRA, opcodes::OP_REG_32),
+
+        "clmul" => (funct7::MINMAX_CLMUL, funct3::CLMUL, opcodes::OP_REG),
+        "clmulh" => (funct7::MINMAX_CLMUL, funct3::CLMULH, opcodes::OP_REG),
+        "clmulr" => (funct7::MINMAX_CLMUL, funct3::CLMULR, opcodes::OP_REG),
+
+        "bset" => (funct7::BSET, funct3::SLL, opcodes::OP_REG),
+        "bclr" => (funct7::BCLR_BEXT, funct3::SLL, opcodes::OP_REG),
+        "binv" => (funct7::BINV, funct3::SLL, opcodes::OP_REG),
+        "bext" => (funct7::BCLR_BEXT, funct3::SRL_SRA, opcodes::OP_REG),
+
+        "pack" => (funct7::PACK, funct3::PACK, opcodes::OP_REG),
+        "packh" => (funct7::PACK, funct3::PACKH, opcodes::OP_REG),
+        "packw" => (funct7::PACK, funct3::PACK, opcodes::OP_REG_32),
+        "xperm4" => (funct7::XPERM, funct3::XPERM4, opcodes::OP_REG),
+        "xperm8" => (funct7::XPERM, funct3::XPERM8, opcodes::OP_REG),
+        _ => {
+            return Err(AssemblerErrorKind::UnknownInstruction(
+                instruction.to_string(),
+            ))
+        }
+    };
+    Ok(encode_r_type(funct7, rs2, rs1, funct3, rd, opcode))
+}
+
+/// Encodes a scalar cryptography instruction. `aes64ks1i` takes a round number and `sm4ed`
+/// and `sm4ks` a byte select as their last operand.
+fn encode_crypto(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let unary = match instruction {
+        "aes64im" => Some(crypto::FUNCT12_AES64IM),
+        "sha256sig0" => Some(crypto::FUNCT12_SHA256SIG0),
+        "sha256sig1" => Some(crypto::FUNCT12_SHA256SIG1),
+        "sha256sum0" => Some(crypto::FUNCT12_SHA256SUM0),
+        "sha256sum1" => Some(crypto::FUNCT12_SHA256SUM1),
+        "sha512sig0" => Some(crypto::FUNCT12_SHA512SIG0),
+        "sha512sig1" => Some(crypto::FUNCT12_SHA512SIG1),
+        "sha512sum0" => Some(crypto::FUNCT12_SHA512SUM0),
+        "sha512sum1" => Some(crypto::FUNCT12_SHA512SUM1),
+        "sm3p0" => Some(crypto::FUNCT12_SM3P0),
+        "sm3p1" => Some(crypto::FUNCT12_SM3P1),
+        _ => None,
+    };
+    let expected_operands = match instruction {
+        _ if unary.is_some() => 2,
+        "sm4ed" | "sm4ks" => 4,
+        _ => 3,
+    };
+    if operands.len() != expected_operands {
+        return Err(AssemblerErrorKind::ParseError(format!(
+            "{} expects {} operands",
+            instruction, expected_operands
+        )));
+    }
+    let rd = parse_register(operands[0])?;
+    let rs1 = parse_register(operands[1])?;
+    if let Some(funct12) = unary {
+        return Ok(encode_i_type(
+            funct12,
+            rs1,
+            funct3::SLL,
+            rd,
+            opcodes::OP_IMM,
+        ));
+    }
+    if instruction == "aes64ks1i" {
+        let rnum = parse_immediate(operands[2])?;
+        if !(0..=crypto::AES64KS1I_MAX_RNUM as i64).contains(&rnum) {
+            return Err(AssemblerErrorKind::ValueOutOfRange(format!(
+                "round number {} for aes64ks1i",
+                rnum
+            )));
+        }
+        let imm = (crypto::FUNCT8_AES64KS1I << 4) | rnum as u32;
+        return Ok(encode_i_type(imm, rs1, funct3::SLL, rd, opcodes::OP_IMM));
+    }
+
+    let rs2 = parse_register(operands[2])?;
+    let funct7 = match instruction {
+        "aes64es" => crypto::FUNCT7_AES64ES,
+        "aes64esm" => crypto::FUNCT7_AES64ESM,
+        "aes64ds" => crypto::FUNCT7_AES64DS,
+        "aes64dsm" => crypto::FUNCT7_AES64DSM,
+        "aes64ks2" => crypto::FUNCT7_AES64KS2,
+        "sm4ed" | "sm4ks" => {
+            let byte_select = parse_immediate(operands[3])?;
+            if !(0..=3).contains(&byte_select) {
+                return Err(AssemblerErrorKind::ValueOutOfRange(format!(
+                    "byte select {} for {}",
+                    byte_select, instruction
+                )));
+            }
+            let funct5 = if instruction == "sm4ed" {
+                crypto::FUNCT5_SM4ED
+            } else {
+                crypto::FUNCT5_SM4KS
+            };
+            ((byte_select as u32) << 5) | funct5
+        }
+        _ => {
+            return Err(AssemblerErrorKind::UnknownInstruction(
+                instruction.to_string(),
+            ))
+        }
+    };
+    Ok(encode_r_type(
+        funct7,
+        rs2,
+        rs1,
+        funct3::ADD_SUB,
+        rd,
+        opcodes::OP_REG,
+    ))
+}
+
+/// Encodes an F or D extension instruction, or one of their pseudo-instructions. Arithmetic,
+/// fused multiply-add and conversions take an optional trailing rounding mode, defaulting
+/// to `dyn`.
+fn encode_float(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let unknown = || AssemblerErrorKind::UnknownInstruction(instruction.to_string());
+    let expect_operands = |min: usize, max: usize| {
+        if operands.len() < min || operands.len() > max {
+            Err(AssemblerErrorKind::ParseError(format!(
+                "{} expects {} operands",
+                instruction, min
+            )))
+        } else {
+            Ok(())
+        }
+    };
+    let fp = |index: usize| parse_fp_register(operands[index]);
+    let int = |index: usize| parse_register(operands[index]);
+    let rm = |index: usize| {
+        operands
+            .get(index)
+            .map_or(Ok(funct3::RM_DYN), |s| parse_rounding_mode(s))
+    };
+    let op_fp = |funct5: u32, fmt: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32| {
+        encode_r_type((funct5 << 2) | fmt, rs2, rs1, funct3, rd, opcodes::OP_FP)
+    };
+
+    // The FP CSR accessors: `frcsr rd` reads, `fscsr [rd,] rs` swaps.
+    let fp_csr = match instruction {
+        "frcsr" | "fscsr" => Some(riscv_core::csr::FCSR),
+        "frrm" | "fsrm" => Some(riscv_core::csr::FRM),
+        "frflags" | "fsflags" => Some(riscv_core::csr::FFLAGS),
+        _ => None,
+    };
+    if let Some(csr) = fp_csr {
+        let (rd, rs1, funct3) = if instruction.starts_with("fr") {
+            expect_operands(1, 1)?;
+            (int(0)?, 0, funct3::CSRRS)
+        } else if operands.len() == 1 {
+            (0, int(0)?, funct3::CSRRW)
+        } else {
+            expect_operands(2, 2)?;
+            (int(0)?, int(1)?, funct3::CSRRW)
+        };
+        return Ok(encode_i_type(csr, rs1, funct3, rd, opcodes::OP_SYSTEM));
+    }
+
+    let parts: Vec<&str> = instruction.split('.').collect();
+    let format = |suffix: &str| match suffix {
+        "s" => Ok(funct7::FMT_S),
+        "d" => Ok(funct7::FMT_D),
+        _ => Err(unknown()),
+    };
+    // The integer operand of a conversion: rs2 selects w, wu, l or lu.
+    let int_type = |suffix: &str| match suffix {
+        "w" => Some(0),
+        "wu" => Some(1),
+        "l" => Some(2),
+        "lu" => Some(3),
+        _ => None,
+    };
+
+    match parts.as_slice() {
+        ["flw" | "fld"] => {
+            expect_operands(2, 2)?;
+            let (offset, base) = parse_memory_operand(operands[1])?;
+            let funct3 = if instruction == "flw" {
+                funct3::FLW
+            } else {
+                funct3::FLD
+            };
+            Ok(encode_i_type(
+                offset as u32 & 0xFFF,
+                base,
+                funct3,
+                fp(0)?,
+                opcodes::OP_LOAD_FP,
+            ))
+        }
+        ["fsw" | "fsd"] => {
+            expect_operands(2, 2)?;
+            let (offset, base) = parse_memory_operand(operands[1])?;
+            let funct3 = if instruction == "fsw" {
+                funct3::FSW
+            } else {
+                funct3::FSD
+            };
+            Ok(encode_s_type(
+                offset as u32,
+                fp(0)?,
+                base,
+                funct3,
+                opcodes::OP_STORE_FP,
+            ))
+        }
+        ["fcvt", to, from] => {
+            expect_operands(2, 3)?;
+            match (int_type(to), int_type(from)) {
+                (Some(rs2), None) => Ok(op_fp(
+                    funct7::FCVT_INT_FMT,
+                    format(from)?,
+                    rs2,
+                    fp(1)?,
+                    rm(2)?,
+                    int(0)?,
+                )),
+                (None, Some(rs2)) => Ok(op_fp(
+                    funct7::FCVT_FMT_INT,
+                    format(to)?,
+                    rs2,
+                    int(1)?,
+                    rm(2)?,
+                    fp(0)?,
+                )),
+                (None, None) if format(to)? != format(from)? => Ok(op_fp(
+                    funct7::FCVT_FMT_FMT,
+                    format(to)?,
+                    format(from)?,
+                    fp(1)?,
+                    rm(2)?,
+                    fp(0)?,
+                )),
+                _ => Err(unknown()),
+            }
+        }
+        ["fmv", "x", "w"] | ["fmv", "x", "d"] => {
+            expect_operands(2, 2)?;
+            let fmt = format(if parts[2] == "w" { "s" } else { "d" })?;
+            Ok(op_fp(funct7::FMV_X_FMT_FCLASS, fmt, 0, fp(1)?, 0, int(0)?))
+        }
+        ["fmv", "w", "x"] | ["fmv", "d", "x"] => {
+            expect_operands(2, 2)?;
+            let fmt = format(if parts[1] == "w" { "s" } else { "d" })?;
+            Ok(op_fp(funct7::FMV_FMT_X, fmt, 0, int(1)?, 0, fp(0)?))
+        }
+        [name @ ("fmv" | "fneg" | "fabs"), suffix] => {
+            expect_operands(2, 2)?;
+            let funct3 = match *name {
+                "fmv" => 0b000,
+                "fneg" => 0b001,
+                _ => 0b010,
+            };
+            let rs = fp(1)?;
+            Ok(op_fp(
+                funct7::FSGNJ,
+                format(suffix)?,
+                rs,
+                rs,
+                funct3,
+                fp(0)?,
+            ))
+        }
+        [name @ ("fadd" | "fsub" | "fmul" | "fdiv"), suffix] => {
+            expect_operands(3, 4)?;
+            let funct5 = match *name {
+                "fadd" => funct7::FADD,
+                "fsub" => funct7::FSUB,
+                "fmul" => funct7::FMUL,
+                _ => funct7::FDIV,
+            };
+            Ok(op_fp(
+                funct5,
+                format(suffix)?,
+                fp(2)?,
+                fp(1)?,
+                rm(3)?,
+                fp(0)?,
+            ))
+        }
+        ["fsqrt", suffix] => {
+            expect_operands(2, 3)?;
+            Ok(op_fp(
+                funct7::FSQRT,
+                format(suffix)?,
+                0,
+                fp(1)?,
+                rm(2)?,
+                fp(0)?,
+            ))
+        }
+        [name @ ("fsgnj" | "fsgnjn" | "fsgnjx" | "fmin" | "fmax"), suffix] => {
+            expect_operands(3, 3)?;
+            let (funct5, funct3) = match *name {
+                "fsgnj" => (funct7::FSGNJ, 0b000),
+                "fsgnjn" => (funct7::FSGNJ, 0b001),
+                "fsgnjx" => (funct7::FSGNJ, 0b010),
+                "fmin" => (funct7::FMIN_MAX, 0b000),
+                _ => (funct7::FMIN_MAX, 0b001),
+            };
+            Ok(op_fp(
+                funct5,
+                format(suffix)?,
+                fp(2)?,
+                fp(1)?,
+                funct3,
+                fp(0)?,
+            ))
+        }
+        [name @ ("feq" | "flt" | "fle"), suffix] => {
+            expect_operands(3, 3)?;
+            let funct3 = match *name {
+                "feq" => 0b010,
+                "flt" => 0b001,
+                _ => 0b000,
+            };
+            Ok(op_fp(
+                funct7::FCMP,
+                format(suffix)?,
+                fp(2)?,
+                fp(1)?,
+                funct3,
+                int(0)?,
+            ))
+        }
+        ["fclass", suffix] => {
+            expect_operands(2, 2)?;
+            Ok(op_fp(
+                funct7::FMV_X_FMT_FCLASS,
+                format(suffix)?,
+                0,
+                fp(1)?,
+                0b001,
+                int(0)?,
+            ))
+        }
+        [name @ ("fmadd" | "fmsub" | "fnmsub" | "fnmadd"), suffix] => {
+            expect_operands(4, 5)?;
+            let opcode = match *name {
+                "fmadd" => opcodes::OP_MADD,
+                "fmsub" => opcodes::OP_MSUB,
+                "fnmsub" => opcodes::OP_NMSUB,
+                _ => opcodes::OP_NMADD,
+            };
+            let funct7 = (fp(3)? << 2) | format(suffix)?;
+            Ok(encode_r_type(
+                funct7,
+                fp(2)?,
+                fp(1)?,
+                rm(4)?,
+                fp(0)?,
+                opcode,
+            ))
+        }
+        _ => Err(unknown()),
+    }
+}
+
+fn parse_rounding_mode(rm_str: &str) -> Result<u32, AssemblerErrorKind> {
+    match rm_str.trim_end_matches(',') {
+        "rne" => Ok(0b000),
+        "rtz" => Ok(0b001),
+        "rdn" => Ok(0b010),
+        "rup" => Ok(0b011),
+        "rmm" => Ok(0b100),
+        "dyn" => Ok(funct3::RM_DYN),
+        _ => Err(AssemblerErrorKind::ParseError(format!(
+            "invalid rounding mode '{}'",
+            rm_str
+        ))),
+    }
+}
+
 fn encode_r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
     (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
 }
@@ -388,11 +957,11 @@ fn encode_j_type(imm: u32, rd: u32, opcode: u32) -> u32 {
     (encoded_imm << 12) | (rd << 7) | opcode
 }
 
-fn parse_csr(csr_str: &str) -> Result<u32, AssemblerErrorKind> {
+pub fn parse_csr(csr_str: &str) -> Result<u32, AssemblerErrorKind> {
     let s = csr_str.trim_end_matches(',');
 
-    if s.starts_with("0x") {
-        u32::from_str_radix(&s[2..], 16)
+    if let Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
             .map_err(|_| AssemblerErrorKind::InvalidImmediateValue(s.to_string()))
     } else {
         match s {
@@ -434,18 +1003,84 @@ fn parse_csr(csr_str: &str) -> Result<u32, AssemblerErrorKind> {
             "mie" => Ok(riscv_core::csr::MIE),
             "mtvec" => Ok(riscv_core::csr::MTVEC),
             "mcounteren" => Ok(0x306),
+            "mcountinhibit" => Ok(riscv_core::csr::MCOUNTINHIBIT),
+            "mstatush" => Ok(riscv_core::csr::MSTATUSH),
             // Machine Trap Handling
             "mscratch" => Ok(riscv_core::csr::MSCRATCH),
             "mepc" => Ok(riscv_core::csr::MEPC),
             "mcause" => Ok(riscv_core::csr::MCAUSE),
             "mtval" => Ok(riscv_core::csr::MTVAL),
             "mip" => Ok(riscv_core::csr::MIP),
-            _ => Err(AssemblerErrorKind::InvalidImmediateValue(s.to_string())),
+            // Counters and Timers
+            "cycle" => Ok(riscv_core::csr::CYCLE),
+            "time" => Ok(riscv_core::csr::TIME),
+            "instret" => Ok(riscv_core::csr::INSTRET),
+            "cycleh" => Ok(riscv_core::csr::CYCLEH),
+            "timeh" => Ok(riscv_core::csr::TIMEH),
+            "instreth" => Ok(riscv_core::csr::INSTRETH),
+            "mcycle" => Ok(riscv_core::csr::MCYCLE),
+            "minstret" => Ok(riscv_core::csr::MINSTRET),
+            "mcycleh" => Ok(riscv_core::csr::MCYCLEH),
+            "minstreth" => Ok(riscv_core::csr::MINSTRETH),
+            "scountovf" => Ok(riscv_core::csr::SCOUNTOVF),
+            // Floating-Point Control and Status
+            "fflags" => Ok(riscv_core::csr::FFLAGS),
+            "frm" => Ok(riscv_core::csr::FRM),
+            "fcsr" => Ok(riscv_core::csr::FCSR),
+            // Entropy Source
+            "seed" => Ok(riscv_core::csr::SEED),
+            "mseccfg" => Ok(riscv_core::csr::MSECCFG),
+            // Physical Memory Protection and Hardware Performance Monitoring
+            _ => parse_pmp_csr(s)
+                .or_else(|| parse_hpm_csr(s))
+                .ok_or_else(|| AssemblerErrorKind::InvalidImmediateValue(s.to_string())),
         }
     }
 }
 
-fn parse_register(reg_str: &str) -> Result<u32, AssemblerErrorKind> {
+/// Parses the numbered PMP CSRs, `pmpcfg0` to `pmpcfg15` and `pmpaddr0` to `pmpaddr63`.
+fn parse_pmp_csr(s: &str) -> Option<u32> {
+    let (base, count, index) = match s.strip_prefix("pmpcfg") {
+        Some(index) => (riscv_core::csr::PMPCFG0, 16, index),
+        None => (riscv_core::csr::PMPADDR0, 64, s.strip_prefix("pmpaddr")?),
+    };
+    let index: u32 = index.parse().ok()?;
+    (index < count).then_some(base + index)
+}
+
+/// Parses the numbered performance-monitoring CSRs: `mhpmcounter3` to `mhpmcounter31`,
+/// `hpmcounter3` to `hpmcounter31` and `mhpmevent3` to `mhpmevent31`, each with an `h`
+/// suffix for its RV32 upper half.
+fn parse_hpm_csr(s: &str) -> Option<u32> {
+    let (base, high_base, index) = if let Some(index) = s.strip_prefix("mhpmcounter") {
+        (
+            riscv_core::csr::MHPMCOUNTER3,
+            riscv_core::csr::MHPMCOUNTER3H,
+            index,
+        )
+    } else if let Some(index) = s.strip_prefix("hpmcounter") {
+        (
+            riscv_core::csr::HPMCOUNTER3,
+            riscv_core::csr::HPMCOUNTER3H,
+            index,
+        )
+    } else {
+        let index = s.strip_prefix("mhpmevent")?;
+        (
+            riscv_core::csr::MHPMEVENT3,
+            riscv_core::csr::MHPMEVENT3H,
+            index,
+        )
+    };
+    let (base, index) = match index.strip_suffix('h') {
+        Some(index) => (high_base, index),
+        None => (base, index),
+    };
+    let index: u32 = index.parse().ok()?;
+    (3..32).contains(&index).then_some(base + index - 3)
+}
+
+pub fn parse_register(reg_str: &str) -> Result<u32, AssemblerErrorKind> {
     match reg_str.trim_end_matches(',') {
         "zero" | "x0" => Ok(0),
         "ra" | "x1" => Ok(1),
@@ -483,7 +1118,53 @@ fn parse_register(reg_str: &str) -> Result<u32, AssemblerErrorKind> {
     }
 }
 
-fn parse_memory_operand(operand: &str) -> Result<(i32, u32), AssemblerErrorKind> {
+pub fn parse_fp_register(reg_str: &str) -> Result<u32, AssemblerErrorKind> {
+    let s = reg_str.trim_end_matches(',');
+    if let Some(reg) = s
+        .strip_prefix('f')
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|&n| n < 32)
+    {
+        return Ok(reg);
+    }
+    match s {
+        "ft0" => Ok(0),
+        "ft1" => Ok(1),
+        "ft2" => Ok(2),
+        "ft3" => Ok(3),
+        "ft4" => Ok(4),
+        "ft5" => Ok(5),
+        "ft6" => Ok(6),
+        "ft7" => Ok(7),
+        "fs0" => Ok(8),
+        "fs1" => Ok(9),
+        "fa0" => Ok(10),
+        "fa1" => Ok(11),
+        "fa2" => Ok(12),
+        "fa3" => Ok(13),
+        "fa4" => Ok(14),
+        "fa5" => Ok(15),
+        "fa6" => Ok(16),
+        "fa7" => Ok(17),
+        "fs2" => Ok(18),
+        "fs3" => Ok(19),
+        "fs4" => Ok(20),
+        "fs5" => Ok(21),
+        "fs6" => Ok(22),
+        "fs7" => Ok(23),
+        "fs8" => Ok(24),
+        "fs9" => Ok(25),
+        "fs10" => Ok(26),
+        "fs11" => Ok(27),
+        "ft8" => Ok(28),
+        "ft9" => Ok(29),
+        "ft10" => Ok(30),
+        "ft11" => Ok(31),
+        _ => Err(AssemblerErrorKind::InvalidRegister(reg_str.to_string())),
+    }
+}
+
+pub(crate) fn parse_memory_operand(operand: &str) -> Result<(i32, u32), AssemblerErrorKind> {
     if !operand.ends_with(')') {
         return Err(AssemblerErrorKind::InvalidMemoryOperand(
             operand.to_string(),
@@ -631,6 +1312,11 @@ mod tests {
         let (tl, dl, bl) = empty_labels();
         let result = encode_instruction("ecall", &[], 0, &tl, &dl, &bl, 0, 0).unwrap();
         assert_eq!(result, vec![0x00000073]);
+        let result = encode_instruction("sfence.vma", &[], 0, &tl, &dl, &bl, 0, 0).unwrap();
+        assert_eq!(result, vec![0x12000073]);
+        let operands = vec!["a0,", "a1"];
+        let result = encode_instruction("sfence.vma", &operands, 0, &tl, &dl, &bl, 0, 0).unwrap();
+        assert_eq!(result, vec![0x12B50073]);
     }
 
     #[test]
@@ -639,6 +1325,99 @@ mod tests {
         let operands = vec!["zero", "mepc", "a0"];
         let result = encode_instruction("csrrw", &operands, 0, &tl, &dl, &bl, 0, 0).unwrap();
         assert_eq!(result, vec![0x34151073]);
+        assert_eq!(parse_csr("pmpcfg2"), Ok(0x3A2));
+        assert_eq!(parse_csr("pmpaddr63,"), Ok(0x3EF));
+        assert!(parse_csr("pmpcfg16").is_err());
+        assert!(parse_csr("pmpaddr64").is_err());
+        assert_eq!(parse_csr("mhpmcounter3"), Ok(0xB03));
+        assert_eq!(parse_csr("hpmcounter31h"), Ok(0xC9F));
+        assert_eq!(parse_csr("mhpmevent7"), Ok(0x327));
+        assert!(parse_csr("mhpmcounter2").is_err());
+        assert!(parse_csr("mhpmevent32").is_err());
+    }
+
+    #[test]
+    fn test_atomic_instructions() {
+        let (tl, dl, bl) = empty_labels();
+        let result = encode_instruction("lr.d", &["a0", "(a1)"], 0, &tl, &dl, &bl, 0, 0).unwrap();
+        assert_eq!(result, vec![0x1005b52f]);
+        let operands = vec!["a0", "a2", "(a1)"];
+        let result = encode_instruction("sc.w.rl", &operands, 0, &tl, &dl, &bl, 0, 0).unwrap();
+        assert_eq!(result, vec![0x1ac5a52f]);
+        let result =
+            encode_instruction("amoadd.w.aqrl", &operands, 0, &tl, &dl, &bl, 0, 0).unwrap();
+        assert_eq!(result, vec![0x06c5a52f]);
+        assert!(encode_instruction("amoadd.q", &operands, 0, &tl, &dl, &bl, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_float_instructions() {
+        let (tl, dl, bl) = empty_labels();
+        let encode = |instruction: &str, operands: &[&str]| {
+            encode_instruction(instruction, operands, 0, &tl, &dl, &bl, 0, 0).map(|v| v[0])
+        };
+        assert_eq!(
+            encode("fadd.s", &["ft0", "fa0", "fa1", "rne"]),
+            Ok(0x00b50053)
+        );
+        assert_eq!(encode("fdiv.d", &["f0", "f10", "f11"]), Ok(0x1ab57053));
+        assert_eq!(encode("fld", &["ft0", "0(a3)"]), Ok(0x0006b007));
+        assert_eq!(encode("fsw", &["fs2", "-4(sp)"]), Ok(0xff212e27));
+        assert_eq!(
+            encode("fmadd.d", &["ft3", "ft0", "ft0", "fa3"]),
+            Ok(0x6a0071c3)
+        );
+        assert_eq!(encode("fcvt.l.d", &["a0", "ft0"]), Ok(0xc2207553));
+        assert_eq!(encode("fcvt.d.s", &["fa0", "fa0"]), Ok(0x42057553));
+        assert_eq!(encode("fmv.x.w", &["ra", "ft0"]), Ok(0xe00000d3));
+        assert_eq!(encode("fneg.d", &["fa0", "fa1"]), Ok(0x22b59553));
+        assert_eq!(encode("flt.s", &["a0", "fa0", "fa1"]), Ok(0xa0b51553));
+        assert_eq!(encode("frflags", &["a0"]), Ok(0x00102573));
+        assert_eq!(encode("fsrm", &["a0"]), Ok(0x00251073));
+        assert!(encode("fadd.q", &["ft0", "fa0", "fa1"]).is_err());
+        assert!(encode("fadd.s", &["ft0", "fa0", "fa1", "rxx"]).is_err());
+        assert!(encode("fadd.s", &["ft0", "a0", "fa1"]).is_err());
+    }
+
+    #[test]
+    fn test_bitmanip_instructions() {
+        let (tl, dl, bl) = empty_labels();
+        let encode = |instruction: &str, operands: &[&str]| {
+            encode_instruction(instruction, operands, 0, &tl, &dl, &bl, 0, 0).map(|v| v[0])
+        };
+        assert_eq!(encode("sh2add", &["a2", "a0", "a1"]), Ok(0x20b54633));
+        assert_eq!(encode("add.uw", &["a2", "a0", "a1"]), Ok(0x08b5063b));
+        assert_eq!(encode("zext.w", &["a2", "a0"]), Ok(0x0805063b));
+        assert_eq!(encode("slli.uw", &["a2", "a0", "4"]), Ok(0x0845161b));
+        assert_eq!(encode("andn", &["a2", "a0", "a1"]), Ok(0x40b57633));
+        assert_eq!(encode("clzw", &["a2", "a0"]), Ok(0x6005161b));
+        assert_eq!(encode("rev8", &["a2", "a0"]), Ok(0x6b855613));
+        assert_eq!(encode("orc.b", &["a2", "a0"]), Ok(0x28755613));
+        assert_eq!(encode("rorw", &["a2", "a0", "a1"]), Ok(0x60b5563b));
+        assert_eq!(encode("clmulh", &["a2", "a0", "a1"]), Ok(0x0ab53633));
+        assert_eq!(encode("bexti", &["a2", "a0", "63"]), Ok(0x4bf55613));
+        assert_eq!(encode("packh", &["a2", "a0", "a1"]), Ok(0x08b57633));
+        assert_eq!(encode("xperm4", &["a2", "a0", "a1"]), Ok(0x28b52633));
+        assert!(encode("roriw", &["a2", "a0", "32"]).is_err());
+        assert!(encode("cpop", &["a2", "a0", "a1"]).is_err());
+    }
+
+    #[test]
+    fn test_crypto_instructions() {
+        let (tl, dl, bl) = empty_labels();
+        let encode = |instruction: &str, operands: &[&str]| {
+            encode_instruction(instruction, operands, 0, &tl, &dl, &bl, 0, 0).map(|v| v[0])
+        };
+        assert_eq!(encode("aes64esm", &["a2", "a0", "a1"]), Ok(0x36b50633));
+        assert_eq!(encode("aes64ks1i", &["a2", "a0", "10"]), Ok(0x31a51613));
+        assert_eq!(encode("aes64ks2", &["a2", "a0", "a1"]), Ok(0x7eb50633));
+        assert_eq!(encode("aes64im", &["a2", "a0"]), Ok(0x30051613));
+        assert_eq!(encode("sha256sig0", &["a2", "a0"]), Ok(0x10251613));
+        assert_eq!(encode("sm3p1", &["a2", "a0"]), Ok(0x10951613));
+        assert_eq!(encode("sm4ks", &["a2", "a0", "a1", "3"]), Ok(0xf4b50633));
+        assert!(encode("aes64ks1i", &["a2", "a0", "11"]).is_err());
+        assert!(encode("sm4ed", &["a2", "a0", "a1", "4"]).is_err());
+        assert_eq!(parse_csr("seed"), Ok(riscv_core::csr::SEED));
     }
 
     #[test]
@@ -1,8 +1,14 @@
+pub mod arch;
+pub mod compress;
 pub mod dissassembler;
 pub mod encoder;
 pub mod parser;
 pub mod types;
+pub mod vector;
 
+pub use arch::Arch;
+pub use compress::{compress, compress_as};
 pub use dissassembler::disassemble;
-pub use parser::parse_program;
+pub use encoder::{parse_csr, parse_fp_register, parse_register};
+pub use parser::{parse_program, parse_program_for};
 pub use types::{AssemblerError, AssemblerErrorKind, Executable};
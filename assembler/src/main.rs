@@ -1,5 +1,4 @@
-use assembler::parse_program;
-use bincode;
+use assembler::{parse_program_for, Arch};
 use riscv_core::SimpleElfHeader;
 use std::env;
 use std::fs;
@@ -13,6 +12,7 @@ fn main() {
     let mut input_path = None;
     let mut output_path = None;
     let mut format = "rbf";
+    let mut arch = Arch::default();
 
     let mut args_iter = args.iter().skip(1);
     while let Some(arg) = args_iter.next() {
@@ -23,6 +23,17 @@ fn main() {
             "--format" => {
                 format = args_iter.next().map_or("rbf", |s| s.as_str());
             }
+            "--arch" => match args_iter.next().map(|s| s.parse::<Arch>()) {
+                Some(Ok(parsed)) => arch = parsed,
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("Error: --arch requires an architecture, e.g. rv32im");
+                    return;
+                }
+            },
             _ => {
                 if !arg.starts_with('-') {
                     input_path = Some(arg);
@@ -35,7 +46,7 @@ fn main() {
         (Some(i), Some(o)) => (i, o),
         _ => {
             eprintln!(
-                "Usage: {} <input.s> -o <output_file> [--format <raw|rbf>]",
+                "Usage: {} <input.s> -o <output_file> [--format <raw|rbf>] [--arch <rv64|rv32i|rv32im>]",
                 args[0]
             );
             return;
@@ -50,7 +61,7 @@ fn main() {
         }
     };
 
-    match parse_program(&source_code) {
+    match parse_program_for(&source_code, arch) {
         Ok(executable) => {
             let mut output_bytes = Vec::new();
 
@@ -1,3 +1,5 @@
+use crate::arch::Arch;
+use crate::compress::{compress, encode_compressed, refers_to_labels};
 use crate::encoder::encode_instruction;
 use crate::types::{AssemblerError, AssemblerErrorKind, Section};
 use riscv_core::{Executable, BASE_ADDRESS};
@@ -5,8 +7,8 @@ use std::collections::HashMap;
 
 fn parse_data_value(value_str: &str) -> Result<i64, std::num::ParseIntError> {
     let s = value_str.trim_end_matches(',');
-    if s.starts_with("0x") {
-        u64::from_str_radix(&s[2..], 16).map(|val| val as i64)
+    if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).map(|val| val as i64)
     } else {
         s.parse::<i64>()
     }
@@ -16,7 +18,131 @@ fn align_up(value: u64, alignment: u64) -> u64 {
     (value + alignment - 1) & !(alignment - 1)
 }
 
+const NOP: u32 = 0x00000013;
+const C_NOP: u16 = 0x0001;
+
+/// Pads the text segment with `nop`s up to `size` bytes, starting with a `c.nop` if it
+/// currently ends halfway through a word.
+fn pad_text(text_segment: &mut Vec<u8>, size: u64) {
+    if (text_segment.len() as u64) < size && text_segment.len() % 4 == 2 {
+        text_segment.extend_from_slice(&C_NOP.to_le_bytes());
+    }
+    while (text_segment.len() as u64) < size {
+        text_segment.extend_from_slice(&NOP.to_le_bytes());
+    }
+}
+
+/// Applies an `.option` directive to the stack of RVC settings, whose last entry is current.
+fn apply_option(
+    rvc: &mut Vec<bool>,
+    option: Option<&&str>,
+    arch: Arch,
+) -> Result<(), AssemblerErrorKind> {
+    let current = *rvc.last().unwrap_or(&false);
+    match option.copied() {
+        Some("rvc") if !arch.has_compressed() => {
+            return Err(AssemblerErrorKind::UnsupportedInstruction(format!(
+                ".option rvc on {}",
+                arch
+            )))
+        }
+        Some("rvc") => *rvc.last_mut().unwrap() = true,
+        Some("norvc") => *rvc.last_mut().unwrap() = false,
+        Some("push") => rvc.push(current),
+        Some("pop") if rvc.len() > 1 => {
+            rvc.pop();
+        }
+        _ => {
+            return Err(AssemblerErrorKind::ParseError(format!(
+                "unsupported .option '{}'",
+                option.unwrap_or(&"")
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Encodes one instruction for `arch`. Explicit `c.*` mnemonics are always compressed;
+/// others are compressed when `rvc` is set and an RVC form exists, unless they refer to
+/// labels.
+#[allow(clippy::too_many_arguments)]
+fn assemble_instruction(
+    instruction: &str,
+    operands: &[&str],
+    arch: Arch,
+    rvc: bool,
+    current_address: u64,
+    text_labels: &HashMap<String, u64>,
+    data_labels: &HashMap<String, u64>,
+    bss_labels: &HashMap<String, u64>,
+    text_size: u64,
+    data_size: u64,
+) -> Result<Vec<u8>, AssemblerErrorKind> {
+    arch.check(instruction, operands)?;
+    if instruction.starts_with("c.") {
+        let half = encode_compressed(
+            instruction,
+            operands,
+            current_address,
+            text_labels,
+            data_labels,
+            bss_labels,
+            text_size,
+            data_size,
+        )?;
+        return Ok(half.to_le_bytes().to_vec());
+    }
+
+    let words = encode_instruction(
+        instruction,
+        operands,
+        current_address,
+        text_labels,
+        data_labels,
+        bss_labels,
+        text_size,
+        data_size,
+    )?;
+    if rvc
+        && !refers_to_labels(instruction)
+        && let [word] = words[..]
+        && let Some(half) = compress(word)
+    {
+        return Ok(half.to_le_bytes().to_vec());
+    }
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+/// The number of bytes an instruction will take, worked out before labels are known.
+fn instruction_size(instruction: &str, operands: &[&str], arch: Arch, rvc: bool) -> u64 {
+    if instruction.starts_with("c.") {
+        return 2;
+    }
+    if refers_to_labels(instruction) {
+        return if instruction == "la" { 8 } else { 4 };
+    }
+    let no_labels = HashMap::new();
+    assemble_instruction(
+        instruction,
+        operands,
+        arch,
+        rvc,
+        0,
+        &no_labels,
+        &no_labels,
+        &no_labels,
+        0,
+        0,
+    )
+    .map_or(4, |bytes| bytes.len() as u64)
+}
+
 pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
+    parse_program_for(program, Arch::default())
+}
+
+/// Assembles `program`, rejecting instructions that `arch` does not have.
+pub fn parse_program_for(program: &str, arch: Arch) -> Result<Executable, AssemblerError> {
     let mut text_labels = HashMap::new();
     let mut data_labels = HashMap::new();
     let mut bss_labels = HashMap::new();
@@ -27,6 +153,7 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
 
     let mut current_section = Section::Text;
     let mut global_label_name: Option<String> = None;
+    let mut rvc = vec![false];
 
     for (i, line) in program.lines().enumerate() {
         let line_number = i + 1;
@@ -91,6 +218,12 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
                 ".text" => {
                     current_section = Section::Text;
                 }
+                ".option" => {
+                    apply_option(&mut rvc, tokens.get(1), arch).map_err(|kind| AssemblerError {
+                        line: line_number,
+                        kind,
+                    })?;
+                }
                 ".data" => {
                     if current_section == Section::Text {
                         text_segment_size = align_up(text_segment_size, 8);
@@ -126,7 +259,7 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
                             }
                         }
                         Section::Data => {
-                            while (data_segment.len() as u64 % align_bytes) != 0 {
+                            while !(data_segment.len() as u64).is_multiple_of(align_bytes) {
                                 data_segment.push(0);
                             }
                             if let Some(l_name) = label {
@@ -226,11 +359,8 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
                 if let Some(l_name) = label {
                     text_labels.insert(l_name.to_string(), text_segment_size);
                 }
-                if mnemonic == "la" {
-                    text_segment_size += 8;
-                } else {
-                    text_segment_size += 4;
-                }
+                text_segment_size +=
+                    instruction_size(&mnemonic, &tokens[1..], arch, *rvc.last().unwrap());
             }
         }
     }
@@ -248,6 +378,7 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
     let mut text_segment = Vec::with_capacity(text_segment_size as usize);
     let mut current_address: u64 = 0;
     let final_data_size = data_segment.len() as u64;
+    let mut rvc = vec![false];
 
     for (i, line) in program.lines().enumerate() {
         let line_number = i + 1;
@@ -265,7 +396,10 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
 
         let mnemonic = tokens[0].to_lowercase();
         if mnemonic.starts_with('.') {
-            if mnemonic == ".align" {
+            if mnemonic == ".option" {
+                // Already validated by the first pass.
+                let _ = apply_option(&mut rvc, tokens.get(1), arch);
+            } else if mnemonic == ".align" {
                 if tokens.len() < 2 {
                     return Err(AssemblerError {
                         line: line_number,
@@ -278,12 +412,8 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
                 })?;
                 if alignment >= 0 {
                     let align_bytes = 1u64 << alignment;
-                    if align_bytes > 0 {
-                        while (current_address % align_bytes) != 0 {
-                            text_segment.extend_from_slice(&0x00000013_u32.to_le_bytes());
-                            current_address += 4;
-                        }
-                    }
+                    pad_text(&mut text_segment, align_up(current_address, align_bytes));
+                    current_address = text_segment.len() as u64;
                 }
             }
             continue;
@@ -291,9 +421,11 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
 
         let instruction = tokens[0].to_lowercase();
         let operands = &tokens[1..];
-        let encoded_insts = encode_instruction(
+        let encoded = assemble_instruction(
             &instruction,
             operands,
+            arch,
+            *rvc.last().unwrap(),
             current_address,
             &text_labels,
             &data_labels,
@@ -305,18 +437,14 @@ pub fn parse_program(program: &str) -> Result<Executable, AssemblerError> {
             line: line_number,
             kind,
         })?;
-        for inst in encoded_insts {
-            text_segment.extend_from_slice(&inst.to_le_bytes());
-            current_address += 4;
-        }
+        current_address += encoded.len() as u64;
+        text_segment.extend(encoded);
     }
 
-    while (text_segment.len() as u64) < text_segment_size {
-        text_segment.extend_from_slice(&0x00000013_u32.to_le_bytes());
-    }
+    pad_text(&mut text_segment, text_segment_size);
 
     let entry_point_address = if let Some(label_name) = global_label_name {
-        let offset = text_labels.get(&label_name).ok_or_else(|| AssemblerError {
+        let offset = text_labels.get(&label_name).ok_or(AssemblerError {
             line: 0,
             kind: AssemblerErrorKind::UndefinedLabel(label_name),
         })?;
@@ -26,6 +26,7 @@ pub enum AssemblerErrorKind {
     ImmediateOutOfRange(String),
     UndefinedLabel(String),
     UnknownInstruction(String),
+    UnsupportedInstruction(String),
     UnknownDirective(String),
     ParseError(String),
     ValueOutOfRange(String),
@@ -42,6 +43,9 @@ impl fmt::Display for AssemblerErrorKind {
             Self::ImmediateOutOfRange(val) => write!(f, "Immediate value out of range: '{}'", val),
             Self::UndefinedLabel(label) => write!(f, "Use of undefined label: '{}'", label),
             Self::UnknownInstruction(inst) => write!(f, "Unknown instruction: '{}'", inst),
+            Self::UnsupportedInstruction(inst) => {
+                write!(f, "Instruction not available: '{}'", inst)
+            }
             Self::UnknownDirective(dir) => write!(f, "Unknown directive: '{}'", dir),
             Self::ParseError(msg) => write!(f, "Parse error: {}", msg),
             Self::ValueOutOfRange(msg) => write!(f, "Value out of range: {}", msg),
@@ -92,6 +96,10 @@ mod tests {
             AssemblerErrorKind::UnknownInstruction("jump".to_string()).to_string(),
             "Unknown instruction: 'jump'"
         );
+        assert_eq!(
+            AssemblerErrorKind::UnsupportedInstruction("ld on rv32im".to_string()).to_string(),
+            "Instruction not available: 'ld on rv32im'"
+        );
         assert_eq!(
             AssemblerErrorKind::UnknownDirective(".dataz".to_string()).to_string(),
             "Unknown directive: '.dataz'"
@@ -0,0 +1,740 @@
+//! Encoding and disassembly of the vector extension subset: `vset{i}vl{i}`, unit-stride and
+//! strided loads and stores, and the integer arithmetic, compare, mask and reduction
+//! instructions.
+
+use crate::dissassembler::abi_to_string;
+use crate::encoder::{parse_immediate, parse_memory_operand, parse_register};
+use crate::types::AssemblerErrorKind;
+use riscv_core::opcodes;
+use riscv_core::vector::{self, *};
+
+/// The operand categories of an arithmetic instruction, each with its own mnemonic suffix.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Form {
+    /// `.vv` between vector elements.
+    Ivv,
+    /// `.vx` with a scalar register.
+    Ivx,
+    /// `.vi` with a 5-bit immediate.
+    Ivi,
+    Mvv,
+    Mvx,
+    /// `.vs`: a reduction into element 0.
+    Reduction,
+    /// `.mm`: a logical operation on mask registers.
+    Mask,
+}
+
+impl Form {
+    fn suffix(self) -> &'static str {
+        match self {
+            Form::Ivv | Form::Mvv => "vv",
+            Form::Ivx | Form::Mvx => "vx",
+            Form::Ivi => "vi",
+            Form::Reduction => "vs",
+            Form::Mask => "mm",
+        }
+    }
+
+    fn funct3(self) -> u32 {
+        match self {
+            Form::Ivv => OPIVV,
+            Form::Ivx => OPIVX,
+            Form::Ivi => OPIVI,
+            Form::Mvv | Form::Reduction | Form::Mask => OPMVV,
+            Form::Mvx => OPMVX,
+        }
+    }
+}
+
+const IVV_IVX_IVI: &[Form] = &[Form::Ivv, Form::Ivx, Form::Ivi];
+const IVV_IVX: &[Form] = &[Form::Ivv, Form::Ivx];
+const IVX_IVI: &[Form] = &[Form::Ivx, Form::Ivi];
+const MVV_MVX: &[Form] = &[Form::Mvv, Form::Mvx];
+
+/// The arithmetic instructions written `<name>.<suffix> vd, vs2, vs1/rs1/imm`, or
+/// `vd, vs1/rs1, vs2` for the multiply-adds.
+const ARITHMETIC: &[(&str, u32, &[Form])] = &[
+    ("vadd", VADD, IVV_IVX_IVI),
+    ("vsub", VSUB, IVV_IVX),
+    ("vrsub", VRSUB, IVX_IVI),
+    ("vminu", VMINU, IVV_IVX),
+    ("vmin", VMIN, IVV_IVX),
+    ("vmaxu", VMAXU, IVV_IVX),
+    ("vmax", VMAX, IVV_IVX),
+    ("vand", VAND, IVV_IVX_IVI),
+    ("vor", VOR, IVV_IVX_IVI),
+    ("vxor", VXOR, IVV_IVX_IVI),
+    ("vmseq", VMSEQ, IVV_IVX_IVI),
+    ("vmsne", VMSNE, IVV_IVX_IVI),
+    ("vmsltu", VMSLTU, IVV_IVX),
+    ("vmslt", VMSLT, IVV_IVX),
+    ("vmsleu", VMSLEU, IVV_IVX_IVI),
+    ("vmsle", VMSLE, IVV_IVX_IVI),
+    ("vmsgtu", VMSGTU, IVX_IVI),
+    ("vmsgt", VMSGT, IVX_IVI),
+    ("vsll", VSLL, IVV_IVX_IVI),
+    ("vsrl", VSRL, IVV_IVX_IVI),
+    ("vsra", VSRA, IVV_IVX_IVI),
+    ("vredsum", VREDSUM, &[Form::Reduction]),
+    ("vredand", VREDAND, &[Form::Reduction]),
+    ("vredor", VREDOR, &[Form::Reduction]),
+    ("vredxor", VREDXOR, &[Form::Reduction]),
+    ("vredminu", VREDMINU, &[Form::Reduction]),
+    ("vredmin", VREDMIN, &[Form::Reduction]),
+    ("vredmaxu", VREDMAXU, &[Form::Reduction]),
+    ("vredmax", VREDMAX, &[Form::Reduction]),
+    ("vmandn", VMANDN, &[Form::Mask]),
+    ("vmand", VMAND, &[Form::Mask]),
+    ("vmor", VMOR, &[Form::Mask]),
+    ("vmxor", VMXOR, &[Form::Mask]),
+    ("vmorn", VMORN, &[Form::Mask]),
+    ("vmnand", VMNAND, &[Form::Mask]),
+    ("vmnor", VMNOR, &[Form::Mask]),
+    ("vmxnor", VMXNOR, &[Form::Mask]),
+    ("vdivu", VDIVU, MVV_MVX),
+    ("vdiv", VDIV, MVV_MVX),
+    ("vremu", VREMU, MVV_MVX),
+    ("vrem", VREM, MVV_MVX),
+    ("vmulhu", VMULHU, MVV_MVX),
+    ("vmul", VMUL, MVV_MVX),
+    ("vmulhsu", VMULHSU, MVV_MVX),
+    ("vmulh", VMULH, MVV_MVX),
+    ("vmadd", VMADD, MVV_MVX),
+    ("vnmsub", VNMSUB, MVV_MVX),
+    ("vmacc", VMACC, MVV_MVX),
+    ("vnmsac", VNMSAC, MVV_MVX),
+];
+
+/// The single-operand instructions: name, funct6, funct3, vs1 field, and whether the
+/// destination is an integer register.
+const UNARY: &[(&str, u32, u32, u32, bool)] = &[
+    ("vcpop.m", VWXUNARY0, OPMVV, VS1_VCPOP, true),
+    ("vfirst.m", VWXUNARY0, OPMVV, VS1_VFIRST, true),
+    ("vmsbf.m", VMUNARY0, OPMVV, VS1_VMSBF, false),
+    ("vmsif.m", VMUNARY0, OPMVV, VS1_VMSIF, false),
+    ("vmsof.m", VMUNARY0, OPMVV, VS1_VMSOF, false),
+    ("viota.m", VMUNARY0, OPMVV, VS1_VIOTA, false),
+];
+
+fn is_multiply_add(funct6: u32, form: Form) -> bool {
+    matches!(form, Form::Mvv | Form::Mvx) && matches!(funct6, VMADD | VNMSUB | VMACC | VNMSAC)
+}
+
+fn is_shift(funct6: u32, form: Form) -> bool {
+    form == Form::Ivi && matches!(funct6, VSLL | VSRL | VSRA)
+}
+
+fn encode_op_v(funct6: u32, unmasked: bool, vs2: u32, vs1: u32, funct3: u32, vd: u32) -> u32 {
+    (funct6 << 26)
+        | ((unmasked as u32) << 25)
+        | (vs2 << 20)
+        | (vs1 << 15)
+        | (funct3 << 12)
+        | (vd << 7)
+        | opcodes::OP_V
+}
+
+pub fn parse_vector_register(reg_str: &str) -> Result<u32, AssemblerErrorKind> {
+    let s = reg_str.trim_end_matches(',');
+    s.strip_prefix('v')
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|&n| n < 32)
+        .ok_or_else(|| AssemblerErrorKind::InvalidRegister(s.to_string()))
+}
+
+/// Splits a trailing `v0.t` mask operand off `operands`, returning the rest and whether the
+/// instruction is unmasked.
+fn split_mask<'a, 'b>(operands: &'a [&'b str]) -> (&'a [&'b str], bool) {
+    match operands.split_last() {
+        Some((last, rest)) if last.trim_end_matches(',') == "v0.t" => (rest, false),
+        _ => (operands, true),
+    }
+}
+
+fn expect_operands(
+    instruction: &str,
+    operands: &[&str],
+    expected: usize,
+) -> Result<(), AssemblerErrorKind> {
+    if operands.len() != expected {
+        return Err(AssemblerErrorKind::ParseError(format!(
+            "{} expects {} operands",
+            instruction, expected
+        )));
+    }
+    Ok(())
+}
+
+fn parse_ranged_immediate(
+    operand: &str,
+    range: std::ops::RangeInclusive<i64>,
+) -> Result<i64, AssemblerErrorKind> {
+    let value = parse_immediate(operand)?;
+    if !range.contains(&value) {
+        return Err(AssemblerErrorKind::ValueOutOfRange(format!(
+            "{} is outside {}..={}",
+            value,
+            range.start(),
+            range.end()
+        )));
+    }
+    Ok(value)
+}
+
+/// Parses the `vtype` operands of `vsetvli` and `vsetivli`, such as `e32, m2, ta, ma`. LMUL
+/// defaults to 1 and the policies to undisturbed.
+fn parse_vtype(operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let invalid = |s: &str| AssemblerErrorKind::ParseError(format!("invalid vtype field {}", s));
+    let mut vsew = None;
+    let mut vlmul = 0;
+    let mut policy = 0;
+    for operand in operands {
+        let s = operand.trim_end_matches(',');
+        match s {
+            "e8" => vsew = Some(0),
+            "e16" => vsew = Some(1),
+            "e32" => vsew = Some(2),
+            "e64" => vsew = Some(3),
+            "m1" => vlmul = 0,
+            "m2" => vlmul = 1,
+            "m4" => vlmul = 2,
+            "m8" => vlmul = 3,
+            "mf8" => vlmul = 5,
+            "mf4" => vlmul = 6,
+            "mf2" => vlmul = 7,
+            "tu" => policy &= !VTYPE_VTA,
+            "ta" => policy |= VTYPE_VTA,
+            "mu" => policy &= !VTYPE_VMA,
+            "ma" => policy |= VTYPE_VMA,
+            _ => return Err(invalid(s)),
+        }
+    }
+    let vsew = vsew.ok_or_else(|| {
+        AssemblerErrorKind::ParseError("vtype needs an element width such as e32".to_string())
+    })?;
+    Ok((policy | (vsew << VTYPE_VSEW_SHIFT)) as u32 | vlmul)
+}
+
+/// Encodes a vector instruction or one of its pseudo-instructions.
+pub fn encode_vector(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+    let unknown = || AssemblerErrorKind::UnknownInstruction(instruction.to_string());
+
+    match instruction {
+        "vsetvli" | "vsetivli" => {
+            if operands.len() < 3 {
+                return Err(AssemblerErrorKind::ParseError(format!(
+                    "{} expects rd, an AVL and a vtype",
+                    instruction
+                )));
+            }
+            let rd = parse_register(operands[0])?;
+            let vtype = parse_vtype(&operands[2..])?;
+            return Ok(if instruction == "vsetvli" {
+                let rs1 = parse_register(operands[1])?;
+                (vtype << 20) | (rs1 << 15) | (OPCFG << 12) | (rd << 7) | opcodes::OP_V
+            } else {
+                let avl = parse_ranged_immediate(operands[1], 0..=31)? as u32;
+                (0b11 << 30)
+                    | (vtype << 20)
+                    | (avl << 15)
+                    | (OPCFG << 12)
+                    | (rd << 7)
+                    | opcodes::OP_V
+            });
+        }
+        "vsetvl" => {
+            expect_operands(instruction, operands, 3)?;
+            let rd = parse_register(operands[0])?;
+            let rs1 = parse_register(operands[1])?;
+            let rs2 = parse_register(operands[2])?;
+            return Ok((1 << 31) | encode_op_v(0, false, rs2, rs1, OPCFG, rd));
+        }
+        _ => {}
+    }
+
+    if let Some(word) = encode_vector_access(instruction, operands)? {
+        return Ok(word);
+    }
+
+    let (operands, unmasked) = split_mask(operands);
+    let masked_only = |word: Result<u32, AssemblerErrorKind>| {
+        if unmasked {
+            word
+        } else {
+            Err(AssemblerErrorKind::ParseError(format!(
+                "{} cannot be masked",
+                instruction
+            )))
+        }
+    };
+
+    match instruction {
+        "vmv.v.v" | "vmv.v.x" | "vmv.v.i" => {
+            expect_operands(instruction, operands, 2)?;
+            let vd = parse_vector_register(operands[0])?;
+            let (vs1, funct3) = match instruction {
+                "vmv.v.v" => (parse_vector_register(operands[1])?, OPIVV),
+                "vmv.v.x" => (parse_register(operands[1])?, OPIVX),
+                _ => (
+                    parse_ranged_immediate(operands[1], -16..=15)? as u32 & 0x1F,
+                    OPIVI,
+                ),
+            };
+            return masked_only(Ok(encode_op_v(VMERGE_VMV, true, 0, vs1, funct3, vd)));
+        }
+        "vmerge.vvm" | "vmerge.vxm" | "vmerge.vim" => {
+            // The mask is always v0 and is written as a plain operand.
+            expect_operands(instruction, operands, 4)?;
+            if operands[3].trim_end_matches(',') != "v0" || !unmasked {
+                return Err(AssemblerErrorKind::ParseError(format!(
+                    "{} takes v0 as its last operand",
+                    instruction
+                )));
+            }
+            let vd = parse_vector_register(operands[0])?;
+            let vs2 = parse_vector_register(operands[1])?;
+            let (vs1, funct3) = match instruction {
+                "vmerge.vvm" => (parse_vector_register(operands[2])?, OPIVV),
+                "vmerge.vxm" => (parse_register(operands[2])?, OPIVX),
+                _ => (
+                    parse_ranged_immediate(operands[2], -16..=15)? as u32 & 0x1F,
+                    OPIVI,
+                ),
+            };
+            return Ok(encode_op_v(VMERGE_VMV, false, vs2, vs1, funct3, vd));
+        }
+        "vmv.x.s" => {
+            expect_operands(instruction, operands, 2)?;
+            let rd = parse_register(operands[0])?;
+            let vs2 = parse_vector_register(operands[1])?;
+            return masked_only(Ok(encode_op_v(
+                VWXUNARY0,
+                true,
+                vs2,
+                VS1_VMV_X_S,
+                OPMVV,
+                rd,
+            )));
+        }
+        "vmv.s.x" => {
+            expect_operands(instruction, operands, 2)?;
+            let vd = parse_vector_register(operands[0])?;
+            let rs1 = parse_register(operands[1])?;
+            return masked_only(Ok(encode_op_v(VWXUNARY0, true, 0, rs1, OPMVX, vd)));
+        }
+        "vid.v" => {
+            expect_operands(instruction, operands, 1)?;
+            let vd = parse_vector_register(operands[0])?;
+            return Ok(encode_op_v(VMUNARY0, unmasked, 0, VS1_VID, OPMVV, vd));
+        }
+        _ => {}
+    }
+
+    if let Some(&(_, funct6, funct3, vs1, integer_rd)) =
+        UNARY.iter().find(|(name, ..)| *name == instruction)
+    {
+        expect_operands(instruction, operands, 2)?;
+        let rd = if integer_rd {
+            parse_register(operands[0])?
+        } else {
+            parse_vector_register(operands[0])?
+        };
+        let vs2 = parse_vector_register(operands[1])?;
+        return Ok(encode_op_v(funct6, unmasked, vs2, vs1, funct3, rd));
+    }
+
+    if let Some((name, operands)) = expand_pseudo(instruction, operands)? {
+        let mut operands = operands;
+        if !unmasked {
+            operands.push("v0.t".to_string());
+        }
+        let operands: Vec<&str> = operands.iter().map(String::as_str).collect();
+        return encode_vector(name, &operands);
+    }
+
+    let (name, suffix) = instruction.rsplit_once('.').ok_or_else(unknown)?;
+    let (funct6, form) = ARITHMETIC
+        .iter()
+        .filter(|(entry, ..)| *entry == name)
+        .flat_map(|&(_, funct6, forms)| forms.iter().map(move |&form| (funct6, form)))
+        .find(|(_, form)| form.suffix() == suffix)
+        .ok_or_else(unknown)?;
+
+    expect_operands(instruction, operands, 3)?;
+    let vd = parse_vector_register(operands[0])?;
+    let (vs2_operand, source_operand) = if is_multiply_add(funct6, form) {
+        (operands[2], operands[1])
+    } else {
+        (operands[1], operands[2])
+    };
+    let vs2 = parse_vector_register(vs2_operand)?;
+    let vs1 = match form {
+        Form::Ivx | Form::Mvx => parse_register(source_operand)?,
+        Form::Ivi if is_shift(funct6, form) => {
+            parse_ranged_immediate(source_operand, 0..=31)? as u32
+        }
+        Form::Ivi => parse_ranged_immediate(source_operand, -16..=15)? as u32 & 0x1F,
+        _ => parse_vector_register(source_operand)?,
+    };
+    let word = encode_op_v(funct6, unmasked, vs2, vs1, form.funct3(), vd);
+    if form == Form::Mask {
+        masked_only(Ok(word))
+    } else {
+        Ok(word)
+    }
+}
+
+/// Rewrites a pseudo-instruction as the instruction it stands for, without its mask operand.
+fn expand_pseudo(
+    instruction: &str,
+    operands: &[&str],
+) -> Result<Option<(&'static str, Vec<String>)>, AssemblerErrorKind> {
+    let operand = |i: usize| operands[i].trim_end_matches(',').to_string();
+    let expected = match instruction {
+        "vmclr.m" | "vmset.m" => 1,
+        "vneg.v" | "vnot.v" | "vmmv.m" | "vmnot.m" => 2,
+        "vmsgt.vv" | "vmsgtu.vv" | "vmsge.vv" | "vmsgeu.vv" | "vmslt.vi" | "vmsltu.vi"
+        | "vmsge.vi" | "vmsgeu.vi" => 3,
+        _ => return Ok(None),
+    };
+    expect_operands(instruction, operands, expected)?;
+
+    let expansion = match instruction {
+        "vmclr.m" => ("vmxor.mm", vec![operand(0), operand(0), operand(0)]),
+        "vmset.m" => ("vmxnor.mm", vec![operand(0), operand(0), operand(0)]),
+        "vneg.v" => ("vrsub.vx", vec![operand(0), operand(1), "zero".to_string()]),
+        "vnot.v" => ("vxor.vi", vec![operand(0), operand(1), "-1".to_string()]),
+        "vmmv.m" => ("vmand.mm", vec![operand(0), operand(1), operand(1)]),
+        "vmnot.m" => ("vmnand.mm", vec![operand(0), operand(1), operand(1)]),
+        // The missing comparisons swap their operands or adjust the immediate by one.
+        "vmsgt.vv" => ("vmslt.vv", vec![operand(0), operand(2), operand(1)]),
+        "vmsgtu.vv" => ("vmsltu.vv", vec![operand(0), operand(2), operand(1)]),
+        "vmsge.vv" => ("vmsle.vv", vec![operand(0), operand(2), operand(1)]),
+        "vmsgeu.vv" => ("vmsleu.vv", vec![operand(0), operand(2), operand(1)]),
+        _ => {
+            let imm = parse_immediate(operands[2])? - 1;
+            let name = match instruction {
+                "vmslt.vi" => "vmsle.vi",
+                "vmsltu.vi" => "vmsleu.vi",
+                "vmsge.vi" => "vmsgt.vi",
+                _ => "vmsgtu.vi",
+            };
+            (name, vec![operand(0), operand(1), imm.to_string()])
+        }
+    };
+    Ok(Some(expansion))
+}
+
+/// Encodes a unit-stride (`vle32.v`), strided (`vlse32.v`) or mask (`vlm.v`) load or store,
+/// or returns `None` if `instruction` is not one.
+fn encode_vector_access(
+    instruction: &str,
+    operands: &[&str],
+) -> Result<Option<u32>, AssemblerErrorKind> {
+    let Some(rest) = instruction.strip_suffix(".v") else {
+        return Ok(None);
+    };
+    let (is_store, rest) = if let Some(rest) = rest.strip_prefix("vl") {
+        (false, rest)
+    } else if let Some(rest) = rest.strip_prefix("vs") {
+        (true, rest)
+    } else {
+        return Ok(None);
+    };
+    let (mop, width, umop) = match rest {
+        "m" => (MOP_UNIT_STRIDE, vector::WIDTH_8, UMOP_MASK),
+        _ => {
+            let (mop, eew) = match rest.strip_prefix("se") {
+                Some(eew) => (MOP_STRIDED, eew),
+                None => match rest.strip_prefix('e') {
+                    Some(eew) => (MOP_UNIT_STRIDE, eew),
+                    None => return Ok(None),
+                },
+            };
+            let width = match eew {
+                "8" => vector::WIDTH_8,
+                "16" => vector::WIDTH_16,
+                "32" => vector::WIDTH_32,
+                "64" => vector::WIDTH_64,
+                _ => return Ok(None),
+            };
+            (mop, width, 0)
+        }
+    };
+
+    let (operands, unmasked) = split_mask(operands);
+    let expected = if mop == MOP_STRIDED { 3 } else { 2 };
+    expect_operands(instruction, operands, expected)?;
+    if umop == UMOP_MASK && !unmasked {
+        return Err(AssemblerErrorKind::ParseError(format!(
+            "{} cannot be masked",
+            instruction
+        )));
+    }
+    let vd = parse_vector_register(operands[0])?;
+    let address = operands[1].trim_end_matches(',');
+    let (offset, rs1) = parse_memory_operand(address)?;
+    if offset != 0 {
+        return Err(AssemblerErrorKind::InvalidMemoryOperand(
+            address.to_string(),
+        ));
+    }
+    let rs2 = if mop == MOP_STRIDED {
+        parse_register(operands[2])?
+    } else {
+        umop
+    };
+    let opcode = if is_store {
+        opcodes::OP_STORE_FP
+    } else {
+        opcodes::OP_LOAD_FP
+    };
+    Ok(Some(
+        (mop << 26)
+            | ((unmasked as u32) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (width << 12)
+            | (vd << 7)
+            | opcode,
+    ))
+}
+
+fn vreg(reg: u32) -> String {
+    format!("v{}", reg)
+}
+
+/// Disassembles a vector instruction, or returns `None` if `word` is not one.
+pub fn disassemble_vector(word: u32) -> Option<String> {
+    let opcode = word & 0x7f;
+    let rd = (word >> 7) & 0x1f;
+    let funct3 = (word >> 12) & 0x7;
+    let rs1 = (word >> 15) & 0x1f;
+    let rs2 = (word >> 20) & 0x1f;
+    let unmasked = (word >> 25) & 1 == 1;
+    let funct6 = word >> 26;
+    let mask = if unmasked { "" } else { ", v0.t" };
+
+    if opcode == opcodes::OP_LOAD_FP || opcode == opcodes::OP_STORE_FP {
+        let eew = match funct3 {
+            vector::WIDTH_8 => 8,
+            vector::WIDTH_16 => 16,
+            vector::WIDTH_32 => 32,
+            vector::WIDTH_64 => 64,
+            _ => return None,
+        };
+        let direction = if opcode == opcodes::OP_LOAD_FP {
+            "l"
+        } else {
+            "s"
+        };
+        let base = abi_to_string(rs1);
+        return match ((word >> 26) & 0b11, rs2) {
+            _ if word >> 28 != 0 => None,
+            (MOP_UNIT_STRIDE, 0) => Some(format!(
+                "v{}e{}.v {}, ({}){}",
+                direction,
+                eew,
+                vreg(rd),
+                base,
+                mask
+            )),
+            (MOP_UNIT_STRIDE, UMOP_MASK) if eew == 8 && unmasked => {
+                Some(format!("v{}m.v {}, ({})", direction, vreg(rd), base))
+            }
+            (MOP_STRIDED, _) => Some(format!(
+                "v{}se{}.v {}, ({}), {}{}",
+                direction,
+                eew,
+                vreg(rd),
+                base,
+                abi_to_string(rs2),
+                mask
+            )),
+            _ => None,
+        };
+    }
+    if opcode != opcodes::OP_V {
+        return None;
+    }
+
+    if funct3 == OPCFG {
+        let rd = abi_to_string(rd);
+        return match word >> 30 {
+            0b00 | 0b01 => Some(format!(
+                "vsetvli {}, {}, {}",
+                rd,
+                abi_to_string(rs1),
+                vtype_to_string((word >> 20) & 0x7FF)
+            )),
+            0b11 => Some(format!(
+                "vsetivli {}, {}, {}",
+                rd,
+                rs1,
+                vtype_to_string((word >> 20) & 0x3FF)
+            )),
+            _ if funct6 & 0b11111 == 0 && !unmasked => Some(format!(
+                "vsetvl {}, {}, {}",
+                rd,
+                abi_to_string(rs1),
+                abi_to_string(rs2)
+            )),
+            _ => None,
+        };
+    }
+
+    match (funct6, funct3) {
+        (VMERGE_VMV, OPIVV | OPIVX | OPIVI) => {
+            let source = match funct3 {
+                OPIVV => vreg(rs1),
+                OPIVX => abi_to_string(rs1),
+                _ => (((rs1 as i32) << 27) >> 27).to_string(),
+            };
+            let suffix = match funct3 {
+                OPIVV => "v",
+                OPIVX => "x",
+                _ => "i",
+            };
+            return if unmasked {
+                (rs2 == 0).then(|| format!("vmv.v.{} {}, {}", suffix, vreg(rd), source))
+            } else {
+                Some(format!(
+                    "vmerge.v{}m {}, {}, {}, v0",
+                    suffix,
+                    vreg(rd),
+                    vreg(rs2),
+                    source
+                ))
+            };
+        }
+        (VWXUNARY0, OPMVV) if rs1 == VS1_VMV_X_S && unmasked => {
+            return Some(format!("vmv.x.s {}, {}", abi_to_string(rd), vreg(rs2)));
+        }
+        (VWXUNARY0, OPMVX) if rs2 == 0 && unmasked => {
+            return Some(format!("vmv.s.x {}, {}", vreg(rd), abi_to_string(rs1)));
+        }
+        (VMUNARY0, OPMVV) if rs1 == VS1_VID && rs2 == 0 => {
+            return Some(format!("vid.v {}{}", vreg(rd), mask));
+        }
+        _ => {}
+    }
+    if let Some(&(name, _, _, _, integer_rd)) = UNARY
+        .iter()
+        .find(|&&(_, f6, f3, vs1, _)| f6 == funct6 && f3 == funct3 && vs1 == rs1)
+    {
+        let rd = if integer_rd {
+            abi_to_string(rd)
+        } else {
+            vreg(rd)
+        };
+        return Some(format!("{} {}, {}{}", name, rd, vreg(rs2), mask));
+    }
+
+    let (name, form) = ARITHMETIC.iter().find_map(|&(name, f6, forms)| {
+        forms
+            .iter()
+            .find(|form| f6 == funct6 && form.funct3() == funct3)
+            .map(|&form| (name, form))
+    })?;
+    if form == Form::Mask && !unmasked {
+        return None;
+    }
+    let source = match form {
+        Form::Ivx | Form::Mvx => abi_to_string(rs1),
+        Form::Ivi if is_shift(funct6, form) => rs1.to_string(),
+        Form::Ivi => (((rs1 as i32) << 27) >> 27).to_string(),
+        _ => vreg(rs1),
+    };
+    let (first, second) = if is_multiply_add(funct6, form) {
+        (source, vreg(rs2))
+    } else {
+        (vreg(rs2), source)
+    };
+    Some(format!(
+        "{}.{} {}, {}, {}{}",
+        name,
+        form.suffix(),
+        vreg(rd),
+        first,
+        second,
+        mask
+    ))
+}
+
+fn vtype_to_string(vtype: u32) -> String {
+    let vtype = vtype as u64;
+    let sew = 8 << ((vtype & VTYPE_VSEW) >> VTYPE_VSEW_SHIFT);
+    let lmul = match vtype & VTYPE_VLMUL {
+        0 => "m1",
+        1 => "m2",
+        2 => "m4",
+        3 => "m8",
+        5 => "mf8",
+        6 => "mf4",
+        7 => "mf2",
+        _ => "m?",
+    };
+    let tail = if vtype & VTYPE_VTA != 0 { "ta" } else { "tu" };
+    let mask = if vtype & VTYPE_VMA != 0 { "ma" } else { "mu" };
+    format!("e{}, {}, {}, {}", sew, lmul, tail, mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(instruction: &str, operands: &[&str]) -> Result<u32, AssemblerErrorKind> {
+        encode_vector(instruction, operands)
+    }
+
+    #[test]
+    fn test_vector_configuration_and_memory_instructions() {
+        assert_eq!(
+            encode("vsetvli", &["a0,", "a1,", "e32,", "m2,", "ta,", "ma"]),
+            Ok(0x0d15f557)
+        );
+        assert_eq!(encode("vsetivli", &["zero,", "4,", "e8"]), Ok(0xc0027057));
+        assert_eq!(encode("vle32.v", &["v1,", "(a2)"]), Ok(0x02066087));
+        assert_eq!(encode("vlse32.v", &["v1,", "(a2),", "a3"]), Ok(0x0ad66087));
+        assert_eq!(encode("vse32.v", &["v2,", "(a4),", "v0.t"]), Ok(0x00076127));
+        assert_eq!(encode("vlm.v", &["v0,", "(a0)"]), Ok(0x02b50007));
+        assert!(encode("vsetvli", &["a0,", "a1,", "m2"]).is_err());
+        assert!(encode("vle32.v", &["v1,", "8(a2)"]).is_err());
+    }
+
+    #[test]
+    fn test_vector_arithmetic_instructions() {
+        assert_eq!(encode("vadd.vi", &["v2,", "v1,", "5"]), Ok(0x0212b157));
+        assert_eq!(encode("vmseq.vi", &["v0,", "v1,", "0"]), Ok(0x62103057));
+        assert_eq!(encode("vmacc.vx", &["v1,", "a3,", "v2"]), Ok(0xb626e0d7));
+        assert_eq!(encode("vredsum.vs", &["v3,", "v1,", "v3"]), Ok(0x0211a1d7));
+        assert_eq!(encode("vfirst.m", &["a3,", "v0"]), Ok(0x4208a6d7));
+        assert_eq!(encode("vmv.x.s", &["a4,", "v3"]), Ok(0x42302757));
+        assert_eq!(encode("vmv.v.x", &["v1,", "a2"]), Ok(0x5e0640d7));
+        assert_eq!(encode("vid.v", &["v2"]), Ok(0x5208a157));
+        // vmsgt.vv swaps its operands into vmslt.vv.
+        assert_eq!(
+            encode("vmsgt.vv", &["v0,", "v1,", "v2"]),
+            encode("vmslt.vv", &["v0,", "v2,", "v1"])
+        );
+        assert!(encode("vsub.vi", &["v1,", "v2,", "3"]).is_err());
+        assert!(encode("vadd.vi", &["v1,", "v2,", "16"]).is_err());
+        assert!(encode("vmand.mm", &["v1,", "v2,", "v3,", "v0.t"]).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_vector_round_trips() {
+        for (word, text) in [
+            (0x0d15f557, "vsetvli a0, a1, e32, m2, ta, ma"),
+            (0x0ad66087, "vlse32.v v1, (a2), a3"),
+            (0x00076127, "vse32.v v2, (a4), v0.t"),
+            (0x0212b157, "vadd.vi v2, v1, 5"),
+            (0xb626e0d7, "vmacc.vx v1, a3, v2"),
+            (0x5208a157, "vid.v v2"),
+            (0x42302757, "vmv.x.s a4, v3"),
+        ] {
+            assert_eq!(disassemble_vector(word).as_deref(), Some(text));
+        }
+    }
+}
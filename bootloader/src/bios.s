@@ -7,10 +7,12 @@ ROOT_PAGE_TABLE:      .quad 0x80010000
 PAGE_TABLE_CLEAR_SIZE:.quad 8192
 SATP_PPN_VALUE:       .quad 0x80010
 SATP_MODE_SV39_VALUE: .quad 0x8000000000000000
-VIRTUAL_DISK_ADDR:    .quad 0x90000000
 KERNEL_LOAD_ADDR:     .quad 0x80100000
-DISK_SIZE_REG_ADDR:   .quad 0x90001000
-CORRECT_PTE_VALUE:    .quad 0x2000000F
+# Both gigapages are V|R|W|X with A and D already set, as the kernel runs without
+# hardware A/D updates (Svade) and would otherwise fault on its first access.
+CORRECT_PTE_VALUE:    .quad 0x200000CF
+# Identity map for the first 1GB, where the CLINT, PLIC and UART live.
+MMIO_PTE_VALUE:       .quad 0x000000CF
 
 # --- Constants for Privilege Drop (avoids large `li` and `~`) ---
 # A mask of all 1s, used to delegate all exceptions and interrupts.
@@ -24,6 +26,20 @@ MSTATUS_MPP_MASK:     .quad 0x1800
 # Used with `csrrs` to set the new privilege level.
 MSTATUS_MPP_S_MODE:   .quad 0x800
 
+# The value to set mstatus.FS and mstatus.VS to Initial (0b01 << 13 | 0b01 << 9), so the
+# kernel can use the FPU and the vector unit.
+MSTATUS_FS_VS_INITIAL: .quad 0x2200
+
+# --- PMP Sandbox for the Supervisor ---
+# Entry 0: NAPOT over the 64KiB holding this BIOS at 0x80000000, with no permissions, so
+# S-mode can neither read nor overwrite the firmware. It is left unlocked so M-mode keeps
+# full access.
+PMPADDR0_BIOS:        .quad 0x20001FFF
+# Entry 1: NAPOT over all of physical memory with R/W/X. Entry 0 takes priority.
+PMPADDR1_ALL:         .quad 0x3FFFFFFFFFFFFF
+# pmpcfg0: entry 0 = NAPOT (0x18), entry 1 = NAPOT|X|W|R (0x1F).
+PMPCFG0_VALUE:        .quad 0x1F18
+
 .section .text
 .global _start
 
@@ -46,7 +62,7 @@ clear_loop:
     addi t1, t1, -8
     bne t1, zero, clear_loop
 
-    # 3. Create the identity map for the first 1GB of memory.
+    # 3. Create the identity map for the 1GB of RAM at 0x80000000.
     la t0, ROOT_PAGE_TABLE
     ld t0, 0(t0)
     addi t0, t0, 16
@@ -56,6 +72,13 @@ clear_loop:
     ld t1, 0(t1)
     sd t1, 0(t0)
 
+    # Also identity map the MMIO region at 0x00000000 so the kernel can
+    # reach its devices once paging is on.
+    addi t0, t0, -16
+    la t1, MMIO_PTE_VALUE
+    ld t1, 0(t1)
+    sd t1, 0(t0)
+
     # 4. Enable Paging!
     la t0, SATP_PPN_VALUE
     ld t0, 0(t0)
@@ -65,29 +88,12 @@ clear_loop:
     csrrw zero, satp, t0
 
     # --- Stage 2: Running in Virtual Memory (MMU is ON) ---
-
-    # 5. Load the kernel from the virtual disk.
-    la t1, DISK_SIZE_REG_ADDR
-    ld t1, 0(t1)
-    ld a2, 0(t1)
-    la a0, VIRTUAL_DISK_ADDR
-    ld a0, 0(a0)
-    la a1, KERNEL_LOAD_ADDR
-    ld a1, 0(a1)
-    beq a2, zero, prepare_s_mode
-
-load_loop:
-    lb t0, 0(a0)
-    sb t0, 0(a1)
-    addi a0, a0, 1
-    addi a1, a1, 1
-    addi a2, a2, -1
-    bne a2, zero, load_loop
+    # The VM has already placed the kernel at KERNEL_LOAD_ADDR.
 
 prepare_s_mode:
     # --- Stage 3: Prepare Handoff to Supervisor-Mode Kernel ---
 
-    # 6. Delegate all exceptions and interrupts to S-mode.
+    # 5. Delegate all exceptions and interrupts to S-mode.
     la t0, DELEGATION_MASK
     ld t0, 0(t0)
     # Use `csrrw` to write the value from t0 into the CSR.
@@ -96,7 +102,7 @@ prepare_s_mode:
     csrrw zero, medeleg, t0
     csrrw zero, mideleg, t0
 
-    # 7. Set mstatus.MPP to Supervisor Mode (0b01).
+    # 6. Set mstatus.MPP to Supervisor Mode (0b01).
     # This is a two-step process using base instructions.
     # First, clear the MPP bits using the mask and `csrrc`.
     la t1, MSTATUS_MPP_MASK
@@ -110,6 +116,28 @@ prepare_s_mode:
     # `csrrs zero, csr, rs1` sets bits in csr specified by rs1.
     csrrs zero, mstatus, t2
 
+    # Turn the FPU and vector unit on; they are Off at reset.
+    la t2, MSTATUS_FS_VS_INITIAL
+    ld t2, 0(t2)         # t2 = 0x2200
+    csrrs zero, mstatus, t2
+
+    # Let the kernel read cycle, time and instret (bits 0-2). It decides what U-mode
+    # sees through scounteren.
+    addi t0, zero, 7
+    csrrw zero, mcounteren, t0
+
+    # 7. Sandbox the supervisor with PMP. Without any matching entry S-mode could not
+    # touch memory at all, so this must happen before the privilege drop.
+    la t0, PMPADDR0_BIOS
+    ld t0, 0(t0)
+    csrrw zero, pmpaddr0, t0
+    la t0, PMPADDR1_ALL
+    ld t0, 0(t0)
+    csrrw zero, pmpaddr1, t0
+    la t0, PMPCFG0_VALUE
+    ld t0, 0(t0)
+    csrrw zero, pmpcfg0, t0
+
     # 8. Set mepc to the kernel's entry point.
     la t0, KERNEL_LOAD_ADDR
     ld t0, 0(t0)
@@ -0,0 +1,120 @@
+# =============================================================================
+# vector.s
+#
+# Data-parallel kernels written with the vector extension: memcpy, strlen,
+# an integer saxpy (y = a * x + y) and a sum reduction.
+#
+# Every loop is strip-mined: vsetvli reports how many elements the vector
+# registers hold on this pass, so the same code runs unchanged whatever
+# VLEN the VM was started with (see --vlen).
+#
+# The program runs in M-mode from reset, so it turns the vector unit on
+# itself by setting mstatus.VS.
+#
+# EXPECTED RESULT: The program should exit with code 93.
+# (strlen of the copied message is 13; y = 2 * [1..8] + 1 sums to 80.)
+# =============================================================================
+
+.section .data
+    message: .asciz "data-parallel"
+
+    .align 3
+    copy: .space 32
+
+    .align 2
+    x: .word 1, 2, 3, 4, 5, 6, 7, 8
+    y: .word 1, 1, 1, 1, 1, 1, 1, 1
+
+.section .text
+.global _start
+_start:
+    # mstatus.VS = Initial (0b01 << 9).
+    li t0, 0x200
+    csrrs zero, mstatus, t0
+
+    # memcpy(copy, message, 14)
+    la a0, copy
+    la a1, message
+    li a2, 14
+    jal ra, memcpy
+
+    # s0 = strlen(copy)
+    la a0, copy
+    jal ra, strlen
+    addi s0, a0, 0
+
+    # saxpy(8, 2, x, y)
+    li a0, 8
+    li a1, 2
+    la a2, x
+    la a3, y
+    jal ra, saxpy
+
+    # a0 = strlen + sum(y)
+    li a0, 8
+    la a1, y
+    jal ra, sum
+    add a0, a0, s0
+
+    li a7, 93
+    ecall
+
+# memcpy(a0 = dst, a1 = src, a2 = n): copies n bytes.
+memcpy:
+    addi a3, a0, 0
+memcpy_loop:
+    vsetvli t0, a2, e8, m8, ta, ma
+    vle8.v v8, (a1)
+    vse8.v v8, (a3)
+    add a1, a1, t0
+    add a3, a3, t0
+    sub a2, a2, t0
+    bne a2, zero, memcpy_loop
+    ret
+
+# strlen(a0 = s): returns the length of the NUL-terminated string at s.
+# Each pass reads a whole register group, which may run past the end of the
+# string; that is fine here because the bytes after it are mapped.
+strlen:
+    addi a3, a0, 0
+strlen_loop:
+    vsetvli t0, zero, e8, m8, ta, ma
+    vle8.v v8, (a3)
+    vmseq.vi v0, v8, 0
+    vfirst.m t1, v0
+    add a3, a3, t0
+    blt t1, zero, strlen_loop
+    # The NUL is t1 bytes into the last group read.
+    sub a0, a3, a0
+    sub a0, a0, t0
+    add a0, a0, t1
+    ret
+
+# saxpy(a0 = n, a1 = a, a2 = x, a3 = y): y[i] = a * x[i] + y[i] for 32-bit ints.
+saxpy:
+    vsetvli t0, a0, e32, m4, ta, ma
+    vle32.v v8, (a2)
+    vle32.v v16, (a3)
+    vmacc.vx v16, a1, v8
+    vse32.v v16, (a3)
+    slli t1, t0, 2
+    add a2, a2, t1
+    add a3, a3, t1
+    sub a0, a0, t0
+    bne a0, zero, saxpy
+    ret
+
+# sum(a0 = n, a1 = p): returns the sum of n 32-bit ints at p.
+sum:
+    vsetivli zero, 1, e32, m1, ta, ma
+    vmv.s.x v24, zero
+sum_loop:
+    vsetvli t0, a0, e32, m4, ta, ma
+    vle32.v v8, (a1)
+    vredsum.vs v24, v8, v24
+    slli t1, t0, 2
+    add a1, a1, t1
+    sub a0, a0, t0
+    bne a0, zero, sum_loop
+    vmv.x.s a0, v24
+    ret
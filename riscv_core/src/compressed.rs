@@ -0,0 +1,303 @@
+//! The RV64C compressed instruction set. Every 16-bit instruction is an abbreviation of a
+//! 32-bit one, so the VM executes compressed code by expanding it first.
+
+use crate::{funct3, funct7, opcodes, system};
+
+/// The two low bits of a 32-bit instruction. Anything else is a 16-bit instruction.
+pub const UNCOMPRESSED_MASK: u32 = 0b11;
+
+/// The length in bytes of the instruction whose first halfword is `low_half`.
+pub fn instruction_length(low_half: u16) -> u64 {
+    if low_half as u32 & UNCOMPRESSED_MASK == UNCOMPRESSED_MASK {
+        4
+    } else {
+        2
+    }
+}
+
+/// Returns the 32-bit instruction that `inst` abbreviates, or `None` if `inst` is illegal or
+/// a reserved encoding.
+pub fn expand(inst: u16) -> Option<u32> {
+    let inst = inst as u32;
+    let funct3 = (inst >> 13) & 0x7;
+    let bit12 = (inst >> 12) & 1;
+    // The full register fields, and the 3-bit `x8..x15` ones.
+    let rd = (inst >> 7) & 0x1F;
+    let rs2 = (inst >> 2) & 0x1F;
+    let rd_prime = ((inst >> 2) & 0x7) + 8;
+    let rs1_prime = ((inst >> 7) & 0x7) + 8;
+    // The 6-bit immediate most quadrant 1 and 2 instructions share, sign-extended.
+    let imm6 = sign_extend((bit12 << 5) | rs2, 6);
+
+    let expanded = match (inst & 0b11, funct3) {
+        // c.addi4spn
+        (0b00, 0b000) => {
+            let imm = bits(inst, 12, 11, 4)
+                | bits(inst, 10, 7, 6)
+                | bits(inst, 6, 6, 2)
+                | bits(inst, 5, 5, 3);
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, funct3::ADD_SUB, rd_prime, opcodes::OP_IMM)
+        }
+        // c.fld, c.lw, c.ld
+        (0b00, 0b001..=0b011) => {
+            let (imm, funct3, opcode) = match funct3 {
+                0b001 => (ld_offset(inst), funct3::FLD, opcodes::OP_LOAD_FP),
+                0b010 => (lw_offset(inst), funct3::LW, opcodes::OP_LOAD),
+                _ => (ld_offset(inst), funct3::LD, opcodes::OP_LOAD),
+            };
+            i_type(imm, rs1_prime, funct3, rd_prime, opcode)
+        }
+        // c.fsd, c.sw, c.sd
+        (0b00, 0b101..=0b111) => {
+            let (imm, funct3, opcode) = match funct3 {
+                0b101 => (ld_offset(inst), funct3::FSD, opcodes::OP_STORE_FP),
+                0b110 => (lw_offset(inst), funct3::SW, opcodes::OP_STORE),
+                _ => (ld_offset(inst), funct3::SD, opcodes::OP_STORE),
+            };
+            s_type(imm, rd_prime, rs1_prime, funct3, opcode)
+        }
+        // c.addi (c.nop when rd is zero)
+        (0b01, 0b000) => i_type(imm6, rd, funct3::ADD_SUB, rd, opcodes::OP_IMM),
+        // c.addiw
+        (0b01, 0b001) if rd != 0 => i_type(imm6, rd, funct3::ADD_SUB, rd, opcodes::OP_IMM_32),
+        (0b01, 0b001) => return None,
+        // c.li
+        (0b01, 0b010) => i_type(imm6, 0, funct3::ADD_SUB, rd, opcodes::OP_IMM),
+        // c.addi16sp
+        (0b01, 0b011) if rd == 2 => {
+            let imm = bits(inst, 12, 12, 9)
+                | bits(inst, 6, 6, 4)
+                | bits(inst, 5, 5, 6)
+                | bits(inst, 4, 3, 7)
+                | bits(inst, 2, 2, 5);
+            if imm == 0 {
+                return None;
+            }
+            i_type(sign_extend(imm, 10), 2, funct3::ADD_SUB, 2, opcodes::OP_IMM)
+        }
+        // c.lui
+        (0b01, 0b011) => {
+            if imm6 == 0 {
+                return None;
+            }
+            (imm6 << 12) | (rd << 7) | opcodes::OP_LUI
+        }
+        (0b01, 0b100) => match (inst >> 10) & 0b11 {
+            // c.srli, c.srai
+            0b00 | 0b01 => {
+                let shamt = (bit12 << 5) | rs2;
+                let funct6 = if (inst >> 10) & 1 == 0 { 0 } else { 0b010000 };
+                i_type(
+                    (funct6 << 6) | shamt,
+                    rs1_prime,
+                    funct3::SRL_SRA,
+                    rs1_prime,
+                    opcodes::OP_IMM,
+                )
+            }
+            // c.andi
+            0b10 => i_type(imm6, rs1_prime, funct3::AND, rs1_prime, opcodes::OP_IMM),
+            // c.sub, c.xor, c.or, c.and, c.subw, c.addw
+            _ => {
+                let (funct7, funct3, opcode) = match (bit12, (inst >> 5) & 0b11) {
+                    (0, 0b00) => (funct7::SUB, funct3::ADD_SUB, opcodes::OP_REG),
+                    (0, 0b01) => (funct7::DEFAULT, funct3::XOR, opcodes::OP_REG),
+                    (0, 0b10) => (funct7::DEFAULT, funct3::OR, opcodes::OP_REG),
+                    (0, _) => (funct7::DEFAULT, funct3::AND, opcodes::OP_REG),
+                    (_, 0b00) => (funct7::SUBW, funct3::ADD_SUB, opcodes::OP_REG_32),
+                    (_, 0b01) => (funct7::ADDW, funct3::ADD_SUB, opcodes::OP_REG_32),
+                    _ => return None,
+                };
+                r_type(funct7, rd_prime, rs1_prime, funct3, rs1_prime, opcode)
+            }
+        },
+        // c.j
+        (0b01, 0b101) => {
+            let imm = bits(inst, 12, 12, 11)
+                | bits(inst, 11, 11, 4)
+                | bits(inst, 10, 9, 8)
+                | bits(inst, 8, 8, 10)
+                | bits(inst, 7, 7, 6)
+                | bits(inst, 6, 6, 7)
+                | bits(inst, 5, 3, 1)
+                | bits(inst, 2, 2, 5);
+            j_type(sign_extend(imm, 12), 0)
+        }
+        // c.beqz, c.bnez
+        (0b01, _) => {
+            let imm = bits(inst, 12, 12, 8)
+                | bits(inst, 11, 10, 3)
+                | bits(inst, 6, 5, 6)
+                | bits(inst, 4, 3, 1)
+                | bits(inst, 2, 2, 5);
+            let funct3 = if funct3 == 0b110 {
+                funct3::BEQ
+            } else {
+                funct3::BNE
+            };
+            b_type(sign_extend(imm, 9), 0, rs1_prime, funct3)
+        }
+        // c.slli
+        (0b10, 0b000) => {
+            let shamt = (bit12 << 5) | rs2;
+            i_type(shamt, rd, funct3::SLL, rd, opcodes::OP_IMM)
+        }
+        // c.fldsp, c.lwsp, c.ldsp
+        (0b10, 0b001..=0b011) => {
+            let word_offset = bits(inst, 12, 12, 5) | bits(inst, 6, 4, 2) | bits(inst, 3, 2, 6);
+            let double_offset = bits(inst, 12, 12, 5) | bits(inst, 6, 5, 3) | bits(inst, 4, 2, 6);
+            let (imm, funct3, opcode) = match funct3 {
+                0b001 => (double_offset, funct3::FLD, opcodes::OP_LOAD_FP),
+                0b010 if rd != 0 => (word_offset, funct3::LW, opcodes::OP_LOAD),
+                0b011 if rd != 0 => (double_offset, funct3::LD, opcodes::OP_LOAD),
+                _ => return None,
+            };
+            i_type(imm, 2, funct3, rd, opcode)
+        }
+        (0b10, 0b100) => match (bit12, rd, rs2) {
+            // c.jr
+            (0, 0, 0) => return None,
+            (0, _, 0) => i_type(0, rd, funct3::ADD_SUB, 0, opcodes::OP_JALR),
+            // c.mv
+            (0, _, _) => r_type(
+                funct7::DEFAULT,
+                rs2,
+                0,
+                funct3::ADD_SUB,
+                rd,
+                opcodes::OP_REG,
+            ),
+            // c.ebreak
+            (_, 0, 0) => system::FUNCT12_EBREAK << 20 | opcodes::OP_SYSTEM,
+            // c.jalr
+            (_, _, 0) => i_type(0, rd, funct3::ADD_SUB, 1, opcodes::OP_JALR),
+            // c.add
+            _ => r_type(
+                funct7::DEFAULT,
+                rs2,
+                rd,
+                funct3::ADD_SUB,
+                rd,
+                opcodes::OP_REG,
+            ),
+        },
+        // c.fsdsp, c.swsp, c.sdsp
+        (0b10, _) => {
+            let (imm, funct3, opcode) = match funct3 {
+                0b101 => (
+                    bits(inst, 12, 10, 3) | bits(inst, 9, 7, 6),
+                    funct3::FSD,
+                    opcodes::OP_STORE_FP,
+                ),
+                0b110 => (
+                    bits(inst, 12, 9, 2) | bits(inst, 8, 7, 6),
+                    funct3::SW,
+                    opcodes::OP_STORE,
+                ),
+                _ => (
+                    bits(inst, 12, 10, 3) | bits(inst, 9, 7, 6),
+                    funct3::SD,
+                    opcodes::OP_STORE,
+                ),
+            };
+            s_type(imm, rs2, 2, funct3, opcode)
+        }
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+/// Extracts `inst[hi:lo]` and moves it to start at bit `to`.
+fn bits(inst: u32, hi: u32, lo: u32, to: u32) -> u32 {
+    ((inst >> lo) & ((1 << (hi - lo + 1)) - 1)) << to
+}
+
+fn sign_extend(value: u32, width: u32) -> u32 {
+    (((value << (32 - width)) as i32) >> (32 - width)) as u32
+}
+
+/// The offset of `c.lw` and `c.sw`: `uimm[5:3|2|6]`.
+fn lw_offset(inst: u32) -> u32 {
+    bits(inst, 12, 10, 3) | bits(inst, 6, 6, 2) | bits(inst, 5, 5, 6)
+}
+
+/// The offset of `c.ld`, `c.sd`, `c.fld` and `c.fsd`: `uimm[5:3|7:6]`.
+fn ld_offset(inst: u32) -> u32 {
+    bits(inst, 12, 10, 3) | bits(inst, 6, 5, 6)
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    ((imm >> 5) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | ((imm & 0x1F) << 7) | opcode
+}
+
+fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm_hi = (((imm >> 12) & 1) << 6) | ((imm >> 5) & 0x3F);
+    let imm_lo = (((imm >> 1) & 0xF) << 1) | ((imm >> 11) & 1);
+    (imm_hi << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (imm_lo << 7) | opcodes::OP_BRANCH
+}
+
+fn j_type(imm: u32, rd: u32) -> u32 {
+    let encoded = (((imm >> 20) & 1) << 19)
+        | (((imm >> 1) & 0x3FF) << 9)
+        | (((imm >> 11) & 1) << 8)
+        | ((imm >> 12) & 0xFF);
+    (encoded << 12) | (rd << 7) | opcodes::OP_JAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_matches_reference_encodings() {
+        // Each pair is a compressed instruction and its expansion, as produced by GNU as.
+        let cases: [(u16, u32); 16] = [
+            (0x0001, 0x00000013), // c.nop
+            (0x0505, 0x00150513), // c.addi a0, 1
+            (0x557d, 0xfff00513), // c.li a0, -1
+            (0x0028, 0x00810513), // c.addi4spn a0, sp, 8
+            (0x7179, 0xfd010113), // c.addi16sp sp, -48
+            (0x6505, 0x00001537), // c.lui a0, 1
+            (0x6188, 0x0005b503), // c.ld a0, 0(a1)
+            (0xc188, 0x00a5a023), // c.sw a0, 0(a1)
+            (0x8d0d, 0x40b50533), // c.sub a0, a1
+            (0x9d2d, 0x00b5053b), // c.addw a0, a1
+            (0x852e, 0x00b00533), // c.mv a0, a1
+            (0x952e, 0x00b50533), // c.add a0, a1
+            (0x8082, 0x00008067), // c.jr ra
+            (0x9002, 0x00100073), // c.ebreak
+            (0xe406, 0x00113423), // c.sdsp ra, 8(sp)
+            (0x60a2, 0x00813083), // c.ldsp ra, 8(sp)
+        ];
+        for (compressed, expanded) in cases {
+            assert_eq!(expand(compressed), Some(expanded), "{:#06x}", compressed);
+        }
+    }
+
+    #[test]
+    fn test_expand_control_flow_and_reserved_encodings() {
+        assert_eq!(expand(0xa001), Some(0x0000006f)); // c.j .
+        assert_eq!(expand(0xbffd), Some(0xfffff06f)); // c.j .-2
+        assert_eq!(expand(0xc111), Some(0x00050263)); // c.beqz a0, .+4
+        assert_eq!(expand(0xfd6d), Some(0xfe051de3)); // c.bnez a0, .-6
+
+        assert_eq!(expand(0x0000), None); // the all-zero halfword is defined illegal
+        assert_eq!(expand(0x6101), None); // c.addi16sp with a zero immediate
+        assert_eq!(expand(0x8002), None); // c.jr zero
+        assert_eq!(expand(0x2001), None); // c.addiw zero
+        assert_eq!(expand(0x4002), None); // c.lwsp zero
+        assert_eq!(instruction_length(0x0505), 2);
+        assert_eq!(instruction_length(0x0513), 4);
+    }
+}
@@ -1,6 +1,8 @@
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+pub mod compressed;
+
 pub mod opcodes {
     pub const OP_LOAD: u32 = 0b0000011;
     pub const OP_MISC_MEM: u32 = 0b0001111;
@@ -23,6 +25,7 @@ pub mod opcodes {
     pub const OP_MSUB: u32 = 0b1000111;
     pub const OP_NMSUB: u32 = 0b1001011;
     pub const OP_NMADD: u32 = 0b1001111;
+    pub const OP_V: u32 = 0b1010111;
 }
 
 pub mod funct3 {
@@ -78,6 +81,30 @@ pub mod funct3 {
     pub const AMO_D: u32 = 0b011;
 
     pub const FP_OPS: u32 = 0b000;
+
+    pub const FLW: u32 = 0b010;
+    pub const FLD: u32 = 0b011;
+    pub const FSW: u32 = 0b010;
+    pub const FSD: u32 = 0b011;
+
+    /// The dynamic rounding mode: use `frm`.
+    pub const RM_DYN: u32 = 0b111;
+
+    pub const SH1ADD: u32 = 0b010;
+    pub const SH2ADD: u32 = 0b100;
+    pub const SH3ADD: u32 = 0b110;
+    pub const MIN: u32 = 0b100;
+    pub const MINU: u32 = 0b101;
+    pub const MAX: u32 = 0b110;
+    pub const MAXU: u32 = 0b111;
+    pub const CLMUL: u32 = 0b001;
+    pub const CLMULR: u32 = 0b010;
+    pub const CLMULH: u32 = 0b011;
+    pub const ZEXT_H: u32 = 0b100;
+    pub const PACK: u32 = 0b100;
+    pub const PACKH: u32 = 0b111;
+    pub const XPERM4: u32 = 0b010;
+    pub const XPERM8: u32 = 0b100;
 }
 
 pub mod funct7 {
@@ -106,6 +133,182 @@ pub mod funct7 {
     pub const AMOMAXU: u32 = 0b11100;
 
     pub const SFENCE_VMA: u32 = 0b0001001;
+
+    // OP_FP: funct7 is one of these in bits 6:2 and the format in bits 1:0.
+    pub const FADD: u32 = 0b00000;
+    pub const FSUB: u32 = 0b00001;
+    pub const FMUL: u32 = 0b00010;
+    pub const FDIV: u32 = 0b00011;
+    pub const FSGNJ: u32 = 0b00100;
+    pub const FMIN_MAX: u32 = 0b00101;
+    pub const FCVT_FMT_FMT: u32 = 0b01000;
+    pub const FSQRT: u32 = 0b01011;
+    pub const FCMP: u32 = 0b10100;
+    pub const FCVT_INT_FMT: u32 = 0b11000;
+    pub const FCVT_FMT_INT: u32 = 0b11010;
+    pub const FMV_X_FMT_FCLASS: u32 = 0b11100;
+    pub const FMV_FMT_X: u32 = 0b11110;
+
+    pub const FMT_S: u32 = 0b00;
+    pub const FMT_D: u32 = 0b01;
+
+    // Bit manipulation. The immediate forms reuse these in bits 31:25, with bit 25 taken by
+    // the top bit of a 6-bit shift amount.
+    pub const SHADD: u32 = 0b0010000;
+    pub const ADD_UW: u32 = 0b0000100;
+    pub const ANDN_ORN_XNOR: u32 = 0b0100000;
+    pub const MINMAX_CLMUL: u32 = 0b0000101;
+    pub const ROTATE: u32 = 0b0110000;
+    pub const BSET: u32 = 0b0010100;
+    pub const BCLR_BEXT: u32 = 0b0100100;
+    pub const BINV: u32 = 0b0110100;
+    pub const PACK: u32 = 0b0000100;
+    pub const XPERM: u32 = 0b0010100;
+}
+
+/// The unary bit-manipulation instructions, identified by their whole 12-bit immediate field.
+pub mod bitmanip {
+    pub const FUNCT12_CLZ: u32 = 0x600;
+    pub const FUNCT12_CTZ: u32 = 0x601;
+    pub const FUNCT12_CPOP: u32 = 0x602;
+    pub const FUNCT12_SEXT_B: u32 = 0x604;
+    pub const FUNCT12_SEXT_H: u32 = 0x605;
+    pub const FUNCT12_REV8: u32 = 0x6B8;
+    pub const FUNCT12_ORC_B: u32 = 0x287;
+    /// `zext.h` is `OP_REG_32` with funct7 `ADD_UW`, funct3 `ZEXT_H` and rs2 zero.
+    pub const FUNCT12_ZEXT_H: u32 = 0x080;
+    pub const FUNCT12_BREV8: u32 = 0x687;
+}
+
+/// The scalar cryptography instructions. The register forms are `OP_REG` with funct3 zero; the
+/// unary forms are `OP_IMM` with funct3 `SLL` and are identified by their 12-bit immediate.
+pub mod crypto {
+    pub const FUNCT7_AES64ES: u32 = 0b0011001;
+    pub const FUNCT7_AES64ESM: u32 = 0b0011011;
+    pub const FUNCT7_AES64DS: u32 = 0b0011101;
+    pub const FUNCT7_AES64DSM: u32 = 0b0011111;
+    pub const FUNCT7_AES64KS2: u32 = 0b0111111;
+    /// `aes64ks1i` has this in bits 31:24 and the round number in bits 23:20.
+    pub const FUNCT8_AES64KS1I: u32 = 0b00110001;
+    /// `sm4ed` and `sm4ks` have this in bits 29:25 and the byte select in bits 31:30.
+    pub const FUNCT5_SM4ED: u32 = 0b11000;
+    pub const FUNCT5_SM4KS: u32 = 0b11010;
+
+    pub const FUNCT12_AES64IM: u32 = 0x300;
+    pub const FUNCT12_SHA256SUM0: u32 = 0x100;
+    pub const FUNCT12_SHA256SUM1: u32 = 0x101;
+    pub const FUNCT12_SHA256SIG0: u32 = 0x102;
+    pub const FUNCT12_SHA256SIG1: u32 = 0x103;
+    pub const FUNCT12_SHA512SUM0: u32 = 0x104;
+    pub const FUNCT12_SHA512SUM1: u32 = 0x105;
+    pub const FUNCT12_SHA512SIG0: u32 = 0x106;
+    pub const FUNCT12_SHA512SIG1: u32 = 0x107;
+    pub const FUNCT12_SM3P0: u32 = 0x108;
+    pub const FUNCT12_SM3P1: u32 = 0x109;
+
+    /// The highest round number `aes64ks1i` accepts.
+    pub const AES64KS1I_MAX_RNUM: u32 = 0xA;
+}
+
+/// The vector extension. Arithmetic is `OP_V` with the operand category in funct3 and the
+/// operation in funct6 (bits 31:26). Loads and stores share `OP_LOAD_FP` and `OP_STORE_FP`,
+/// told apart from scalar FP accesses by their width field.
+pub mod vector {
+    pub const OPIVV: u32 = 0b000;
+    pub const OPMVV: u32 = 0b010;
+    pub const OPIVI: u32 = 0b011;
+    pub const OPIVX: u32 = 0b100;
+    pub const OPMVX: u32 = 0b110;
+    pub const OPCFG: u32 = 0b111;
+
+    // The width field of a vector load or store.
+    pub const WIDTH_8: u32 = 0b000;
+    pub const WIDTH_16: u32 = 0b101;
+    pub const WIDTH_32: u32 = 0b110;
+    pub const WIDTH_64: u32 = 0b111;
+
+    // The addressing mode in bits 27:26 of a load or store.
+    pub const MOP_UNIT_STRIDE: u32 = 0b00;
+    pub const MOP_STRIDED: u32 = 0b10;
+    /// The rs2 field of a unit-stride access that moves a whole mask register (`vlm.v`, `vsm.v`).
+    pub const UMOP_MASK: u32 = 0b01011;
+
+    // OPIVV, OPIVX and OPIVI.
+    pub const VADD: u32 = 0b000000;
+    pub const VSUB: u32 = 0b000010;
+    pub const VRSUB: u32 = 0b000011;
+    pub const VMINU: u32 = 0b000100;
+    pub const VMIN: u32 = 0b000101;
+    pub const VMAXU: u32 = 0b000110;
+    pub const VMAX: u32 = 0b000111;
+    pub const VAND: u32 = 0b001001;
+    pub const VOR: u32 = 0b001010;
+    pub const VXOR: u32 = 0b001011;
+    /// `vmerge` when masked, `vmv.v` when not.
+    pub const VMERGE_VMV: u32 = 0b010111;
+    pub const VMSEQ: u32 = 0b011000;
+    pub const VMSNE: u32 = 0b011001;
+    pub const VMSLTU: u32 = 0b011010;
+    pub const VMSLT: u32 = 0b011011;
+    pub const VMSLEU: u32 = 0b011100;
+    pub const VMSLE: u32 = 0b011101;
+    pub const VMSGTU: u32 = 0b011110;
+    pub const VMSGT: u32 = 0b011111;
+    pub const VSLL: u32 = 0b100101;
+    pub const VSRL: u32 = 0b101000;
+    pub const VSRA: u32 = 0b101001;
+
+    // OPMVV and OPMVX.
+    pub const VREDSUM: u32 = 0b000000;
+    pub const VREDAND: u32 = 0b000001;
+    pub const VREDOR: u32 = 0b000010;
+    pub const VREDXOR: u32 = 0b000011;
+    pub const VREDMINU: u32 = 0b000100;
+    pub const VREDMIN: u32 = 0b000101;
+    pub const VREDMAXU: u32 = 0b000110;
+    pub const VREDMAX: u32 = 0b000111;
+    /// `vmv.x.s`, `vcpop.m` and `vfirst.m` as OPMVV, told apart by vs1; `vmv.s.x` as OPMVX.
+    pub const VWXUNARY0: u32 = 0b010000;
+    /// `vmsbf.m`, `vmsof.m`, `vmsif.m`, `viota.m` and `vid.v`, told apart by vs1.
+    pub const VMUNARY0: u32 = 0b010100;
+    pub const VMANDN: u32 = 0b011000;
+    pub const VMAND: u32 = 0b011001;
+    pub const VMOR: u32 = 0b011010;
+    pub const VMXOR: u32 = 0b011011;
+    pub const VMORN: u32 = 0b011100;
+    pub const VMNAND: u32 = 0b011101;
+    pub const VMNOR: u32 = 0b011110;
+    pub const VMXNOR: u32 = 0b011111;
+    pub const VDIVU: u32 = 0b100000;
+    pub const VDIV: u32 = 0b100001;
+    pub const VREMU: u32 = 0b100010;
+    pub const VREM: u32 = 0b100011;
+    pub const VMULHU: u32 = 0b100100;
+    pub const VMUL: u32 = 0b100101;
+    pub const VMULHSU: u32 = 0b100110;
+    pub const VMULH: u32 = 0b100111;
+    pub const VMADD: u32 = 0b101001;
+    pub const VNMSUB: u32 = 0b101011;
+    pub const VMACC: u32 = 0b101101;
+    pub const VNMSAC: u32 = 0b101111;
+
+    // The vs1 field of VWXUNARY0 and VMUNARY0.
+    pub const VS1_VMV_X_S: u32 = 0b00000;
+    pub const VS1_VCPOP: u32 = 0b10000;
+    pub const VS1_VFIRST: u32 = 0b10001;
+    pub const VS1_VMSBF: u32 = 0b00001;
+    pub const VS1_VMSOF: u32 = 0b00010;
+    pub const VS1_VMSIF: u32 = 0b00011;
+    pub const VS1_VIOTA: u32 = 0b10000;
+    pub const VS1_VID: u32 = 0b10001;
+
+    // vtype.
+    pub const VTYPE_VLMUL: u64 = 0b111;
+    pub const VTYPE_VSEW_SHIFT: u64 = 3;
+    pub const VTYPE_VSEW: u64 = 0b111 << VTYPE_VSEW_SHIFT;
+    pub const VTYPE_VTA: u64 = 1 << 6;
+    pub const VTYPE_VMA: u64 = 1 << 7;
+    pub const VTYPE_VILL: u64 = 1 << 63;
 }
 
 pub mod system {
@@ -136,6 +339,7 @@ pub mod cause {
     pub const LOAD_PAGE_FAULT: u64 = 13;
     pub const STORE_AMO_PAGE_FAULT: u64 = 15;
 
+    #[allow(clippy::identity_op)]
     pub const USER_SOFTWARE_INTERRUPT: u64 = INTERRUPT_BIT | 0;
     pub const SUPERVISOR_SOFTWARE_INTERRUPT: u64 = INTERRUPT_BIT | 1;
     pub const MACHINE_SOFTWARE_INTERRUPT: u64 = INTERRUPT_BIT | 3;
@@ -145,6 +349,8 @@ pub mod cause {
     pub const USER_EXTERNAL_INTERRUPT: u64 = INTERRUPT_BIT | 8;
     pub const SUPERVISOR_EXTERNAL_INTERRUPT: u64 = INTERRUPT_BIT | 9;
     pub const MACHINE_EXTERNAL_INTERRUPT: u64 = INTERRUPT_BIT | 11;
+    /// The Sscofpmf local counter-overflow interrupt.
+    pub const COUNTER_OVERFLOW_INTERRUPT: u64 = INTERRUPT_BIT | 13;
 }
 
 pub mod csr {
@@ -159,6 +365,14 @@ pub mod csr {
     pub const FFLAGS: u32 = 0x001;
     pub const FRM: u32 = 0x002;
     pub const FCSR: u32 = 0x003;
+    pub const SEED: u32 = 0x015;
+    pub const VSTART: u32 = 0x008;
+    pub const VXSAT: u32 = 0x009;
+    pub const VXRM: u32 = 0x00A;
+    pub const VCSR: u32 = 0x00F;
+    pub const VL: u32 = 0xC20;
+    pub const VTYPE: u32 = 0xC21;
+    pub const VLENB: u32 = 0xC22;
     pub const CYCLE: u32 = 0xC00;
     pub const TIME: u32 = 0xC01;
     pub const INSTRET: u32 = 0xC02;
@@ -190,24 +404,42 @@ pub mod csr {
     pub const MIE: u32 = 0x304;
     pub const MTVEC: u32 = 0x305;
     pub const MCOUNTEREN: u32 = 0x306;
+    pub const MCOUNTINHIBIT: u32 = 0x320;
+    pub const MSTATUSH: u32 = 0x310;
     pub const MSCRATCH: u32 = 0x340;
     pub const MEPC: u32 = 0x341;
     pub const MCAUSE: u32 = 0x342;
     pub const MTVAL: u32 = 0x343;
     pub const MIP: u32 = 0x344;
+    pub const MSECCFG: u32 = 0x747;
 
     pub const PMPCFG0: u32 = 0x3A0;
     pub const PMPCFG1: u32 = 0x3A1;
     pub const PMPCFG2: u32 = 0x3A2;
     pub const PMPCFG3: u32 = 0x3A3;
+    pub const PMPCFG15: u32 = 0x3AF;
 
     pub const PMPADDR0: u32 = 0x3B0;
     pub const PMPADDR1: u32 = 0x3B1;
+    pub const PMPADDR63: u32 = 0x3EF;
 
     pub const MCYCLE: u32 = 0xB00;
     pub const MINSTRET: u32 = 0xB02;
     pub const MCYCLEH: u32 = 0xB80;
     pub const MINSTRETH: u32 = 0xB82;
+    pub const MHPMCOUNTER3: u32 = 0xB03;
+    pub const MHPMCOUNTER31: u32 = 0xB1F;
+    pub const MHPMCOUNTER3H: u32 = 0xB83;
+    pub const MHPMCOUNTER31H: u32 = 0xB9F;
+    pub const HPMCOUNTER3: u32 = 0xC03;
+    pub const HPMCOUNTER31: u32 = 0xC1F;
+    pub const HPMCOUNTER3H: u32 = 0xC83;
+    pub const HPMCOUNTER31H: u32 = 0xC9F;
+    pub const MHPMEVENT3: u32 = 0x323;
+    pub const MHPMEVENT31: u32 = 0x33F;
+    pub const MHPMEVENT3H: u32 = 0x723;
+    pub const MHPMEVENT31H: u32 = 0x73F;
+    pub const SCOUNTOVF: u32 = 0xDA0;
 
     pub const TSELECT: u32 = 0x7A0;
     pub const TDATA1: u32 = 0x7A1;
@@ -0,0 +1,313 @@
+use crate::isa::Extension;
+use crate::VM;
+use riscv_core::{bitmanip, cause, funct3, funct7, opcodes};
+
+const ZBA: &[Extension] = &[Extension::Zba];
+const ZBB: &[Extension] = &[Extension::Zbb];
+const ZBC: &[Extension] = &[Extension::Zbc];
+const ZBS: &[Extension] = &[Extension::Zbs];
+const ZBKB: &[Extension] = &[Extension::Zbkb];
+const ZBKX: &[Extension] = &[Extension::Zbkx];
+/// Zbkb and Zbkc repeat some Zbb and Zbc instructions for use by cryptography code.
+const ZBB_OR_ZBKB: &[Extension] = &[Extension::Zbb, Extension::Zbkb];
+const ZBC_OR_ZBKC: &[Extension] = &[Extension::Zbc, Extension::Zbkc];
+
+/// The carry-less product of `a` and `b`, as a 128-bit value.
+fn clmul(a: u64, b: u64) -> u128 {
+    (0..64)
+        .filter(|i| (b >> i) & 1 == 1)
+        .fold(0, |product, i| product ^ ((a as u128) << i))
+}
+
+/// Sets every byte of `value` that is not zero to 0xFF.
+fn orc_b(value: u64) -> u64 {
+    (0..8)
+        .map(|byte| 0xFFu64 << (byte * 8))
+        .filter(|&mask| value & mask != 0)
+        .fold(0, |result, mask| result | mask)
+}
+
+/// Reverses the bits within each byte of `value`.
+fn brev8(value: u64) -> u64 {
+    u64::from_le_bytes(value.to_le_bytes().map(u8::reverse_bits))
+}
+
+/// Replaces each `width`-bit element of `indices` with the element of `table` it selects,
+/// or zero if the index is out of range.
+fn xperm(table: u64, indices: u64, width: u32) -> u64 {
+    let mask = (1 << width) - 1;
+    (0..64).step_by(width as usize).fold(0, |result, position| {
+        let index = (indices >> position) & mask;
+        let element = if index * (width as u64) < 64 {
+            (table >> (index * width as u64)) & mask
+        } else {
+            0
+        };
+        result | (element << position)
+    })
+}
+
+fn sext_w(value: u32) -> u64 {
+    value as i32 as i64 as u64
+}
+
+impl VM {
+    /// Executes a bit-manipulation or scalar cryptography instruction. Encodings that belong
+    /// to neither, or whose extensions are all disabled, raise an illegal instruction
+    /// exception.
+    pub(crate) fn execute_bitmanip(&mut self, inst: u32, next_pc: u64) -> bool {
+        let decoded = self
+            .bitmanip_result(inst)
+            .or_else(|| self.crypto_result(inst));
+        match decoded {
+            Some((extensions, result))
+                if extensions
+                    .iter()
+                    .any(|&extension| self.extension_enabled(extension)) =>
+            {
+                let rd = ((inst >> 7) & 0x1F) as usize;
+                if rd > 0 {
+                    self.registers[rd] = result;
+                }
+                self.pc = next_pc;
+                true
+            }
+            _ => self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+        }
+    }
+
+    /// Decodes `inst` as a Zba, Zbb, Zbc, Zbs, Zbkb or Zbkx instruction and computes its
+    /// result, tagged with the extensions that provide it.
+    fn bitmanip_result(&self, inst: u32) -> Option<(&'static [Extension], u64)> {
+        let opcode = inst & 0x7F;
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = self.registers[((inst >> 15) & 0x1F) as usize];
+        let rs2 = self.registers[((inst >> 20) & 0x1F) as usize];
+        let funct7 = (inst >> 25) & 0x7F;
+        let funct12 = inst >> 20;
+        // The immediate shifts use bit 25 for the top of the shift amount.
+        let funct6 = (inst >> 26) << 1;
+        let shamt = (inst >> 20) & 0x3F;
+        let index = rs2 & 0x3F;
+
+        let result = match opcode {
+            opcodes::OP_REG => match (funct7, funct3) {
+                (funct7::SHADD, funct3::SH1ADD) => (ZBA, (rs1 << 1).wrapping_add(rs2)),
+                (funct7::SHADD, funct3::SH2ADD) => (ZBA, (rs1 << 2).wrapping_add(rs2)),
+                (funct7::SHADD, funct3::SH3ADD) => (ZBA, (rs1 << 3).wrapping_add(rs2)),
+
+                (funct7::ANDN_ORN_XNOR, funct3::AND) => (ZBB_OR_ZBKB, rs1 & !rs2),
+                (funct7::ANDN_ORN_XNOR, funct3::OR) => (ZBB_OR_ZBKB, rs1 | !rs2),
+                (funct7::ANDN_ORN_XNOR, funct3::XOR) => (ZBB_OR_ZBKB, !(rs1 ^ rs2)),
+                (funct7::MINMAX_CLMUL, funct3::MIN) => (ZBB, (rs1 as i64).min(rs2 as i64) as u64),
+                (funct7::MINMAX_CLMUL, funct3::MINU) => (ZBB, rs1.min(rs2)),
+                (funct7::MINMAX_CLMUL, funct3::MAX) => (ZBB, (rs1 as i64).max(rs2 as i64) as u64),
+                (funct7::MINMAX_CLMUL, funct3::MAXU) => (ZBB, rs1.max(rs2)),
+                (funct7::ROTATE, funct3::SLL) => (ZBB_OR_ZBKB, rs1.rotate_left(index as u32)),
+                (funct7::ROTATE, funct3::SRL_SRA) => (ZBB_OR_ZBKB, rs1.rotate_right(index as u32)),
+
+                (funct7::MINMAX_CLMUL, funct3::CLMUL) => (ZBC_OR_ZBKC, clmul(rs1, rs2) as u64),
+                (funct7::MINMAX_CLMUL, funct3::CLMULH) => {
+                    (ZBC_OR_ZBKC, (clmul(rs1, rs2) >> 64) as u64)
+                }
+                (funct7::MINMAX_CLMUL, funct3::CLMULR) => (ZBC, (clmul(rs1, rs2) >> 63) as u64),
+
+                (funct7::BSET, funct3::SLL) => (ZBS, rs1 | (1 << index)),
+                (funct7::BCLR_BEXT, funct3::SLL) => (ZBS, rs1 & !(1 << index)),
+                (funct7::BINV, funct3::SLL) => (ZBS, rs1 ^ (1 << index)),
+                (funct7::BCLR_BEXT, funct3::SRL_SRA) => (ZBS, (rs1 >> index) & 1),
+
+                (funct7::PACK, funct3::PACK) => (ZBKB, (rs2 << 32) | (rs1 & 0xFFFF_FFFF)),
+                (funct7::PACK, funct3::PACKH) => (ZBKB, ((rs2 & 0xFF) << 8) | (rs1 & 0xFF)),
+                (funct7::XPERM, funct3::XPERM4) => (ZBKX, xperm(rs1, rs2, 4)),
+                (funct7::XPERM, funct3::XPERM8) => (ZBKX, xperm(rs1, rs2, 8)),
+                _ => return None,
+            },
+            opcodes::OP_REG_32 => {
+                let uw = rs1 & 0xFFFF_FFFF;
+                match (funct7, funct3) {
+                    (funct7::ADD_UW, funct3::ADD_SUB) => (ZBA, uw.wrapping_add(rs2)),
+                    (funct7::SHADD, funct3::SH1ADD) => (ZBA, (uw << 1).wrapping_add(rs2)),
+                    (funct7::SHADD, funct3::SH2ADD) => (ZBA, (uw << 2).wrapping_add(rs2)),
+                    (funct7::SHADD, funct3::SH3ADD) => (ZBA, (uw << 3).wrapping_add(rs2)),
+                    // zext.h is packw with rs2 = zero.
+                    (funct7::PACK, funct3::PACK) => {
+                        let extensions = if funct12 == bitmanip::FUNCT12_ZEXT_H {
+                            ZBB_OR_ZBKB
+                        } else {
+                            ZBKB
+                        };
+                        (
+                            extensions,
+                            sext_w(((rs2 as u32) << 16) | (rs1 as u16 as u32)),
+                        )
+                    }
+                    (funct7::ROTATE, funct3::SLL) => (
+                        ZBB_OR_ZBKB,
+                        sext_w((rs1 as u32).rotate_left((rs2 & 0x1F) as u32)),
+                    ),
+                    (funct7::ROTATE, funct3::SRL_SRA) => (
+                        ZBB_OR_ZBKB,
+                        sext_w((rs1 as u32).rotate_right((rs2 & 0x1F) as u32)),
+                    ),
+                    _ => return None,
+                }
+            }
+            opcodes::OP_IMM => match (funct3, funct12) {
+                (funct3::SLL, bitmanip::FUNCT12_CLZ) => (ZBB, rs1.leading_zeros() as u64),
+                (funct3::SLL, bitmanip::FUNCT12_CTZ) => (ZBB, rs1.trailing_zeros() as u64),
+                (funct3::SLL, bitmanip::FUNCT12_CPOP) => (ZBB, rs1.count_ones() as u64),
+                (funct3::SLL, bitmanip::FUNCT12_SEXT_B) => (ZBB, rs1 as i8 as i64 as u64),
+                (funct3::SLL, bitmanip::FUNCT12_SEXT_H) => (ZBB, rs1 as i16 as i64 as u64),
+                (funct3::SRL_SRA, bitmanip::FUNCT12_REV8) => (ZBB_OR_ZBKB, rs1.swap_bytes()),
+                (funct3::SRL_SRA, bitmanip::FUNCT12_ORC_B) => (ZBB, orc_b(rs1)),
+                (funct3::SRL_SRA, bitmanip::FUNCT12_BREV8) => (ZBKB, brev8(rs1)),
+                _ => match (funct6, funct3) {
+                    (funct7::ROTATE, funct3::SRL_SRA) => (ZBB_OR_ZBKB, rs1.rotate_right(shamt)),
+                    (funct7::BSET, funct3::SLL) => (ZBS, rs1 | (1 << shamt)),
+                    (funct7::BCLR_BEXT, funct3::SLL) => (ZBS, rs1 & !(1 << shamt)),
+                    (funct7::BINV, funct3::SLL) => (ZBS, rs1 ^ (1 << shamt)),
+                    (funct7::BCLR_BEXT, funct3::SRL_SRA) => (ZBS, (rs1 >> shamt) & 1),
+                    _ => return None,
+                },
+            },
+            opcodes::OP_IMM_32 => match (funct3, funct12) {
+                (funct3::SLL, bitmanip::FUNCT12_CLZ) => (ZBB, (rs1 as u32).leading_zeros() as u64),
+                (funct3::SLL, bitmanip::FUNCT12_CTZ) => (ZBB, (rs1 as u32).trailing_zeros() as u64),
+                (funct3::SLL, bitmanip::FUNCT12_CPOP) => (ZBB, (rs1 as u32).count_ones() as u64),
+                _ => match (funct6, funct7, funct3) {
+                    (funct7::ADD_UW, _, funct3::SLL) => (ZBA, (rs1 & 0xFFFF_FFFF) << shamt),
+                    (_, funct7::ROTATE, funct3::SRL_SRA) => {
+                        (ZBB_OR_ZBKB, sext_w((rs1 as u32).rotate_right(shamt & 0x1F)))
+                    }
+                    _ => return None,
+                },
+            },
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+    use riscv_core::csr;
+
+    /// Runs `inst` with a0 = `a`, a1 = `b` and returns the value it leaves in a2.
+    fn run(vm: &mut VM, inst: u32, a: u64, b: u64) -> u64 {
+        vm.bus.write(BASE_ADDRESS, 4, inst as u64);
+        vm.pc = BASE_ADDRESS;
+        vm.registers[10] = a;
+        vm.registers[11] = b;
+        vm.registers[12] = 0;
+        assert_eq!(vm.step(), None);
+        vm.registers[12]
+    }
+
+    #[test]
+    fn test_bitmanip_results() {
+        let mut vm = VM::new();
+        // sh2add a2, a0, a1
+        assert_eq!(run(&mut vm, 0x20B54633, 3, 100), 112);
+        // add.uw a2, a0, a1
+        assert_eq!(run(&mut vm, 0x08B5063B, u64::MAX, 1), 0x1_0000_0000);
+        // slli.uw a2, a0, 4
+        assert_eq!(
+            run(&mut vm, 0x0845161B, 0xFFFF_FFFF_8000_0001, 0),
+            0x8_0000_0010
+        );
+        // andn a2, a0, a1
+        assert_eq!(run(&mut vm, 0x40B57633, 0b1111, 0b0101), 0b1010);
+        // clz, ctz and cpop a2, a0
+        assert_eq!(run(&mut vm, 0x60051613, 1 << 40, 0), 23);
+        assert_eq!(run(&mut vm, 0x60151613, 1 << 40, 0), 40);
+        assert_eq!(run(&mut vm, 0x60251613, 0xF0F0, 0), 8);
+        // clzw a2, a0
+        assert_eq!(run(&mut vm, 0x6005161B, 0xFFFF_FFFF_0000_0100, 0), 23);
+        // min and maxu a2, a0, a1
+        assert_eq!(run(&mut vm, 0x0AB54633, -5i64 as u64, 3), -5i64 as u64);
+        assert_eq!(run(&mut vm, 0x0AB57633, -5i64 as u64, 3), -5i64 as u64);
+        // sext.b, rev8 and orc.b a2, a0
+        assert_eq!(run(&mut vm, 0x60451613, 0x80, 0), 0xFFFF_FFFF_FFFF_FF80);
+        assert_eq!(
+            run(&mut vm, 0x6B855613, 0x0102_0304_0506_0708, 0),
+            0x0807_0605_0403_0201
+        );
+        assert_eq!(
+            run(&mut vm, 0x28755613, 0x0100_2000_0000_0003, 0),
+            0xFF00_FF00_0000_00FF
+        );
+        // rorw a2, a0, a1
+        assert_eq!(run(&mut vm, 0x60B5563B, 1, 1), 0xFFFF_FFFF_8000_0000);
+        // clmul and clmulh a2, a0, a1
+        assert_eq!(run(&mut vm, 0x0AB51633, 0b11, 0b11), 0b101);
+        assert_eq!(run(&mut vm, 0x0AB53633, 1 << 63, 0b110), 0b11);
+        // bset a2, a0, a1 and bexti a2, a0, 63
+        assert_eq!(run(&mut vm, 0x28B51633, 0, 65), 0b10);
+        assert_eq!(run(&mut vm, 0x4BF55613, 1 << 63, 0), 1);
+        // pack a2, a0, a1, brev8 a2, a0 and xperm8 a2, a0, a1
+        assert_eq!(run(&mut vm, 0x08B54633, u64::MAX, 2), 0x2_FFFF_FFFF);
+        assert_eq!(run(&mut vm, 0x68755613, 0x0180, 0), 0x8001);
+        assert_eq!(
+            run(&mut vm, 0x28B54633, 0x1122, 0xFFFF_FFFF_0900_FF01),
+            0x22_0011
+        );
+    }
+
+    #[test]
+    fn test_disabled_bitmanip_extension_is_illegal() {
+        let mut vm = VM::new_config(VmConfig {
+            isa: "rv64imafdc_zbb".parse().unwrap(),
+            ..VmConfig::default()
+        });
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        // clmul a2, a0, a1
+        run(&mut vm, 0x0AB51633, 3, 3);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+
+        // Clearing misa.B switches Zba, Zbb and Zbs off.
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        let misa = vm.csrs.read(csr::MISA, 3).unwrap();
+        vm.csrs.write(csr::MISA, misa & !(1 << 1), 3);
+        // cpop a2, a0
+        run(&mut vm, 0x60251613, 0xFF, 0);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+        assert_eq!(vm.csrs.read(csr::MISA, 3), Some(misa & !(1 << 1)));
+    }
+
+    #[test]
+    fn test_x0_destination_still_traps_on_unknown_encodings() {
+        let mut vm = VM::new_config(VmConfig {
+            isa: "rv64imafdc".parse().unwrap(),
+            ..VmConfig::default()
+        });
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        // clz x0, a0 without Zbb
+        run(&mut vm, 0x60051013, 1, 0);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+
+        // An OP encoding with a reserved funct7 and rd = x0.
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        run(&mut vm, 0xFEB50033, 1, 2);
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(0xFEB50033));
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+
+        // A known instruction writing x0 retires without changing it.
+        run(&mut vm, 0x00B50033, 1, 2); // add x0, a0, a1
+        assert_eq!(vm.pc, BASE_ADDRESS + 4);
+        assert_eq!(vm.registers[0], 0);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::memory::BASE_ADDRESS;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A memory-mapped device. Offsets are relative to the base address the device was
+/// registered at, and `size` is the access width in bytes (1, 2, 4 or 8).
+pub trait Device {
+    /// Returns `None` if the device does not accept this access, which the CPU reports as an
+    /// access fault.
+    fn read(&mut self, offset: u64, size: u64) -> Option<u64>;
+
+    /// Returns `false` if the device does not accept this access.
+    fn write(&mut self, offset: u64, size: u64, value: u64) -> bool;
+
+    /// Advances the device by one step of the run loop. `ram` is physical memory starting at
+    /// `BASE_ADDRESS`, for devices that do DMA.
+    fn tick(&mut self, _ram: &mut [u8]) {}
+
+    /// Whether the device is asserting its interrupt line.
+    fn interrupt_pending(&self) -> bool {
+        false
+    }
+}
+
+struct Mapping {
+    base: u64,
+    size: u64,
+    irq: Option<u32>,
+    device: Rc<RefCell<dyn Device>>,
+}
+
+/// The physical address space: RAM at `BASE_ADDRESS` plus every registered device.
+pub struct Bus {
+    pub ram: Vec<u8>,
+    mappings: Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            ram: vec![0; ram_size],
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Maps `device` at `[base, base + size)`. If `irq` is given, the device's interrupt line
+    /// is wired to that PLIC source.
+    pub fn register(
+        &mut self,
+        base: u64,
+        size: u64,
+        irq: Option<u32>,
+        device: Rc<RefCell<dyn Device>>,
+    ) {
+        self.mappings.push(Mapping {
+            base,
+            size,
+            irq,
+            device,
+        });
+    }
+
+    /// Offset into `ram` of an access, if it lies entirely within RAM.
+    fn ram_offset(&self, paddr: u64, size: u64) -> Option<usize> {
+        let offset = paddr.checked_sub(BASE_ADDRESS)?;
+        let end = offset.checked_add(size)?;
+        if end <= self.ram.len() as u64 {
+            Some(offset as usize)
+        } else {
+            None
+        }
+    }
+
+    fn mapping(&self, paddr: u64, size: u64) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .find(|m| paddr >= m.base && paddr - m.base + size <= m.size)
+    }
+
+    pub fn read(&mut self, paddr: u64, size: u64) -> Option<u64> {
+        if let Some(offset) = self.ram_offset(paddr, size) {
+            let mut bytes = [0u8; 8];
+            bytes[..size as usize].copy_from_slice(&self.ram[offset..offset + size as usize]);
+            return Some(u64::from_le_bytes(bytes));
+        }
+        let mapping = self.mapping(paddr, size)?;
+        let offset = paddr - mapping.base;
+        mapping.device.borrow_mut().read(offset, size)
+    }
+
+    pub fn write(&mut self, paddr: u64, size: u64, value: u64) -> bool {
+        if let Some(offset) = self.ram_offset(paddr, size) {
+            self.ram[offset..offset + size as usize]
+                .copy_from_slice(&value.to_le_bytes()[..size as usize]);
+            return true;
+        }
+        match self.mapping(paddr, size) {
+            Some(mapping) => {
+                let offset = paddr - mapping.base;
+                mapping.device.borrow_mut().write(offset, size, value)
+            }
+            None => false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for mapping in &self.mappings {
+            mapping.device.borrow_mut().tick(&mut self.ram);
+        }
+    }
+
+    /// The PLIC source and current level of every device with a wired interrupt line.
+    pub fn interrupt_lines(&self) -> impl Iterator<Item = (u32, bool)> + '_ {
+        self.mappings.iter().filter_map(|mapping| {
+            let irq = mapping.irq?;
+            Some((irq, mapping.device.borrow().interrupt_pending()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Scratch {
+        value: u64,
+    }
+
+    impl Device for Scratch {
+        fn read(&mut self, offset: u64, _size: u64) -> Option<u64> {
+            if offset == 0 {
+                Some(self.value)
+            } else {
+                None
+            }
+        }
+
+        fn write(&mut self, offset: u64, _size: u64, value: u64) -> bool {
+            self.value = value;
+            offset == 0
+        }
+    }
+
+    #[test]
+    fn test_ram_accesses_are_little_endian() {
+        let mut bus = Bus::new(4096);
+        assert!(bus.write(BASE_ADDRESS + 8, 4, 0x1122_3344));
+        assert_eq!(bus.read(BASE_ADDRESS + 8, 1), Some(0x44));
+        assert_eq!(bus.read(BASE_ADDRESS + 8, 8), Some(0x1122_3344));
+        assert_eq!(bus.read(BASE_ADDRESS + 4092, 8), None);
+        assert_eq!(bus.read(BASE_ADDRESS - 1, 1), None);
+    }
+
+    #[test]
+    fn test_registered_device_receives_offsets() {
+        let mut bus = Bus::new(4096);
+        let scratch = Rc::new(RefCell::new(Scratch { value: 0 }));
+        bus.register(0x1000_0000, 0x100, None, scratch.clone());
+
+        assert!(bus.write(0x1000_0000, 8, 42));
+        assert_eq!(scratch.borrow().value, 42);
+        assert_eq!(bus.read(0x1000_0000, 8), Some(42));
+        assert_eq!(bus.read(0x1000_0008, 8), None);
+        assert_eq!(bus.read(0x1000_00FC, 8), None);
+        assert_eq!(bus.read(0x2000_0000, 4), None);
+    }
+}
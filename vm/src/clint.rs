@@ -0,0 +1,192 @@
+use crate::bus::Device;
+use bincode::{Decode, Encode};
+use riscv_core::cause;
+use std::time::Instant;
+
+pub const CLINT_BASE_ADDRESS: u64 = 0x0200_0000;
+pub const CLINT_SIZE: u64 = 0x10000;
+
+const MSIP_OFFSET: u64 = 0x0000;
+const MTIMECMP_OFFSET: u64 = 0x4000;
+const MTIME_OFFSET: u64 = 0xBFF8;
+
+/// Rate at which `mtime` advances when it follows host time, matching QEMU's virt machine.
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+pub const MIP_MSIP: u64 = 1 << (cause::MACHINE_SOFTWARE_INTERRUPT & !cause::INTERRUPT_BIT);
+pub const MIP_MTIP: u64 = 1 << (cause::MACHINE_TIMER_INTERRUPT & !cause::INTERRUPT_BIT);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimerSource {
+    /// `mtime` advances by one for every retired instruction, so runs are reproducible.
+    #[default]
+    Instructions,
+    /// `mtime` follows the host's monotonic clock at `TIMEBASE_FREQUENCY`.
+    HostTime,
+}
+
+/// The guest-visible registers of a `Clint`, as saved in a snapshot.
+#[derive(Encode, Decode)]
+pub struct ClintState {
+    pub msip: u32,
+    pub mtimecmp: u64,
+    pub mtime: u64,
+}
+
+/// Core-local interruptor for a single hart: the `msip` software interrupt register and the
+/// `mtime`/`mtimecmp` machine timer.
+pub struct Clint {
+    pub msip: u32,
+    pub mtimecmp: u64,
+    pub mtime: u64,
+    source: TimerSource,
+    host_epoch: Instant,
+    mtime_at_epoch: u64,
+}
+
+impl Clint {
+    pub fn new(source: TimerSource) -> Self {
+        Self {
+            msip: 0,
+            mtimecmp: u64::MAX,
+            mtime: 0,
+            source,
+            host_epoch: Instant::now(),
+            mtime_at_epoch: 0,
+        }
+    }
+
+    /// Advances `mtime`. Called once per step of the run loop.
+    pub fn advance(&mut self) {
+        match self.source {
+            TimerSource::Instructions => self.mtime = self.mtime.wrapping_add(1),
+            TimerSource::HostTime => {
+                let elapsed = self.host_epoch.elapsed();
+                let ticks = elapsed.as_nanos() * TIMEBASE_FREQUENCY as u128 / 1_000_000_000;
+                self.mtime = self.mtime_at_epoch.wrapping_add(ticks as u64);
+            }
+        }
+    }
+
+    pub fn save_state(&self) -> ClintState {
+        ClintState {
+            msip: self.msip,
+            mtimecmp: self.mtimecmp,
+            mtime: self.mtime,
+        }
+    }
+
+    /// Restores saved registers. With `TimerSource::HostTime`, `mtime` resumes counting from
+    /// the saved value.
+    pub fn restore_state(&mut self, state: ClintState) {
+        self.msip = state.msip;
+        self.mtimecmp = state.mtimecmp;
+        self.mtime = state.mtime;
+        self.host_epoch = Instant::now();
+        self.mtime_at_epoch = state.mtime;
+    }
+
+    /// The MSIP and MTIP bits this device is currently driving into `mip`.
+    pub fn mip_bits(&self) -> u64 {
+        let mut bits = 0;
+        if self.msip & 1 != 0 {
+            bits |= MIP_MSIP;
+        }
+        if self.mtime >= self.mtimecmp {
+            bits |= MIP_MTIP;
+        }
+        bits
+    }
+}
+
+impl Device for Clint {
+    fn read(&mut self, offset: u64, size: u64) -> Option<u64> {
+        let (reg_offset, value) = match offset {
+            MSIP_OFFSET..=0x3 => (MSIP_OFFSET, self.msip as u64),
+            MTIMECMP_OFFSET..=0x4007 => (MTIMECMP_OFFSET, self.mtimecmp),
+            MTIME_OFFSET..=0xBFFF => (MTIME_OFFSET, self.mtime),
+            _ => return Some(0),
+        };
+        let shift = (offset - reg_offset) * 8;
+        Some((value >> shift) & size_mask(size))
+    }
+
+    fn write(&mut self, offset: u64, size: u64, value: u64) -> bool {
+        match offset {
+            MSIP_OFFSET..=0x3 => {
+                let merged = merge(self.msip as u64, offset - MSIP_OFFSET, size, value);
+                self.msip = (merged & 1) as u32;
+            }
+            MTIMECMP_OFFSET..=0x4007 => {
+                self.mtimecmp = merge(self.mtimecmp, offset - MTIMECMP_OFFSET, size, value);
+            }
+            MTIME_OFFSET..=0xBFFF => {
+                self.mtime = merge(self.mtime, offset - MTIME_OFFSET, size, value);
+                self.host_epoch = Instant::now();
+                self.mtime_at_epoch = self.mtime;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn tick(&mut self, _ram: &mut [u8]) {
+        self.advance();
+    }
+}
+
+fn size_mask(size: u64) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1u64 << (size * 8)) - 1
+    }
+}
+
+/// Replaces `size` bytes of `current`, starting `byte_offset` bytes in, with `value`.
+fn merge(current: u64, byte_offset: u64, size: u64, value: u64) -> u64 {
+    let shift = byte_offset * 8;
+    let mask = size_mask(size) << shift;
+    (current & !mask) | ((value << shift) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timer_interrupt_follows_mtimecmp() {
+        let mut clint = Clint::new(TimerSource::Instructions);
+        assert_eq!(clint.mip_bits(), 0);
+
+        clint.write(MTIMECMP_OFFSET, 8, 3);
+        clint.advance();
+        clint.advance();
+        assert_eq!(clint.mip_bits() & MIP_MTIP, 0);
+        clint.advance();
+        assert_eq!(clint.mip_bits() & MIP_MTIP, MIP_MTIP);
+
+        clint.write(MTIMECMP_OFFSET, 8, 100);
+        assert_eq!(clint.mip_bits() & MIP_MTIP, 0);
+    }
+
+    #[test]
+    fn test_word_accesses_to_64_bit_registers() {
+        let mut clint = Clint::new(TimerSource::Instructions);
+        clint.write(MTIMECMP_OFFSET, 4, 0xdead_beef);
+        clint.write(MTIMECMP_OFFSET + 4, 4, 0x1234_5678);
+        assert_eq!(clint.mtimecmp, 0x1234_5678_dead_beef);
+        assert_eq!(clint.read(MTIMECMP_OFFSET + 4, 4), Some(0x1234_5678));
+        assert_eq!(clint.read(MTIMECMP_OFFSET, 8), Some(0x1234_5678_dead_beef));
+    }
+
+    #[test]
+    fn test_msip_raises_software_interrupt() {
+        let mut clint = Clint::new(TimerSource::Instructions);
+        clint.write(MSIP_OFFSET, 4, 1);
+        assert_eq!(clint.mip_bits(), MIP_MSIP);
+        assert_eq!(clint.read(MSIP_OFFSET, 4), Some(1));
+        clint.write(MSIP_OFFSET, 4, 0);
+        assert_eq!(clint.mip_bits(), 0);
+    }
+}
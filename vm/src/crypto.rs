@@ -0,0 +1,379 @@
+use crate::isa::Extension;
+use crate::VM;
+use riscv_core::{crypto, funct3, opcodes};
+
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const AES_INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+    0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+    0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+    0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+    0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+    0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+    0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+    0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+    0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+    0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+    0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+    0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+    0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+    0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+    0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+];
+
+const SM4_SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48,
+];
+
+/// The AES round constants used by `aes64ks1i`, indexed by round number.
+const AES_RCON: [u32; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// Multiplies two elements of GF(2^8) modulo the AES polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+fn sub_word(word: u32, sbox: &[u8; 256]) -> u32 {
+    u32::from_le_bytes(word.to_le_bytes().map(|byte| sbox[byte as usize]))
+}
+
+/// Applies MixColumns, or its inverse, to one 32-bit column.
+fn mix_column(column: u32, inverse: bool) -> u32 {
+    let coefficients: [u8; 4] = if inverse {
+        [0x0e, 0x0b, 0x0d, 0x09]
+    } else {
+        [0x02, 0x03, 0x01, 0x01]
+    };
+    let bytes = column.to_le_bytes();
+    let mixed: [u8; 4] = std::array::from_fn(|row| {
+        (0..4).fold(0, |sum, i| {
+            sum ^ gf_mul(bytes[(row + i) % 4], coefficients[i])
+        })
+    });
+    u32::from_le_bytes(mixed)
+}
+
+fn mix_columns(half: u64, inverse: bool) -> u64 {
+    let low = mix_column(half as u32, inverse) as u64;
+    let high = mix_column((half >> 32) as u32, inverse) as u64;
+    (high << 32) | low
+}
+
+/// Returns the first two columns of the AES state held in `rs1` (bytes 0-7) and `rs2`
+/// (bytes 8-15) after ShiftRows or InvShiftRows, with every byte passed through `sbox`.
+fn shift_rows_and_substitute(rs1: u64, rs2: u64, inverse: bool, sbox: &[u8; 256]) -> u64 {
+    let state = ((rs2 as u128) << 64) | rs1 as u128;
+    (0..8).fold(0, |result, index| {
+        let (row, column) = (index % 4, index / 4);
+        let source_column = if inverse {
+            (column + 4 - row) % 4
+        } else {
+            (column + row) % 4
+        };
+        let byte = (state >> (8 * (row + 4 * source_column))) as u8;
+        result | (sbox[byte as usize] as u64) << (8 * index)
+    })
+}
+
+fn sext_w(value: u32) -> u64 {
+    value as i32 as i64 as u64
+}
+
+/// The SM4 round function's linear transformation L, applied by `sm4ed`.
+fn sm4_ed_linear(x: u32) -> u32 {
+    x ^ x.rotate_left(2) ^ x.rotate_left(10) ^ x.rotate_left(18) ^ x.rotate_left(24)
+}
+
+/// The SM4 key schedule's linear transformation L', applied by `sm4ks`.
+fn sm4_ks_linear(x: u32) -> u32 {
+    x ^ x.rotate_left(13) ^ x.rotate_left(23)
+}
+
+impl VM {
+    /// Decodes `inst` as a scalar cryptography instruction from Zkne, Zknd, Zknh, Zksed or
+    /// Zksh and computes its result, tagged with the extensions that provide it.
+    pub(crate) fn crypto_result(&self, inst: u32) -> Option<(&'static [Extension], u64)> {
+        let opcode = inst & 0x7F;
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = self.registers[((inst >> 15) & 0x1F) as usize];
+        let rs2 = self.registers[((inst >> 20) & 0x1F) as usize];
+        let funct7 = (inst >> 25) & 0x7F;
+        let funct12 = inst >> 20;
+
+        let result = match (opcode, funct3) {
+            (opcodes::OP_REG, funct3::ADD_SUB) => match funct7 {
+                crypto::FUNCT7_AES64ES => (
+                    &[Extension::Zkne][..],
+                    shift_rows_and_substitute(rs1, rs2, false, &AES_SBOX),
+                ),
+                crypto::FUNCT7_AES64ESM => {
+                    let substituted = shift_rows_and_substitute(rs1, rs2, false, &AES_SBOX);
+                    (&[Extension::Zkne][..], mix_columns(substituted, false))
+                }
+                crypto::FUNCT7_AES64DS => (
+                    &[Extension::Zknd][..],
+                    shift_rows_and_substitute(rs1, rs2, true, &AES_INV_SBOX),
+                ),
+                crypto::FUNCT7_AES64DSM => {
+                    let substituted = shift_rows_and_substitute(rs1, rs2, true, &AES_INV_SBOX);
+                    (&[Extension::Zknd][..], mix_columns(substituted, true))
+                }
+                crypto::FUNCT7_AES64KS2 => {
+                    let w0 = (rs1 >> 32) as u32 ^ rs2 as u32;
+                    let w1 = w0 ^ (rs2 >> 32) as u32;
+                    (
+                        &[Extension::Zkne, Extension::Zknd][..],
+                        ((w1 as u64) << 32) | w0 as u64,
+                    )
+                }
+                _ => {
+                    let byte_select = funct7 >> 5;
+                    let shamt = byte_select * 8;
+                    let x = SM4_SBOX[(rs2 >> shamt) as u8 as usize] as u32;
+                    let linear = match funct7 & 0x1F {
+                        crypto::FUNCT5_SM4ED => sm4_ed_linear(x),
+                        crypto::FUNCT5_SM4KS => sm4_ks_linear(x),
+                        _ => return None,
+                    };
+                    (
+                        &[Extension::Zksed][..],
+                        sext_w(linear.rotate_left(shamt) ^ rs1 as u32),
+                    )
+                }
+            },
+            (opcodes::OP_IMM, funct3::SLL) => {
+                if funct12 >> 4 == crypto::FUNCT8_AES64KS1I {
+                    let rnum = funct12 & 0xF;
+                    if rnum > crypto::AES64KS1I_MAX_RNUM {
+                        return None;
+                    }
+                    let word = (rs1 >> 32) as u32;
+                    let mut temp = if rnum == crypto::AES64KS1I_MAX_RNUM {
+                        sub_word(word, &AES_SBOX)
+                    } else {
+                        sub_word(word.rotate_right(8), &AES_SBOX)
+                    };
+                    if let Some(&rcon) = AES_RCON.get(rnum as usize) {
+                        temp ^= rcon;
+                    }
+                    return Some((
+                        &[Extension::Zkne, Extension::Zknd],
+                        ((temp as u64) << 32) | temp as u64,
+                    ));
+                }
+
+                let x = rs1 as u32;
+                match funct12 {
+                    crypto::FUNCT12_AES64IM => (&[Extension::Zknd][..], mix_columns(rs1, true)),
+                    crypto::FUNCT12_SHA256SIG0 => (
+                        &[Extension::Zknh][..],
+                        sext_w(x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3)),
+                    ),
+                    crypto::FUNCT12_SHA256SIG1 => (
+                        &[Extension::Zknh][..],
+                        sext_w(x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10)),
+                    ),
+                    crypto::FUNCT12_SHA256SUM0 => (
+                        &[Extension::Zknh][..],
+                        sext_w(x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)),
+                    ),
+                    crypto::FUNCT12_SHA256SUM1 => (
+                        &[Extension::Zknh][..],
+                        sext_w(x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)),
+                    ),
+                    crypto::FUNCT12_SHA512SIG0 => (
+                        &[Extension::Zknh][..],
+                        rs1.rotate_right(1) ^ rs1.rotate_right(8) ^ (rs1 >> 7),
+                    ),
+                    crypto::FUNCT12_SHA512SIG1 => (
+                        &[Extension::Zknh][..],
+                        rs1.rotate_right(19) ^ rs1.rotate_right(61) ^ (rs1 >> 6),
+                    ),
+                    crypto::FUNCT12_SHA512SUM0 => (
+                        &[Extension::Zknh][..],
+                        rs1.rotate_right(28) ^ rs1.rotate_right(34) ^ rs1.rotate_right(39),
+                    ),
+                    crypto::FUNCT12_SHA512SUM1 => (
+                        &[Extension::Zknh][..],
+                        rs1.rotate_right(14) ^ rs1.rotate_right(18) ^ rs1.rotate_right(41),
+                    ),
+                    crypto::FUNCT12_SM3P0 => (
+                        &[Extension::Zksh][..],
+                        sext_w(x ^ x.rotate_left(9) ^ x.rotate_left(17)),
+                    ),
+                    crypto::FUNCT12_SM3P1 => (
+                        &[Extension::Zksh][..],
+                        sext_w(x ^ x.rotate_left(15) ^ x.rotate_left(23)),
+                    ),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aes64es(rs1: u64, rs2: u64) -> u64 {
+        shift_rows_and_substitute(rs1, rs2, false, &AES_SBOX)
+    }
+
+    fn aes64ds(rs1: u64, rs2: u64) -> u64 {
+        shift_rows_and_substitute(rs1, rs2, true, &AES_INV_SBOX)
+    }
+
+    /// Runs one of the crypto instructions through `crypto_result`, with rs1 = a0 and rs2 = a1.
+    fn run(vm: &mut VM, inst: u32, rs1: u64, rs2: u64) -> u64 {
+        vm.registers[10] = rs1;
+        vm.registers[11] = rs2;
+        vm.crypto_result(inst).unwrap().1
+    }
+
+    #[test]
+    fn test_aes128_round_trip_matches_fips_197() {
+        let mut vm = VM::new();
+        // aes64ks1i a2, a0, rnum; aes64ks2 a2, a0, a1; aes64esm/aes64dsm a2, a0, a1;
+        // aes64im a2, a0
+        let ks1i = |rnum: u32| 0x31051613 | (rnum << 20);
+        let (ks2, esm, dsm, im) = (0x7eb50633, 0x36b50633, 0x3eb50633, 0x30051613);
+
+        let mut round_keys = vec![(0x0706050403020100u64, 0x0f0e0d0c0b0a0908u64)];
+        for rnum in 0..10 {
+            let (k0, k1) = *round_keys.last().unwrap();
+            let temp = run(&mut vm, ks1i(rnum), k1, 0);
+            let k0 = run(&mut vm, ks2, temp, k0);
+            let k1 = run(&mut vm, ks2, k0, k1);
+            round_keys.push((k0, k1));
+        }
+
+        let (mut low, mut high) = (0x7766554433221100u64, 0xffeeddccbbaa9988u64);
+        low ^= round_keys[0].0;
+        high ^= round_keys[0].1;
+        for (round, &(k0, k1)) in round_keys.iter().enumerate().skip(1) {
+            (low, high) = if round == 10 {
+                (aes64es(low, high), aes64es(high, low))
+            } else {
+                (run(&mut vm, esm, low, high), run(&mut vm, esm, high, low))
+            };
+            low ^= k0;
+            high ^= k1;
+        }
+        assert_eq!(
+            (low, high),
+            (0x30047b6ad8e0c469, 0x5ac5b47080b7cdd8),
+            "ciphertext 69c4e0d86a7b0430d8cdb78070b4c55a"
+        );
+
+        low ^= round_keys[10].0;
+        high ^= round_keys[10].1;
+        for round in (0..10).rev() {
+            let (k0, k1) = round_keys[round];
+            (low, high) = if round == 0 {
+                (aes64ds(low, high) ^ k0, aes64ds(high, low) ^ k1)
+            } else {
+                let (low, high) = (run(&mut vm, dsm, low, high), run(&mut vm, dsm, high, low));
+                (
+                    low ^ run(&mut vm, im, k0, 0),
+                    high ^ run(&mut vm, im, k1, 0),
+                )
+            };
+        }
+        assert_eq!((low, high), (0x7766554433221100, 0xffeeddccbbaa9988));
+    }
+
+    #[test]
+    fn test_sm4_encryption_matches_reference_vector() {
+        let mut vm = VM::new();
+        // sm4ks and sm4ed a2, a0, a1, bs
+        let sm4 = |funct5: u32, bs: u32| (bs << 30) | (funct5 << 25) | 0x00b50633;
+        let t = |vm: &mut VM, funct5: u32, mut acc: u64, x: u64| {
+            for bs in 0..4 {
+                acc = run(vm, sm4(funct5, bs), acc, x);
+            }
+            acc as u32
+        };
+
+        let key = [0x01234567u32, 0x89abcdef, 0xfedcba98, 0x76543210];
+        let fk = [0xa3b1bac6u32, 0x56aa3350, 0x677d9197, 0xb27022dc];
+        let mut k: Vec<u32> = (0..4).map(|i| key[i] ^ fk[i]).collect();
+        for i in 0..32 {
+            let ck = u32::from_be_bytes(std::array::from_fn(|j| ((4 * i + j) * 7) as u8));
+            let x = k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck;
+            let next = t(&mut vm, crypto::FUNCT5_SM4KS, k[i] as u64, x as u64);
+            k.push(next);
+        }
+
+        let mut x = key.to_vec();
+        for i in 0..32 {
+            let input = x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4];
+            let next = t(&mut vm, crypto::FUNCT5_SM4ED, x[i] as u64, input as u64);
+            x.push(next);
+        }
+        assert_eq!(
+            [x[35], x[34], x[33], x[32]],
+            [0x681edf34, 0xd206965e, 0x86b3e94f, 0x536e4246]
+        );
+    }
+
+    #[test]
+    fn test_sha2_and_sm3_functions() {
+        let mut vm = VM::new();
+        // sha256sig0, sha512sum1 and sm3p0 a2, a0
+        assert_eq!(run(&mut vm, 0x10251613, 0x80000000, 0), 0x11002000);
+        assert_eq!(
+            run(&mut vm, 0x10551613, 1, 0),
+            (1 << 50) | (1 << 46) | (1 << 23)
+        );
+        assert_eq!(run(&mut vm, 0x10851613, 1, 0), 0x20201);
+        // Results of the 32-bit functions are sign-extended.
+        assert_eq!(run(&mut vm, 0x10851613, 0x4000, 0), 0xFFFFFFFF80804000);
+        // aes64ks1i with a round number above 10 is reserved.
+        assert!(vm.crypto_result(0x31B51613).is_none());
+    }
+}
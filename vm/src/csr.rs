@@ -1,105 +1,493 @@
+use crate::hpm::{Hpm, HpmEvent};
+use crate::isa::{misa_bit, Xlen};
+use crate::mmu::SatpMode;
+use crate::plic::MIP_SEIP;
+use crate::pmp::Pmp;
+use bincode::{Decode, Encode};
 use riscv_core::csr;
+use riscv_core::vector::VTYPE_VILL;
 use std::collections::HashMap;
 
-// For now, this is a simplified mask. A full implementation would be more granular.
-const MSTATUS_MASK: u64 = 0x00000003_00001888;
-pub const SATP_MODE_SV39: u64 = 8 << 60;
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_MPP_SHIFT: u64 = 11;
+pub const MSTATUS_MPP: u64 = 0b11 << MSTATUS_MPP_SHIFT;
+pub const MSTATUS_FS_SHIFT: u64 = 13;
+pub const MSTATUS_FS: u64 = 0b11 << MSTATUS_FS_SHIFT;
+pub const MSTATUS_FS_DIRTY: u64 = 0b11 << MSTATUS_FS_SHIFT;
+pub const MSTATUS_VS_SHIFT: u64 = 9;
+pub const MSTATUS_VS: u64 = 0b11 << MSTATUS_VS_SHIFT;
+pub const MSTATUS_VS_DIRTY: u64 = 0b11 << MSTATUS_VS_SHIFT;
+/// Loads and stores in M-mode are translated and checked as if at the privilege in MPP.
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+/// Trap S-mode accesses to satp and `sfence.vma`.
+pub const MSTATUS_TVM: u64 = 1 << 20;
+/// S-mode may load and store through user pages.
+pub const MSTATUS_SUM: u64 = 1 << 18;
+/// Loads may read pages that are executable but not readable.
+pub const MSTATUS_MXR: u64 = 1 << 19;
+/// Trap `wfi` in S-mode.
+pub const MSTATUS_TW: u64 = 1 << 21;
+/// Trap `sret` in S-mode.
+pub const MSTATUS_TSR: u64 = 1 << 22;
+/// Read-only summary bit, set while FS or VS is Dirty.
+pub const MSTATUS_SD: u64 = 1 << 63;
+/// UXL and SXL, hardwired to 2 (64-bit) on RV64.
+const MSTATUS_XL_64: u64 = (2 << 32) | (2 << 34);
+// The mstatus fields software can change. XS, UXL, SXL and the endianness controls are
+// hardwired and SD is computed on read.
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE
+    | MSTATUS_MIE
+    | MSTATUS_SPIE
+    | MSTATUS_MPIE
+    | MSTATUS_SPP
+    | MSTATUS_VS
+    | MSTATUS_MPP
+    | MSTATUS_FS
+    | MSTATUS_MPRV
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_TVM
+    | MSTATUS_TW
+    | MSTATUS_TSR;
+
+pub const FFLAGS_MASK: u64 = 0x1F;
+pub const FRM_SHIFT: u64 = 5;
+pub const FCSR_MASK: u64 = 0xFF;
+
+pub const VCSR_VXSAT: u64 = 1;
+pub const VCSR_VXRM_SHIFT: u64 = 1;
+pub const VCSR_MASK: u64 = 0b111;
+
+// The subset of mstatus visible through sstatus: SIE, SPIE, UBE, SPP, VS, FS, XS, SUM, MXR,
+// UXL and SD.
+const SSTATUS_MASK: u64 = 0x80000003_000DE762;
+
+/// Whether U-mode and S-mode may access the Zkr `seed` CSR.
+pub const MSECCFG_USEED: u64 = 1 << 8;
+pub const MSECCFG_SSEED: u64 = 1 << 9;
+
+/// The Sscofpmf counter-overflow interrupt, raised when a programmable counter wraps.
+pub const MIP_LCOFIP: u64 = 1 << 13;
+/// The interrupt bits in mip and mie that S-mode can own: its software, timer and external
+/// interrupts and the counter-overflow interrupt, followed by those of M-mode.
+const S_INTERRUPTS: u64 = (1 << 1) | (1 << 5) | (1 << 9) | MIP_LCOFIP;
+const M_INTERRUPTS: u64 = (1 << 3) | (1 << 7) | (1 << 11);
+/// The exceptions medeleg can delegate: every implemented cause except an ecall from
+/// M-mode, which can never be taken below M-mode.
+const MEDELEG_WRITABLE: u64 = 0xB3FF;
+/// The bits of cycle, time and instret in mcounteren, scounteren and mcountinhibit. The
+/// programmable counters follow at the bit given by the low five bits of their CSR address.
+pub const COUNTER_CY: u64 = 1 << 0;
+pub const COUNTER_TM: u64 = 1 << 1;
+pub const COUNTER_IR: u64 = 1 << 2;
+const COUNTEREN_WRITABLE: u64 = 0xFFFF_FFFF;
+/// time is driven by the CLINT, so it cannot be inhibited.
+const COUNTINHIBIT_WRITABLE: u64 = COUNTEREN_WRITABLE & !COUNTER_TM;
+
+pub const TVEC_MODE_MASK: u64 = 0b11;
+pub const TVEC_MODE_VECTORED: u64 = 1;
+pub const SATP_MODE_SHIFT: u64 = 60;
+pub const SATP_MODE_SV39: u64 = 8 << SATP_MODE_SHIFT;
+pub const SATP_MODE_SV48: u64 = 9 << SATP_MODE_SHIFT;
+pub const SATP_MODE_SV57: u64 = 10 << SATP_MODE_SHIFT;
 pub const SATP_ASID_MASK: u64 = 0xFFFF << 44;
 pub const SATP_PPN_MASK: u64 = (1u64 << 44) - 1;
+/// The RV32 satp layout: MODE in bit 31, ASID in bits 30:22 and the root PPN below.
+pub const SATP32_MODE_SV32: u64 = 1 << 31;
+pub const SATP32_ASID_MASK: u64 = 0x1FF << 22;
+pub const SATP32_PPN_MASK: u64 = (1u64 << 22) - 1;
+
+/// Distance from a counter CSR to the RV32 CSR holding its upper half, e.g. `cycleh`.
+const COUNTER_HIGH_OFFSET: u32 = 0x80;
+
+/// Which harts implement a CSR.
+#[derive(Clone, Copy)]
+enum Presence {
+    Always,
+    /// The upper halves of 64-bit registers, which only RV32 splits off.
+    Rv32,
+    /// Needs the extension with this misa letter.
+    Extension(char),
+}
+
+/// A row of the CSR table: whether the CSR exists, whether it can be written at all and
+/// which bits a write may change. Legalization of individual WARL fields beyond the mask
+/// happens in `CsrFile::write`.
+#[derive(Clone, Copy)]
+struct CsrSpec {
+    presence: Presence,
+    read_only: bool,
+    writable: u64,
+}
+
+const fn rw(presence: Presence, writable: u64) -> CsrSpec {
+    CsrSpec {
+        presence,
+        read_only: false,
+        writable,
+    }
+}
+
+const fn ro(presence: Presence) -> CsrSpec {
+    CsrSpec {
+        presence,
+        read_only: true,
+        writable: 0,
+    }
+}
+
+/// The CSRs this hart implements. Anything missing here does not exist, and accessing it
+/// is an illegal instruction.
+fn csr_spec(addr: u32) -> Option<CsrSpec> {
+    use Presence::{Always, Extension, Rv32};
+    const F: Presence = Extension('f');
+    const V: Presence = Extension('v');
+    const S: Presence = Extension('s');
+
+    Some(match addr {
+        csr::FFLAGS => rw(F, FFLAGS_MASK),
+        csr::FRM => rw(F, FCSR_MASK >> FRM_SHIFT),
+        csr::FCSR => rw(F, FCSR_MASK),
+
+        csr::VSTART => rw(V, !0),
+        csr::VXSAT => rw(V, VCSR_VXSAT),
+        csr::VXRM => rw(V, VCSR_MASK >> VCSR_VXRM_SHIFT),
+        csr::VCSR => rw(V, VCSR_MASK),
+        // vl and vtype only change through vsetvl{i}, and vlenb is fixed.
+        csr::VL | csr::VTYPE | csr::VLENB => ro(V),
+
+        csr::CYCLE | csr::TIME | csr::INSTRET => ro(Always),
+        csr::CYCLEH | csr::TIMEH | csr::INSTRETH => ro(Rv32),
+        csr::HPMCOUNTER3..=csr::HPMCOUNTER31 => ro(Always),
+        csr::HPMCOUNTER3H..=csr::HPMCOUNTER31H => ro(Rv32),
+
+        csr::SSTATUS => rw(S, SSTATUS_MASK),
+        csr::SIE => rw(S, S_INTERRUPTS),
+        csr::STVEC => rw(S, !0),
+        csr::SCOUNTEREN => rw(S, COUNTEREN_WRITABLE),
+        csr::SSCRATCH | csr::SCAUSE | csr::STVAL => rw(S, !0),
+        // Bit 0 of the exception PCs is hardwired to zero; without C, IALIGN is 32 and
+        // `epc_mask` clears bit 1 too.
+        csr::SEPC => rw(S, !1),
+        // Only the software and counter-overflow interrupts can be changed from S-mode; the
+        // timer and external bits are driven by M-mode and the interrupt controllers.
+        csr::SIP => rw(S, (1 << 1) | MIP_LCOFIP),
+        csr::SATP => rw(S, !0),
+        csr::SCOUNTOVF => ro(S),
+
+        csr::MVENDORID | csr::MARCHID | csr::MIMPID | csr::MHARTID => ro(Always),
+        csr::MSTATUS => rw(Always, MSTATUS_WRITABLE),
+        csr::MISA => rw(Always, !0),
+        csr::MEDELEG => rw(S, MEDELEG_WRITABLE),
+        csr::MIDELEG => rw(S, S_INTERRUPTS),
+        csr::MIE => rw(Always, S_INTERRUPTS | M_INTERRUPTS),
+        csr::MTVEC => rw(Always, !0),
+        csr::MCOUNTEREN => rw(Always, COUNTEREN_WRITABLE),
+        csr::MCOUNTINHIBIT => rw(Always, COUNTINHIBIT_WRITABLE),
+        // The endianness controls in mstatush are hardwired to little-endian.
+        csr::MSTATUSH => rw(Rv32, 0),
+        csr::MSCRATCH | csr::MCAUSE | csr::MTVAL => rw(Always, !0),
+        csr::MEPC => rw(Always, !1),
+        // The M-mode pending bits reflect the CLINT and PLIC; M-mode software can only
+        // raise the S-mode ones.
+        csr::MIP => rw(Always, S_INTERRUPTS),
+        csr::MSECCFG => rw(Always, MSECCFG_USEED | MSECCFG_SSEED),
+
+        // RV64 packs eight entries into each even pmpcfg; the odd ones do not exist.
+        csr::PMPCFG0..=csr::PMPCFG15 if addr % 2 == 1 => rw(Rv32, !0),
+        csr::PMPCFG0..=csr::PMPCFG15 | csr::PMPADDR0..=csr::PMPADDR63 => rw(Always, !0),
 
+        csr::MCYCLE | csr::MINSTRET => rw(Always, !0),
+        csr::MCYCLEH | csr::MINSTRETH => rw(Rv32, !0),
+        csr::MHPMCOUNTER3..=csr::MHPMCOUNTER31 | csr::MHPMEVENT3..=csr::MHPMEVENT31 => {
+            rw(Always, !0)
+        }
+        csr::MHPMCOUNTER3H..=csr::MHPMCOUNTER31H | csr::MHPMEVENT3H..=csr::MHPMEVENT31H => {
+            rw(Rv32, !0)
+        }
+
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Encode, Decode)]
 pub struct CsrFile {
     pub mstatus: u64,
     pub mie: u64,
+    /// The pending bits held in the CSR itself. SEIP here is the software-written bit; the
+    /// PLIC's line is kept in `seip_line` and the two are ORed on read.
     pub mip: u64,
+    pub seip_line: bool,
     pub mepc: u64,
     pub mcause: u64,
     pub mtval: u64,
     pub mscratch: u64,
     pub mtvec: u64,
     pub satp: u64,
+    /// Accrued exception flags in bits 4:0 and the dynamic rounding mode in bits 7:5.
+    pub fcsr: u64,
+    /// The first element a vector instruction executes, left non-zero by a trap part-way
+    /// through a vector load or store.
+    pub vstart: u64,
+    pub vl: u64,
+    pub vtype: u64,
+    /// The fixed-point saturation flag in bit 0 and rounding mode in bits 2:1.
+    pub vcsr: u64,
+    /// The vector register length in bytes.
+    vlenb: u64,
+    /// Mirrors the CLINT's `mtime` for reads of the `time` CSR.
+    pub time: u64,
+    /// The cycle and retired-instruction counters, advanced by `tick_counters`.
+    pub mcycle: u64,
+    pub minstret: u64,
+    mcountinhibit: u64,
+    /// The programmable counters behind mhpmcounter3..31 and mhpmevent3..31.
+    pub hpm: Hpm,
+    /// The counters that must not advance at the end of this step: ones the instruction
+    /// wrote, so the next instruction reads back the written value, and minstret when the
+    /// instruction trapped instead of retiring.
+    held_counters: u64,
+    pub misa: u64,
+    /// The width of the hart, which decides whether the RV32-only CSRs exist.
+    xlen: Xlen,
+    /// The largest RV64 paging mode satp accepts.
+    max_satp_mode: SatpMode,
+    /// The physical memory protection entries behind the pmpcfg and pmpaddr CSRs.
+    pub pmp: Pmp,
+    /// The misa bits software may toggle: extensions that are implemented but can be
+    /// switched off.
+    misa_writable: u64,
     other_csrs: HashMap<u32, u64>,
 }
 
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CsrFile {
     pub fn new() -> Self {
-        let mut other_csrs = HashMap::new();
-
-        other_csrs.insert(csr::MEDELEG, 0);
-        other_csrs.insert(csr::MIDELEG, 0);
-
-        other_csrs.insert(csr::SEDELEG, 0);
-        other_csrs.insert(csr::SIDELEG, 0);
-
         Self {
             mstatus: 0,
             mie: 0,
             mip: 0,
+            seip_line: false,
             mepc: 0,
             mcause: 0,
             mtval: 0,
             mscratch: 0,
             mtvec: 0,
             satp: 0,
-            other_csrs,
+            fcsr: 0,
+            vstart: 0,
+            vl: 0,
+            vtype: VTYPE_VILL,
+            vcsr: 0,
+            vlenb: 0,
+            time: 0,
+            mcycle: 0,
+            minstret: 0,
+            mcountinhibit: 0,
+            hpm: Hpm::default(),
+            held_counters: 0,
+            misa: 0,
+            xlen: Xlen::Rv64,
+            max_satp_mode: SatpMode::default(),
+            pmp: Pmp::default(),
+            misa_writable: 0,
+            other_csrs: HashMap::new(),
         }
     }
 
     pub fn read(&self, addr: u32, privilege_level: u8) -> Option<u64> {
-        let required_priv = (addr >> 8) & 0x3;
-        if privilege_level < required_priv as u8 {
-            return None;
-        }
+        self.accessible(addr, privilege_level)?;
 
         match addr {
-            csr::MSTATUS => Some(self.mstatus),
+            csr::FFLAGS | csr::FRM | csr::FCSR if !self.fp_enabled() => None,
+            csr::FFLAGS => Some(self.fcsr & FFLAGS_MASK),
+            csr::FRM => Some(self.fcsr >> FRM_SHIFT),
+            csr::FCSR => Some(self.fcsr),
+
+            csr::VSTART
+            | csr::VXSAT
+            | csr::VXRM
+            | csr::VCSR
+            | csr::VL
+            | csr::VTYPE
+            | csr::VLENB
+                if !self.vector_enabled() =>
+            {
+                None
+            }
+            csr::VSTART => Some(self.vstart),
+            csr::VXSAT => Some(self.vcsr & VCSR_VXSAT),
+            csr::VXRM => Some(self.vcsr >> VCSR_VXRM_SHIFT),
+            csr::VCSR => Some(self.vcsr),
+            csr::VL => Some(self.vl),
+            csr::VTYPE => Some(self.vtype),
+            csr::VLENB => Some(self.vlenb),
+
+            csr::MSTATUS => Some(self.mstatus_value()),
+            csr::MISA => Some(self.misa),
             csr::MIE => Some(self.mie),
-            csr::MIP => Some(self.mip),
+            csr::MIP => Some(self.mip_value()),
             csr::MEPC => Some(self.mepc),
             csr::MCAUSE => Some(self.mcause),
             csr::MTVAL => Some(self.mtval),
             csr::MSCRATCH => Some(self.mscratch),
             csr::MTVEC => Some(self.mtvec),
+            csr::SATP if self.satp_trapped(privilege_level) => None,
             csr::SATP => Some(self.satp),
 
-            csr::SSTATUS => Some(self.mstatus & MSTATUS_MASK),
+            csr::SSTATUS => Some(self.mstatus_value() & SSTATUS_MASK),
             csr::SIE => Some(self.mie & self.read(csr::MIDELEG, 3).unwrap_or(0)),
-            csr::SIP => Some(self.mip & self.read(csr::MIDELEG, 3).unwrap_or(0)),
+            csr::SIP => Some(self.mip_value() & self.read(csr::MIDELEG, 3).unwrap_or(0)),
 
-            csr::MHARTID => Some(0),
+            csr::MVENDORID | csr::MARCHID | csr::MIMPID | csr::MHARTID | csr::MSTATUSH => Some(0),
 
-            _ => self.other_csrs.get(&addr).copied(),
+            csr::PMPCFG0..=csr::PMPCFG15 => {
+                Some(self.pmp.read_cfg((addr - csr::PMPCFG0) as usize, self.xlen))
+            }
+            csr::PMPADDR0..=csr::PMPADDR63 => {
+                Some(self.pmp.read_addr((addr - csr::PMPADDR0) as usize))
+            }
+
+            csr::CYCLE..=csr::HPMCOUNTER31 | csr::CYCLEH..=csr::HPMCOUNTER31H
+                if !self.counter_enabled(addr, privilege_level) =>
+            {
+                None
+            }
+            // The unprivileged counters are read-only views of the machine counters.
+            csr::TIME => Some(self.time),
+            csr::CYCLE..=csr::HPMCOUNTER31 | csr::MCYCLE..=csr::MHPMCOUNTER31 => {
+                Some(self.counter((addr & 0x1F) as usize))
+            }
+            csr::CYCLEH..=csr::HPMCOUNTER31H | csr::MCYCLEH..=csr::MHPMCOUNTER31H => self
+                .read(addr - COUNTER_HIGH_OFFSET, privilege_level)
+                .map(|value| value >> 32),
+            csr::MCOUNTINHIBIT => Some(self.mcountinhibit),
+            csr::MHPMEVENT3..=csr::MHPMEVENT31 => Some(self.hpm.event((addr & 0x1F) as usize)),
+            csr::MHPMEVENT3H..=csr::MHPMEVENT31H => {
+                Some(self.hpm.event((addr & 0x1F) as usize) >> 32)
+            }
+            // S-mode only sees the overflow flags of counters mcounteren exposes to it.
+            csr::SCOUNTOVF if privilege_level < 3 => {
+                Some(self.hpm.overflowed() & self.read(csr::MCOUNTEREN, 3).unwrap_or(0))
+            }
+            csr::SCOUNTOVF => Some(self.hpm.overflowed()),
+
+            _ => Some(self.other_csrs.get(&addr).copied().unwrap_or(0)),
         }
     }
 
+    /// Writes `value` to the CSR at `addr`, keeping only the bits the CSR implements and
+    /// legalizing its WARL fields. Returns `false` if the CSR does not exist, is read-only
+    /// or needs more privilege, in which case the access is an illegal instruction.
     pub fn write(&mut self, addr: u32, value: u64, privilege_level: u8) -> bool {
-        let required_priv = (addr >> 8) & 0x3;
-        if privilege_level < required_priv as u8 {
+        let Some(spec) = self.accessible(addr, privilege_level) else {
+            return false;
+        };
+        if spec.read_only {
             return false;
         }
+        let value = value & spec.writable;
 
         match addr {
-            csr::MSTATUS => self.mstatus = value,
-            csr::MIE => self.mie = value,
-            csr::MIP => self.mip = value,
-            csr::MEPC => self.mepc = value,
-            csr::MCAUSE => self.mcause = value,
-            csr::MTVAL => self.mtval = value,
-            csr::MSCRATCH => self.mscratch = value,
-            csr::MTVEC => self.mtvec = value,
-            csr::SATP => self.satp = value,
+            csr::FFLAGS | csr::FRM | csr::FCSR if !self.fp_enabled() => return false,
+            csr::FFLAGS => {
+                self.fcsr = (self.fcsr & !FFLAGS_MASK) | value;
+                self.mark_fp_dirty();
+            }
+            csr::FRM => {
+                self.fcsr = (self.fcsr & FFLAGS_MASK) | (value << FRM_SHIFT);
+                self.mark_fp_dirty();
+            }
+            csr::FCSR => {
+                self.fcsr = value;
+                self.mark_fp_dirty();
+            }
 
+            csr::VSTART | csr::VXSAT | csr::VXRM | csr::VCSR if !self.vector_enabled() => {
+                return false
+            }
+            csr::VSTART => {
+                self.vstart = value & (self.vlenb * 8 - 1);
+                self.mark_vector_dirty();
+            }
+            csr::VXSAT => {
+                self.vcsr = (self.vcsr & !VCSR_VXSAT) | value;
+                self.mark_vector_dirty();
+            }
+            csr::VXRM => {
+                self.vcsr = (self.vcsr & VCSR_VXSAT) | (value << VCSR_VXRM_SHIFT);
+                self.mark_vector_dirty();
+            }
+            csr::VCSR => {
+                self.vcsr = value;
+                self.mark_vector_dirty();
+            }
+
+            csr::MSTATUS => self.mstatus = self.legalize_mstatus(value),
             csr::SSTATUS => {
-                let new_mstatus = (self.mstatus & !MSTATUS_MASK) | (value & MSTATUS_MASK);
-                self.mstatus = new_mstatus;
+                self.mstatus = self.legalize_mstatus((self.mstatus & !SSTATUS_MASK) | value)
+            }
+            csr::MISA => {
+                self.misa = (self.misa & !self.misa_writable) | (value & self.misa_writable)
             }
+            csr::MIE => self.mie = value,
+            csr::MIP => self.mip = (self.mip & !spec.writable) | value,
             csr::SIE => {
                 let mideleg = self.read(csr::MIDELEG, 3).unwrap_or(0);
                 self.mie = (self.mie & !mideleg) | (value & mideleg);
             }
             csr::SIP => {
-                let mideleg = self.read(csr::MIDELEG, 3).unwrap_or(0);
-                self.mip = (self.mip & !mideleg) | (value & mideleg);
+                let writable = spec.writable & self.read(csr::MIDELEG, 3).unwrap_or(0);
+                self.mip = (self.mip & !writable) | (value & writable);
             }
+            csr::MEPC => self.mepc = value & self.epc_mask(),
+            csr::SEPC => {
+                self.other_csrs.insert(addr, value & self.epc_mask());
+            }
+            csr::MCAUSE => self.mcause = value,
+            csr::MTVAL => self.mtval = value,
+            csr::MSCRATCH => self.mscratch = value,
+            csr::MTVEC => self.mtvec = legalize_tvec(self.mtvec, value),
+            csr::STVEC => {
+                let old = self.other_csrs.get(&addr).copied().unwrap_or(0);
+                self.other_csrs.insert(addr, legalize_tvec(old, value));
+            }
+            csr::SATP if self.satp_trapped(privilege_level) => return false,
+            // satp is WARL: a write selecting a mode the hart lacks has no effect at all.
+            csr::SATP if !self.satp_mode_supported(value) => {}
+            csr::SATP => self.satp = value,
+            csr::MSTATUSH => {}
+
+            csr::PMPCFG0..=csr::PMPCFG15 => {
+                self.pmp
+                    .write_cfg((addr - csr::PMPCFG0) as usize, value, self.xlen)
+            }
+            csr::PMPADDR0..=csr::PMPADDR63 => {
+                self.pmp
+                    .write_addr((addr - csr::PMPADDR0) as usize, value, self.xlen)
+            }
+
+            csr::MCYCLE..=csr::MHPMCOUNTER31 | csr::MCYCLEH..=csr::MHPMCOUNTER31H => {
+                self.write_counter(addr, value)
+            }
+            csr::MHPMEVENT3..=csr::MHPMEVENT31 | csr::MHPMEVENT3H..=csr::MHPMEVENT31H => {
+                let index = (addr & 0x1F) as usize;
+                let old = self.hpm.event(index);
+                self.hpm.set_event(
+                    index,
+                    self.split_write(addr >= csr::MHPMEVENT3H, old, value),
+                );
+            }
+            csr::MCOUNTINHIBIT => self.mcountinhibit = value,
 
             _ => {
                 self.other_csrs.insert(addr, value);
@@ -107,4 +495,376 @@ impl CsrFile {
         }
         true
     }
+
+    /// The bits mepc and sepc keep beyond bit 0. Without C every instruction address is
+    /// four-byte aligned, so bit 1 is zero as well.
+    fn epc_mask(&self) -> u64 {
+        if self.misa & misa_bit('c') != 0 {
+            !0
+        } else {
+            !0b10
+        }
+    }
+
+    /// Writes a machine counter, or on RV32 one half of it, and holds it for the rest of
+    /// the step.
+    fn write_counter(&mut self, addr: u32, value: u64) {
+        let index = (addr & 0x1F) as usize;
+        let high = addr >= csr::MCYCLEH;
+        let value = self.split_write(high, self.counter(index), value);
+        match index {
+            0 => self.mcycle = value,
+            2 => self.minstret = value,
+            _ => self.hpm.set_counter(index, value),
+        }
+        self.held_counters |= 1 << index;
+    }
+
+    /// The new value of a 64-bit register after a write of `value`. On RV32 each half of
+    /// the register is written on its own, the upper one through its `h` CSR.
+    fn split_write(&self, high: bool, old: u64, value: u64) -> u64 {
+        match (high, self.xlen) {
+            (true, _) => (value << 32) | (old & 0xFFFF_FFFF),
+            (false, Xlen::Rv32) => (old & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF),
+            (false, Xlen::Rv64) => value,
+        }
+    }
+
+    /// The machine counter at `index`: mcycle, mtime, minstret or an mhpmcounter.
+    fn counter(&self, index: usize) -> u64 {
+        match index {
+            0 => self.mcycle,
+            1 => self.time,
+            2 => self.minstret,
+            _ => self.hpm.counter(index),
+        }
+    }
+
+    /// Whether `privilege_level` may read the unprivileged counter at `addr`. S-mode needs
+    /// the counter's bit in mcounteren, and U-mode needs it in scounteren as well.
+    fn counter_enabled(&self, addr: u32, privilege_level: u8) -> bool {
+        let bit = 1 << (addr & 0x1F);
+        let enabled_by =
+            |counteren| self.other_csrs.get(&counteren).copied().unwrap_or(0) & bit != 0;
+        match privilege_level {
+            3 => true,
+            1 => enabled_by(csr::MCOUNTEREN),
+            _ => enabled_by(csr::MCOUNTEREN) && enabled_by(csr::SCOUNTEREN),
+        }
+    }
+
+    /// Advances mcycle by one cycle and minstret by one retired instruction at the end of a
+    /// step, unless mcountinhibit stops them or the step held them.
+    pub fn tick_counters(&mut self) {
+        let counting = !(self.mcountinhibit | self.held_counters);
+        if counting & COUNTER_CY != 0 {
+            self.mcycle = self.mcycle.wrapping_add(1);
+        }
+        if counting & COUNTER_IR != 0 {
+            self.minstret = self.minstret.wrapping_add(1);
+        }
+        self.held_counters = 0;
+    }
+
+    /// Counts `event` at `privilege` in the programmable counters, raising the
+    /// counter-overflow interrupt if one of them wraps.
+    pub fn count_event(&mut self, event: HpmEvent, privilege: u8) {
+        if self.hpm.record(event, privilege, self.mcountinhibit) {
+            self.mip |= MIP_LCOFIP;
+        }
+    }
+
+    /// Records that the current instruction trapped, so it does not count as retired.
+    pub fn hold_instret(&mut self) {
+        self.held_counters |= COUNTER_IR;
+    }
+
+    /// The table row for `addr` if this hart implements the CSR and `privilege_level` is
+    /// high enough to access it.
+    fn accessible(&self, addr: u32, privilege_level: u8) -> Option<CsrSpec> {
+        let required_priv = (addr >> 8) & 0x3;
+        if privilege_level < required_priv as u8 {
+            return None;
+        }
+        let spec = csr_spec(addr)?;
+        let present = match spec.presence {
+            Presence::Always => true,
+            Presence::Rv32 => self.xlen == Xlen::Rv32,
+            Presence::Extension(letter) => self.misa & misa_bit(letter) != 0,
+        };
+        present.then_some(spec)
+    }
+
+    /// Sets misa to the value reported for the configured ISA. Of the extensions it lists,
+    /// only B can later be switched off and on again by software.
+    pub fn set_misa(&mut self, misa: u64) {
+        self.misa = misa;
+        self.misa_writable = misa & misa_bit('b');
+    }
+
+    /// Sets the register width, which decides whether the RV32-only CSRs (`mstatush` and the
+    /// upper halves of the counters) exist.
+    pub fn set_xlen(&mut self, xlen: Xlen) {
+        self.xlen = xlen;
+    }
+
+    pub fn xlen(&self) -> Xlen {
+        self.xlen
+    }
+
+    /// Sets the largest paging mode an RV64 satp accepts. Sv32 is the only RV32 mode.
+    pub fn set_max_satp_mode(&mut self, mode: SatpMode) {
+        self.max_satp_mode = mode;
+    }
+
+    /// Sets how many PMP entries the hart implements. The pmpcfg and pmpaddr CSRs of the
+    /// missing entries read as zero and ignore writes.
+    pub fn set_pmp_entries(&mut self, entries: usize) {
+        self.pmp = Pmp::new(entries);
+    }
+
+    /// mstatus.TVM makes satp inaccessible to S-mode, so a hypervisor can trap its writes.
+    fn satp_trapped(&self, privilege_level: u8) -> bool {
+        privilege_level == 1 && self.mstatus & MSTATUS_TVM != 0
+    }
+
+    fn satp_mode_supported(&self, satp: u64) -> bool {
+        match self.xlen {
+            Xlen::Rv32 => true,
+            Xlen::Rv64 => {
+                satp >> SATP_MODE_SHIFT == 0
+                    || SatpMode::from_satp(satp).is_some_and(|mode| mode <= self.max_satp_mode)
+            }
+        }
+    }
+
+    /// Sets the vector register length reported by `vlenb`.
+    pub fn set_vlenb(&mut self, vlenb: u64) {
+        self.vlenb = vlenb;
+    }
+
+    /// Legalizes a value written to mstatus. FS and VS are read-only zero unless the F and V
+    /// extensions are implemented, and MPP keeps its old value when the write selects the
+    /// reserved privilege level 2.
+    fn legalize_mstatus(&self, value: u64) -> u64 {
+        let mut value = value & MSTATUS_WRITABLE;
+        if self.misa & misa_bit('f') == 0 {
+            value &= !MSTATUS_FS;
+        }
+        if self.misa & misa_bit('v') == 0 {
+            value &= !MSTATUS_VS;
+        }
+        if value & MSTATUS_MPP == 2 << MSTATUS_MPP_SHIFT {
+            value = (value & !MSTATUS_MPP) | (self.mstatus & MSTATUS_MPP);
+        }
+        value
+    }
+
+    /// Whether vector instructions and the vector CSRs are usable: V is implemented and
+    /// mstatus.VS is not Off.
+    pub fn vector_enabled(&self) -> bool {
+        self.misa & misa_bit('v') != 0 && self.mstatus & MSTATUS_VS != 0
+    }
+
+    /// Records that vector state changed, like `mark_fp_dirty` does for FP state.
+    pub fn mark_vector_dirty(&mut self) {
+        self.mstatus |= MSTATUS_VS_DIRTY;
+    }
+
+    /// Whether FP instructions and the FP CSRs are usable: mstatus.FS is not Off.
+    pub fn fp_enabled(&self) -> bool {
+        self.mstatus & MSTATUS_FS != 0
+    }
+
+    /// Records that FP state changed, so the kernel knows to save it on a context switch.
+    pub fn mark_fp_dirty(&mut self) {
+        self.mstatus |= MSTATUS_FS_DIRTY;
+    }
+
+    /// mip as software reads it: the supervisor external interrupt is pending if either
+    /// software set SEIP or the PLIC is asserting it.
+    fn mip_value(&self) -> u64 {
+        if self.seip_line {
+            self.mip | MIP_SEIP
+        } else {
+            self.mip
+        }
+    }
+
+    /// mstatus as software reads it, with SD computed and UXL/SXL filled in.
+    fn mstatus_value(&self) -> u64 {
+        let mut mstatus = self.mstatus;
+        if self.xlen == Xlen::Rv64 {
+            mstatus |= MSTATUS_XL_64;
+        }
+        if self.mstatus & MSTATUS_FS == MSTATUS_FS_DIRTY
+            || self.mstatus & MSTATUS_VS == MSTATUS_VS_DIRTY
+        {
+            mstatus |= MSTATUS_SD;
+        }
+        mstatus
+    }
+}
+
+/// The trap vector modes above vectored are reserved, so a write selecting one keeps the old
+/// mode.
+fn legalize_tvec(old: u64, value: u64) -> u64 {
+    if value & TVEC_MODE_MASK > TVEC_MODE_VECTORED {
+        (value & !TVEC_MODE_MASK) | (old & TVEC_MODE_MASK)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::Isa;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VM;
+    use riscv_core::cause;
+
+    fn csr_file(isa: &str) -> CsrFile {
+        let isa: Isa = isa.parse().unwrap();
+        let mut csrs = CsrFile::new();
+        csrs.set_misa(isa.misa());
+        csrs.set_xlen(isa.xlen());
+        csrs
+    }
+
+    #[test]
+    fn test_nonexistent_and_read_only_csrs_are_illegal() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        // csrr a0, tselect; csrr a0, mvendorid; csrw mvendorid, a0
+        for (inst, legal) in [(0x7A002573, false), (0xF1102573, true), (0xF1151073, false)] {
+            vm.pc = BASE_ADDRESS;
+            vm.csrs.write(csr::MCAUSE, 0, 3);
+            vm.bus.write(BASE_ADDRESS, 4, inst);
+            assert_eq!(vm.step(), None);
+            let expected = if legal { 0 } else { cause::ILLEGAL_INSTRUCTION };
+            assert_eq!(vm.csrs.read(csr::MCAUSE, 3), Some(expected), "{inst:#x}");
+        }
+
+        assert_eq!(vm.csrs.read(csr::USTATUS, 3), None);
+        assert!(!vm.csrs.write(csr::SEDELEG, 1, 3));
+        assert!(!vm.csrs.write(csr::CYCLE, 1, 3));
+    }
+
+    #[test]
+    fn test_existence_follows_the_isa() {
+        let csrs = csr_file("rv64imafdc");
+        assert_eq!(csrs.read(csr::VLENB, 3), None);
+        assert_eq!(csrs.read(csr::MSTATUSH, 3), None);
+        assert_eq!(csrs.read(csr::CYCLEH, 3), None);
+        assert_eq!(csrs.read(csr::PMPCFG1, 3), None);
+
+        let csrs = csr_file("rv32im");
+        assert_eq!(csrs.read(csr::FCSR, 3), None);
+        assert_eq!(csrs.read(csr::MSTATUSH, 3), Some(0));
+        assert_eq!(csrs.read(csr::CYCLEH, 3), Some(0));
+        assert_eq!(csrs.read(csr::PMPCFG1, 3), Some(0));
+        assert_eq!(csrs.read(csr::SSTATUS, 3), Some(0));
+    }
+
+    #[test]
+    fn test_misa_reports_the_isa_and_ignores_writes() {
+        let isa: Isa = "rv64imafdc".parse().unwrap();
+        let mut csrs = csr_file("rv64imafdc");
+        let misa = csrs.read(csr::MISA, 3).unwrap();
+        assert_eq!(misa, isa.misa());
+        for letter in ['i', 'm', 'a', 'f', 'd', 'c', 's', 'u'] {
+            assert_ne!(misa & misa_bit(letter), 0, "{letter}");
+        }
+        assert_eq!(misa & misa_bit('v'), 0);
+
+        assert!(csrs.write(csr::MISA, misa | misa_bit('v'), 3));
+        assert!(csrs.write(csr::MISA, misa & !misa_bit('c'), 3));
+        assert_eq!(csrs.read(csr::MISA, 3), Some(misa));
+    }
+
+    #[test]
+    fn test_warl_fields_are_legalized() {
+        let mut csrs = csr_file("rv64imafdc");
+
+        // MPP=2 is reserved and keeps the previous mode; VS stays off without V.
+        csrs.write(csr::MSTATUS, 1 << MSTATUS_MPP_SHIFT, 3);
+        csrs.write(
+            csr::MSTATUS,
+            (2 << MSTATUS_MPP_SHIFT) | MSTATUS_VS | MSTATUS_SD,
+            3,
+        );
+        let mstatus = csrs.read(csr::MSTATUS, 3).unwrap();
+        assert_eq!(mstatus & MSTATUS_MPP, 1 << MSTATUS_MPP_SHIFT);
+        assert_eq!(mstatus & (MSTATUS_VS | MSTATUS_SD), 0);
+        assert_eq!(mstatus & MSTATUS_XL_64, MSTATUS_XL_64);
+
+        // The reserved trap vector modes keep the old mode.
+        csrs.write(csr::MTVEC, 0x8000_0001, 3);
+        csrs.write(csr::MTVEC, 0x8000_1002, 3);
+        assert_eq!(csrs.read(csr::MTVEC, 3), Some(0x8000_1001));
+
+        // medeleg cannot delegate ecalls from M-mode, and M-mode software cannot raise
+        // machine interrupts through mip.
+        csrs.write(csr::MEDELEG, u64::MAX, 3);
+        assert_eq!(csrs.read(csr::MEDELEG, 3), Some(MEDELEG_WRITABLE));
+        csrs.write(csr::MIP, u64::MAX, 3);
+        assert_eq!(csrs.read(csr::MIP, 3), Some(S_INTERRUPTS));
+        csrs.write(csr::MEPC, 0x8000_0003, 3);
+        assert_eq!(csrs.read(csr::MEPC, 3), Some(0x8000_0002));
+    }
+
+    #[test]
+    fn test_counters_advance_per_step() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        // nop; nop; an illegal instruction; csrw minstret, a1; csrr a0, cycle
+        for (i, inst) in [0x13, 0x13, 0, 0xB0259073, 0xC0002573]
+            .into_iter()
+            .enumerate()
+        {
+            vm.bus.write(BASE_ADDRESS + 4 * i as u64, 4, inst);
+        }
+
+        // The trapping instruction takes a cycle but does not retire.
+        for _ in 0..3 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!((vm.csrs.mcycle, vm.csrs.minstret), (3, 2));
+
+        vm.csrs.write(csr::MCOUNTINHIBIT, COUNTER_IR, 3);
+        vm.pc = BASE_ADDRESS;
+        assert_eq!(vm.step(), None);
+        assert_eq!((vm.csrs.mcycle, vm.csrs.minstret), (4, 2));
+
+        // A written counter holds its value until the next instruction has read it.
+        vm.csrs.write(csr::MCOUNTINHIBIT, 0, 3);
+        vm.registers[11] = 100;
+        vm.pc = BASE_ADDRESS + 12;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.csrs.read(csr::MINSTRET, 3), Some(100));
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], 5);
+        assert_eq!((vm.csrs.mcycle, vm.csrs.minstret), (6, 101));
+    }
+
+    #[test]
+    fn test_counter_enables_gate_lower_privileges() {
+        let mut csrs = csr_file("rv64imafdc");
+        csrs.time = 42;
+        assert_eq!(csrs.read(csr::TIME, 3), Some(42));
+        assert_eq!(csrs.read(csr::TIME, 1), None);
+
+        csrs.write(csr::MCOUNTEREN, COUNTER_TM, 3);
+        assert_eq!(csrs.read(csr::TIME, 1), Some(42));
+        assert_eq!(csrs.read(csr::CYCLE, 1), None);
+        assert_eq!(csrs.read(csr::TIME, 0), None);
+
+        csrs.write(csr::SCOUNTEREN, COUNTER_TM, 1);
+        assert_eq!(csrs.read(csr::TIME, 0), Some(42));
+
+        // mcounteren still wins over scounteren.
+        csrs.write(csr::MCOUNTEREN, 0, 3);
+        assert_eq!(csrs.read(csr::TIME, 0), None);
+    }
 }
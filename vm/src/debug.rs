@@ -0,0 +1,203 @@
+use crate::isa::Xlen;
+use crate::VM;
+use std::collections::HashMap;
+
+/// `ebreak`, written over guest code to plant a 4-byte software breakpoint.
+const EBREAK: u32 = 0x0010_0073;
+/// `c.ebreak`, for a 2-byte software breakpoint.
+const C_EBREAK: u16 = 0x9002;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u64,
+    pub len: u64,
+    pub kind: WatchKind,
+}
+
+/// Why the hart stopped for an attached debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugStop {
+    /// An `ebreak` was reached while `halt_on_ebreak` was set. `pc` still points at it.
+    Breakpoint,
+    /// A load or store touched a watched range. The access has not been performed and `pc`
+    /// still points at the instruction.
+    Watchpoint { kind: WatchKind, addr: u64 },
+}
+
+/// State shared by the debugger front ends (the GDB stub and the monitor).
+#[derive(Default)]
+pub struct DebugState {
+    /// Makes `ebreak` stop the hart for the debugger instead of raising a breakpoint
+    /// exception, like `dcsr.ebreakm/s/u` on real hardware.
+    pub halt_on_ebreak: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Set when execution stops for the debugger; front ends take it after every step.
+    pub stop: Option<DebugStop>,
+    /// The original bytes under each planted software breakpoint.
+    breakpoints: HashMap<u64, Vec<u8>>,
+}
+
+impl DebugState {
+    /// Whether an access of `size` bytes at `vaddr` hits a watchpoint. If so, records the stop.
+    pub(crate) fn check_watchpoints(&mut self, vaddr: u64, size: u64, is_write: bool) -> bool {
+        let hit = self.watchpoints.iter().find(|watch| {
+            let kind_matches = match watch.kind {
+                WatchKind::Write => is_write,
+                WatchKind::Read => !is_write,
+                WatchKind::Access => true,
+            };
+            kind_matches && vaddr < watch.addr.wrapping_add(watch.len) && watch.addr < vaddr + size
+        });
+        match hit {
+            Some(watch) => {
+                self.stop = Some(DebugStop::Watchpoint {
+                    kind: watch.kind,
+                    addr: vaddr.max(watch.addr),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn breakpoint_addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        self.breakpoints.keys().copied()
+    }
+}
+
+impl VM {
+    /// Translates `vaddr` for a debugger access. Debugger accesses only need the page to be
+    /// mapped readable, so breakpoints can be planted in read-only code. The walk goes
+    /// around the TLB and leaves the A/D bits and the event counters alone, so inspecting
+    /// memory never changes what the guest sees.
+    fn debug_translate(&mut self, vaddr: u64) -> Option<u64> {
+        let privilege = self.effective_privilege(false);
+        let Some(mode) = self.active_paging_mode(privilege) else {
+            return Some(self.effective_address(vaddr));
+        };
+        if self.config.isa.xlen() == Xlen::Rv64 && !mode.is_canonical(vaddr) {
+            return None;
+        }
+        let (steps, paddr) = self.page_walk(vaddr);
+        let leaf = steps.last()?;
+        self.leaf_permits(leaf.pte, privilege, false, false)
+            .then_some(paddr)
+            .flatten()
+    }
+
+    /// Reads guest memory at `vaddr` through the MMU, as the hart currently sees it. Returns
+    /// the number of bytes read before the first unmapped or inaccessible byte.
+    pub fn debug_read(&mut self, vaddr: u64, buf: &mut [u8]) -> usize {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let value = self
+                .debug_translate(vaddr.wrapping_add(i as u64))
+                .and_then(|paddr| self.bus.read(paddr, 1));
+            match value {
+                Some(value) => *byte = value as u8,
+                None => return i,
+            }
+        }
+        buf.len()
+    }
+
+    /// Writes `data` to guest memory at `vaddr` through the MMU. Returns `false` if any byte
+    /// could not be written; bytes before it have been.
+    pub fn debug_write(&mut self, vaddr: u64, data: &[u8]) -> bool {
+        for (i, &byte) in data.iter().enumerate() {
+            let written = self
+                .debug_translate(vaddr.wrapping_add(i as u64))
+                .is_some_and(|paddr| self.bus.write(paddr, 1, byte as u64));
+            if !written {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Plants a software breakpoint of `len` bytes (4 for `ebreak`, 2 for `c.ebreak`) at
+    /// `vaddr`.
+    pub fn insert_breakpoint(&mut self, vaddr: u64, len: u64) -> bool {
+        if self.debug.breakpoints.contains_key(&vaddr) {
+            return true;
+        }
+        let patch = match len {
+            4 => EBREAK.to_le_bytes().to_vec(),
+            2 => C_EBREAK.to_le_bytes().to_vec(),
+            _ => return false,
+        };
+        let mut original = vec![0; patch.len()];
+        if self.debug_read(vaddr, &mut original) != original.len() {
+            return false;
+        }
+        if !self.debug_write(vaddr, &patch) {
+            return false;
+        }
+        self.debug.breakpoints.insert(vaddr, original);
+        true
+    }
+
+    /// Restores the instruction under the breakpoint at `vaddr`.
+    pub fn remove_breakpoint(&mut self, vaddr: u64) -> bool {
+        match self.debug.breakpoints.remove(&vaddr) {
+            Some(original) => self.debug_write(vaddr, &original),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+
+    #[test]
+    fn test_breakpoint_patches_and_restores_code() {
+        let mut vm = VM::new();
+        let addi = 0x0015_0513u32; // addi a0, a0, 1
+        vm.bus.write(BASE_ADDRESS, 4, addi as u64);
+
+        assert!(vm.insert_breakpoint(BASE_ADDRESS, 4));
+        assert_eq!(vm.bus.read(BASE_ADDRESS, 4), Some(EBREAK as u64));
+
+        vm.debug.halt_on_ebreak = true;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.debug.stop.take(), Some(DebugStop::Breakpoint));
+        assert_eq!(vm.pc, BASE_ADDRESS);
+
+        assert!(vm.remove_breakpoint(BASE_ADDRESS));
+        assert_eq!(vm.bus.read(BASE_ADDRESS, 4), Some(addi as u64));
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], 1);
+    }
+
+    #[test]
+    fn test_write_watchpoint_stops_before_the_store() {
+        let mut vm = VM::new();
+        vm.registers[11] = BASE_ADDRESS + 0x100;
+        vm.registers[10] = 42;
+        vm.bus.write(BASE_ADDRESS, 4, 0x00a5_b023); // sd a0, 0(a1)
+        vm.debug.watchpoints.push(Watchpoint {
+            addr: BASE_ADDRESS + 0x104,
+            len: 4,
+            kind: WatchKind::Write,
+        });
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.debug.stop.take(),
+            Some(DebugStop::Watchpoint {
+                kind: WatchKind::Write,
+                addr: BASE_ADDRESS + 0x104
+            })
+        );
+        assert_eq!(vm.pc, BASE_ADDRESS);
+        assert_eq!(vm.bus.read(BASE_ADDRESS + 0x100, 8), Some(0));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::csr::{MSECCFG_SSEED, MSECCFG_USEED};
+use crate::isa::Extension;
+use crate::VM;
+use riscv_core::{cause, csr, funct3};
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+/// `seed` status: the low 16 bits hold fresh entropy.
+const SEED_OPST_ES16: u64 = 0b10 << 30;
+/// `seed` status: the entropy source has failed and will not recover.
+const SEED_OPST_DEAD: u64 = 0b11 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropySource {
+    /// A pseudo-random sequence from a fixed seed, so runs are reproducible.
+    Seeded(u64),
+    /// The host's `/dev/urandom`.
+    Host,
+}
+
+impl Default for EntropySource {
+    fn default() -> Self {
+        EntropySource::Seeded(0)
+    }
+}
+
+impl FromStr for EntropySource {
+    type Err = String;
+
+    /// Parses `host`, or a decimal or `0x` hexadecimal seed.
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "host" {
+            return Ok(EntropySource::Host);
+        }
+        let seed = match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        seed.map(EntropySource::Seeded)
+            .map_err(|_| format!("{}: expected host or a numeric seed", s))
+    }
+}
+
+/// The entropy source behind the Zkr `seed` CSR.
+pub struct Entropy {
+    source: EntropySource,
+    state: u64,
+    host: Option<File>,
+}
+
+impl Entropy {
+    pub fn new(source: EntropySource) -> Self {
+        let state = match source {
+            EntropySource::Seeded(seed) => seed,
+            EntropySource::Host => 0,
+        };
+        Self {
+            source,
+            state,
+            host: None,
+        }
+    }
+
+    /// Returns the next 16 bits of entropy, or `None` if the host source cannot be read.
+    fn next(&mut self) -> Option<u16> {
+        match self.source {
+            EntropySource::Seeded(_) => {
+                // SplitMix64.
+                self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = self.state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                Some((z ^ (z >> 31)) as u16)
+            }
+            EntropySource::Host => {
+                if self.host.is_none() {
+                    self.host = File::open("/dev/urandom").ok();
+                }
+                let mut bytes = [0; 2];
+                self.host.as_mut()?.read_exact(&mut bytes).ok()?;
+                Some(u16::from_le_bytes(bytes))
+            }
+        }
+    }
+
+    /// The SplitMix64 state, so a seeded sequence carries on from a snapshot.
+    pub fn save_state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn restore_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    /// Polls the source the way a read of `seed` does, returning the CSR value.
+    pub fn poll(&mut self) -> u64 {
+        match self.next() {
+            Some(bits) => SEED_OPST_ES16 | bits as u64,
+            None => SEED_OPST_DEAD,
+        }
+    }
+}
+
+impl VM {
+    /// Executes a CSR instruction that targets `seed`. Every access polls the entropy source,
+    /// so read-only forms are illegal, and below M-mode the access must be allowed by
+    /// `mseccfg.SSEED` or `mseccfg.USEED`.
+    pub(crate) fn execute_seed_access(&mut self, inst: u32, next_pc: u64) -> bool {
+        let funct3 = (inst >> 12) & 0x7;
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let rs1_field = (inst >> 15) & 0x1F;
+
+        let mseccfg = self.csrs.read(csr::MSECCFG, 3).unwrap_or(0);
+        let permitted = match self.privilege_level {
+            3 => true,
+            1 => mseccfg & MSECCFG_SSEED != 0,
+            _ => mseccfg & MSECCFG_USEED != 0,
+        };
+        let writes = funct3 & 0b011 == funct3::CSRRW || rs1_field != 0;
+        if !self.extension_enabled(Extension::Zkr) || !permitted || !writes {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+
+        // Writes to seed are ignored.
+        let value = self.entropy.poll();
+        if rd > 0 {
+            self.registers[rd] = value;
+        }
+        self.pc = next_pc;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+
+    #[test]
+    fn test_seed_csr_access() {
+        let mut vm = VM::new_config(VmConfig {
+            entropy: EntropySource::Seeded(42),
+            ..VmConfig::default()
+        });
+        let mut expected = Entropy::new(EntropySource::Seeded(42));
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+
+        // csrrw a0, seed, zero
+        vm.bus.write(BASE_ADDRESS, 4, 0x01501573);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], expected.poll());
+        assert_eq!(vm.registers[10] >> 30, 0b10);
+
+        // csrr a0, seed is a read-only access.
+        vm.pc = BASE_ADDRESS;
+        vm.bus.write(BASE_ADDRESS, 4, 0x01502573);
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+
+        // S-mode needs mseccfg.SSEED.
+        vm.pc = BASE_ADDRESS;
+        vm.privilege_level = 1;
+        vm.open_pmp();
+        vm.bus.write(BASE_ADDRESS, 4, 0x01501573);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+        vm.csrs.write(csr::MSECCFG, MSECCFG_SSEED, 3);
+        vm.pc = BASE_ADDRESS;
+        vm.privilege_level = 1;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], expected.poll());
+    }
+
+    #[test]
+    fn test_parse_entropy_source() {
+        assert_eq!("host".parse(), Ok(EntropySource::Host));
+        assert_eq!("0x10".parse(), Ok(EntropySource::Seeded(16)));
+        assert_eq!("7".parse(), Ok(EntropySource::Seeded(7)));
+        assert!("urandom".parse::<EntropySource>().is_err());
+    }
+}
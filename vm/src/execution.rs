@@ -1,18 +1,28 @@
-use std::io::{self, Write};
-
-use crate::{
-    memory::{VIRTUAL_DISK_ADDRESS, VIRTUAL_DISK_SIZE_ADDRESS},
-    VM,
-};
+use crate::csr::{MSTATUS_MPRV, MSTATUS_TSR, MSTATUS_TW};
+use crate::debug::DebugStop;
+use crate::hpm::HpmEvent;
+use crate::isa::Xlen;
+use crate::mmu::TranslateFault;
+use crate::pmp::AccessType;
+use crate::rv32::{narrow_csr, widen_csr};
+use crate::vector::is_vector_access;
+use crate::VM;
 use riscv_core::{cause, csr, funct3, funct7, opcodes, system};
 
-const UART_BASE_ADDRESS: u64 = 0x10000000;
-const UART_SIZE: u64 = 8;
-
 impl VM {
-    pub(crate) fn execute(&mut self, inst: u32) -> bool {
+    /// Executes `inst`, which was `length` bytes long before any expansion from its
+    /// compressed form.
+    pub(crate) fn execute(&mut self, inst: u32, length: u64) -> bool {
+        let xlen = self.config.isa.xlen();
+        let inst = match xlen {
+            Xlen::Rv64 => inst,
+            Xlen::Rv32 => match self.rv32_equivalent(inst) {
+                Some(equivalent) => equivalent,
+                None => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+            },
+        };
         let opcode = inst & 0x7F;
-        let mut next_pc = self.pc.wrapping_add(4);
+        let mut next_pc = self.pc.wrapping_add(length);
 
         match opcode {
             opcodes::OP_LUI => {
@@ -31,25 +41,34 @@ impl VM {
             }
             opcodes::OP_JAL => {
                 let rd = ((inst >> 7) & 0x1F) as usize;
-                if rd > 0 {
-                    self.registers[rd] = next_pc;
-                }
                 let imm20 = (inst >> 31) & 1;
                 let imm10_1 = (inst >> 21) & 0x3FF;
                 let imm11 = (inst >> 20) & 1;
                 let imm19_12 = (inst >> 12) & 0xFF;
                 let offset = (imm20 << 20) | (imm19_12 << 12) | (imm11 << 11) | (imm10_1 << 1);
                 let offset = (((offset as i32) << 11) >> 11) as i64 as u64;
-                next_pc = self.pc.wrapping_add(offset);
+                let target = self.pc.wrapping_add(offset);
+                if !target.is_multiple_of(self.ialign()) {
+                    return self.handle_trap(cause::INSTRUCTION_ADDRESS_MISALIGNED, target);
+                }
+                if rd > 0 {
+                    self.registers[rd] = next_pc;
+                }
+                next_pc = target;
             }
             opcodes::OP_JALR => {
                 let rd = ((inst >> 7) & 0x1F) as usize;
                 let rs1 = ((inst >> 15) & 0x1F) as usize;
+                let imm = (inst as i32 >> 20) as i64 as u64;
+                let target = (self.registers[rs1].wrapping_add(imm)) & !1;
+                // The trap is taken before rd is written, so `jalr ra, 0(ra)` keeps its base.
+                if !target.is_multiple_of(self.ialign()) {
+                    return self.handle_trap(cause::INSTRUCTION_ADDRESS_MISALIGNED, target);
+                }
                 if rd > 0 {
                     self.registers[rd] = next_pc;
                 }
-                let imm = (inst as i32 >> 20) as i64 as u64;
-                next_pc = (self.registers[rs1].wrapping_add(imm)) & !1;
+                next_pc = target;
             }
             opcodes::OP_BRANCH => {
                 let funct3 = (inst >> 12) & 0x7;
@@ -74,125 +93,58 @@ impl VM {
                     }
                 };
                 if condition_met {
-                    next_pc = self.pc.wrapping_add(offset);
+                    let target = self.pc.wrapping_add(offset);
+                    if !target.is_multiple_of(self.ialign()) {
+                        return self.handle_trap(cause::INSTRUCTION_ADDRESS_MISALIGNED, target);
+                    }
+                    self.count_event(HpmEvent::TakenBranch);
+                    next_pc = target;
                 }
             }
             opcodes::OP_LOAD => {
                 let rd = ((inst >> 7) & 0x1F) as usize;
                 let funct3 = (inst >> 12) & 0x7;
                 let rs1 = ((inst >> 15) & 0x1F) as usize;
-                if rd > 0 {
-                    let imm = (inst as i32 >> 20) as i64 as u64;
-                    let vaddr = self.registers[rs1].wrapping_add(imm);
+                let imm = (inst as i32 >> 20) as i64 as u64;
+                let vaddr = self.registers[rs1].wrapping_add(imm);
+
+                if !matches!(
+                    funct3,
+                    funct3::LB
+                        | funct3::LH
+                        | funct3::LW
+                        | funct3::LD
+                        | funct3::LBU
+                        | funct3::LHU
+                        | funct3::LWU
+                ) {
+                    return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                }
 
-                    if vaddr == VIRTUAL_DISK_SIZE_ADDRESS {
-                        if funct3 == funct3::LD {
-                            self.registers[rd] = self.virtual_disk.len() as u64;
-                        } else {
-                            self.registers[rd] = 0;
-                        }
-                    } else if vaddr >= VIRTUAL_DISK_ADDRESS
-                        && vaddr < VIRTUAL_DISK_ADDRESS + self.virtual_disk.len() as u64
-                    {
-                        let disk_offset = (vaddr - VIRTUAL_DISK_ADDRESS) as usize;
-                        match funct3 {
-                            funct3::LB => {
-                                self.registers[rd] = self.virtual_disk[disk_offset] as i8 as u64
-                            }
-                            funct3::LH => {
-                                let bytes: [u8; 2] = self.virtual_disk
-                                    [disk_offset..disk_offset + 2]
-                                    .try_into()
-                                    .unwrap();
-                                self.registers[rd] = i16::from_le_bytes(bytes) as i64 as u64;
-                            }
-                            funct3::LW => {
-                                let bytes: [u8; 4] = self.virtual_disk
-                                    [disk_offset..disk_offset + 4]
-                                    .try_into()
-                                    .unwrap();
-                                self.registers[rd] = i32::from_le_bytes(bytes) as i64 as u64;
-                            }
-                            funct3::LD => {
-                                let bytes: [u8; 8] = self.virtual_disk
-                                    [disk_offset..disk_offset + 8]
-                                    .try_into()
-                                    .unwrap();
-                                self.registers[rd] = u64::from_le_bytes(bytes);
-                            }
-                            funct3::LBU => {
-                                self.registers[rd] = self.virtual_disk[disk_offset] as u64
-                            }
-                            funct3::LHU => {
-                                let bytes: [u8; 2] = self.virtual_disk
-                                    [disk_offset..disk_offset + 2]
-                                    .try_into()
-                                    .unwrap();
-                                self.registers[rd] = u16::from_le_bytes(bytes) as u64;
-                            }
-                            funct3::LWU => {
-                                let bytes: [u8; 4] = self.virtual_disk
-                                    [disk_offset..disk_offset + 4]
-                                    .try_into()
-                                    .unwrap();
-                                self.registers[rd] = u32::from_le_bytes(bytes) as u64;
-                            }
-                            _ => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
-                        }
-                    } else {
-                        let alignment = match funct3 {
-                            funct3::LW | funct3::LWU => 4,
-                            funct3::LD => 8,
-                            funct3::LH | funct3::LHU => 2,
-                            _ => 1,
-                        };
+                let size = access_size(funct3);
+                if self.debug.check_watchpoints(vaddr, size, false) {
+                    return true;
+                }
 
-                        if alignment > 1 && vaddr % alignment != 0 {
-                            return self.handle_trap(cause::LOAD_ADDRESS_MISALIGNED, vaddr);
-                        }
+                let paddr = match self.translate_access(vaddr, size, AccessType::Read) {
+                    Ok(addr) => addr,
+                    Err(fault) => {
+                        return self.handle_trap(fault.cause(AccessType::Read), fault.vaddr());
+                    }
+                };
 
-                        let paddr = match self.translate(vaddr, false, false) {
-                            Ok(addr) => addr as usize,
-                            Err(fault_addr) => {
-                                return self.handle_trap(cause::LOAD_ACCESS_FAULT, fault_addr);
-                            }
-                        };
+                let raw = match self.read_physical(paddr, size, AccessType::Read) {
+                    Some(val) => val,
+                    None => return self.handle_trap(cause::LOAD_ACCESS_FAULT, vaddr),
+                };
 
-                        match funct3 {
-                            funct3::LB => {
-                                self.registers[rd] = self.memory[paddr] as i8 as i64 as u64
-                            }
-                            funct3::LH => {
-                                let bytes: [u8; 2] =
-                                    self.memory[paddr..paddr + 2].try_into().unwrap();
-                                self.registers[rd] = i16::from_le_bytes(bytes) as i64 as u64;
-                            }
-                            funct3::LW => {
-                                let bytes: [u8; 4] =
-                                    self.memory[paddr..paddr + 4].try_into().unwrap();
-                                self.registers[rd] = i32::from_le_bytes(bytes) as i64 as u64;
-                            }
-                            funct3::LD => {
-                                let bytes: [u8; 8] =
-                                    self.memory[paddr..paddr + 8].try_into().unwrap();
-                                self.registers[rd] = u64::from_le_bytes(bytes);
-                            }
-                            funct3::LBU => self.registers[rd] = self.memory[paddr] as u64,
-                            funct3::LHU => {
-                                let bytes: [u8; 2] =
-                                    self.memory[paddr..paddr + 2].try_into().unwrap();
-                                self.registers[rd] = u16::from_le_bytes(bytes) as u64;
-                            }
-                            funct3::LWU => {
-                                let bytes: [u8; 4] =
-                                    self.memory[paddr..paddr + 4].try_into().unwrap();
-                                self.registers[rd] = u32::from_le_bytes(bytes) as u64;
-                            }
-                            _ => {
-                                return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
-                            }
-                        }
-                    }
+                if rd > 0 {
+                    self.registers[rd] = match funct3 {
+                        funct3::LB => raw as i8 as i64 as u64,
+                        funct3::LH => raw as i16 as i64 as u64,
+                        funct3::LW => raw as i32 as i64 as u64,
+                        _ => raw,
+                    };
                 }
             }
 
@@ -207,126 +159,112 @@ impl VM {
                 let vaddr = self.registers[rs1].wrapping_add(imm as i64 as u64);
                 let data = self.registers[rs2];
 
-                if vaddr >= UART_BASE_ADDRESS && vaddr < UART_BASE_ADDRESS + UART_SIZE {
-                    if funct3 == funct3::SB {
-                        print!("{}", data as u8 as char);
-                        io::stdout().flush().unwrap();
-                    }
-                } else if vaddr >= VIRTUAL_DISK_ADDRESS
-                    && vaddr < VIRTUAL_DISK_ADDRESS + self.virtual_disk.len() as u64
-                {
-                    // Do nothing
-                } else {
-                    let alignment = match funct3 {
-                        funct3::SW => 4,
-                        funct3::SD => 8,
-                        funct3::SH => 2,
-                        _ => 1,
-                    };
-
-                    if alignment > 1 && vaddr % alignment != 0 {
-                        return self.handle_trap(cause::STORE_AMO_ADDRESS_MISALIGNED, vaddr);
-                    }
+                if funct3 > funct3::SD {
+                    return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                }
 
-                    let paddr = match self.translate(vaddr, true, false) {
-                        Ok(addr) => addr as usize,
-                        Err(fault_addr) => {
-                            return self.handle_trap(cause::STORE_AMO_ACCESS_FAULT, fault_addr);
-                        }
-                    };
+                let size = access_size(funct3);
+                if self.debug.check_watchpoints(vaddr, size, true) {
+                    return true;
+                }
 
-                    match funct3 {
-                        funct3::SB => self.memory[paddr] = data as u8,
-                        funct3::SH => self.memory[paddr..paddr + 2]
-                            .copy_from_slice(&(data as u16).to_le_bytes()),
-                        funct3::SW => self.memory[paddr..paddr + 4]
-                            .copy_from_slice(&(data as u32).to_le_bytes()),
-                        funct3::SD => {
-                            self.memory[paddr..paddr + 8].copy_from_slice(&data.to_le_bytes())
-                        }
-                        _ => {
-                            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
-                        }
+                let paddr = match self.translate_access(vaddr, size, AccessType::Write) {
+                    Ok(addr) => addr,
+                    Err(fault) => {
+                        return self.handle_trap(fault.cause(AccessType::Write), fault.vaddr());
                     }
+                };
+
+                if !self.write_physical(paddr, size, data) {
+                    return self.handle_trap(cause::STORE_AMO_ACCESS_FAULT, vaddr);
                 }
+                self.invalidate_reservation(paddr, size);
+            }
+            opcodes::OP_AMO if self.config.isa.has_base('a') => {
+                return self.execute_amo(inst, next_pc)
+            }
+            opcodes::OP_LOAD_FP | opcodes::OP_STORE_FP if is_vector_access(inst) => {
+                return self.execute_vector_access(inst, next_pc)
             }
+            opcodes::OP_V => return self.execute_vector(inst, next_pc),
+            opcodes::OP_LOAD_FP
+            | opcodes::OP_STORE_FP
+            | opcodes::OP_FP
+            | opcodes::OP_MADD
+            | opcodes::OP_MSUB
+            | opcodes::OP_NMSUB
+            | opcodes::OP_NMADD => return self.execute_fp(inst, next_pc),
             opcodes::OP_IMM => {
                 let rd = ((inst >> 7) & 0x1F) as usize;
                 let funct3 = (inst >> 12) & 0x7;
                 let rs1 = ((inst >> 15) & 0x1F) as usize;
+                let imm = (inst as i32 >> 20) as i64 as u64;
+                let val1 = self.registers[rs1];
 
-                if rd > 0 {
-                    let imm = (inst as i32 >> 20) as i64 as u64;
-
-                    match funct3 {
-                        funct3::ADD_SUB => {
-                            self.registers[rd] =
-                                self.registers[rs1].wrapping_add(imm) as i64 as u64;
-                        }
-                        funct3::SLT => {
-                            self.registers[rd] = if (self.registers[rs1] as i64) < (imm as i64) {
-                                1
-                            } else {
-                                0
-                            }
-                        }
-                        funct3::SLTU => {
-                            self.registers[rd] = if self.registers[rs1] < imm { 1 } else { 0 }
-                        }
-                        funct3::XOR => self.registers[rd] = self.registers[rs1] ^ imm,
-                        funct3::OR => self.registers[rd] = self.registers[rs1] | imm,
-                        funct3::AND => self.registers[rd] = self.registers[rs1] & imm,
-                        funct3::SLL => {
-                            let shamt = ((inst >> 20) & 0x3F) as u32;
-                            self.registers[rd] = self.registers[rs1].wrapping_shl(shamt);
+                let result = match funct3 {
+                    funct3::ADD_SUB => val1.wrapping_add(imm) as i64 as u64,
+                    funct3::SLT => {
+                        if (val1 as i64) < (imm as i64) {
+                            1
+                        } else {
+                            0
                         }
-                        funct3::SRL_SRA => {
-                            let shamt = ((inst >> 20) & 0x3F) as u32;
-                            if (inst >> 30) & 1 == 1 {
-                                self.registers[rd] =
-                                    (self.registers[rs1] as i64).wrapping_shr(shamt) as u64;
-                            } else {
-                                self.registers[rd] = self.registers[rs1].wrapping_shr(shamt);
-                            }
+                    }
+                    funct3::SLTU => {
+                        if val1 < imm {
+                            1
+                        } else {
+                            0
                         }
-                        _ => {
-                            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                    }
+                    funct3::XOR => val1 ^ imm,
+                    funct3::OR => val1 | imm,
+                    funct3::AND => val1 & imm,
+                    funct3::SLL if inst >> 26 == 0 => {
+                        let shamt = (inst >> 20) & 0x3F;
+                        val1.wrapping_shl(shamt)
+                    }
+                    funct3::SRL_SRA if (inst >> 26) & !0b010000 == 0 => {
+                        let shamt = (inst >> 20) & 0x3F;
+                        if (inst >> 30) & 1 == 1 {
+                            (val1 as i64).wrapping_shr(shamt) as u64
+                        } else {
+                            val1.wrapping_shr(shamt)
                         }
                     }
+                    _ => return self.execute_bitmanip(inst, next_pc),
+                };
+                // Only the write is dropped for x0; the encoding is still decoded above, so
+                // an unknown one traps.
+                if rd > 0 {
+                    self.registers[rd] = result;
                 }
             }
             opcodes::OP_IMM_32 => {
                 let rd = ((inst >> 7) & 0x1F) as usize;
                 let funct3 = (inst >> 12) & 0x7;
                 let rs1 = ((inst >> 15) & 0x1F) as usize;
+                let imm = inst as i32 >> 20;
+                let val1 = self.registers[rs1] as i32;
 
-                if rd > 0 {
-                    let imm = (inst as i32 >> 20) as i32;
-                    let val1 = self.registers[rs1] as i32;
-
-                    match funct3 {
-                        funct3::ADD_SUB => {
-                            let val_rs1_32 = (self.registers[rs1] as i32) as i64;
-                            let result_32 = val_rs1_32.wrapping_add(imm as i64);
-                            self.registers[rd] = (result_32 as i32) as i64 as u64;
-                        }
-                        funct3::SLL => {
-                            let shamt = ((inst >> 20) & 0x1F) as u32;
-                            self.registers[rd] = val1.wrapping_shl(shamt) as i64 as u64;
-                        }
-                        funct3::SRL_SRA => {
-                            let shamt = ((inst >> 20) & 0x1F) as u32;
-                            if (inst >> 30) & 1 == 1 {
-                                self.registers[rd] = val1.wrapping_shr(shamt) as i64 as u64;
-                            } else {
-                                self.registers[rd] =
-                                    (val1 as u32).wrapping_shr(shamt) as i32 as i64 as u64;
-                            }
-                        }
-                        _ => {
-                            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                let result = match funct3 {
+                    funct3::ADD_SUB => val1.wrapping_add(imm) as i64 as u64,
+                    funct3::SLL if inst >> 25 == 0 => {
+                        let shamt = (inst >> 20) & 0x1F;
+                        val1.wrapping_shl(shamt) as i64 as u64
+                    }
+                    funct3::SRL_SRA if (inst >> 25) & !funct7::SRA == 0 => {
+                        let shamt = (inst >> 20) & 0x1F;
+                        if (inst >> 30) & 1 == 1 {
+                            val1.wrapping_shr(shamt) as i64 as u64
+                        } else {
+                            (val1 as u32).wrapping_shr(shamt) as i32 as i64 as u64
                         }
                     }
+                    _ => return self.execute_bitmanip(inst, next_pc),
+                };
+                if rd > 0 {
+                    self.registers[rd] = result;
                 }
             }
             opcodes::OP_REG => {
@@ -338,80 +276,81 @@ impl VM {
 
                 let val1 = self.registers[rs1];
                 let val2 = self.registers[rs2];
-                if rd > 0 {
-                    match (funct3, funct7) {
-                        (funct3::ADD_SUB, funct7::DEFAULT) => {
-                            self.registers[rd] = val1.wrapping_add(val2)
-                        }
-                        (funct3::ADD_SUB, funct7::SUB) => {
-                            self.registers[rd] = val1.wrapping_sub(val2)
-                        }
-                        (funct3::SLL, funct7::DEFAULT) => {
-                            self.registers[rd] = val1.wrapping_shl(val2 as u32)
-                        }
-                        (funct3::SLT, funct7::DEFAULT) => {
-                            self.registers[rd] = if (val1 as i64) < (val2 as i64) { 1 } else { 0 }
-                        }
-                        (funct3::SLTU, funct7::DEFAULT) => {
-                            self.registers[rd] = if val1 < val2 { 1 } else { 0 }
-                        }
-                        (funct3::XOR, funct7::DEFAULT) => self.registers[rd] = val1 ^ val2,
-                        (funct3::SRL_SRA, funct7::DEFAULT) => {
-                            self.registers[rd] = val1.wrapping_shr(val2 as u32)
-                        }
-                        (funct3::SRL_SRA, funct7::SRA) => {
-                            self.registers[rd] = (val1 as i64).wrapping_shr(val2 as u32) as u64
-                        }
-                        (funct3::OR, funct7::DEFAULT) => self.registers[rd] = val1 | val2,
-                        (funct3::AND, funct7::DEFAULT) => self.registers[rd] = val1 & val2,
-                        // M Extension
-                        (funct3::MUL, funct7::MULDIV) => {
-                            self.registers[rd] = val1.wrapping_mul(val2)
-                        }
-                        (funct3::MULH, funct7::MULDIV) => {
-                            let result = (val1 as i64 as i128).wrapping_mul(val2 as i64 as i128);
-                            self.registers[rd] = (result >> 64) as u64;
-                        }
-                        (funct3::MULHSU, funct7::MULDIV) => {
-                            let result = (val1 as i64 as i128).wrapping_mul(val2 as u128 as i128);
-                            self.registers[rd] = (result >> 64) as u64;
-                        }
-                        (funct3::MULHU, funct7::MULDIV) => {
-                            let result = (val1 as u128).wrapping_mul(val2 as u128);
-                            self.registers[rd] = (result >> 64) as u64;
+                let result = match (funct3, funct7) {
+                    (funct3::ADD_SUB, funct7::DEFAULT) => val1.wrapping_add(val2),
+                    (funct3::ADD_SUB, funct7::SUB) => val1.wrapping_sub(val2),
+                    (funct3::SLL, funct7::DEFAULT) => val1.wrapping_shl(val2 as u32),
+                    (funct3::SLT, funct7::DEFAULT) => {
+                        if (val1 as i64) < (val2 as i64) {
+                            1
+                        } else {
+                            0
                         }
-                        (funct3::DIV, funct7::MULDIV) => {
-                            if val2 == 0 {
-                                self.registers[rd] = u64::MAX;
-                            } else {
-                                self.registers[rd] = (val1 as i64).wrapping_div(val2 as i64) as u64;
-                            }
+                    }
+                    (funct3::SLTU, funct7::DEFAULT) => {
+                        if val1 < val2 {
+                            1
+                        } else {
+                            0
                         }
-                        (funct3::DIVU, funct7::MULDIV) => {
-                            if val2 == 0 {
-                                self.registers[rd] = u64::MAX;
-                            } else {
-                                self.registers[rd] = val1.wrapping_div(val2);
-                            }
+                    }
+                    (funct3::XOR, funct7::DEFAULT) => val1 ^ val2,
+                    (funct3::SRL_SRA, funct7::DEFAULT) => val1.wrapping_shr(val2 as u32),
+                    (funct3::SRL_SRA, funct7::SRA) => {
+                        (val1 as i64).wrapping_shr(val2 as u32) as u64
+                    }
+                    (funct3::OR, funct7::DEFAULT) => val1 | val2,
+                    (funct3::AND, funct7::DEFAULT) => val1 & val2,
+                    // M Extension
+                    (funct3::MUL, funct7::MULDIV) => val1.wrapping_mul(val2),
+                    // The unsigned operands of the high-half multiplies are zero-extended
+                    // from XLEN, and the result is the upper XLEN bits of the product.
+                    (funct3::MULH, funct7::MULDIV) => {
+                        let result = (val1 as i64 as i128).wrapping_mul(val2 as i64 as i128);
+                        (result >> xlen.bits()) as u64
+                    }
+                    (funct3::MULHSU, funct7::MULDIV) => {
+                        let unsigned2 = (val2 & xlen.mask()) as i128;
+                        let result = (val1 as i64 as i128).wrapping_mul(unsigned2);
+                        (result >> xlen.bits()) as u64
+                    }
+                    (funct3::MULHU, funct7::MULDIV) => {
+                        let unsigned1 = (val1 & xlen.mask()) as u128;
+                        let result = unsigned1.wrapping_mul((val2 & xlen.mask()) as u128);
+                        (result >> xlen.bits()) as u64
+                    }
+                    (funct3::DIV, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            u64::MAX
+                        } else {
+                            (val1 as i64).wrapping_div(val2 as i64) as u64
                         }
-                        (funct3::REM, funct7::MULDIV) => {
-                            if val2 == 0 {
-                                self.registers[rd] = val1;
-                            } else {
-                                self.registers[rd] = (val1 as i64).wrapping_rem(val2 as i64) as u64;
-                            }
+                    }
+                    (funct3::DIVU, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            u64::MAX
+                        } else {
+                            val1.wrapping_div(val2)
                         }
-                        (funct3::REMU, funct7::MULDIV) => {
-                            if val2 == 0 {
-                                self.registers[rd] = val1;
-                            } else {
-                                self.registers[rd] = val1.wrapping_rem(val2);
-                            }
+                    }
+                    (funct3::REM, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            val1
+                        } else {
+                            (val1 as i64).wrapping_rem(val2 as i64) as u64
                         }
-                        _ => {
-                            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                    }
+                    (funct3::REMU, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            val1
+                        } else {
+                            val1.wrapping_rem(val2)
                         }
                     }
+                    _ => return self.execute_bitmanip(inst, next_pc),
+                };
+                if rd > 0 {
+                    self.registers[rd] = result;
                 }
             }
             opcodes::OP_REG_32 => {
@@ -423,69 +362,63 @@ impl VM {
 
                 let val1 = self.registers[rs1] as i32;
                 let val2 = self.registers[rs2] as i32;
-                if rd > 0 {
-                    match (funct3, funct7) {
-                        (funct3::ADD_SUB, funct7::DEFAULT) => {
-                            self.registers[rd] = val1.wrapping_add(val2) as i64 as u64;
-                        }
-                        (funct3::ADD_SUB, funct7::SUB) => {
-                            self.registers[rd] = val1.wrapping_sub(val2) as i64 as u64;
-                        }
-                        (funct3::SLL, funct7::DEFAULT) => {
-                            let shamt = (val2 & 0x1F) as u32;
-                            self.registers[rd] = (val1.wrapping_shl(shamt)) as i64 as u64;
-                        }
-                        (funct3::SRL_SRA, funct7::DEFAULT) => {
-                            let shamt = (val2 & 0x1F) as u32;
-                            self.registers[rd] =
-                                ((val1 as u32).wrapping_shr(shamt)) as i32 as i64 as u64;
-                        }
-                        (funct3::SRL_SRA, funct7::SRA) => {
-                            let shamt = (val2 & 0x1F) as u32;
-                            self.registers[rd] = (val1.wrapping_shr(shamt)) as i64 as u64;
-                        }
-                        (funct3::MUL, funct7::MULDIV) => {
-                            let result = (val1 as i64).wrapping_mul(val2 as i64) as i32;
-                            self.registers[rd] = result as i64 as u64;
-                        }
-                        (funct3::DIV, funct7::MULDIV) => {
-                            self.registers[rd] = if val2 == 0 {
-                                (-1i32) as i64 as u64
-                            } else if val1 == i32::MIN && val2 == -1 {
-                                (i32::MIN as i64) as u64
-                            } else {
-                                (val1.wrapping_div(val2)) as i64 as u64
-                            };
-                        }
-                        (funct3::DIVU, funct7::MULDIV) => {
-                            let lhs = self.registers[rs1] as u32;
-                            let rhs = self.registers[rs2] as u32;
-                            let result = if rhs == 0 {
-                                0xFFFF_FFFFu32
-                            } else {
-                                lhs.wrapping_div(rhs)
-                            };
-                            self.registers[rd] = (result as i32 as i64) as u64;
-                        }
-                        (funct3::REM, funct7::MULDIV) => {
-                            self.registers[rd] = if val2 == 0 {
-                                val1 as i64 as u64
-                            } else if val1 == i32::MIN && val2 == -1 {
-                                0
-                            } else {
-                                (val1.wrapping_rem(val2)) as i64 as u64
-                            };
-                        }
-                        (funct3::REMU, funct7::MULDIV) => {
-                            let lhs = self.registers[rs1] as u32;
-                            let rhs = self.registers[rs2] as u32;
-                            let result = if rhs == 0 { lhs } else { lhs.wrapping_rem(rhs) };
-                            self.registers[rd] = (result as i32 as i64) as u64;
+                let result = match (funct3, funct7) {
+                    (funct3::ADD_SUB, funct7::DEFAULT) => val1.wrapping_add(val2) as i64 as u64,
+                    (funct3::ADD_SUB, funct7::SUB) => val1.wrapping_sub(val2) as i64 as u64,
+                    (funct3::SLL, funct7::DEFAULT) => {
+                        let shamt = (val2 & 0x1F) as u32;
+                        (val1.wrapping_shl(shamt)) as i64 as u64
+                    }
+                    (funct3::SRL_SRA, funct7::DEFAULT) => {
+                        let shamt = (val2 & 0x1F) as u32;
+                        ((val1 as u32).wrapping_shr(shamt)) as i32 as i64 as u64
+                    }
+                    (funct3::SRL_SRA, funct7::SRA) => {
+                        let shamt = (val2 & 0x1F) as u32;
+                        (val1.wrapping_shr(shamt)) as i64 as u64
+                    }
+                    (funct3::MUL, funct7::MULDIV) => {
+                        let result = (val1 as i64).wrapping_mul(val2 as i64) as i32;
+                        result as i64 as u64
+                    }
+                    (funct3::DIV, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            (-1i32) as i64 as u64
+                        } else if val1 == i32::MIN && val2 == -1 {
+                            (i32::MIN as i64) as u64
+                        } else {
+                            (val1.wrapping_div(val2)) as i64 as u64
                         }
-                        _ => {
-                            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                    }
+                    (funct3::DIVU, funct7::MULDIV) => {
+                        let lhs = self.registers[rs1] as u32;
+                        let rhs = self.registers[rs2] as u32;
+                        let result = if rhs == 0 {
+                            0xFFFF_FFFFu32
+                        } else {
+                            lhs.wrapping_div(rhs)
+                        };
+                        (result as i32 as i64) as u64
+                    }
+                    (funct3::REM, funct7::MULDIV) => {
+                        if val2 == 0 {
+                            val1 as i64 as u64
+                        } else if val1 == i32::MIN && val2 == -1 {
+                            0
+                        } else {
+                            (val1.wrapping_rem(val2)) as i64 as u64
                         }
                     }
+                    (funct3::REMU, funct7::MULDIV) => {
+                        let lhs = self.registers[rs1] as u32;
+                        let rhs = self.registers[rs2] as u32;
+                        let result = if rhs == 0 { lhs } else { lhs.wrapping_rem(rhs) };
+                        (result as i32 as i64) as u64
+                    }
+                    _ => return self.execute_bitmanip(inst, next_pc),
+                };
+                if rd > 0 {
+                    self.registers[rd] = result;
                 }
             }
 
@@ -505,6 +438,9 @@ impl VM {
                 let funct3 = (inst >> 12) & 0x7;
                 let rs1 = ((inst >> 15) & 0x1F) as usize;
                 match funct3 {
+                    0b000 if inst >> 25 == funct7::SFENCE_VMA => {
+                        return self.execute_sfence_vma(inst, next_pc)
+                    }
                     0b000 => {
                         let funct12 = (inst >> 20) & 0xFFF;
                         match funct12 {
@@ -515,8 +451,23 @@ impl VM {
                                 );
                             }
                             system::FUNCT12_EBREAK => {
+                                if self.debug.halt_on_ebreak {
+                                    self.debug.stop = Some(DebugStop::Breakpoint);
+                                    return true;
+                                }
                                 return self.handle_trap(cause::BREAKPOINT, 0);
                             }
+                            system::FUNCT12_WFI => {
+                                // Interrupts are checked before every instruction, so waiting
+                                // is the same as retiring a NOP. U-mode, and S-mode with
+                                // mstatus.TW set, may not stall the hart.
+                                let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+                                let tw = mstatus & MSTATUS_TW != 0;
+                                if self.privilege_level == 0 || (self.privilege_level == 1 && tw) {
+                                    return self
+                                        .handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                                }
+                            }
                             system::FUNCT12_MRET => {
                                 let current_priv = self.privilege_level;
 
@@ -544,6 +495,10 @@ impl VM {
                                 new_mstatus = (new_mstatus & !(1 << 3)) | (mpie << 3);
                                 new_mstatus |= 1 << 7;
                                 new_mstatus &= !(0b11 << 11);
+                                // Leaving M-mode also ends any MPRV-redirected accesses.
+                                if new_priv_level != 3 {
+                                    new_mstatus &= !MSTATUS_MPRV;
+                                }
 
                                 if !self.csrs.write(csr::MSTATUS, new_mstatus, current_priv) {
                                     return self
@@ -553,23 +508,26 @@ impl VM {
                                 self.privilege_level = new_priv_level;
                             }
                             system::FUNCT12_SRET => {
-                                let sstatus =
-                                    self.csrs.read(csr::SSTATUS, self.privilege_level).unwrap();
-
-                                self.privilege_level = ((sstatus >> 8) & 0b1) as u8;
+                                // mstatus.TSR lets M-mode catch S-mode's returns.
+                                let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+                                let tsr = mstatus & MSTATUS_TSR != 0;
+                                if self.privilege_level < 1 || (self.privilege_level == 1 && tsr) {
+                                    return self
+                                        .handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                                }
 
+                                let sstatus = self.csrs.read(csr::SSTATUS, 3).unwrap_or(0);
                                 let spie = (sstatus >> 5) & 1;
 
-                                let mut mstatus =
-                                    self.csrs.read(csr::MSTATUS, self.privilege_level).unwrap();
-
+                                let mut mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
                                 mstatus = (mstatus & !(1 << 1)) | (spie << 1);
                                 mstatus |= 1 << 5;
                                 mstatus &= !(1 << 8);
+                                mstatus &= !MSTATUS_MPRV;
+                                self.csrs.write(csr::MSTATUS, mstatus, 3);
 
-                                self.csrs.write(csr::MSTATUS, mstatus, self.privilege_level);
-
-                                next_pc = self.csrs.read(csr::SEPC, self.privilege_level).unwrap();
+                                next_pc = self.csrs.read(csr::SEPC, 3).unwrap_or(0);
+                                self.privilege_level = ((sstatus >> 8) & 0b1) as u8;
                             }
                             _ => {
                                 return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
@@ -582,8 +540,12 @@ impl VM {
                     | funct3::CSRRWI
                     | funct3::CSRRSI
                     | funct3::CSRRCI => {
-                        let csr_addr = (inst >> 20) as u32;
+                        let csr_addr = inst >> 20;
+                        if csr_addr == csr::SEED {
+                            return self.execute_seed_access(inst, next_pc);
+                        }
                         let old_val = match self.csrs.read(csr_addr, self.privilege_level) {
+                            Some(val) if xlen == Xlen::Rv32 => narrow_csr(csr_addr, val),
                             Some(val) => val,
                             None => {
                                 return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
@@ -596,16 +558,33 @@ impl VM {
                             self.registers[rs1]
                         };
 
+                        // Setting or clearing mip bits starts from the software-written
+                        // bits, so the PLIC's SEIP line is not latched into the CSR.
+                        let base = if csr_addr == csr::MIP {
+                            self.csrs.mip
+                        } else {
+                            old_val
+                        };
                         let new_val = match funct3 & 0b011 {
                             funct3::CSRRW => write_val,
-                            funct3::CSRRS => old_val | write_val,
-                            funct3::CSRRC => old_val & !write_val,
+                            funct3::CSRRS => base | write_val,
+                            funct3::CSRRC => base & !write_val,
                             _ => unreachable!(),
                         };
+                        let new_val = match xlen {
+                            Xlen::Rv32 => widen_csr(csr_addr, new_val),
+                            Xlen::Rv64 => new_val,
+                        };
 
-                        if !self.csrs.write(csr_addr, new_val, self.privilege_level) {
+                        // csrrs and csrrc with x0 (or a zero immediate) only read the CSR.
+                        let writes = funct3 & 0b011 == funct3::CSRRW || rs1 != 0;
+                        let old_satp = self.csrs.satp;
+                        if writes && !self.csrs.write(csr_addr, new_val, self.privilege_level) {
                             return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
                         }
+                        if self.csrs.satp != old_satp {
+                            self.satp_written(old_satp);
+                        }
 
                         if rd > 0 {
                             self.registers[rd] = old_val;
@@ -624,4 +603,337 @@ impl VM {
         self.pc = next_pc;
         true
     }
+
+    /// Executes an A-extension instruction. There is only one hart and accesses are never
+    /// reordered, so the `aq` and `rl` bits need no handling.
+    fn execute_amo(&mut self, inst: u32, next_pc: u64) -> bool {
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+        let funct5 = inst >> 27;
+
+        let size = match funct3 {
+            funct3::AMO_W => 4,
+            funct3::AMO_D => 8,
+            _ => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+        };
+        let is_valid_op = matches!(
+            funct5,
+            funct7::SC
+                | funct7::AMOSWAP
+                | funct7::AMOADD
+                | funct7::AMOXOR
+                | funct7::AMOAND
+                | funct7::AMOOR
+                | funct7::AMOMIN
+                | funct7::AMOMAX
+                | funct7::AMOMINU
+                | funct7::AMOMAXU
+        ) || (funct5 == funct7::LR && rs2 == 0);
+        if !is_valid_op {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+
+        let vaddr = self.registers[rs1];
+        let is_lr = funct5 == funct7::LR;
+        // An AMO both reads and writes its operand.
+        let watch_hit = match funct5 {
+            funct7::LR => self.debug.check_watchpoints(vaddr, size, false),
+            funct7::SC => self.debug.check_watchpoints(vaddr, size, true),
+            _ => {
+                self.debug.check_watchpoints(vaddr, size, false)
+                    || self.debug.check_watchpoints(vaddr, size, true)
+            }
+        };
+        if watch_hit {
+            return true;
+        }
+
+        // LR faults as a load; SC and the AMOs fault as stores.
+        let access = if is_lr {
+            AccessType::Read
+        } else {
+            AccessType::Write
+        };
+        let access_fault = TranslateFault::AccessFault(vaddr).cause(access);
+        let paddr = match self.translate_access(vaddr, size, access) {
+            Ok(addr) => addr,
+            Err(fault) => return self.handle_trap(fault.cause(access), fault.vaddr()),
+        };
+        // Sign-extends a loaded word to XLEN, as every A-extension result is.
+        let extend = |value: u64| {
+            if size == 4 {
+                value as i32 as i64 as u64
+            } else {
+                value
+            }
+        };
+
+        let result = match funct5 {
+            funct7::LR => {
+                let Some(value) = self.read_physical(paddr, size, AccessType::Read) else {
+                    return self.handle_trap(access_fault, vaddr);
+                };
+                self.reservation = Some(paddr & !(RESERVATION_GRANULE - 1));
+                extend(value)
+            }
+            funct7::SC => {
+                let reserved = self.reservation.take() == Some(paddr & !(RESERVATION_GRANULE - 1));
+                if reserved {
+                    if !self.write_physical(paddr, size, self.registers[rs2]) {
+                        return self.handle_trap(access_fault, vaddr);
+                    }
+                    0
+                } else {
+                    1
+                }
+            }
+            _ => {
+                // An AMO both reads and writes, so PMP must grant both.
+                let Some(loaded) = self
+                    .read_physical(paddr, size, AccessType::Read)
+                    .filter(|_| self.pmp_allows(paddr, size, AccessType::Write))
+                else {
+                    return self.handle_trap(access_fault, vaddr);
+                };
+                let old = extend(loaded);
+                let src = extend(self.registers[rs2]);
+                let new = match funct5 {
+                    funct7::AMOSWAP => src,
+                    funct7::AMOADD => old.wrapping_add(src),
+                    funct7::AMOXOR => old ^ src,
+                    funct7::AMOAND => old & src,
+                    funct7::AMOOR => old | src,
+                    funct7::AMOMIN => (old as i64).min(src as i64) as u64,
+                    funct7::AMOMAX => (old as i64).max(src as i64) as u64,
+                    // Word operands compare as 32-bit unsigned values, not sign-extended ones.
+                    funct7::AMOMINU if size == 4 => (old as u32).min(src as u32) as u64,
+                    funct7::AMOMAXU if size == 4 => (old as u32).max(src as u32) as u64,
+                    funct7::AMOMINU => old.min(src),
+                    funct7::AMOMAXU => old.max(src),
+                    _ => unreachable!(),
+                };
+                if !self.write_physical(paddr, size, new) {
+                    return self.handle_trap(access_fault, vaddr);
+                }
+                self.invalidate_reservation(paddr, size);
+                old
+            }
+        };
+
+        if rd > 0 {
+            self.registers[rd] = result;
+        }
+        self.pc = next_pc;
+        true
+    }
+
+    /// Drops the `lr` reservation if a store of `size` bytes at `paddr` overlaps it.
+    pub(crate) fn invalidate_reservation(&mut self, paddr: u64, size: u64) {
+        if let Some(granule) = self.reservation
+            && paddr < granule + RESERVATION_GRANULE
+            && granule < paddr + size
+        {
+            self.reservation = None;
+        }
+    }
+}
+
+/// Size and alignment of the block of memory an `lr` reserves.
+const RESERVATION_GRANULE: u64 = 8;
+
+/// Number of bytes accessed by the load or store with the given `funct3`.
+fn access_size(funct3: u32) -> u64 {
+    1 << (funct3 & 0b11)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+
+    const DATA: u64 = BASE_ADDRESS + 0x100;
+
+    fn vm_with_program(program: &[u32]) -> VM {
+        let mut vm = VM::new();
+        for (i, &inst) in program.iter().enumerate() {
+            vm.bus.write(BASE_ADDRESS + 4 * i as u64, 4, inst as u64);
+        }
+        vm.registers[11] = DATA;
+        vm
+    }
+
+    #[test]
+    fn test_lr_sc_succeeds_unless_a_store_intervenes() {
+        let mut vm = vm_with_program(&[
+            0x1005b52f, // lr.d a0, (a1)
+            0x18c5b6af, // sc.d a3, a2, (a1)
+            0x1005b52f, // lr.d a0, (a1)
+            0x00e5b023, // sd a4, 0(a1)
+            0x18c5b6af, // sc.d a3, a2, (a1)
+        ]);
+        vm.bus.write(DATA, 8, 7);
+        vm.registers[12] = 42;
+        vm.registers[14] = 9;
+
+        for _ in 0..2 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!(vm.registers[10], 7);
+        assert_eq!(vm.registers[13], 0);
+        assert_eq!(vm.bus.read(DATA, 8), Some(42));
+
+        for _ in 0..3 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!(vm.registers[13], 1);
+        assert_eq!(vm.bus.read(DATA, 8), Some(9));
+    }
+
+    #[test]
+    fn test_word_amos_sign_extend_and_compare_unsigned() {
+        let mut vm = vm_with_program(&[
+            0xe0c5a52f, // amomaxu.w a0, a2, (a1)
+            0x00c5a6af, // amoadd.w a3, a2, (a1)
+        ]);
+        vm.bus.write(DATA, 4, 0xFFFF_FFFE);
+        vm.registers[12] = 1;
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], 0xFFFF_FFFF_FFFF_FFFE);
+        assert_eq!(vm.bus.read(DATA, 4), Some(0xFFFF_FFFE));
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[13], 0xFFFF_FFFF_FFFF_FFFE);
+        assert_eq!(vm.bus.read(DATA, 8), Some(0xFFFF_FFFF));
+    }
+
+    #[test]
+    fn test_misaligned_amo_raises_store_amo_misaligned() {
+        let mut vm = vm_with_program(&[0x00c5b52f]); // amoadd.d a0, a2, (a1)
+        vm.registers[11] = DATA + 4;
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x200);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::STORE_AMO_ADDRESS_MISALIGNED)
+        );
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(DATA + 4));
+    }
+
+    #[test]
+    fn test_compressed_instructions_advance_pc_by_two() {
+        let mut vm = VM::new();
+        vm.bus.write(BASE_ADDRESS, 2, 0x4515); // c.li a0, 5
+        vm.bus.write(BASE_ADDRESS + 2, 2, 0x9582); // c.jalr a1
+        vm.bus.write(BASE_ADDRESS + 4, 2, 0x0000); // the illegal all-zero halfword
+        vm.registers[11] = BASE_ADDRESS + 0x40;
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!((vm.registers[10], vm.pc), (5, BASE_ADDRESS + 2));
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            (vm.registers[1], vm.pc),
+            (BASE_ADDRESS + 4, BASE_ADDRESS + 0x40)
+        );
+
+        vm.pc = BASE_ADDRESS + 4;
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+    }
+
+    #[test]
+    fn test_fetch_of_an_instruction_straddling_two_pages() {
+        const ROOT: u64 = BASE_ADDRESS + 0x10000;
+        const PTE_VRX_AD: u64 = 0xCB;
+        let mut vm = VM::new();
+        let pointer = |table: u64| ((table >> 12) << 10) | 1;
+        vm.bus.write(ROOT, 8, pointer(ROOT + 0x1000));
+        vm.bus.write(ROOT + 0x1000, 8, pointer(ROOT + 0x2000));
+        // Virtual pages 0 and 1 map to physical pages that are not adjacent.
+        let leaf = |page: u64| ((page >> 12) << 10) | PTE_VRX_AD;
+        vm.bus.write(ROOT + 0x2000, 8, leaf(BASE_ADDRESS + 0x3000));
+        vm.bus.write(ROOT + 0x2008, 8, leaf(BASE_ADDRESS + 0x5000));
+        vm.csrs.write(csr::SATP, (8 << 60) | (ROOT >> 12), 3);
+        // M-mode fetches are not translated.
+        vm.privilege_level = 1;
+        vm.open_pmp();
+
+        vm.bus.write(BASE_ADDRESS + 0x3FFC, 2, 0x4515); // c.li a0, 5
+        vm.bus.write(BASE_ADDRESS + 0x3FFE, 2, 0x0513); // addi a0, a0, 7 (low half)
+        vm.bus.write(BASE_ADDRESS + 0x5000, 2, 0x0075); // addi a0, a0, 7 (high half)
+        vm.pc = 0xFFC;
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.step(), None);
+        assert_eq!((vm.registers[10], vm.pc), (12, 0x1002));
+
+        // With the second page unmapped, the fault is reported at its first byte.
+        vm.bus.write(ROOT + 0x2008, 8, 0);
+        vm.tlb.clear();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        vm.pc = 0xFFE;
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::INSTRUCTION_PAGE_FAULT)
+        );
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(0x1000));
+        assert_eq!(vm.csrs.read(csr::MEPC, 3), Some(0xFFE));
+    }
+
+    #[test]
+    fn test_jumps_to_halfword_targets_trap_without_c() {
+        let mut vm = VM::new_config(VmConfig {
+            isa: "rv32im".parse().unwrap(),
+            ..VmConfig::default()
+        });
+        vm.bus.write(BASE_ADDRESS, 4, 0x0000_1363); // bne zero, zero, 6
+        vm.bus.write(BASE_ADDRESS + 4, 4, 0x0020_80E7); // jalr ra, 2(ra)
+        vm.bus.write(BASE_ADDRESS + 8, 4, 0x0000_0363); // beq zero, zero, 6
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        vm.registers[1] = BASE_ADDRESS + 0x40;
+
+        // A branch that is not taken never checks its target.
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 4);
+
+        // The jump traps before writing ra.
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x200);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::INSTRUCTION_ADDRESS_MISALIGNED)
+        );
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(BASE_ADDRESS + 0x42));
+        assert_eq!(vm.csrs.read(csr::MEPC, 3), Some(BASE_ADDRESS + 4));
+        assert_eq!(vm.registers[1] as u32 as u64, BASE_ADDRESS + 0x40);
+
+        vm.pc = BASE_ADDRESS + 8;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(BASE_ADDRESS + 0xE));
+
+        // A pc that is only halfword aligned faults on fetch.
+        vm.pc = BASE_ADDRESS + 2;
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::INSTRUCTION_ADDRESS_MISALIGNED)
+        );
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(BASE_ADDRESS + 2));
+
+        // Without C the exception PCs are four-byte aligned.
+        vm.csrs.write(csr::MEPC, BASE_ADDRESS + 3, 3);
+        assert_eq!(vm.csrs.read(csr::MEPC, 3), Some(BASE_ADDRESS));
+        vm.csrs.write(csr::SEPC, BASE_ADDRESS + 6, 3);
+        assert_eq!(vm.csrs.read(csr::SEPC, 3), Some(BASE_ADDRESS + 4));
+    }
 }
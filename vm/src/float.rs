@@ -0,0 +1,385 @@
+use crate::csr::FRM_SHIFT;
+use crate::pmp::AccessType;
+use crate::softfloat::{Format, RoundingMode, SoftFloat, F32, F64};
+use crate::VM;
+use riscv_core::{cause, funct3, funct7, opcodes};
+
+/// The upper half of an FP register holding a single-precision value.
+const NAN_BOX: u64 = 0xFFFF_FFFF_0000_0000;
+
+impl VM {
+    /// Reads `reg` as a value of `fmt`. A single-precision value that is not properly
+    /// NaN-boxed reads as the canonical NaN.
+    fn read_fp(&self, reg: usize, fmt: Format) -> u64 {
+        let bits = self.fregs[reg];
+        if fmt == F64 {
+            bits
+        } else if bits & NAN_BOX == NAN_BOX {
+            bits & !NAN_BOX
+        } else {
+            F32.canonical_nan()
+        }
+    }
+
+    fn write_fp(&mut self, reg: usize, fmt: Format, bits: u64) {
+        self.fregs[reg] = if fmt == F64 { bits } else { bits | NAN_BOX };
+        self.csrs.mark_fp_dirty();
+    }
+
+    fn write_int(&mut self, rd: usize, value: u64) {
+        if rd > 0 {
+            self.registers[rd] = value;
+        }
+    }
+
+    fn accrue_fp_flags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csrs.fcsr |= flags;
+            self.csrs.mark_fp_dirty();
+        }
+    }
+
+    /// Resolves the instruction's `rm` field, where `DYN` selects `frm`. `None` for the
+    /// reserved encodings, which make the instruction illegal.
+    fn rounding_mode(&self, inst: u32) -> Option<RoundingMode> {
+        let rm = (inst >> 12) & 0x7;
+        if rm == funct3::RM_DYN {
+            RoundingMode::from_bits(self.csrs.fcsr >> FRM_SHIFT)
+        } else {
+            RoundingMode::from_bits(rm as u64)
+        }
+    }
+
+    /// Executes an F or D extension instruction. All of them are illegal while mstatus.FS
+    /// is Off.
+    pub(crate) fn execute_fp(&mut self, inst: u32, next_pc: u64) -> bool {
+        if !self.csrs.fp_enabled() {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+        match inst & 0x7F {
+            opcodes::OP_LOAD_FP => return self.execute_fp_load(inst, next_pc),
+            opcodes::OP_STORE_FP => return self.execute_fp_store(inst, next_pc),
+            opcodes::OP_MADD | opcodes::OP_MSUB | opcodes::OP_NMSUB | opcodes::OP_NMADD => {
+                if !self.execute_fused(inst) {
+                    return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                }
+            }
+            _ => {
+                if !self.execute_fp_op(inst) {
+                    return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+                }
+            }
+        }
+        self.pc = next_pc;
+        true
+    }
+
+    fn execute_fp_load(&mut self, inst: u32, next_pc: u64) -> bool {
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let imm = (inst as i32 >> 20) as i64 as u64;
+        let vaddr = self.registers[rs1].wrapping_add(imm);
+
+        let (fmt, size) = match funct3 {
+            funct3::FLW => (F32, 4),
+            funct3::FLD => (F64, 8),
+            _ => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+        };
+        if self.debug.check_watchpoints(vaddr, size, false) {
+            return true;
+        }
+
+        let paddr = match self.translate_access(vaddr, size, AccessType::Read) {
+            Ok(addr) => addr,
+            Err(fault) => return self.handle_trap(fault.cause(AccessType::Read), fault.vaddr()),
+        };
+        let Some(value) = self.read_physical(paddr, size, AccessType::Read) else {
+            return self.handle_trap(cause::LOAD_ACCESS_FAULT, vaddr);
+        };
+
+        self.write_fp(rd, fmt, value);
+        self.pc = next_pc;
+        true
+    }
+
+    fn execute_fp_store(&mut self, inst: u32, next_pc: u64) -> bool {
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+        let imm4_0 = (inst >> 7) & 0x1F;
+        let imm11_5 = (inst >> 25) & 0x7F;
+        let imm = (((imm11_5 << 5) | imm4_0) as i32) << 20 >> 20;
+        let vaddr = self.registers[rs1].wrapping_add(imm as i64 as u64);
+
+        // Stores move the raw register bits; fsw does not check the NaN box.
+        let size = match funct3 {
+            funct3::FSW => 4,
+            funct3::FSD => 8,
+            _ => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+        };
+        if self.debug.check_watchpoints(vaddr, size, true) {
+            return true;
+        }
+
+        let paddr = match self.translate_access(vaddr, size, AccessType::Write) {
+            Ok(addr) => addr,
+            Err(fault) => return self.handle_trap(fault.cause(AccessType::Write), fault.vaddr()),
+        };
+        if !self.write_physical(paddr, size, self.fregs[rs2]) {
+            return self.handle_trap(cause::STORE_AMO_ACCESS_FAULT, vaddr);
+        }
+        self.invalidate_reservation(paddr, size);
+
+        self.pc = next_pc;
+        true
+    }
+
+    /// `fmadd`, `fmsub`, `fnmsub` and `fnmadd`. Returns `false` for an illegal encoding.
+    fn execute_fused(&mut self, inst: u32) -> bool {
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+        let rs3 = (inst >> 27) as usize;
+        let fmt = match (inst >> 25) & 0b11 {
+            funct7::FMT_S => F32,
+            funct7::FMT_D => F64,
+            _ => return false,
+        };
+        let Some(rm) = self.rounding_mode(inst) else {
+            return false;
+        };
+
+        let sign = fmt.sign_bit();
+        let (a, b, c) = (
+            self.read_fp(rs1, fmt),
+            self.read_fp(rs2, fmt),
+            self.read_fp(rs3, fmt),
+        );
+        let mut sf = SoftFloat::new(fmt, rm);
+        let result = match inst & 0x7F {
+            opcodes::OP_MADD => sf.mul_add(a, b, c),
+            opcodes::OP_MSUB => sf.mul_add(a, b, c ^ sign),
+            opcodes::OP_NMSUB => sf.mul_add(a ^ sign, b, c),
+            _ => sf.mul_add(a ^ sign, b, c ^ sign),
+        };
+        self.write_fp(rd, fmt, result);
+        self.accrue_fp_flags(sf.flags);
+        true
+    }
+
+    /// Everything on the OP-FP major opcode. Returns `false` for an illegal encoding.
+    fn execute_fp_op(&mut self, inst: u32) -> bool {
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let funct3 = (inst >> 12) & 0x7;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+        let funct5 = inst >> 27;
+        let fmt = match (inst >> 25) & 0b11 {
+            funct7::FMT_S => F32,
+            funct7::FMT_D => F64,
+            _ => return false,
+        };
+        let (signed, width) = match rs2 {
+            0 => (true, 32),
+            1 => (false, 32),
+            2 => (true, 64),
+            _ => (false, 64),
+        };
+
+        match funct5 {
+            funct7::FADD | funct7::FSUB | funct7::FMUL | funct7::FDIV | funct7::FSQRT => {
+                if funct5 == funct7::FSQRT && rs2 != 0 {
+                    return false;
+                }
+                let Some(rm) = self.rounding_mode(inst) else {
+                    return false;
+                };
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+                let mut sf = SoftFloat::new(fmt, rm);
+                let result = match funct5 {
+                    funct7::FADD => sf.add(a, b),
+                    funct7::FSUB => sf.sub(a, b),
+                    funct7::FMUL => sf.mul(a, b),
+                    funct7::FDIV => sf.div(a, b),
+                    _ => sf.sqrt(a),
+                };
+                self.write_fp(rd, fmt, result);
+                self.accrue_fp_flags(sf.flags);
+            }
+            funct7::FSGNJ => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+                let sign = fmt.sign_bit();
+                let new_sign = match funct3 {
+                    0b000 => b & sign,
+                    0b001 => !b & sign,
+                    0b010 => (a ^ b) & sign,
+                    _ => return false,
+                };
+                self.write_fp(rd, fmt, (a & !sign) | new_sign);
+            }
+            funct7::FMIN_MAX => {
+                if funct3 > 0b001 {
+                    return false;
+                }
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+                let mut sf = SoftFloat::new(fmt, RoundingMode::NearestEven);
+                let result = sf.min_max(a, b, funct3 == 0b001);
+                self.write_fp(rd, fmt, result);
+                self.accrue_fp_flags(sf.flags);
+            }
+            funct7::FCVT_FMT_FMT => {
+                let from = match (fmt == F64, rs2) {
+                    (true, 0) => F32,
+                    (false, 1) => F64,
+                    _ => return false,
+                };
+                let Some(rm) = self.rounding_mode(inst) else {
+                    return false;
+                };
+                let mut sf = SoftFloat::new(fmt, rm);
+                let result = sf.convert(self.read_fp(rs1, from), from);
+                self.write_fp(rd, fmt, result);
+                self.accrue_fp_flags(sf.flags);
+            }
+            funct7::FCMP => {
+                let (a, b) = (self.read_fp(rs1, fmt), self.read_fp(rs2, fmt));
+                let mut sf = SoftFloat::new(fmt, RoundingMode::NearestEven);
+                let result = match funct3 {
+                    0b010 => sf.eq(a, b),
+                    0b001 => sf.lt(a, b, false),
+                    0b000 => sf.lt(a, b, true),
+                    _ => return false,
+                };
+                self.write_int(rd, result as u64);
+                self.accrue_fp_flags(sf.flags);
+            }
+            funct7::FCVT_INT_FMT | funct7::FCVT_FMT_INT => {
+                if rs2 > 3 {
+                    return false;
+                }
+                let Some(rm) = self.rounding_mode(inst) else {
+                    return false;
+                };
+                let mut sf = SoftFloat::new(fmt, rm);
+                if funct5 == funct7::FCVT_INT_FMT {
+                    let result = sf.to_int(self.read_fp(rs1, fmt), signed, width);
+                    self.write_int(rd, result);
+                } else {
+                    let result = sf.from_int(self.registers[rs1], signed, width);
+                    self.write_fp(rd, fmt, result);
+                }
+                self.accrue_fp_flags(sf.flags);
+            }
+            funct7::FMV_X_FMT_FCLASS if rs2 == 0 => match funct3 {
+                // fmv.x.w copies the low 32 bits as they are, NaN box or not.
+                0b000 if fmt == F32 => self.write_int(rd, self.fregs[rs1] as i32 as i64 as u64),
+                0b000 => self.write_int(rd, self.fregs[rs1]),
+                0b001 => self.write_int(rd, fmt.classify(self.read_fp(rs1, fmt))),
+                _ => return false,
+            },
+            funct7::FMV_FMT_X if rs2 == 0 && funct3 == 0 => {
+                let value = if fmt == F32 {
+                    self.registers[rs1] & !NAN_BOX
+                } else {
+                    self.registers[rs1]
+                };
+                self.write_fp(rd, fmt, value);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::MSTATUS_FS;
+    use crate::memory::BASE_ADDRESS;
+    use riscv_core::csr;
+
+    fn vm_with_program(program: &[u32]) -> VM {
+        let mut vm = VM::new();
+        for (i, &inst) in program.iter().enumerate() {
+            vm.bus.write(BASE_ADDRESS + 4 * i as u64, 4, inst as u64);
+        }
+        vm.csrs.write(csr::MSTATUS, 1 << 13, 3); // FS = Initial
+        vm
+    }
+
+    #[test]
+    fn test_single_precision_results_are_nan_boxed() {
+        let mut vm = vm_with_program(&[
+            0x00b50053, // fadd.s ft0, fa0, fa1 (rne)
+            0xe00000d3, // fmv.x.w ra, ft0
+        ]);
+        vm.fregs[10] = NAN_BOX | 1.5f32.to_bits() as u64;
+        vm.fregs[11] = NAN_BOX | 2.25f32.to_bits() as u64;
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.fregs[0], NAN_BOX | 3.75f32.to_bits() as u64);
+        assert_eq!(vm.csrs.mstatus & MSTATUS_FS, MSTATUS_FS);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[1], 3.75f32.to_bits() as u64);
+
+        // An improperly boxed operand reads as the canonical NaN.
+        let mut vm = vm_with_program(&[0x00b50053]);
+        vm.fregs[10] = 1.5f32.to_bits() as u64;
+        vm.fregs[11] = NAN_BOX;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.fregs[0], NAN_BOX | 0x7FC0_0000);
+    }
+
+    #[test]
+    fn test_dynamic_rounding_and_accrued_flags() {
+        let mut vm = vm_with_program(&[
+            0x1ab57053, // fdiv.d ft0, fa0, fa1 (dyn)
+            0xc2207553, // fcvt.l.d a0, ft0 (dyn)
+        ]);
+        vm.fregs[10] = 1.0f64.to_bits();
+        vm.fregs[11] = 3.0f64.to_bits();
+        vm.csrs.write(csr::FRM, 3, 3); // round up
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.fregs[0], (1.0f64 / 3.0).next_up().to_bits());
+        assert_eq!(vm.csrs.read(csr::FFLAGS, 3), Some(1));
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers[10], 1);
+    }
+
+    #[test]
+    fn test_fp_is_illegal_while_fs_is_off() {
+        let mut vm = vm_with_program(&[0x00b50053]); // fadd.s ft0, fa0, fa1
+        vm.csrs.write(csr::MSTATUS, 0, 3);
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+
+        assert_eq!(vm.csrs.read(csr::FCSR, 3), None);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x100);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+    }
+
+    #[test]
+    fn test_fused_multiply_add_and_loads() {
+        let mut vm = vm_with_program(&[
+            0x0006b007, // fld ft0, 0(a3)
+            0x0406a087, // flw ft1, 64(a3)
+            0x6a0071c3, // fmadd.d ft3, ft0, ft0, fa3 (dyn)
+        ]);
+        vm.registers[13] = BASE_ADDRESS + 0x100;
+        vm.bus.write(BASE_ADDRESS + 0x100, 8, 3.0f64.to_bits());
+        vm.bus
+            .write(BASE_ADDRESS + 0x140, 4, 0.5f32.to_bits() as u64);
+        vm.fregs[13] = (-1.0f64).to_bits();
+
+        for _ in 0..3 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!(vm.fregs[1], NAN_BOX | 0.5f32.to_bits() as u64);
+        assert_eq!(vm.fregs[3], 8.0f64.to_bits());
+    }
+}
@@ -0,0 +1,784 @@
+use crate::debug::{DebugStop, WatchKind, Watchpoint};
+use crate::isa::{Isa, Xlen};
+use crate::rv32::{narrow_csr, widen_csr};
+use crate::VM;
+use riscv_core::csr;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// GDB's register numbers for RISC-V: x0-x31, then pc, then the FPRs, then every CSR at
+/// `FIRST_CSR_REGNUM + csr`, then the virtual `priv` register.
+const PC_REGNUM: usize = 32;
+const FIRST_FP_REGNUM: usize = 33;
+const FIRST_CSR_REGNUM: usize = 65;
+const PRIV_REGNUM: usize = FIRST_CSR_REGNUM + 4096;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Interrupt request GDB sends outside of a packet when the user presses Ctrl-C.
+const INTERRUPT_BYTE: u8 = 0x03;
+
+/// How many instructions run between checks for a Ctrl-C from GDB.
+const INTERRUPT_POLL_INTERVAL: u64 = 4096;
+
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// The FP CSRs, which GDB expects in the FPU feature rather than the CSR one.
+const FP_CSRS: [(u32, &str); 3] = [
+    (csr::FFLAGS, "fflags"),
+    (csr::FRM, "frm"),
+    (csr::FCSR, "fcsr"),
+];
+
+/// CSRs described in the target XML. GDB can still access any other CSR by number.
+const DESCRIBED_CSRS: [(u32, &str); 20] = [
+    (csr::SSTATUS, "sstatus"),
+    (csr::SIE, "sie"),
+    (csr::STVEC, "stvec"),
+    (csr::SSCRATCH, "sscratch"),
+    (csr::SEPC, "sepc"),
+    (csr::SCAUSE, "scause"),
+    (csr::STVAL, "stval"),
+    (csr::SIP, "sip"),
+    (csr::SATP, "satp"),
+    (csr::MSTATUS, "mstatus"),
+    (csr::MISA, "misa"),
+    (csr::MEDELEG, "medeleg"),
+    (csr::MIDELEG, "mideleg"),
+    (csr::MIE, "mie"),
+    (csr::MTVEC, "mtvec"),
+    (csr::MSCRATCH, "mscratch"),
+    (csr::MEPC, "mepc"),
+    (csr::MCAUSE, "mcause"),
+    (csr::MTVAL, "mtval"),
+    (csr::MIP, "mip"),
+];
+
+/// How a debugging session ended.
+pub enum SessionEnd {
+    /// GDB detached; the guest should keep running on its own.
+    Detached,
+    /// GDB killed the inferior or went away.
+    Killed,
+    /// The guest halted while under the debugger.
+    Halted(Result<(), String>),
+}
+
+/// A connection GDB can talk to us over.
+pub trait GdbConnection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl GdbConnection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl GdbConnection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Waits for GDB to connect on `address` and runs a session with it. `address` is either
+/// `unix:<path>` for a Unix socket or `[host:]port` for TCP, with the host defaulting to
+/// 127.0.0.1.
+pub fn listen(vm: &mut VM, address: &str) -> io::Result<SessionEnd> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        eprintln!("VM: Waiting for GDB on unix socket {}...", path);
+        let (stream, _) = listener.accept()?;
+        GdbSession::new(vm, stream).run()
+    } else {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("127.0.0.1:{}", address)
+        };
+        let listener = TcpListener::bind(&address)?;
+        eprintln!("VM: Waiting for GDB on {}...", address);
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        GdbSession::new(vm, stream).run()
+    }
+}
+
+/// The RISC-V target description GDB uses to lay out registers for `isa`. The integer
+/// registers and CSRs are XLEN bits wide, and the FPU feature is only described when the
+/// hart has F.
+fn target_xml(isa: &Isa) -> String {
+    let xlen = isa.xlen().bits();
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>riscv:rv{}</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+        xlen
+    );
+    for (regnum, name) in ABI_NAMES.iter().enumerate() {
+        let reg_type = match *name {
+            "ra" => "code_ptr",
+            "sp" | "gp" | "tp" | "fp" => "data_ptr",
+            _ => "int",
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+            name, xlen, reg_type, regnum
+        ));
+    }
+    xml.push_str(&format!(
+        "<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"{}\"/>",
+        xlen, PC_REGNUM
+    ));
+    xml.push_str("</feature>");
+    if isa.has_base('f') {
+        let (flen, fp_type) = if isa.has_base('d') {
+            (64, "ieee_double")
+        } else {
+            (32, "ieee_single")
+        };
+        xml.push_str("<feature name=\"org.gnu.gdb.riscv.fpu\">");
+        for (index, name) in FP_ABI_NAMES.iter().enumerate() {
+            xml.push_str(&format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+                name,
+                flen,
+                fp_type,
+                FIRST_FP_REGNUM + index
+            ));
+        }
+        for (addr, name) in FP_CSRS {
+            xml.push_str(&format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+                name,
+                xlen,
+                FIRST_CSR_REGNUM + addr as usize
+            ));
+        }
+        xml.push_str("</feature>");
+    }
+    xml.push_str("<feature name=\"org.gnu.gdb.riscv.csr\">");
+    for (addr, name) in DESCRIBED_CSRS {
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+            name,
+            xlen,
+            FIRST_CSR_REGNUM + addr as usize
+        ));
+    }
+    xml.push_str(&format!(
+        "</feature><feature name=\"org.gnu.gdb.riscv.virtual\">\
+         <reg name=\"priv\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>\
+         </feature></target>",
+        xlen, PRIV_REGNUM
+    ));
+    xml
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// Parses the `addr,length` that most memory and breakpoint packets start with.
+fn parse_addr_len(text: &str) -> Option<(u64, u64)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// Decodes a register value GDB sent as `size` little-endian bytes.
+fn decode_register(hex: &str, size: usize) -> Option<u64> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != size {
+        return None;
+    }
+    let mut value = [0; 8];
+    value[..size].copy_from_slice(&bytes);
+    Some(u64::from_le_bytes(value))
+}
+
+/// Why the last resume stopped, in a form that can be turned into a stop reply.
+enum StopReason {
+    Signal(u8),
+    Watchpoint { kind: WatchKind, addr: u64 },
+    Halted(Result<(), String>),
+}
+
+struct GdbSession<'a, S: GdbConnection> {
+    vm: &'a mut VM,
+    stream: S,
+    /// Bytes received from GDB that have not been parsed yet.
+    received: Vec<u8>,
+    last_signal: u8,
+}
+
+impl<'a, S: GdbConnection> GdbSession<'a, S> {
+    fn new(vm: &'a mut VM, stream: S) -> Self {
+        Self {
+            vm,
+            stream,
+            received: Vec::new(),
+            last_signal: SIGTRAP,
+        }
+    }
+
+    fn run(mut self) -> io::Result<SessionEnd> {
+        self.vm.debug.halt_on_ebreak = true;
+        let end = self.serve();
+        self.vm.debug.halt_on_ebreak = false;
+        self.vm.debug.watchpoints.clear();
+        let breakpoints: Vec<u64> = self.vm.debug.breakpoint_addresses().collect();
+        for addr in breakpoints {
+            self.vm.remove_breakpoint(addr);
+        }
+        end
+    }
+
+    fn serve(&mut self) -> io::Result<SessionEnd> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                return Ok(SessionEnd::Killed);
+            };
+            let response = match packet.as_bytes().first() {
+                Some(b'?') => format!("S{:02x}", self.last_signal),
+                Some(b'q') => self.handle_query(&packet[1..]),
+                Some(b'H') | Some(b'T') => "OK".to_string(),
+                Some(b'g') => self.read_registers(),
+                Some(b'G') => self.write_registers(&packet[1..]),
+                Some(b'p') => self.read_register(&packet[1..]),
+                Some(b'P') => self.write_register(&packet[1..]),
+                Some(b'm') => self.read_memory(&packet[1..]),
+                Some(b'M') => self.write_memory(&packet[1..]),
+                Some(b'Z') => self.set_breakpoint(&packet[1..], true),
+                Some(b'z') => self.set_breakpoint(&packet[1..], false),
+                Some(b'c') | Some(b's') => {
+                    if let Some(addr) = packet.get(1..).and_then(parse_hex) {
+                        self.vm.pc = addr;
+                    }
+                    let reason = self.resume(packet.starts_with('s'))?;
+                    match reason {
+                        StopReason::Halted(result) => {
+                            let reply = match &result {
+                                Ok(()) => format!(
+                                    "W{:02x}",
+                                    self.vm.registers[riscv_core::abi::A0 as usize] as u8
+                                ),
+                                Err(_) => format!("X{:02x}", SIGTRAP),
+                            };
+                            self.send_packet(&reply)?;
+                            return Ok(SessionEnd::Halted(result));
+                        }
+                        reason => self.stop_reply(reason),
+                    }
+                }
+                Some(b'D') => {
+                    self.send_packet("OK")?;
+                    return Ok(SessionEnd::Detached);
+                }
+                Some(b'k') => return Ok(SessionEnd::Killed),
+                _ => String::new(),
+            };
+            self.send_packet(&response)?;
+        }
+    }
+
+    fn stop_reply(&mut self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Signal(signal) => {
+                self.last_signal = signal;
+                format!("S{:02x}", signal)
+            }
+            StopReason::Watchpoint { kind, addr } => {
+                self.last_signal = SIGTRAP;
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, name, addr)
+            }
+            StopReason::Halted(_) => unreachable!("halts are reported by the caller"),
+        }
+    }
+
+    fn handle_query(&mut self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+".to_string()
+        } else if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_addr_len(args) else {
+                return "E01".to_string();
+            };
+            let xml = target_xml(&self.vm.config.isa);
+            let start = (offset as usize).min(xml.len());
+            let end = start.saturating_add(length as usize).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            format!("{}{}", marker, &xml[start..end])
+        } else if query == "Attached" {
+            "1".to_string()
+        } else if query == "C" {
+            "QC1".to_string()
+        } else if query == "fThreadInfo" {
+            "m1".to_string()
+        } else if query == "sThreadInfo" {
+            "l".to_string()
+        } else if query.starts_with("Symbol") {
+            "OK".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// The number of bytes GDB exchanges for `regnum`: FLEN for the FPRs and XLEN for
+    /// everything else.
+    fn register_size(&self, regnum: usize) -> usize {
+        let isa = &self.vm.config.isa;
+        match regnum {
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM if !isa.has_base('d') => 4,
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM => 8,
+            _ => isa.xlen().bits() as usize / 8,
+        }
+    }
+
+    fn encode_register(&self, regnum: usize, value: u64) -> String {
+        encode_hex(&value.to_le_bytes()[..self.register_size(regnum)])
+    }
+
+    fn register(&self, regnum: usize) -> Option<u64> {
+        let xlen = self.vm.config.isa.xlen();
+        match regnum {
+            0..PC_REGNUM => Some(self.vm.registers[regnum] & xlen.mask()),
+            PC_REGNUM => Some(self.vm.pc),
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM if !self.vm.config.isa.has_base('f') => None,
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM => Some(self.vm.fregs[regnum - FIRST_FP_REGNUM]),
+            FIRST_CSR_REGNUM..PRIV_REGNUM => {
+                let addr = (regnum - FIRST_CSR_REGNUM) as u32;
+                let value = self.vm.csrs.read(addr, 3)?;
+                Some(match xlen {
+                    Xlen::Rv32 => narrow_csr(addr, value),
+                    Xlen::Rv64 => value,
+                })
+            }
+            PRIV_REGNUM => Some(self.vm.privilege_level as u64),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, regnum: usize, value: u64) -> bool {
+        let xlen = self.vm.config.isa.xlen();
+        match regnum {
+            0 => true,
+            // RV32 keeps its registers sign-extended, as `retire_rv32` leaves them.
+            1..PC_REGNUM => {
+                self.vm.registers[regnum] = match xlen {
+                    Xlen::Rv32 => value as i32 as i64 as u64,
+                    Xlen::Rv64 => value,
+                };
+                true
+            }
+            PC_REGNUM => {
+                self.vm.pc = value & xlen.mask();
+                true
+            }
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM if !self.vm.config.isa.has_base('f') => false,
+            // A single-precision value is NaN-boxed in the 64-bit register.
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM if !self.vm.config.isa.has_base('d') => {
+                self.vm.fregs[regnum - FIRST_FP_REGNUM] = value | 0xFFFF_FFFF_0000_0000;
+                true
+            }
+            FIRST_FP_REGNUM..FIRST_CSR_REGNUM => {
+                self.vm.fregs[regnum - FIRST_FP_REGNUM] = value;
+                true
+            }
+            FIRST_CSR_REGNUM..PRIV_REGNUM => {
+                let addr = (regnum - FIRST_CSR_REGNUM) as u32;
+                let value = match xlen {
+                    Xlen::Rv32 => widen_csr(addr, value),
+                    Xlen::Rv64 => value,
+                };
+                self.vm.csrs.write(addr, value, 3)
+            }
+            PRIV_REGNUM if matches!(value, 0 | 1 | 3) => {
+                self.vm.privilege_level = value as u8;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..=PC_REGNUM)
+            .map(|regnum| self.encode_register(regnum, self.register(regnum).unwrap_or(0)))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        for regnum in 0..=PC_REGNUM {
+            let digits = self.register_size(regnum) * 2;
+            let Some(value) = hex
+                .get(regnum * digits..(regnum + 1) * digits)
+                .and_then(|value| decode_register(value, digits / 2))
+            else {
+                return "E01".to_string();
+            };
+            self.set_register(regnum, value);
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let value = parse_hex(args).and_then(|regnum| {
+            let regnum = regnum as usize;
+            Some(self.encode_register(regnum, self.register(regnum)?))
+        });
+        value.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(regnum, value)| {
+            let regnum = parse_hex(regnum)? as usize;
+            Some((regnum, decode_register(value, self.register_size(regnum))?))
+        });
+        match parsed {
+            Some((regnum, value)) if self.set_register(regnum, value) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&mut self, args: &str) -> String {
+        let Some((addr, len)) = parse_addr_len(args) else {
+            return "E01".to_string();
+        };
+        let mut buf = vec![0; len.min(0x1000) as usize];
+        let read = self.vm.debug_read(addr, &mut buf);
+        if read == 0 && !buf.is_empty() {
+            return "E14".to_string();
+        }
+        encode_hex(&buf[..read])
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = parse_addr_len(range)?;
+            let data = decode_hex(data)?;
+            (data.len() as u64 == len).then_some((addr, data))
+        });
+        match parsed {
+            Some((addr, data)) if self.vm.debug_write(addr, &data) => "OK".to_string(),
+            Some(_) => "E14".to_string(),
+            None => "E01".to_string(),
+        }
+    }
+
+    /// Handles `Z`/`z` packets: software breakpoints (type 0) and watchpoints (types 2-4).
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let Some((kind, rest)) = args.split_once(',') else {
+            return "E01".to_string();
+        };
+        let Some((addr, len)) = parse_addr_len(rest.split(';').next().unwrap_or(rest)) else {
+            return "E01".to_string();
+        };
+        let watch_kind = match kind {
+            "0" => {
+                let ok = if insert {
+                    self.vm.insert_breakpoint(addr, len)
+                } else {
+                    self.vm.remove_breakpoint(addr)
+                };
+                return if ok { "OK" } else { "E01" }.to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint {
+            addr,
+            len,
+            kind: watch_kind,
+        };
+        let watchpoints = &mut self.vm.debug.watchpoints;
+        if insert {
+            watchpoints.push(watchpoint);
+        } else if let Some(index) = watchpoints.iter().position(|w| *w == watchpoint) {
+            watchpoints.remove(index);
+        }
+        "OK".to_string()
+    }
+
+    /// Runs the guest until it stops for the debugger, halts, or GDB interrupts it. With
+    /// `single_step` set, runs exactly one step.
+    fn resume(&mut self, single_step: bool) -> io::Result<StopReason> {
+        let mut steps: u64 = 0;
+        loop {
+            if let Some(result) = self.vm.step() {
+                return Ok(StopReason::Halted(result));
+            }
+            match self.vm.debug.stop.take() {
+                Some(DebugStop::Breakpoint) => return Ok(StopReason::Signal(SIGTRAP)),
+                Some(DebugStop::Watchpoint { kind, addr }) => {
+                    return Ok(StopReason::Watchpoint { kind, addr });
+                }
+                None => {}
+            }
+            if single_step {
+                return Ok(StopReason::Signal(SIGTRAP));
+            }
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_POLL_INTERVAL) && self.interrupt_requested()? {
+                return Ok(StopReason::Signal(SIGINT));
+            }
+        }
+    }
+
+    /// Checks, without blocking, whether GDB has sent a Ctrl-C.
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 256];
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => {
+                let interrupted = buf[..n].contains(&INTERRUPT_BYTE);
+                self.received
+                    .extend(buf[..n].iter().filter(|&&b| b != INTERRUPT_BYTE));
+                Ok(interrupted)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if !self.received.is_empty() {
+            return Ok(Some(self.received.remove(0)));
+        }
+        let mut byte = [0u8; 1];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads the next well-formed packet, acknowledging it. Returns `None` once GDB has
+    /// closed the connection.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks, stray Ctrl-Cs and anything else until the start of a packet.
+            loop {
+                match self.next_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.next_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for digit in checksum.iter_mut() {
+                match self.next_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None),
+                }
+            }
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            if expected == Some(actual) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+    use std::collections::VecDeque;
+
+    /// A scripted GDB: feeds `input` to the stub and collects everything it sends back.
+    struct ScriptedGdb {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl ScriptedGdb {
+        fn new(packets: &[&str]) -> Self {
+            let mut input = VecDeque::new();
+            for packet in packets {
+                let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+                input.extend(format!("${}#{:02x}", packet, checksum).bytes());
+            }
+            Self {
+                input,
+                output: Vec::new(),
+            }
+        }
+
+        /// The payloads of every packet the stub sent.
+        fn replies(&self) -> Vec<String> {
+            let text = String::from_utf8_lossy(&self.output);
+            text.split('$')
+                .skip(1)
+                .map(|packet| packet.split('#').next().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl Read for ScriptedGdb {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.input.pop_front().unwrap();
+            Ok(1)
+        }
+    }
+
+    impl Write for ScriptedGdb {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl GdbConnection for ScriptedGdb {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registers_memory_and_breakpoints() {
+        let mut vm = VM::new();
+        // addi a0, a0, 1 three times.
+        for i in 0..3 {
+            vm.bus.write(BASE_ADDRESS + 4 * i, 4, 0x0015_0513);
+        }
+        let breakpoint = format!("Z0,{:x},4", BASE_ADDRESS + 8);
+        let packets = [
+            "qSupported:swbreak+",
+            "P20=0000008000000000",
+            "p20",
+            "s",
+            breakpoint.as_str(),
+            "c",
+            "pa",
+            "m80000008,4",
+            "D",
+        ];
+        let mut gdb = ScriptedGdb::new(&packets);
+        let end = GdbSession::new(&mut vm, &mut gdb).run().unwrap();
+
+        assert!(matches!(end, SessionEnd::Detached));
+        let replies = gdb.replies();
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "0000008000000000");
+        assert_eq!(replies[3], "S05");
+        assert_eq!(replies[5], "S05");
+        assert_eq!(replies[6], "0200000000000000");
+        assert_eq!(replies[7], "73001000");
+        assert_eq!(vm.pc, BASE_ADDRESS + 8);
+        // Detaching removes the breakpoint again.
+        assert_eq!(vm.bus.read(BASE_ADDRESS + 8, 4), Some(0x0015_0513));
+    }
+
+    #[test]
+    fn test_target_xml_describes_gprs_pc_and_csrs() {
+        let xml = target_xml(&VmConfig::default().isa);
+        assert!(xml.contains("<architecture>riscv:rv64</architecture>"));
+        assert!(xml.contains("org.gnu.gdb.riscv.cpu"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"32\"/>"));
+        assert!(xml.contains("name=\"mstatus\" bitsize=\"64\" type=\"int\" regnum=\"833\""));
+        assert!(
+            xml.contains("<reg name=\"fa0\" bitsize=\"64\" type=\"ieee_double\" regnum=\"43\"/>")
+        );
+        assert!(xml.contains("name=\"fcsr\" bitsize=\"64\" type=\"int\" regnum=\"68\""));
+    }
+
+    #[test]
+    fn test_rv32_registers_are_four_bytes() {
+        let isa: Isa = "rv32im".parse().unwrap();
+        let xml = target_xml(&isa);
+        assert!(xml.contains("<architecture>riscv:rv32</architecture>"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\"/>"));
+        assert!(xml.contains("name=\"mstatus\" bitsize=\"32\" type=\"int\" regnum=\"833\""));
+        assert!(!xml.contains("org.gnu.gdb.riscv.fpu"));
+
+        let mut vm = VM::new_config(VmConfig {
+            isa,
+            ..VmConfig::default()
+        });
+        vm.registers[10] = 0xFFFF_FFFF_8000_0000;
+        let packets = ["g", "pa", "Pb=feffffff", "p20", "p21", "D"];
+        let mut gdb = ScriptedGdb::new(&packets);
+        GdbSession::new(&mut vm, &mut gdb).run().unwrap();
+
+        let replies = gdb.replies();
+        assert_eq!(replies[0].len(), 33 * 8);
+        assert_eq!(&replies[0][10 * 8..11 * 8], "00000080");
+        assert_eq!(replies[1], "00000080");
+        assert_eq!(replies[2], "OK");
+        assert_eq!(replies[3], "00000080");
+        // There are no FPRs to read without F.
+        assert_eq!(replies[4], "E01");
+        assert_eq!(vm.registers[11], -2i64 as u64);
+    }
+
+    impl<T: GdbConnection + ?Sized> GdbConnection for &mut T {
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            (**self).set_nonblocking(nonblocking)
+        }
+    }
+}
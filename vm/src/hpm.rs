@@ -0,0 +1,259 @@
+use crate::VM;
+use bincode::{Decode, Encode};
+use riscv_core::{funct7, opcodes};
+
+/// The programmable counters are mhpmcounter3 to mhpmcounter31; 0 to 2 are cycle, time and
+/// instret.
+pub const FIRST_HPM_COUNTER: usize = 3;
+pub const HPM_COUNTERS: usize = 29;
+
+/// Sscofpmf adds an overflow flag and per-mode inhibits to the top of mhpmevent.
+pub const MHPMEVENT_OF: u64 = 1 << 63;
+pub const MHPMEVENT_MINH: u64 = 1 << 62;
+pub const MHPMEVENT_SINH: u64 = 1 << 61;
+pub const MHPMEVENT_UINH: u64 = 1 << 60;
+/// The event selector in the low bits of mhpmevent.
+pub const MHPMEVENT_EVENT_MASK: u64 = 0xFF;
+const MHPMEVENT_WRITABLE: u64 =
+    MHPMEVENT_OF | MHPMEVENT_MINH | MHPMEVENT_SINH | MHPMEVENT_UINH | MHPMEVENT_EVENT_MASK;
+
+/// The events an mhpmevent selector can pick. 0 counts nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HpmEvent {
+    Load = 1,
+    Store = 2,
+    TakenBranch = 3,
+    Trap = 4,
+    TlbMiss = 5,
+    UserInstruction = 6,
+    SupervisorInstruction = 7,
+    MachineInstruction = 8,
+}
+
+impl HpmEvent {
+    const LAST: u64 = HpmEvent::MachineInstruction as u64;
+
+    /// The event counting instructions executed at `privilege`.
+    pub fn instruction(privilege: u8) -> Self {
+        match privilege {
+            0 => HpmEvent::UserInstruction,
+            1 => HpmEvent::SupervisorInstruction,
+            _ => HpmEvent::MachineInstruction,
+        }
+    }
+
+    /// The event for the memory access `inst` makes, if it loads or stores. `lr` counts as a
+    /// load and the other AMOs as stores.
+    pub fn memory(inst: u32) -> Option<Self> {
+        match inst & 0x7F {
+            opcodes::OP_LOAD | opcodes::OP_LOAD_FP => Some(HpmEvent::Load),
+            opcodes::OP_STORE | opcodes::OP_STORE_FP => Some(HpmEvent::Store),
+            opcodes::OP_AMO if inst >> 27 == funct7::LR => Some(HpmEvent::Load),
+            opcodes::OP_AMO => Some(HpmEvent::Store),
+            _ => None,
+        }
+    }
+}
+
+/// The programmable counters and the events they count.
+#[derive(Clone, Default, Encode, Decode)]
+pub struct Hpm {
+    counters: [u64; HPM_COUNTERS],
+    events: [u64; HPM_COUNTERS],
+}
+
+impl Hpm {
+    pub fn counter(&self, index: usize) -> u64 {
+        self.counters[index - FIRST_HPM_COUNTER]
+    }
+
+    pub fn set_counter(&mut self, index: usize, value: u64) {
+        self.counters[index - FIRST_HPM_COUNTER] = value;
+    }
+
+    pub fn event(&self, index: usize) -> u64 {
+        self.events[index - FIRST_HPM_COUNTER]
+    }
+
+    /// Sets mhpmevent. The selector is WARL: an event the hart does not implement reads back
+    /// as zero and counts nothing.
+    pub fn set_event(&mut self, index: usize, value: u64) {
+        let mut value = value & MHPMEVENT_WRITABLE;
+        if value & MHPMEVENT_EVENT_MASK > HpmEvent::LAST {
+            value &= !MHPMEVENT_EVENT_MASK;
+        }
+        self.events[index - FIRST_HPM_COUNTER] = value;
+    }
+
+    /// The counters with their overflow flag set, one bit per counter as in scountovf.
+    pub fn overflowed(&self) -> u64 {
+        self.events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| *event & MHPMEVENT_OF != 0)
+            .fold(0, |bits, (i, _)| bits | 1 << (i + FIRST_HPM_COUNTER))
+    }
+
+    /// Counts `event` in every counter that selects it, unless mcountinhibit (`inhibit`) or
+    /// the counter's inhibit for `privilege` stops it. Returns whether a counter wrapped
+    /// while its overflow flag was clear, which raises the overflow interrupt.
+    pub fn record(&mut self, event: HpmEvent, privilege: u8, inhibit: u64) -> bool {
+        let mode_inhibit = match privilege {
+            0 => MHPMEVENT_UINH,
+            1 => MHPMEVENT_SINH,
+            _ => MHPMEVENT_MINH,
+        };
+        let mut overflow = false;
+        for (i, (counter, selector)) in self
+            .counters
+            .iter_mut()
+            .zip(self.events.iter_mut())
+            .enumerate()
+        {
+            if *selector & MHPMEVENT_EVENT_MASK != event as u64
+                || *selector & mode_inhibit != 0
+                || inhibit & (1 << (i + FIRST_HPM_COUNTER)) != 0
+            {
+                continue;
+            }
+            *counter = counter.wrapping_add(1);
+            if *counter == 0 && *selector & MHPMEVENT_OF == 0 {
+                *selector |= MHPMEVENT_OF;
+                overflow = true;
+            }
+        }
+        overflow
+    }
+}
+
+impl VM {
+    /// Counts one occurrence of `event` at the current privilege level.
+    pub(crate) fn count_event(&mut self, event: HpmEvent) {
+        self.csrs.count_event(event, self.privilege_level);
+    }
+
+    /// Counts the events of `inst`, which has just retired after executing at `privilege`:
+    /// the instruction itself and, for a load or store, its access.
+    pub(crate) fn count_retired(&mut self, inst: u32, privilege: u8) {
+        self.csrs
+            .count_event(HpmEvent::instruction(privilege), privilege);
+        if let Some(event) = HpmEvent::memory(inst) {
+            self.csrs.count_event(event, privilege);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::{COUNTER_CY, MIP_LCOFIP};
+    use crate::debug::{WatchKind, Watchpoint};
+    use crate::memory::BASE_ADDRESS;
+    use riscv_core::{cause, csr};
+
+    #[test]
+    fn test_counters_select_events_and_modes() {
+        let mut vm = VM::new();
+        vm.csrs
+            .write(csr::MHPMEVENT3, HpmEvent::TakenBranch as u64, 3);
+        vm.csrs.write(csr::MHPMEVENT3 + 1, HpmEvent::Load as u64, 3);
+        vm.csrs.write(
+            csr::MHPMEVENT3 + 2,
+            HpmEvent::MachineInstruction as u64 | MHPMEVENT_MINH,
+            3,
+        );
+        vm.csrs.write(csr::MHPMEVENT3 + 3, 0xFF, 3);
+        assert_eq!(vm.csrs.read(csr::MHPMEVENT3 + 3, 3), Some(0));
+
+        // beq zero, zero, 8; nop; lw a0, 0(zero) faults on the unmapped address, and only
+        // the lw a0, 0(a1) in the handler counts as a load.
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+        vm.bus.write(BASE_ADDRESS, 4, 0x00000463);
+        vm.bus.write(BASE_ADDRESS + 8, 4, 0x00002503);
+        vm.bus.write(BASE_ADDRESS + 0x100, 4, 0x0005A503);
+        vm.registers[11] = BASE_ADDRESS;
+        for _ in 0..3 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(1));
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3 + 1, 3), Some(1));
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3 + 2, 3), Some(0));
+
+        // S-mode needs mcounteren to read a counter.
+        assert_eq!(vm.csrs.read(csr::HPMCOUNTER3, 1), None);
+        vm.csrs.write(csr::MCOUNTEREN, COUNTER_CY | 1 << 3, 3);
+        assert_eq!(vm.csrs.read(csr::HPMCOUNTER3, 1), Some(1));
+        assert_eq!(vm.csrs.read(csr::HPMCOUNTER3 + 1, 1), None);
+    }
+
+    #[test]
+    fn test_events_count_when_the_instruction_retires() {
+        let mut vm = VM::new();
+        vm.csrs
+            .write(csr::MHPMEVENT3, HpmEvent::MachineInstruction as u64, 3);
+        vm.csrs.write(csr::MHPMEVENT3 + 1, HpmEvent::Load as u64, 3);
+        vm.bus.write(BASE_ADDRESS, 4, 0x0005A503); // lw a0, 0(a1)
+        vm.registers[11] = BASE_ADDRESS + 0x100;
+        vm.debug.watchpoints.push(Watchpoint {
+            addr: BASE_ADDRESS + 0x100,
+            len: 4,
+            kind: WatchKind::Read,
+        });
+
+        // The load stops before it runs, so neither it nor its access counts yet.
+        let instret = vm.csrs.minstret;
+        assert_eq!(vm.step(), None);
+        assert!(vm.debug.stop.take().is_some());
+        assert_eq!(vm.csrs.minstret, instret);
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(0));
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3 + 1, 3), Some(0));
+
+        vm.debug.watchpoints.clear();
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.csrs.minstret, instret + 1);
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(1));
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3 + 1, 3), Some(1));
+
+        // An instruction that traps is not counted either.
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        vm.pc = BASE_ADDRESS + 4;
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(1));
+    }
+
+    #[test]
+    fn test_overflow_sets_of_and_raises_lcofi() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MHPMEVENT3, HpmEvent::Trap as u64, 3);
+        vm.csrs.write(csr::MHPMCOUNTER3, u64::MAX, 3);
+        vm.csrs.write(csr::MCOUNTEREN, 1 << 3, 3);
+        vm.csrs.write(csr::MIE, MIP_LCOFIP, 3);
+        vm.csrs.write(csr::MIDELEG, MIP_LCOFIP, 3);
+        vm.csrs.write(csr::STVEC, BASE_ADDRESS + 0x200, 3);
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x100, 3);
+
+        // The illegal instruction at BASE_ADDRESS is the trap that wraps the counter.
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(0));
+        assert_ne!(vm.csrs.read(csr::MHPMEVENT3, 3).unwrap() & MHPMEVENT_OF, 0);
+        assert_eq!(vm.csrs.read(csr::SCOUNTOVF, 3), Some(1 << 3));
+        assert_eq!(vm.csrs.mip & MIP_LCOFIP, MIP_LCOFIP);
+
+        // The delegated interrupt is taken as soon as the hart drops to S-mode.
+        vm.privilege_level = 1;
+        vm.csrs.write(csr::MSTATUS, crate::csr::MSTATUS_SIE, 3);
+        vm.open_pmp();
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::SCAUSE, 3),
+            Some(cause::COUNTER_OVERFLOW_INTERRUPT)
+        );
+        assert_eq!(vm.csrs.read(csr::SCOUNTOVF, 1), Some(1 << 3));
+
+        // Further wraps with OF still set do not interrupt again.
+        vm.csrs.mip = 0;
+        vm.csrs.write(csr::MHPMCOUNTER3, u64::MAX, 3);
+        vm.count_event(HpmEvent::Trap);
+        assert_eq!(vm.csrs.mip & MIP_LCOFIP, 0);
+    }
+}
@@ -0,0 +1,356 @@
+use bincode::{Decode, Encode};
+use std::fmt;
+use std::str::FromStr;
+
+/// The ISA the VM implements unless configured otherwise.
+pub const DEFAULT_ISA: &str =
+    "rv64imafdcv_zba_zbb_zbc_zbkb_zbkc_zbkx_zbs_zknd_zkne_zknh_zkr_zksed_zksh";
+
+/// The single-letter extensions an RV64 hart always implements, in canonical order.
+const BASE_EXTENSIONS: &str = "imafdc";
+/// The single-letter extensions an RV32 hart may implement. Only I is required.
+const RV32_EXTENSIONS: &str = "im";
+
+/// misa.MXL for a 32-bit and a 64-bit hart.
+const MISA_MXL_32: u64 = 1 << 30;
+const MISA_MXL_64: u64 = 2 << 62;
+/// The misa bit for the single-letter extension `letter`.
+pub const fn misa_bit(letter: char) -> u64 {
+    1 << (letter as u8 - b'a')
+}
+/// The privilege modes are reported in misa alongside the extensions.
+const MISA_MODES: u64 = misa_bit('s') | misa_bit('u');
+
+/// The width of the integer registers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub enum Xlen {
+    Rv32,
+    #[default]
+    Rv64,
+}
+
+impl Xlen {
+    pub fn bits(self) -> u32 {
+        match self {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        }
+    }
+
+    /// The mask of the bits a register of this width holds.
+    pub fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+}
+
+/// An optional extension that can be left out of the configured ISA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    V,
+    Zba,
+    Zbb,
+    Zbc,
+    Zbkb,
+    Zbkc,
+    Zbkx,
+    Zbs,
+    Zknd,
+    Zkne,
+    Zknh,
+    Zkr,
+    Zksed,
+    Zksh,
+    /// Hardware updating of the PTE Accessed and Dirty bits. Without it, a page walk that
+    /// would have to set either bit raises a page fault instead (Svade).
+    Svadu,
+}
+
+impl Extension {
+    /// Every extension, in the canonical order for ISA strings.
+    const ALL: [Extension; 15] = [
+        Extension::V,
+        Extension::Zba,
+        Extension::Zbb,
+        Extension::Zbc,
+        Extension::Zbkb,
+        Extension::Zbkc,
+        Extension::Zbkx,
+        Extension::Zbs,
+        Extension::Zknd,
+        Extension::Zkne,
+        Extension::Zknh,
+        Extension::Zkr,
+        Extension::Zksed,
+        Extension::Zksh,
+        Extension::Svadu,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::V => "v",
+            Extension::Zba => "zba",
+            Extension::Zbb => "zbb",
+            Extension::Zbc => "zbc",
+            Extension::Zbkb => "zbkb",
+            Extension::Zbkc => "zbkc",
+            Extension::Zbkx => "zbkx",
+            Extension::Zbs => "zbs",
+            Extension::Zknd => "zknd",
+            Extension::Zkne => "zkne",
+            Extension::Zknh => "zknh",
+            Extension::Zkr => "zkr",
+            Extension::Zksed => "zksed",
+            Extension::Zksh => "zksh",
+            Extension::Svadu => "svadu",
+        }
+    }
+
+    /// Whether this is a single-letter extension, written straight after the base ones
+    /// rather than as an `_`-separated name.
+    fn is_single_letter(self) -> bool {
+        self.name().len() == 1
+    }
+
+    /// The misa bit that can switch this extension off at run time, if there is one.
+    pub fn misa_bit(self) -> Option<u64> {
+        match self {
+            Extension::Zba | Extension::Zbb | Extension::Zbs => Some(misa_bit('b')),
+            _ => None,
+        }
+    }
+}
+
+/// The register width and extensions of a hart, parsed from an ISA string such as
+/// `rv64imafdc_zba_zbb` or `rv32im`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Isa {
+    xlen: Xlen,
+    /// The misa bits of the base single-letter extensions present.
+    base: u64,
+    extensions: u32,
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        DEFAULT_ISA
+            .parse()
+            .expect("the default ISA string is valid")
+    }
+}
+
+impl Isa {
+    pub fn xlen(&self) -> Xlen {
+        self.xlen
+    }
+
+    /// Whether the base single-letter extension `letter` (one of `imafdc`) is present.
+    pub fn has_base(&self, letter: char) -> bool {
+        self.base & misa_bit(letter) != 0
+    }
+
+    pub fn has(&self, extension: Extension) -> bool {
+        self.extensions & (1 << extension as u32) != 0
+    }
+
+    fn add(&mut self, extension: Extension) {
+        self.extensions |= 1 << extension as u32;
+    }
+
+    /// The reset value of misa. B is only reported when all of Zba, Zbb and Zbs are present.
+    pub fn misa(&self) -> u64 {
+        let mxl = match self.xlen {
+            Xlen::Rv32 => MISA_MXL_32,
+            Xlen::Rv64 => MISA_MXL_64,
+        };
+        let mut misa = mxl | MISA_MODES | self.base;
+        for extension in Extension::ALL {
+            if extension.is_single_letter() && self.has(extension) {
+                misa |= misa_bit(extension.name().as_bytes()[0] as char);
+            }
+        }
+        if [Extension::Zba, Extension::Zbb, Extension::Zbs]
+            .iter()
+            .all(|&extension| self.has(extension))
+        {
+            misa |= misa_bit('b');
+        }
+        misa
+    }
+}
+
+impl FromStr for Isa {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let lower = s.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(rest) = lower.strip_prefix("rv64") {
+            (Xlen::Rv64, rest)
+        } else if let Some(rest) = lower.strip_prefix("rv32") {
+            (Xlen::Rv32, rest)
+        } else {
+            return Err(format!(
+                "{}: the ISA string must start with rv32 or rv64",
+                s
+            ));
+        };
+
+        let mut parts = rest.split('_');
+        let letters = parts.next().unwrap_or("");
+        let mut isa = Isa {
+            xlen,
+            base: 0,
+            extensions: 0,
+        };
+        let mut base = String::new();
+        for letter in letters.chars() {
+            match letter {
+                'g' => base.push_str("imafd"),
+                'v' => isa.add(Extension::V),
+                'b' => {
+                    isa.add(Extension::Zba);
+                    isa.add(Extension::Zbb);
+                    isa.add(Extension::Zbs);
+                }
+                _ if BASE_EXTENSIONS.contains(letter) => base.push(letter),
+                _ => return Err(format!("{}: unsupported extension '{}'", s, letter)),
+            }
+        }
+        let required = match xlen {
+            Xlen::Rv32 => "i",
+            Xlen::Rv64 => BASE_EXTENSIONS,
+        };
+        if let Some(missing) = required.chars().find(|&c| !base.contains(c)) {
+            return Err(format!(
+                "{}: the '{}' extension cannot be disabled",
+                s, missing
+            ));
+        }
+        for letter in base.chars() {
+            isa.base |= misa_bit(letter);
+        }
+
+        for name in parts {
+            // Zkn and Zks are shorthands for the NIST and ShangMi suites.
+            let suite: &[Extension] = match name {
+                "zkn" => &[
+                    Extension::Zbkb,
+                    Extension::Zbkc,
+                    Extension::Zbkx,
+                    Extension::Zkne,
+                    Extension::Zknd,
+                    Extension::Zknh,
+                ],
+                "zks" => &[
+                    Extension::Zbkb,
+                    Extension::Zbkc,
+                    Extension::Zbkx,
+                    Extension::Zksed,
+                    Extension::Zksh,
+                ],
+                _ => &[],
+            };
+            if !suite.is_empty() {
+                suite.iter().for_each(|&extension| isa.add(extension));
+                continue;
+            }
+            match Extension::ALL
+                .iter()
+                .find(|extension| !extension.is_single_letter() && extension.name() == name)
+            {
+                Some(&extension) => isa.add(extension),
+                // Zicsr and Zifencei are part of the base the VM always implements.
+                None if name == "zicsr" || name == "zifencei" => {}
+                None => return Err(format!("{}: unsupported extension '{}'", s, name)),
+            }
+        }
+
+        // RV32 runs the integer core only, though Sv32 can update A/D bits like Sv39.
+        if xlen == Xlen::Rv32 {
+            let extra = base.chars().find(|&c| !RV32_EXTENSIONS.contains(c));
+            let optional = Extension::ALL
+                .iter()
+                .find(|&&e| isa.has(e) && e != Extension::Svadu);
+            if let Some(name) = extra
+                .map(String::from)
+                .or(optional.map(|e| e.name().into()))
+            {
+                return Err(format!(
+                    "{}: the '{}' extension is not supported on RV32",
+                    s, name
+                ));
+            }
+        }
+        Ok(isa)
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv{}", self.xlen.bits())?;
+        for letter in BASE_EXTENSIONS.chars().filter(|&c| self.has_base(c)) {
+            write!(f, "{}", letter)?;
+        }
+        for extension in Extension::ALL {
+            if extension.is_single_letter() && self.has(extension) {
+                write!(f, "{}", extension.name())?;
+            }
+        }
+        for extension in Extension::ALL {
+            if !extension.is_single_letter() && self.has(extension) {
+                write!(f, "_{}", extension.name())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_isa_strings() {
+        let isa: Isa = "rv64gc_zbc".parse().unwrap();
+        assert!(isa.has(Extension::Zbc));
+        assert!(!isa.has(Extension::Zba));
+        assert_eq!(isa.misa() & misa_bit('b'), 0);
+        assert_eq!(isa.to_string(), "rv64imafdc_zbc");
+
+        let isa: Isa = "RV64IMAFDCB".parse().unwrap();
+        assert_eq!(isa.to_string(), "rv64imafdc_zba_zbb_zbs");
+        assert_ne!(isa.misa() & misa_bit('b'), 0);
+
+        let isa: Isa = "rv64gcv".parse().unwrap();
+        assert_eq!(isa.to_string(), "rv64imafdcv");
+        assert_ne!(isa.misa() & misa_bit('v'), 0);
+
+        let isa: Isa = "rv64imafdc_zks_zkr".parse().unwrap();
+        assert_eq!(isa.to_string(), "rv64imafdc_zbkb_zbkc_zbkx_zkr_zksed_zksh");
+
+        assert_eq!(Isa::default().to_string(), DEFAULT_ISA);
+        let isa: Isa = "RV32IM_zicsr".parse().unwrap();
+        assert_eq!(isa.xlen(), Xlen::Rv32);
+        assert!(isa.has_base('m') && !isa.has_base('a'));
+        assert_eq!(isa.to_string(), "rv32im");
+        assert_eq!(
+            isa.misa(),
+            (1 << 30) | misa_bit('i') | misa_bit('m') | MISA_MODES
+        );
+        assert_eq!("rv32i".parse::<Isa>().unwrap().to_string(), "rv32i");
+
+        assert_eq!(
+            "rv32im_svadu".parse::<Isa>().unwrap().to_string(),
+            "rv32im_svadu"
+        );
+        assert_eq!(
+            "rv64gc_svadu_zba".parse::<Isa>().unwrap().to_string(),
+            "rv64imafdc_zba_svadu"
+        );
+
+        assert!("rv32imafdc".parse::<Isa>().is_err());
+        assert!("rv32im_zba".parse::<Isa>().is_err());
+        assert!("rv32m".parse::<Isa>().is_err());
+        assert!("rv64imafd".parse::<Isa>().is_err());
+        assert!("rv64imafdc_zbx".parse::<Isa>().is_err());
+    }
+}
@@ -1,42 +1,152 @@
+pub mod bitmanip;
+pub mod bus;
+pub mod clint;
+pub mod crypto;
 pub mod csr;
+pub mod debug;
+pub mod entropy;
 pub mod execution;
+pub mod float;
+pub mod gdbstub;
+pub mod hpm;
+pub mod isa;
 pub mod memory;
 pub mod mmu;
+pub mod monitor;
+pub mod plic;
+pub mod pmp;
+pub mod rv32;
+pub mod snapshot;
+pub mod softfloat;
 pub mod trap;
+pub mod uart;
+pub mod vector;
+pub mod virtio;
 
+use crate::bus::Bus;
+use crate::clint::{Clint, TimerSource, CLINT_BASE_ADDRESS, CLINT_SIZE, MIP_MSIP, MIP_MTIP};
 use crate::csr::CsrFile;
-use crate::memory::{BASE_ADDRESS, MEMORY_SIZE};
+use crate::debug::DebugState;
+use crate::entropy::{Entropy, EntropySource};
+use crate::isa::{Extension, Isa, Xlen};
+use crate::memory::{BASE_ADDRESS, KERNEL_LOAD_ADDRESS, MEMORY_SIZE};
+use crate::mmu::{SatpMode, Tlb};
+use crate::plic::{Plic, MIP_MEIP, MIP_SEIP, PLIC_BASE_ADDRESS, PLIC_SIZE};
+use crate::pmp::DEFAULT_PMP_ENTRIES;
+use crate::uart::{Uart, UART_BASE_ADDRESS, UART_IRQ, UART_SIZE};
+use crate::vector::DEFAULT_VLEN;
+use crate::virtio::{VirtioBlk, VIRTIO_BLK_BASE_ADDRESS, VIRTIO_BLK_IRQ, VIRTIO_MMIO_SIZE};
 use assembler::disassemble;
+use riscv_core::compressed::{self, UNCOMPRESSED_MASK};
 use riscv_core::csr as rv_csrs;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
 
-#[derive(Default)]
 pub struct VmConfig {
     pub trace: bool,
+    pub timer: TimerSource,
+    /// The register width and the extensions the hart implements.
+    pub isa: Isa,
+    /// Where reads of the Zkr `seed` CSR get their entropy.
+    pub entropy: EntropySource,
+    /// The width of each vector register in bits.
+    pub vlen: u64,
+    /// The largest paging mode `satp` accepts on RV64.
+    pub max_satp_mode: SatpMode,
+    /// How many PMP entries the hart implements: 0, 16 or 64.
+    pub pmp_entries: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            trace: false,
+            timer: TimerSource::default(),
+            isa: Isa::default(),
+            entropy: EntropySource::default(),
+            vlen: DEFAULT_VLEN,
+            max_satp_mode: SatpMode::default(),
+            pmp_entries: DEFAULT_PMP_ENTRIES,
+        }
+    }
 }
 
 pub struct VM {
     pub registers: [u64; 32],
+    /// The F/D register file. Single-precision values are NaN-boxed in the upper 32 bits.
+    pub fregs: [u64; 32],
+    /// The vector register file: `v0` to `v31`, each `vlenb` bytes, stored back to back so
+    /// that a register group is one contiguous slice.
+    pub vregs: Vec<u8>,
     pub pc: u64,
-    pub memory: Vec<u8>,
+    pub bus: Bus,
     pub csrs: CsrFile,
     pub privilege_level: u8,
+    /// The privilege level the most recent trap was taken into, whose xcause explains a
+    /// halt.
+    pub trap_level: u8,
+    /// Set when the instruction being executed raises an exception, so it does not retire.
+    pub(crate) trapped: bool,
     pub config: VmConfig,
-    pub virtual_disk: Vec<u8>,
-    pub tlb: HashMap<u64, u64>,
+    pub virtio_blk: Option<Rc<RefCell<VirtioBlk>>>,
+    pub tlb: Tlb,
+    /// The reservation granule held by the last `lr`, if any. `sc` and ordinary stores to the
+    /// granule clear it.
+    pub reservation: Option<u64>,
+    pub debug: DebugState,
+    pub entropy: Entropy,
+    pub clint: Rc<RefCell<Clint>>,
+    pub plic: Rc<RefCell<Plic>>,
+    pub uart: Rc<RefCell<Uart>>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new_config(config: VmConfig) -> Self {
+        let clint = Rc::new(RefCell::new(Clint::new(config.timer)));
+        let plic = Rc::new(RefCell::new(Plic::new()));
+        let uart = Rc::new(RefCell::new(Uart::default()));
+
+        let mut bus = Bus::new(MEMORY_SIZE);
+        bus.register(CLINT_BASE_ADDRESS, CLINT_SIZE, None, clint.clone());
+        bus.register(PLIC_BASE_ADDRESS, PLIC_SIZE, None, plic.clone());
+        bus.register(UART_BASE_ADDRESS, UART_SIZE, Some(UART_IRQ), uart.clone());
+
+        let mut csrs = CsrFile::new();
+        csrs.set_misa(config.isa.misa());
+        csrs.set_xlen(config.isa.xlen());
+        csrs.set_max_satp_mode(config.max_satp_mode);
+        csrs.set_pmp_entries(config.pmp_entries);
+        csrs.set_vlenb(config.vlen / 8);
+        let vregs = vec![0; 32 * (config.vlen / 8) as usize];
+        let entropy = Entropy::new(config.entropy);
+
         Self {
             registers: [0; 32],
+            fregs: [0; 32],
+            vregs,
             pc: BASE_ADDRESS,
-            memory: vec![0; MEMORY_SIZE],
-            csrs: CsrFile::new(),
+            bus,
+            csrs,
+            clint,
+            plic,
+            uart,
             privilege_level: 3,
+            trap_level: 3,
+            trapped: false,
             config,
-            virtual_disk: Vec::new(),
-            tlb: HashMap::new(),
+            virtio_blk: None,
+            tlb: Tlb::default(),
+            reservation: None,
+            debug: DebugState::default(),
+            entropy,
         }
     }
 
@@ -44,79 +154,185 @@ impl VM {
         VM::new_config(VmConfig::default())
     }
 
+    /// Whether instructions from `extension` may execute: it is part of the configured ISA
+    /// and, if misa can switch it off, software has not done so.
+    pub(crate) fn extension_enabled(&self, extension: Extension) -> bool {
+        self.config.isa.has(extension)
+            && extension
+                .misa_bit()
+                .is_none_or(|bit| self.config.isa.misa() & bit == 0 || self.csrs.misa & bit != 0)
+    }
+
+    /// The alignment, in bytes, of instruction addresses: 2 with C, 4 without.
+    pub(crate) fn ialign(&self) -> u64 {
+        if self.config.isa.has_base('c') {
+            2
+        } else {
+            4
+        }
+    }
+
     pub fn load_bios(&mut self, bios_bytes: &[u8]) {
-        self.memory[0..bios_bytes.len()].copy_from_slice(bios_bytes);
+        self.bus.ram[0..bios_bytes.len()].copy_from_slice(bios_bytes);
     }
 
-    pub fn load_virtual_disk(&mut self, disk_bytes: Vec<u8>) {
-        self.virtual_disk = disk_bytes;
+    /// Places the kernel image in RAM at `KERNEL_LOAD_ADDRESS`, where the BIOS jumps to it.
+    pub fn load_kernel(&mut self, kernel_bytes: &[u8]) {
+        let offset = (KERNEL_LOAD_ADDRESS - BASE_ADDRESS) as usize;
+        self.bus.ram[offset..offset + kernel_bytes.len()].copy_from_slice(kernel_bytes);
+    }
+
+    /// Attaches `path` as a virtio-blk disk in the first virtio-mmio slot.
+    pub fn attach_disk(&mut self, path: &Path, read_only: bool) -> io::Result<()> {
+        let disk = Rc::new(RefCell::new(VirtioBlk::open(path, read_only)?));
+        self.bus.register(
+            VIRTIO_BLK_BASE_ADDRESS,
+            VIRTIO_MMIO_SIZE,
+            Some(VIRTIO_BLK_IRQ),
+            disk.clone(),
+        );
+        self.virtio_blk = Some(disk);
+        Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), String> {
         const INSTRUCTION_LIMIT: u64 = 5_000_000;
 
         for _ in 0..INSTRUCTION_LIMIT {
-            let pc_before_fetch = self.pc;
-
-            let instruction = match self.fetch() {
-                Some(inst) => inst,
-                None => {
-                    let cause = self
-                        .csrs
-                        .read(riscv_core::csr::SCAUSE, 3)
-                        .or_else(|| self.csrs.read(riscv_core::csr::MCAUSE, 3))
-                        .unwrap_or(0);
-
-                    if self.is_exit_ecall(cause) {
-                        return Ok(());
-                    } else {
-                        return Err(format!(
-                            "Execution halted by trap: {}",
-                            self.cause_to_string(cause)
-                        ));
-                    }
-                }
-            };
+            if let Some(result) = self.step() {
+                return result;
+            }
+        }
+
+        Err("Instruction limit reached. Program may be in an infinite loop.".to_string())
+    }
 
-            if self.config.trace {
-                let disassembled_text = disassemble(instruction, pc_before_fetch);
-                eprintln!("TRACE: 0x{:016x}: {}", pc_before_fetch, disassembled_text);
+    /// Runs one step of the machine: ticks the devices, takes a pending interrupt if there
+    /// is one, then fetches and executes a single instruction. Returns `Some` with the halt
+    /// result once the guest has stopped.
+    pub fn step(&mut self) -> Option<Result<(), String>> {
+        self.tick_devices();
+        let result = self.step_hart();
+        self.csrs.tick_counters();
+        result
+    }
+
+    /// Takes a pending interrupt or executes one instruction; each step counts as a cycle.
+    fn step_hart(&mut self) -> Option<Result<(), String>> {
+        if let Some(cause) = self.pending_interrupt()
+            && !self.handle_trap(cause, 0)
+        {
+            return Some(self.halt_result());
+        }
+
+        let pc_before_fetch = self.pc;
+
+        let raw_instruction = match self.fetch() {
+            Ok(inst) => inst,
+            Err((cause, tval)) => {
+                if !self.handle_trap(cause, tval) {
+                    return Some(self.halt_result());
+                }
+                return None;
             }
+        };
+
+        if self.config.trace {
+            let disassembled_text = disassemble(raw_instruction, pc_before_fetch);
+            eprintln!("TRACE: 0x{:016x}: {}", pc_before_fetch, disassembled_text);
+        }
 
-            if !self.execute(instruction) {
-                let cause = self
-                    .csrs
-                    .read(riscv_core::csr::SCAUSE, 3)
-                    .or_else(|| self.csrs.read(riscv_core::csr::MCAUSE, 3))
-                    .unwrap_or(0);
-
-                if self.is_exit_ecall(cause) {
-                    return Ok(());
-                } else {
-                    return Err(format!(
-                        "Execution halted by trap: {}",
-                        self.cause_to_string(cause)
-                    ));
+        let (instruction, length) = if raw_instruction & UNCOMPRESSED_MASK == UNCOMPRESSED_MASK {
+            (raw_instruction, 4)
+        } else {
+            match compressed::expand(raw_instruction as u16) {
+                Some(inst) if self.config.isa.has_base('c') => (inst, 2),
+                _ => {
+                    if !self.handle_trap(
+                        riscv_core::cause::ILLEGAL_INSTRUCTION,
+                        raw_instruction as u64,
+                    ) {
+                        return Some(self.halt_result());
+                    }
+                    return None;
                 }
             }
+        };
+
+        let privilege = self.privilege_level;
+        self.trapped = false;
+        if !self.execute(instruction, length) {
+            return Some(self.halt_result());
+        }
+        // An instruction stopped for the debugger runs again when execution resumes, so
+        // like one that traps it does not retire now.
+        if self.trapped || self.debug.stop.is_some() {
+            self.csrs.hold_instret();
+        } else {
+            self.count_retired(instruction, privilege);
         }
+        if self.config.isa.xlen() == Xlen::Rv32 {
+            self.retire_rv32(instruction);
+        }
+        None
+    }
 
-        Err("Instruction limit reached. Program may be in an infinite loop.".to_string())
+    /// Advances the platform devices by one step and refreshes the interrupt-pending bits
+    /// they drive in `mip`.
+    fn tick_devices(&mut self) {
+        self.bus.tick();
+        let mut plic = self.plic.borrow_mut();
+        for (irq, asserted) in self.bus.interrupt_lines() {
+            plic.set_level(irq, asserted);
+        }
+
+        // SEIP is also writable by M-mode software, so the PLIC's line is kept apart from
+        // the CSR bit rather than overwriting it.
+        let driven = MIP_MSIP | MIP_MTIP | MIP_MEIP;
+        let clint = self.clint.borrow();
+        let device_bits = clint.mip_bits() | plic.mip_bits();
+        self.csrs.mip = (self.csrs.mip & !driven) | (device_bits & driven);
+        self.csrs.seip_line = device_bits & MIP_SEIP != 0;
+        self.csrs.time = clint.mtime;
+    }
+
+    fn halt_result(&self) -> Result<(), String> {
+        let cause_csr = if self.trap_level == 3 {
+            riscv_core::csr::MCAUSE
+        } else {
+            riscv_core::csr::SCAUSE
+        };
+        let cause = self.csrs.read(cause_csr, 3).unwrap_or(0);
+
+        if self.is_exit_ecall(cause) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Execution halted by trap: {}",
+                self.cause_to_string(cause)
+            ))
+        }
     }
 
     fn is_exit_ecall(&self, cause: u64) -> bool {
         if (cause == riscv_core::cause::ECALL_FROM_M_MODE)
             | (cause == riscv_core::cause::ECALL_FROM_S_MODE)
             | (cause == riscv_core::cause::ECALL_FROM_U_MODE)
+            && self.registers[riscv_core::abi::A7 as usize] == 93
         {
-            if self.registers[riscv_core::abi::A7 as usize] == 93 {
-                return true;
-            }
+            return true;
         }
         false
     }
 
+    /// Prints the registers and key CSRs to stdout.
     pub fn print_state(&self) {
+        let _ = self.write_state(&mut io::stdout().lock());
+    }
+
+    /// Writes the general-purpose registers, the key CSRs, the pc and the privilege level
+    /// to `out`.
+    pub fn write_state(&self, out: &mut impl io::Write) -> io::Result<()> {
         let key_csrs_to_print = [
             (rv_csrs::MSTATUS, "mstatus"),
             (rv_csrs::MISA, "misa"),
@@ -142,16 +358,22 @@ impl VM {
             "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
             "t3", "t4", "t5", "t6",
         ];
-        println!();
+        writeln!(out)?;
         let gpr_header = format!("{:<5} {:<7} {:<18}", "Reg", "ABI", "Value");
         let csr_header = format!("{:<8} {:<10} {:<18}", "Address", "Name", "Value");
         let seperator = " | ";
-        println!("------------------------------------------------------------------");
-        println!("{}{}{}", gpr_header, seperator, csr_header);
-        println!("------------------------------------------------------------------");
+        writeln!(
+            out,
+            "------------------------------------------------------------------"
+        )?;
+        writeln!(out, "{}{}{}", gpr_header, seperator, csr_header)?;
+        writeln!(
+            out,
+            "------------------------------------------------------------------"
+        )?;
         for i in 0..32 {
             let reg_name = format!("x{}", i);
-            let abi_name = format!("{}", abi[i]);
+            let abi_name = abi[i].to_string();
             let gpr_line = format!(
                 "{:<5} {:<7} {:#018x}",
                 reg_name, abi_name, self.registers[i]
@@ -161,14 +383,15 @@ impl VM {
                 let (addr, name) = key_csrs_to_print[i];
                 let csr_val = self.csrs.read(addr, 3).unwrap_or(0);
                 let csr_line = format!("{:<#8x} {:<10} {:#018x}", addr, name, csr_val);
-                println!("{}{}{}", gpr_line, seperator, csr_line);
+                writeln!(out, "{}{}{}", gpr_line, seperator, csr_line)?;
             } else {
-                println!("{}{}", gpr_line, seperator);
+                writeln!(out, "{}{}", gpr_line, seperator)?;
             }
         }
-        println!();
-        println!("PC: {:#018x}", self.pc);
-        println!("Privilege Level: {}", self.privilege_level_to_string());
+        writeln!(out)?;
+        writeln!(out, "PC: {:#018x}", self.pc)?;
+        writeln!(out, "Privilege Level: {}", self.privilege_level_to_string())?;
+        Ok(())
     }
 
     fn privilege_level_to_string(&self) -> &str {
@@ -1,5 +1,17 @@
 use std::env;
-use vm::{VmConfig, VM};
+use std::fs;
+use std::path::PathBuf;
+use vm::{
+    clint::TimerSource,
+    entropy::EntropySource,
+    gdbstub::{self, SessionEnd},
+    isa::{Isa, Xlen},
+    mmu::SatpMode,
+    monitor::{self, Monitor},
+    pmp::{DEFAULT_PMP_ENTRIES, PMP_ENTRY_COUNTS},
+    vector::{self, DEFAULT_VLEN},
+    VmConfig, VM,
+};
 
 const BIOS_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bios.bin"));
 const KERNEL_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kernel.bin"));
@@ -7,11 +19,133 @@ const KERNEL_BYTES: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/kernel.bin
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut trace_enabled = false;
+    let mut timer = TimerSource::Instructions;
+    let mut disk_path: Option<PathBuf> = None;
+    let mut disk_read_only = false;
+    let mut snapshot_path: Option<PathBuf> = None;
+    let mut restore_path: Option<PathBuf> = None;
+    let mut gdb_address: Option<String> = None;
+    let mut monitor_enabled = false;
+    let mut symbols_path: Option<PathBuf> = None;
+    let mut bios_path: Option<PathBuf> = None;
+    let mut isa = Isa::default();
+    let mut entropy = EntropySource::default();
+    let mut vlen = DEFAULT_VLEN;
+    let mut max_satp_mode = SatpMode::default();
+    let mut pmp_entries = DEFAULT_PMP_ENTRIES;
 
-    let mut iter = args.iter().skip(1);
-    while let Some(arg) = iter.next() {
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
         match arg.as_str() {
             "--trace" => trace_enabled = true,
+            "--host-timer" => timer = TimerSource::HostTime,
+            "--disk" => match arg_iter.next() {
+                Some(path) => disk_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--disk requires a path");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--disk-readonly" => disk_read_only = true,
+            "--bios" => match arg_iter.next() {
+                Some(path) => bios_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--bios requires a path");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--gdb" => match arg_iter.next() {
+                Some(address) => gdb_address = Some(address.clone()),
+                None => {
+                    eprintln!("--gdb requires a port or unix:<path>");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--isa" => match arg_iter.next().map(|s| s.parse::<Isa>()) {
+                Some(Ok(parsed)) => isa = parsed,
+                Some(Err(e)) => {
+                    eprintln!("Invalid --isa: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("--isa requires an ISA string, e.g. rv64imafdc_zba_zbb or rv32im");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--entropy" => match arg_iter.next().map(|s| s.parse::<EntropySource>()) {
+                Some(Ok(parsed)) => entropy = parsed,
+                Some(Err(e)) => {
+                    eprintln!("Invalid --entropy: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("--entropy requires host or a seed");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--vlen" => match arg_iter.next().map(|s| s.parse::<u64>()) {
+                Some(Ok(bits)) if vector::valid_vlen(bits) => vlen = bits,
+                Some(_) => {
+                    eprintln!(
+                        "Invalid --vlen: expected a power of two from {} to {}",
+                        vector::MIN_VLEN,
+                        vector::MAX_VLEN
+                    );
+                    return;
+                }
+                None => {
+                    eprintln!("--vlen requires a width in bits");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--paging" => match arg_iter.next().map(|s| s.parse::<SatpMode>()) {
+                Some(Ok(parsed)) => max_satp_mode = parsed,
+                Some(Err(e)) => {
+                    eprintln!("Invalid --paging: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("--paging requires the largest mode, e.g. sv48");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--pmp" => match arg_iter.next().map(|s| s.parse::<usize>()) {
+                Some(Ok(entries)) if PMP_ENTRY_COUNTS.contains(&entries) => pmp_entries = entries,
+                Some(_) => {
+                    eprintln!("Invalid --pmp: expected 0, 16 or 64 entries");
+                    return;
+                }
+                None => {
+                    eprintln!("--pmp requires a number of entries");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--monitor" => monitor_enabled = true,
+            "--symbols" => match arg_iter.next() {
+                Some(path) => symbols_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--symbols requires a path");
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+            "--snapshot" | "--restore" => match arg_iter.next() {
+                Some(path) if arg == "--snapshot" => snapshot_path = Some(PathBuf::from(path)),
+                Some(path) => restore_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{} requires a path", arg);
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
             _ => {
                 eprintln!("Unknown argument: {}", arg);
                 print_usage(&args[0]);
@@ -23,18 +157,112 @@ fn main() {
     println!("VM: Initializing...");
     let vm_config = VmConfig {
         trace: trace_enabled,
+        timer,
+        isa,
+        entropy,
+        vlen,
+        max_satp_mode,
+        pmp_entries,
     };
     let mut vm = VM::new_config(vm_config);
+    // The monitor reads its commands from stdin, so the guest console only gets output.
+    if !monitor_enabled {
+        vm.uart.borrow_mut().connect_stdin();
+    }
+
+    if let Some(path) = disk_path {
+        println!("VM: Attaching disk image {}...", path.display());
+        if let Err(e) = vm.attach_disk(&path, disk_read_only) {
+            eprintln!("Failed to open disk image {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    if let Some(path) = &restore_path {
+        println!("VM: Restoring snapshot {}...", path.display());
+        if let Err(e) = vm.load_snapshot(path) {
+            eprintln!("Failed to restore snapshot {}: {}", path.display(), e);
+            return;
+        }
+    } else if let Some(path) = &bios_path {
+        // A raw image replaces both the embedded BIOS and the kernel.
+        println!("VM: Loading BIOS image {}...", path.display());
+        match fs::read(path) {
+            Ok(bytes) => vm.load_bios(&bytes),
+            Err(e) => {
+                eprintln!("Failed to read BIOS image {}: {}", path.display(), e);
+                return;
+            }
+        }
+    } else if vm.config.isa.xlen() == Xlen::Rv32 {
+        eprintln!("The embedded BIOS and kernel are RV64; pass --bios <image> to run RV32 code");
+        return;
+    } else {
+        println!("VM: Loading embedded BIOS...");
+        vm.load_bios(BIOS_BYTES);
+
+        println!("VM: Loading embedded kernel...");
+        vm.load_kernel(KERNEL_BYTES);
+    }
+
+    if let Some(path) = &snapshot_path {
+        // The boot is over once the BIOS drops out of M-mode into the kernel.
+        println!(
+            "VM: Booting to the kernel for snapshot {}...",
+            path.display()
+        );
+        while vm.privilege_level == 3 {
+            if let Some(result) = vm.step() {
+                report_halt(&vm, result);
+                return;
+            }
+        }
+        if let Err(e) = vm.save_snapshot(path) {
+            eprintln!("Failed to save snapshot {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    if let Some(address) = &gdb_address {
+        match gdbstub::listen(&mut vm, address) {
+            Ok(SessionEnd::Detached) => {}
+            Ok(SessionEnd::Killed) => return,
+            Ok(SessionEnd::Halted(result)) => {
+                report_halt(&vm, result);
+                return;
+            }
+            Err(e) => {
+                eprintln!("GDB stub error: {}", e);
+                return;
+            }
+        }
+    }
 
-    println!("VM: Loading embedded BIOS...");
-    vm.load_bios(BIOS_BYTES);
+    if monitor_enabled {
+        let symbols = match &symbols_path {
+            Some(path) => match monitor::load_symbols(path) {
+                Ok(symbols) => symbols,
+                Err(e) => {
+                    eprintln!("Failed to read symbols {}: {}", path.display(), e);
+                    return;
+                }
+            },
+            None => Default::default(),
+        };
+        if let Some(result) = Monitor::new(symbols).run(&mut vm) {
+            report_halt(&vm, result);
+        }
+        return;
+    }
 
-    println!("VM: Loading embedded kernel into virtual disk...");
-    vm.load_virtual_disk(KERNEL_BYTES.to_vec());
+    println!("VM: Starting execution at {:#x}...", vm.pc);
+    println!();
+    let result = vm.run();
+    report_halt(&vm, result);
+}
 
-    println!("VM: Starting execution at reset vector...");
-    println!("");
-    if let Err(e) = vm.run() {
+fn report_halt(vm: &VM, result: Result<(), String>) {
+    if let Err(e) = result {
         eprintln!("\n--- VM Runtime Error ---");
         eprintln!("{}", e);
         vm.print_state();
@@ -44,5 +272,8 @@ fn main() {
 }
 
 fn print_usage(program_name: &str) {
-    eprintln!("Usage: {} [--trace]", program_name);
+    eprintln!(
+        "Usage: {} [--trace] [--host-timer] [--isa <string>] [--entropy <host|seed>] [--vlen <bits>] [--paging <sv39|sv48|sv57>] [--pmp <0|16|64>] [--bios <image>] [--disk <image> [--disk-readonly]] [--snapshot <file> | --restore <file>] [--gdb <[host:]port|unix:path>] [--monitor [--symbols <file>]]",
+        program_name
+    );
 }
@@ -1,39 +1,78 @@
+use crate::mmu::PAGE_SIZE;
+use crate::pmp::AccessType;
 use crate::VM;
 use riscv_core::cause;
+use riscv_core::compressed::instruction_length;
 
 pub const MEMORY_SIZE: usize = 1024 * 1024 * 128; // 128MB of physical RAM
 pub const BASE_ADDRESS: u64 = 0x80000000;
-pub const VIRTUAL_DISK_ADDRESS: u64 = 0x90000000;
-pub const VIRTUAL_DISK_SIZE_ADDRESS: u64 = 0x90001000;
+pub const KERNEL_LOAD_ADDRESS: u64 = 0x80100000;
 
 impl VM {
-    pub(crate) fn fetch(&mut self) -> Option<u32> {
-        let satp = self
-            .csrs
-            .read(riscv_core::csr::SATP, self.privilege_level)
-            .unwrap_or(0);
-        let mmu_is_on = (satp >> 60) == (crate::csr::SATP_MODE_SV39 >> 60);
-
-        let paddr = match self.translate(self.pc, false, true) {
-            Ok(addr) => addr,
-            Err(fault_addr) => {
-                if mmu_is_on {
-                    self.handle_trap(cause::INSTRUCTION_PAGE_FAULT, fault_addr);
-                } else {
-                    self.handle_trap(cause::INSTRUCTION_ACCESS_FAULT, fault_addr);
-                }
-                return None;
-            }
+    /// Fetches the instruction at `pc`. A compressed instruction is returned as is, in the low
+    /// 16 bits. A 32-bit instruction that straddles a page boundary is fetched as two halves,
+    /// each translated on its own. On failure, returns the `(cause, tval)` pair of the trap
+    /// the caller should raise.
+    pub(crate) fn fetch(&mut self) -> Result<u32, (u64, u64)> {
+        if !self.pc.is_multiple_of(self.ialign()) {
+            return Err((cause::INSTRUCTION_ADDRESS_MISALIGNED, self.pc));
+        }
+        let paddr = self.translate_fetch(self.pc)?;
+        let low = self.fetch_halfword(paddr, self.pc)?;
+        if instruction_length(low) == 2 {
+            return Ok(low as u32);
+        }
+
+        let high_vaddr = self.pc.wrapping_add(2);
+        let high_paddr = if high_vaddr & (PAGE_SIZE - 1) == 0 {
+            self.translate_fetch(high_vaddr)?
+        } else {
+            paddr + 2
         };
+        let high = self.fetch_halfword(high_paddr, high_vaddr)?;
+        Ok(((high as u32) << 16) | low as u32)
+    }
 
-        if paddr.checked_add(3).is_none() || paddr + 3 >= self.memory.len() as u64 {
-            self.handle_trap(cause::INSTRUCTION_ACCESS_FAULT, self.pc);
+    fn translate_fetch(&mut self, vaddr: u64) -> Result<u64, (u64, u64)> {
+        self.translate_access(vaddr, 2, AccessType::Execute)
+            .map_err(|fault| (fault.cause(AccessType::Execute), fault.vaddr()))
+    }
+
+    fn fetch_halfword(&mut self, paddr: u64, vaddr: u64) -> Result<u16, (u64, u64)> {
+        match self.read_physical(paddr, 2, AccessType::Execute) {
+            Some(half) => Ok(half as u16),
+            None => Err((cause::INSTRUCTION_ACCESS_FAULT, vaddr)),
+        }
+    }
+
+    /// Reads `size` bytes of physical memory for a load or fetch. Returns `None` if PMP
+    /// denies the access or nothing is mapped at `paddr`; either is an access fault.
+    pub(crate) fn read_physical(
+        &mut self,
+        paddr: u64,
+        size: u64,
+        access: AccessType,
+    ) -> Option<u64> {
+        if !self.pmp_allows(paddr, size, access) {
             return None;
         }
+        self.bus.read(paddr, size)
+    }
 
-        let inst_bytes: [u8; 4] = self.memory[paddr as usize..(paddr + 4) as usize]
-            .try_into()
-            .unwrap();
-        Some(u32::from_le_bytes(inst_bytes))
+    /// Writes `size` bytes of physical memory for a store, returning false on an access
+    /// fault.
+    pub(crate) fn write_physical(&mut self, paddr: u64, size: u64, value: u64) -> bool {
+        self.pmp_allows(paddr, size, AccessType::Write) && self.bus.write(paddr, size, value)
+    }
+
+    /// The length of the instruction at `vaddr`, read without side effects. Used to step over
+    /// an `ebreak`, which may be the 2-byte `c.ebreak`.
+    pub(crate) fn instruction_length_at(&mut self, vaddr: u64) -> u64 {
+        let mut half = [0u8; 2];
+        if self.debug_read(vaddr, &mut half) == 2 {
+            instruction_length(u16::from_le_bytes(half))
+        } else {
+            4
+        }
     }
 }
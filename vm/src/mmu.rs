@@ -1,112 +1,837 @@
 use crate::{
-    csr::{SATP_MODE_SV39, SATP_PPN_MASK},
+    csr::{
+        MSTATUS_MPP, MSTATUS_MPP_SHIFT, MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SUM, MSTATUS_TVM,
+        SATP32_ASID_MASK, SATP32_MODE_SV32, SATP32_PPN_MASK, SATP_ASID_MASK, SATP_MODE_SHIFT,
+        SATP_MODE_SV39, SATP_MODE_SV48, SATP_MODE_SV57, SATP_PPN_MASK,
+    },
+    hpm::HpmEvent,
+    isa::{Extension, Xlen},
+    pmp::AccessType,
     VM,
 };
-use riscv_core::csr;
+use bincode::{Decode, Encode};
+use riscv_core::{cause, csr};
+use std::collections::HashMap;
+use std::str::FromStr;
 
-const PAGE_SIZE: u64 = 4096;
-const PTE_SIZE: u64 = 8;
-const LEVELS: u64 = 3;
+pub(crate) const PAGE_SIZE: u64 = 4096;
 
 const PTE_VALID: u64 = 1 << 0;
 const PTE_READ: u64 = 1 << 1;
 const PTE_WRITE: u64 = 1 << 2;
 const PTE_EXECUTE: u64 = 1 << 3;
-//const PTE_USER: u64 = 1 << 4;
+const PTE_USER: u64 = 1 << 4;
+const PTE_GLOBAL: u64 = 1 << 5;
+const PTE_ACCESSED: u64 = 1 << 6;
+const PTE_DIRTY: u64 = 1 << 7;
+
+/// Why a memory access could not be translated, with the virtual address reported in
+/// `mtval`/`stval`. The trap cause also depends on whether the access was a load, a
+/// store/AMO or a fetch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslateFault {
+    /// The page tables do not map the address, or the mapping forbids the access.
+    PageFault(u64),
+    /// The walk could not read or update a PTE: no memory is there, or PMP denies it.
+    AccessFault(u64),
+    /// The address is not aligned to the size of the access.
+    Misaligned(u64),
+}
+
+impl TranslateFault {
+    pub fn vaddr(self) -> u64 {
+        match self {
+            Self::PageFault(vaddr) | Self::AccessFault(vaddr) | Self::Misaligned(vaddr) => vaddr,
+        }
+    }
+
+    /// The exception this fault raises on an access of kind `access`.
+    pub fn cause(self, access: AccessType) -> u64 {
+        match (self, access) {
+            (Self::PageFault(_), AccessType::Read) => cause::LOAD_PAGE_FAULT,
+            (Self::PageFault(_), AccessType::Write) => cause::STORE_AMO_PAGE_FAULT,
+            (Self::PageFault(_), AccessType::Execute) => cause::INSTRUCTION_PAGE_FAULT,
+            (Self::AccessFault(_), AccessType::Read) => cause::LOAD_ACCESS_FAULT,
+            (Self::AccessFault(_), AccessType::Write) => cause::STORE_AMO_ACCESS_FAULT,
+            (Self::AccessFault(_), AccessType::Execute) => cause::INSTRUCTION_ACCESS_FAULT,
+            (Self::Misaligned(_), AccessType::Read) => cause::LOAD_ADDRESS_MISALIGNED,
+            (Self::Misaligned(_), AccessType::Write) => cause::STORE_AMO_ADDRESS_MISALIGNED,
+            (Self::Misaligned(_), AccessType::Execute) => cause::INSTRUCTION_ADDRESS_MISALIGNED,
+        }
+    }
+}
+
+/// The shape of the page tables for one satp mode.
+pub(crate) struct PagingMode {
+    levels: u64,
+    pte_size: u64,
+    /// Bits of the virtual page number each level indexes.
+    vpn_bits: u64,
+    /// Mask of the PPN field, both in satp and in a PTE above bit 10.
+    ppn_mask: u64,
+    /// PTE bits that must be zero: the reserved bits and those of unimplemented extensions
+    /// (Svpbmt and Svnapot).
+    reserved_mask: u64,
+    /// Bits of a virtual address. On RV64 the bits above must all equal the top one.
+    va_bits: u32,
+}
+
+const SV32: PagingMode = PagingMode {
+    levels: 2,
+    pte_size: 4,
+    vpn_bits: 10,
+    ppn_mask: SATP32_PPN_MASK,
+    reserved_mask: 0,
+    va_bits: 32,
+};
+
+const SV39: PagingMode = PagingMode {
+    levels: 3,
+    pte_size: 8,
+    vpn_bits: 9,
+    ppn_mask: SATP_PPN_MASK,
+    reserved_mask: 0xFFC0_0000_0000_0000,
+    va_bits: 39,
+};
+
+/// Sv48 and Sv57 add a level of 9 VPN bits each to Sv39.
+const SV48: PagingMode = PagingMode {
+    levels: 4,
+    va_bits: 48,
+    ..SV39
+};
+
+const SV57: PagingMode = PagingMode {
+    levels: 5,
+    va_bits: 57,
+    ..SV39
+};
+
+/// An RV64 paging mode. A hart that implements one implements every smaller one, so the
+/// largest is all that needs configuring.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
+pub enum SatpMode {
+    Sv39,
+    Sv48,
+    #[default]
+    Sv57,
+}
+
+impl SatpMode {
+    /// The mode selected by the MODE field of an RV64 satp value, if it is a paging mode.
+    pub fn from_satp(satp: u64) -> Option<Self> {
+        match satp & (0xF << SATP_MODE_SHIFT) {
+            SATP_MODE_SV39 => Some(SatpMode::Sv39),
+            SATP_MODE_SV48 => Some(SatpMode::Sv48),
+            SATP_MODE_SV57 => Some(SatpMode::Sv57),
+            _ => None,
+        }
+    }
+
+    fn paging_mode(self) -> &'static PagingMode {
+        match self {
+            SatpMode::Sv39 => &SV39,
+            SatpMode::Sv48 => &SV48,
+            SatpMode::Sv57 => &SV57,
+        }
+    }
+}
+
+impl FromStr for SatpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "sv39" => Ok(SatpMode::Sv39),
+            "sv48" => Ok(SatpMode::Sv48),
+            "sv57" => Ok(SatpMode::Sv57),
+            _ => Err(format!("{}: expected sv39, sv48 or sv57", s)),
+        }
+    }
+}
+
+impl PagingMode {
+    /// The bit at which the VPN field for `level` starts.
+    fn shift(&self, level: u64) -> u64 {
+        12 + self.vpn_bits * level
+    }
+
+    fn vpn_part(&self, vaddr: u64, level: u64) -> u64 {
+        (vaddr >> self.shift(level)) & ((1 << self.vpn_bits) - 1)
+    }
+
+    /// The physical address `vaddr` maps to through the leaf `pte` found at `level`. A
+    /// superpage keeps the low virtual address bits below its level.
+    fn leaf_address(&self, pte: u64, level: u64, vaddr: u64) -> u64 {
+        let ppn = (pte >> 10) & self.ppn_mask;
+        let offset_mask = (1u64 << self.shift(level)) - 1;
+        ((ppn << 12) & !offset_mask) | (vaddr & offset_mask)
+    }
+
+    /// A superpage must start on a boundary of its own size: the PPN bits that the virtual
+    /// address supplies must be zero.
+    fn is_misaligned_superpage(&self, pte: u64, level: u64) -> bool {
+        let ppn = (pte >> 10) & self.ppn_mask;
+        (ppn << 12) & ((1u64 << self.shift(level)) - 1) != 0
+    }
+
+    pub(crate) fn is_canonical(&self, vaddr: u64) -> bool {
+        let unused = 64 - self.va_bits;
+        (((vaddr << unused) as i64) >> unused) as u64 == vaddr
+    }
+}
+
+/// A cached translation of one 4 KiB virtual page.
+#[derive(Clone, Copy, Debug)]
+pub struct TlbEntry {
+    /// The physical address of the page.
+    pub page: u64,
+    /// The leaf PTE the walk ended at, as written back if its A/D bits were updated. Its
+    /// permission, U and A/D bits are checked again on every hit.
+    pub pte: u64,
+    /// The address space the walk was made in, and the root table it started from.
+    pub asid: u64,
+    pub root: u64,
+    /// Whether the mapping is global: the leaf or any table above it has the G bit set.
+    /// A global entry serves every ASID.
+    pub global: bool,
+    /// log2 of the size of the page the leaf maps: 12, or more for a superpage.
+    pub page_shift: u64,
+}
+
+/// The translation cache, holding one entry per 4 KiB virtual page and address space. A
+/// superpage gets an entry for each of its pages that has been used. Global entries are
+/// keyed without an ASID, so every address space finds them.
+#[derive(Default)]
+pub struct Tlb {
+    entries: HashMap<(u64, Option<u64>), TlbEntry>,
+}
+
+impl Tlb {
+    /// The cached translation of `vaddr` usable in address space `asid`.
+    pub fn lookup(&self, vaddr: u64, asid: u64) -> Option<&TlbEntry> {
+        let vpn = vaddr / PAGE_SIZE;
+        self.entries
+            .get(&(vpn, Some(asid)))
+            .or_else(|| self.entries.get(&(vpn, None)))
+    }
+
+    pub fn insert(&mut self, vaddr: u64, entry: TlbEntry) {
+        let asid = (!entry.global).then_some(entry.asid);
+        self.entries.insert((vaddr / PAGE_SIZE, asid), entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drops the entries `sfence.vma` names: those whose page contains `vaddr` and those of
+    /// `asid`, or every address or ASID when it is `None`. Global entries are only dropped
+    /// when no ASID is given.
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u64>) {
+        self.entries.retain(|&(vpn, _), entry| {
+            let address_matches =
+                vaddr.is_none_or(|vaddr| ((vpn * PAGE_SIZE) ^ vaddr) >> entry.page_shift == 0);
+            let asid_matches = asid.is_none_or(|asid| !entry.global && entry.asid == asid);
+            !(address_matches && asid_matches)
+        });
+    }
+
+    /// Drops the non-global entries of `asid` that were walked from a root other than
+    /// `root`.
+    pub fn flush_other_roots(&mut self, asid: u64, root: u64) {
+        self.entries
+            .retain(|_, entry| entry.global || entry.asid != asid || entry.root == root);
+    }
+}
+
+/// One page-table entry visited during a walk.
+pub struct PageWalkStep {
+    pub level: u64,
+    pub pte_addr: u64,
+    pub pte: u64,
+}
 
 impl VM {
-    pub fn translate(&mut self, vaddr: u64, is_write: bool, is_execute: bool) -> Result<u64, u64> {
-        let satp = self.csrs.read(csr::SATP, self.privilege_level).unwrap_or(0);
-        let mode = satp >> 60;
+    /// Walks the page table for `vaddr` the way `translate` would, without checking
+    /// privilege, permissions or A/D bits, and reports every PTE it reads. Returns the
+    /// visited entries and the resulting physical address, or `None` if the walk ends at an
+    /// invalid or unreadable PTE.
+    pub fn page_walk(&mut self, vaddr: u64) -> (Vec<PageWalkStep>, Option<u64>) {
+        let satp = self.csrs.read(csr::SATP, 3).unwrap_or(0);
+        let vaddr = self.effective_address(vaddr);
+        let Some(mode) = self.paging_mode(satp) else {
+            return (Vec::new(), Some(vaddr));
+        };
 
-        if mode != (SATP_MODE_SV39 >> 60) {
-            if vaddr < super::memory::BASE_ADDRESS {
-                return Err(vaddr);
+        let mut steps = Vec::new();
+        let mut table_addr = (satp & mode.ppn_mask) * PAGE_SIZE;
+        for level in (0..mode.levels).rev() {
+            let pte_addr = table_addr + mode.vpn_part(vaddr, level) * mode.pte_size;
+            let Some(pte) = self.bus.read(pte_addr, mode.pte_size) else {
+                return (steps, None);
+            };
+            steps.push(PageWalkStep {
+                level,
+                pte_addr,
+                pte,
+            });
+            if (pte & PTE_VALID) == 0 {
+                return (steps, None);
             }
-            let paddr = vaddr - super::memory::BASE_ADDRESS;
-            if paddr >= self.memory.len() as u64 {
-                return Err(vaddr);
+            if (pte & (PTE_READ | PTE_WRITE | PTE_EXECUTE)) != 0 {
+                return (steps, Some(mode.leaf_address(pte, level, vaddr)));
             }
-            return Ok(paddr);
+            table_addr = ((pte >> 10) & mode.ppn_mask) * PAGE_SIZE;
         }
+        (steps, None)
+    }
 
-        let vpn = vaddr / PAGE_SIZE;
-        if let Some(&paddr_base) = self.tlb.get(&vpn) {
-            return Ok(paddr_base + (vaddr % PAGE_SIZE));
+    /// The page-table shape selected by `satp`, or `None` when translation is off. satp
+    /// only ever holds a mode the hart implements.
+    pub(crate) fn paging_mode(&self, satp: u64) -> Option<&'static PagingMode> {
+        match self.config.isa.xlen() {
+            Xlen::Rv32 if satp & SATP32_MODE_SV32 != 0 => Some(&SV32),
+            Xlen::Rv32 => None,
+            Xlen::Rv64 => SatpMode::from_satp(satp).map(SatpMode::paging_mode),
+        }
+    }
+
+    /// The position of the ASID field in satp.
+    fn satp_asid_mask(&self) -> u64 {
+        match self.config.isa.xlen() {
+            Xlen::Rv32 => SATP32_ASID_MASK,
+            Xlen::Rv64 => SATP_ASID_MASK,
+        }
+    }
+
+    /// The address space `satp` selects.
+    fn asid(&self, satp: u64) -> u64 {
+        let mask = self.satp_asid_mask();
+        (satp & mask) >> mask.trailing_zeros()
+    }
+
+    /// Drops the cached translations a write of satp has made stale. Entries of other
+    /// address spaces stay valid, since they are tagged with their ASID, but those of the
+    /// new ASID walked from a different root are replaced, and a new mode replaces them all.
+    pub(crate) fn satp_written(&mut self, old_satp: u64) {
+        let new_satp = self.csrs.satp;
+        let mode = self.paging_mode(new_satp);
+        if mode.map(|mode| mode.levels) != self.paging_mode(old_satp).map(|mode| mode.levels) {
+            self.tlb.clear();
+        } else if let Some(mode) = mode {
+            let root = (new_satp & mode.ppn_mask) * PAGE_SIZE;
+            self.tlb.flush_other_roots(self.asid(new_satp), root);
+        }
+    }
+
+    /// Executes `sfence.vma rs1, rs2`, flushing the translations of the address in rs1 and
+    /// the ASID in rs2. x0 in either place stands for every address or every ASID.
+    pub(crate) fn execute_sfence_vma(&mut self, inst: u32, next_pc: u64) -> bool {
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+        let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+        if self.privilege_level == 0 || (self.privilege_level == 1 && mstatus & MSTATUS_TVM != 0) {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
         }
 
-        let root_ppn = satp & SATP_PPN_MASK;
-        let mut table_addr = root_ppn * PAGE_SIZE;
+        let vaddr = (rs1 != 0).then(|| self.effective_address(self.registers[rs1]));
+        let asid_mask = self.satp_asid_mask() >> self.satp_asid_mask().trailing_zeros();
+        let asid = (rs2 != 0).then(|| self.registers[rs2] & asid_mask);
+        self.tlb.flush(vaddr, asid);
+        self.pc = next_pc;
+        true
+    }
+
+    /// The page-table shape an access at `privilege` goes through, or `None` if it is not
+    /// translated: satp is Bare, or the access is made in M-mode.
+    pub(crate) fn active_paging_mode(&self, privilege: u8) -> Option<&'static PagingMode> {
+        if privilege == 3 {
+            return None;
+        }
+        self.paging_mode(self.csrs.read(csr::SATP, 3).unwrap_or(0))
+    }
+
+    /// The privilege a memory access is translated and checked at. While mstatus.MPRV is
+    /// set, loads and stores in M-mode use the privilege in mstatus.MPP; fetches always use
+    /// the current privilege.
+    pub(crate) fn effective_privilege(&self, is_execute: bool) -> u8 {
+        let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+        if !is_execute && self.privilege_level == 3 && mstatus & MSTATUS_MPRV != 0 {
+            ((mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT) as u8
+        } else {
+            self.privilege_level
+        }
+    }
+
+    /// An RV32 address register holds its value sign-extended; only the low 32 bits name
+    /// the address.
+    pub(crate) fn effective_address(&self, vaddr: u64) -> u64 {
+        vaddr & self.config.isa.xlen().mask()
+    }
+
+    /// Whether the leaf `pte` allows the access at `privilege`. U-mode may only use user
+    /// pages; S-mode may load and store through them when mstatus.SUM is set, but never
+    /// execute from them. mstatus.MXR lets loads read executable pages.
+    pub(crate) fn leaf_permits(
+        &self,
+        pte: u64,
+        privilege: u8,
+        is_write: bool,
+        is_execute: bool,
+    ) -> bool {
+        let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+        let user_page = pte & PTE_USER != 0;
+        let privilege_ok = if privilege == 0 {
+            user_page
+        } else {
+            !user_page || (!is_execute && mstatus & MSTATUS_SUM != 0)
+        };
+        let access_ok = if is_write {
+            pte & PTE_WRITE != 0
+        } else if is_execute {
+            pte & PTE_EXECUTE != 0
+        } else {
+            pte & PTE_READ != 0 || (mstatus & MSTATUS_MXR != 0 && pte & PTE_EXECUTE != 0)
+        };
+        privilege_ok && access_ok
+    }
+
+    pub fn translate(
+        &mut self,
+        vaddr: u64,
+        is_write: bool,
+        is_execute: bool,
+    ) -> Result<u64, TranslateFault> {
+        let vaddr = self.effective_address(vaddr);
+        let privilege = self.effective_privilege(is_execute);
+        let Some(mode) = self.active_paging_mode(privilege) else {
+            return Ok(vaddr);
+        };
+        if self.config.isa.xlen() == Xlen::Rv64 && !mode.is_canonical(vaddr) {
+            return Err(TranslateFault::PageFault(vaddr));
+        }
+
+        // A cached entry is only used if it allows the access outright; anything else,
+        // including a first write to a clean page, takes the walk below.
+        let satp = self.csrs.read(csr::SATP, 3).unwrap_or(0);
+        let asid = self.asid(satp);
+        let root = (satp & mode.ppn_mask) * PAGE_SIZE;
+        if let Some(entry) = self.tlb.lookup(vaddr, asid)
+            && self.leaf_permits(entry.pte, privilege, is_write, is_execute)
+            && (!is_write || entry.pte & PTE_DIRTY != 0)
+        {
+            return Ok(entry.page + (vaddr % PAGE_SIZE));
+        }
+        self.count_event(HpmEvent::TlbMiss);
 
-        for level in (0..LEVELS).rev() {
-            let vpn_part = (vaddr >> (12 + 9 * level)) & 0x1FF;
-            let pte_addr = table_addr + vpn_part * PTE_SIZE;
+        let mut table_addr = root;
+        let mut global = false;
 
-            if pte_addr < super::memory::BASE_ADDRESS
-                || pte_addr >= super::memory::BASE_ADDRESS + self.memory.len() as u64
+        for level in (0..mode.levels).rev() {
+            let pte_addr = table_addr + mode.vpn_part(vaddr, level) * mode.pte_size;
+
+            // PMP checks the walk's own accesses as if they were made from S-mode.
+            if !self
+                .csrs
+                .pmp
+                .check(pte_addr, mode.pte_size, 1, AccessType::Read)
             {
-                return Err(vaddr);
+                return Err(TranslateFault::AccessFault(vaddr));
             }
-            let pte_offset = (pte_addr - super::memory::BASE_ADDRESS) as usize;
+            let mut pte = match self.bus.read(pte_addr, mode.pte_size) {
+                Some(pte) => pte,
+                None => return Err(TranslateFault::AccessFault(vaddr)),
+            };
 
-            let pte_bytes: [u8; 8] = self.memory[pte_offset..(pte_offset + PTE_SIZE as usize)]
-                .try_into()
-                .unwrap();
-            let pte = u64::from_le_bytes(pte_bytes);
+            // Writable but not readable is a reserved encoding.
+            if (pte & PTE_VALID) == 0
+                || pte & mode.reserved_mask != 0
+                || (pte & (PTE_READ | PTE_WRITE)) == PTE_WRITE
+            {
+                return Err(TranslateFault::PageFault(vaddr));
+            }
+            global |= pte & PTE_GLOBAL != 0;
 
-            if (pte & PTE_VALID) == 0 {
-                return Err(vaddr);
+            if (pte & (PTE_READ | PTE_EXECUTE)) == 0 {
+                table_addr = ((pte >> 10) & mode.ppn_mask) * PAGE_SIZE;
+                continue;
             }
 
-            if (pte & (PTE_READ | PTE_WRITE | PTE_EXECUTE)) != 0 {
-                if is_write && (pte & PTE_WRITE) == 0 {
-                    return Err(vaddr);
-                }
-                if !is_write && is_execute && (pte & PTE_EXECUTE) == 0 {
-                    return Err(vaddr);
+            if mode.is_misaligned_superpage(pte, level)
+                || !self.leaf_permits(pte, privilege, is_write, is_execute)
+            {
+                return Err(TranslateFault::PageFault(vaddr));
+            }
+
+            // Svade faults when the A bit, or the D bit on a write, is clear so that the
+            // kernel can track use itself; Svadu sets them in the PTE instead.
+            let needed = PTE_ACCESSED | if is_write { PTE_DIRTY } else { 0 };
+            if pte & needed != needed {
+                if !self.extension_enabled(Extension::Svadu) {
+                    return Err(TranslateFault::PageFault(vaddr));
                 }
-                if !is_write && !is_execute && (pte & PTE_READ) == 0 {
-                    return Err(vaddr);
+                pte |= needed;
+                if !self
+                    .csrs
+                    .pmp
+                    .check(pte_addr, mode.pte_size, 1, AccessType::Write)
+                    || !self.bus.write(pte_addr, mode.pte_size, pte)
+                {
+                    return Err(TranslateFault::AccessFault(vaddr));
                 }
+            }
 
-                let paddr = match level {
-                    // Level 2 -> 1GB Gigapage.
-                    // Physical Address = [PTE PPN[2] | vaddr[29:0]]
-                    2 => {
-                        let pte_ppn2 = (pte >> 28) & 0x3FFFFFF;
-                        let vaddr_offset = vaddr & 0x3FFFFFFF;
-                        (pte_ppn2 << 30) | vaddr_offset
-                    }
-                    // Level 1 -> 2MB Megapage.
-                    // Physical Address = [PTE PPN[2] | PTE PPN[1] | vaddr[20:0]]
-                    1 => {
-                        let pte_ppn2 = (pte >> 28) & 0x3FFFFFF;
-                        let pte_ppn1 = (pte >> 19) & 0x1FF;
-                        let vaddr_offset = vaddr & 0x1FFFFF;
-                        (pte_ppn2 << 30) | (pte_ppn1 << 21) | vaddr_offset
-                    }
-                    // Level 0 -> 4KB Page.
-                    // Physical Address = [PTE PPN[2] | PTE PPN[1] | PTE PPN[0] | vaddr[11:0]]
-                    _ => {
-                        let pte_ppn = (pte >> 10) & SATP_PPN_MASK;
-                        let vaddr_offset = vaddr & 0xFFF;
-                        (pte_ppn << 12) | vaddr_offset
-                    }
-                };
-
-                if level == 0 {
-                    self.tlb.insert(vpn, paddr - (vaddr % PAGE_SIZE));
-                }
+            // A leaf above level 0 is a superpage: a megapage or gigapage.
+            let paddr = mode.leaf_address(pte, level, vaddr);
+            self.tlb.insert(
+                vaddr,
+                TlbEntry {
+                    page: paddr - (vaddr % PAGE_SIZE),
+                    pte,
+                    asid,
+                    root,
+                    global,
+                    page_shift: mode.shift(level),
+                },
+            );
+            return Ok(paddr);
+        }
 
-                if paddr < super::memory::BASE_ADDRESS {
-                    return Err(paddr);
-                }
-                return Ok(paddr - super::memory::BASE_ADDRESS);
+        Err(TranslateFault::PageFault(vaddr))
+    }
+
+    /// Translates a load, store/AMO or fetch of `size` bytes at `vaddr`, which must be
+    /// naturally aligned.
+    pub(crate) fn translate_access(
+        &mut self,
+        vaddr: u64,
+        size: u64,
+        access: AccessType,
+    ) -> Result<u64, TranslateFault> {
+        if !vaddr.is_multiple_of(size) {
+            return Err(TranslateFault::Misaligned(vaddr));
+        }
+        self.translate(
+            vaddr,
+            access == AccessType::Write,
+            access == AccessType::Execute,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::{VmConfig, VM};
+
+    const ROOT: u64 = BASE_ADDRESS + 0x10000;
+    const DATA: u64 = BASE_ADDRESS + 0x20000;
+    const PTE_AD: u64 = PTE_ACCESSED | PTE_DIRTY;
+
+    /// A VM in S-mode whose Sv39 table maps virtual page 0 to `DATA` with `flags`.
+    fn vm_with_page(isa: &str, flags: u64) -> VM {
+        let mut vm = VM::new_config(VmConfig {
+            isa: isa.parse().unwrap(),
+            ..VmConfig::default()
+        });
+        let pointer = |table: u64| ((table >> 12) << 10) | PTE_VALID;
+        vm.bus.write(ROOT, 8, pointer(ROOT + 0x1000));
+        vm.bus.write(ROOT + 0x1000, 8, pointer(ROOT + 0x2000));
+        vm.bus
+            .write(ROOT + 0x2000, 8, ((DATA >> 12) << 10) | PTE_VALID | flags);
+        vm.csrs.write(csr::SATP, SATP_MODE_SV39 | (ROOT >> 12), 3);
+        vm.privilege_level = 1;
+        vm.open_pmp();
+        vm
+    }
+
+    fn set_mstatus(vm: &mut VM, bits: u64) {
+        let mstatus = vm.csrs.read(csr::MSTATUS, 3).unwrap();
+        vm.csrs.write(csr::MSTATUS, mstatus | bits, 3);
+    }
+
+    #[test]
+    fn test_user_pages_need_u_mode_or_sum() {
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_WRITE | PTE_EXECUTE | PTE_AD);
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        vm.privilege_level = 0;
+        assert!(vm.translate(0x10, false, false).is_err());
+
+        let user = PTE_READ | PTE_WRITE | PTE_EXECUTE | PTE_USER | PTE_AD;
+        let mut vm = vm_with_page("rv64gc", user);
+        assert!(vm.translate(0x10, false, false).is_err());
+        set_mstatus(&mut vm, MSTATUS_SUM);
+        vm.tlb.clear();
+        assert_eq!(vm.translate(0x10, true, false), Ok(DATA + 0x10));
+        // SUM never lets S-mode execute user code.
+        assert!(vm.translate(0x10, false, true).is_err());
+        vm.privilege_level = 0;
+        assert_eq!(vm.translate(0x10, false, true), Ok(DATA + 0x10));
+    }
+
+    #[test]
+    fn test_mxr_makes_executable_pages_readable() {
+        let mut vm = vm_with_page("rv64gc", PTE_EXECUTE | PTE_AD);
+        assert!(vm.translate(0x10, false, false).is_err());
+        set_mstatus(&mut vm, MSTATUS_MXR);
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        assert!(vm.translate(0x10, true, false).is_err());
+    }
+
+    #[test]
+    fn test_m_mode_is_untranslated_unless_mprv() {
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_WRITE | PTE_AD);
+        vm.privilege_level = 3;
+        assert_eq!(vm.translate(0x10, false, false), Ok(0x10));
+
+        // MPP = S.
+        set_mstatus(&mut vm, MSTATUS_MPRV | (1 << MSTATUS_MPP_SHIFT));
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        // Fetches ignore MPRV.
+        assert_eq!(vm.translate(0x10, false, true), Ok(0x10));
+    }
+
+    #[test]
+    fn test_svade_faults_and_svadu_updates_accessed_and_dirty() {
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_WRITE);
+        assert!(vm.translate(0x10, false, false).is_err());
+        assert_eq!(vm.bus.read(ROOT + 0x2000, 8).unwrap() & PTE_AD, 0);
+
+        let mut vm = vm_with_page("rv64gc_svadu", PTE_READ | PTE_WRITE);
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        assert_eq!(
+            vm.bus.read(ROOT + 0x2000, 8).unwrap() & PTE_AD,
+            PTE_ACCESSED
+        );
+        // The cached translation is clean, so the first write walks again to set D.
+        assert_eq!(vm.translate(0x18, true, false), Ok(DATA + 0x18));
+        assert_eq!(vm.bus.read(ROOT + 0x2000, 8).unwrap() & PTE_AD, PTE_AD);
+    }
+
+    #[test]
+    fn test_debugger_reads_leave_no_trace() {
+        let mut vm = vm_with_page("rv64gc_svadu", PTE_READ | PTE_WRITE);
+        vm.csrs.write(csr::MHPMEVENT3, HpmEvent::TlbMiss as u64, 3);
+        vm.bus.write(DATA + 0x10, 4, 0x1234_5678);
+
+        let mut buf = [0; 4];
+        assert_eq!(vm.debug_read(0x10, &mut buf), 4);
+        assert_eq!(u32::from_le_bytes(buf), 0x1234_5678);
+        assert!(vm.tlb.lookup(0x10, 0).is_none());
+        assert_eq!(vm.bus.read(ROOT + 0x2000, 8).unwrap() & PTE_AD, 0);
+        assert_eq!(vm.csrs.read(csr::MHPMCOUNTER3, 3), Some(0));
+
+        // The debugger sees only what the hart could load.
+        vm.privilege_level = 0;
+        assert_eq!(vm.debug_read(0x10, &mut buf), 0);
+    }
+
+    #[test]
+    fn test_malformed_mappings_fault() {
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_WRITE | PTE_AD);
+        // Bits 63:39 must copy bit 38.
+        assert!(vm.translate(1 << 39, false, false).is_err());
+
+        // A megapage whose PPN is not 2 MiB aligned.
+        vm.bus.write(
+            ROOT + 0x1000,
+            8,
+            (((DATA + 0x1000) >> 12) << 10) | PTE_VALID | PTE_READ | PTE_AD,
+        );
+        vm.tlb.clear();
+        assert!(vm.translate(0x10, false, false).is_err());
+
+        // Write-only is reserved, as are the high PTE bits.
+        let mut vm = vm_with_page("rv64gc", PTE_WRITE | PTE_AD);
+        assert!(vm.translate(0x10, true, false).is_err());
+        let mut vm = vm_with_page("rv64gc", (1 << 62) | PTE_READ | PTE_AD);
+        assert!(vm.translate(0x10, false, false).is_err());
+    }
+
+    #[test]
+    fn test_faults_report_page_or_access_causes() {
+        const LD: u32 = 0x0005_b503; // ld a0, 0(a1)
+        const SD: u32 = 0x00a5_b023; // sd a0, 0(a1)
+        const LR_D: u32 = 0x1005_b52f; // lr.d a0, (a1)
+        const AMOADD_D: u32 = 0x00c5_b52f; // amoadd.d a0, a2, (a1)
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_AD);
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        let trap = |vm: &mut VM, inst: u32, vaddr: u64| {
+            vm.privilege_level = 1;
+            vm.registers[11] = vaddr;
+            vm.csrs.write(csr::MCAUSE, 0, 3);
+            vm.execute(inst, 4);
+            let cause = vm.csrs.read(csr::MCAUSE, 3).unwrap();
+            (cause, vm.csrs.read(csr::MTVAL, 3).unwrap())
+        };
+
+        assert_eq!(trap(&mut vm, LD, 0x10).0, 0);
+        assert_eq!(trap(&mut vm, SD, 0x10), (cause::STORE_AMO_PAGE_FAULT, 0x10));
+        assert_eq!(
+            trap(&mut vm, AMOADD_D, 0x10),
+            (cause::STORE_AMO_PAGE_FAULT, 0x10)
+        );
+        assert_eq!(trap(&mut vm, LD, 0x1008), (cause::LOAD_PAGE_FAULT, 0x1008));
+        assert_eq!(
+            trap(&mut vm, LR_D, 0x1008),
+            (cause::LOAD_PAGE_FAULT, 0x1008)
+        );
+        assert_eq!(
+            trap(&mut vm, LD, 0x11),
+            (cause::LOAD_ADDRESS_MISALIGNED, 0x11)
+        );
+        vm.pc = 0x1000;
+        vm.privilege_level = 1;
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::INSTRUCTION_PAGE_FAULT)
+        );
+
+        // PMP denies the walk its reads of the page tables.
+        vm.csrs.write(csr::PMPADDR0, (ROOT >> 2) | 0x1FFF, 3);
+        vm.csrs.write(csr::PMPCFG0, 0x18, 3);
+        vm.tlb.clear();
+        assert_eq!(trap(&mut vm, LD, 0x10), (cause::LOAD_ACCESS_FAULT, 0x10));
+        assert_eq!(
+            trap(&mut vm, SD, 0x10),
+            (cause::STORE_AMO_ACCESS_FAULT, 0x10)
+        );
+        vm.pc = 0x10;
+        vm.privilege_level = 1;
+        assert_eq!(vm.step(), None);
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::INSTRUCTION_ACCESS_FAULT)
+        );
+    }
+
+    #[test]
+    fn test_sv48_and_sv57_walk_their_extra_levels() {
+        for (mode, satp_mode) in [(&SV48, SATP_MODE_SV48), (&SV57, SATP_MODE_SV57)] {
+            let mut vm = VM::new();
+            // A canonical address above the Sv39 range, using every level's index.
+            let vaddr = (1u64 << (mode.va_bits - 2)) | (0x1FF << 21) | 0x5678;
+            let mut table = ROOT;
+            for level in (1..mode.levels).rev() {
+                let next = table + 0x1000;
+                let entry = table + mode.vpn_part(vaddr, level) * 8;
+                vm.bus.write(entry, 8, ((next >> 12) << 10) | PTE_VALID);
+                table = next;
             }
+            let leaf = ((DATA >> 12) << 10) | PTE_VALID | PTE_READ | PTE_AD;
+            vm.bus.write(table + mode.vpn_part(vaddr, 0) * 8, 8, leaf);
+            vm.csrs.write(csr::SATP, satp_mode | (ROOT >> 12), 3);
+            vm.privilege_level = 1;
+            vm.open_pmp();
 
-            table_addr = ((pte >> 10) & SATP_PPN_MASK) * PAGE_SIZE;
+            assert_eq!(vm.translate(vaddr, false, false), Ok(DATA + 0x678));
+            let (steps, paddr) = vm.page_walk(vaddr);
+            assert_eq!(
+                (steps.len() as u64, paddr),
+                (mode.levels, Some(DATA + 0x678))
+            );
+            assert!(vm.translate(1 << (mode.va_bits - 1), false, false).is_err());
         }
+    }
+
+    #[test]
+    fn test_satp_ignores_writes_of_unsupported_modes() {
+        let mut vm = VM::new_config(VmConfig {
+            max_satp_mode: SatpMode::Sv48,
+            ..VmConfig::default()
+        });
+        vm.csrs.write(csr::SATP, SATP_MODE_SV48 | 0x80010, 3);
+        for unsupported in [SATP_MODE_SV57, 5 << SATP_MODE_SHIFT, 11 << SATP_MODE_SHIFT] {
+            vm.csrs.write(csr::SATP, unsupported | 0x80020, 3);
+            assert_eq!(vm.csrs.read(csr::SATP, 3), Some(SATP_MODE_SV48 | 0x80010));
+        }
+        vm.csrs.write(csr::SATP, 0, 3);
+        assert_eq!(vm.csrs.read(csr::SATP, 3), Some(0));
+        assert_eq!("SV39".parse::<SatpMode>(), Ok(SatpMode::Sv39));
+    }
+
+    #[test]
+    fn test_sfence_vma_flushes_by_address_and_asid() {
+        const SFENCE_A0_A1: u32 = 0x12B50073;
+        const SFENCE_A0_ZERO: u32 = 0x12050073;
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_AD);
+        vm.csrs
+            .write(csr::SATP, SATP_MODE_SV39 | (7 << 44) | (ROOT >> 12), 3);
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+
+        // The cached translation outlives an edit of the PTE until it is flushed.
+        let moved = ((DATA + 0x1000) >> 12) << 10 | PTE_VALID | PTE_READ | PTE_AD;
+        vm.bus.write(ROOT + 0x2000, 8, moved);
+        vm.registers[10] = 0x1000;
+        vm.registers[11] = 7;
+        assert!(vm.execute(SFENCE_A0_A1, 4));
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        vm.registers[10] = 0x18;
+        vm.registers[11] = 8;
+        assert!(vm.execute(SFENCE_A0_A1, 4));
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x10));
+        vm.registers[11] = 7;
+        assert!(vm.execute(SFENCE_A0_A1, 4));
+        assert_eq!(vm.translate(0x10, false, false), Ok(DATA + 0x1010));
+
+        // Global entries are kept by a flush of one ASID, but not by one of every ASID.
+        vm.bus.write(ROOT + 0x2000, 8, moved | PTE_GLOBAL);
+        vm.tlb.clear();
+        assert!(vm.translate(0x10, false, false).is_ok());
+        vm.bus.write(ROOT + 0x2000, 8, 0);
+        assert!(vm.execute(SFENCE_A0_A1, 4));
+        assert!(vm.translate(0x10, false, false).is_ok());
+        assert!(vm.execute(SFENCE_A0_ZERO, 4));
+        assert!(vm.translate(0x10, false, false).is_err());
+
+        vm.privilege_level = 0;
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        assert!(vm.execute(SFENCE_A0_ZERO, 4));
+        assert_eq!(
+            vm.csrs.read(csr::MCAUSE, 3),
+            Some(cause::ILLEGAL_INSTRUCTION)
+        );
+    }
+
+    #[test]
+    fn test_satp_write_flushes_only_the_stale_address_space() {
+        const CSRRW_SATP_A0: u32 = 0x18051073;
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_AD);
+        let satp = |asid: u64, root: u64| SATP_MODE_SV39 | (asid << 44) | (root >> 12);
+        vm.csrs.write(csr::SATP, satp(1, ROOT), 3);
+        assert!(vm.translate(0x10, false, false).is_ok());
+
+        // Another ASID has its own translations; the first one's stay cached.
+        vm.registers[10] = satp(2, ROOT + 0x3000);
+        assert!(vm.execute(CSRRW_SATP_A0, 4));
+        assert!(vm.translate(0x10, false, false).is_err());
+        assert!(vm.tlb.lookup(0x10, 1).is_some());
+
+        // Both ASIDs can cache the same page at once.
+        vm.registers[10] = satp(3, ROOT);
+        assert!(vm.execute(CSRRW_SATP_A0, 4));
+        assert!(vm.translate(0x10, false, false).is_ok());
+        assert!(vm.tlb.lookup(0x10, 3).is_some());
+        assert!(vm.tlb.lookup(0x10, 1).is_some());
+
+        // A new root under the same ASID replaces its mappings.
+        vm.registers[10] = satp(1, ROOT + 0x3000);
+        assert!(vm.execute(CSRRW_SATP_A0, 4));
+        assert!(vm.tlb.lookup(0x10, 1).is_none());
+    }
 
-        Err(vaddr)
+    #[test]
+    fn test_global_bit_is_inherited_from_the_tables_above() {
+        let mut vm = vm_with_page("rv64gc", PTE_READ | PTE_AD);
+        vm.bus.write(
+            ROOT,
+            8,
+            (((ROOT + 0x1000) >> 12) << 10) | PTE_VALID | PTE_GLOBAL,
+        );
+        assert!(vm.translate(0x10, false, false).is_ok());
+        assert!(vm.tlb.lookup(0, 0x1234).unwrap().global);
     }
 }
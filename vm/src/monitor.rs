@@ -0,0 +1,520 @@
+use crate::debug::DebugStop;
+use crate::isa::Xlen;
+use crate::VM;
+use assembler::{disassemble, parse_csr, parse_register};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the SIGINT handler while the monitor is running the guest.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+const HELP: &str = "\
+commands:
+  step [N]             execute N instructions (default 1)
+  continue             run until a breakpoint, ebreak, Ctrl-C or halt
+  break <addr|symbol>  stop when pc reaches the address
+  delete [addr|symbol] remove one breakpoint, or all of them
+  regs                 show general-purpose registers and key CSRs
+  csr <name|0xaddr>    show one CSR
+  x/N[x][bhwg] <addr>  examine N units of memory through the MMU
+  disas <addr> [N]     disassemble N instructions
+  set <reg|pc> <value> change a register
+  pagewalk <vaddr>     show the page-table walk for an address
+  quit                 stop the VM";
+
+/// Reads an `nm`-style symbol listing: one `<hex address> [type] <name>` per line.
+pub fn load_symbols(path: &Path) -> io::Result<HashMap<String, u64>> {
+    let mut symbols = HashMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(addr), Some(name)) = (fields.first(), fields.last()) else {
+            continue;
+        };
+        if fields.len() < 2 {
+            continue;
+        }
+        if let Ok(addr) = u64::from_str_radix(addr.trim_start_matches("0x"), 16) {
+            symbols.insert(name.to_string(), addr);
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    unsafe extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_sigint(_signum: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    const SIGINT: i32 = 2;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        signal(SIGINT, on_sigint);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler() {}
+
+/// What the command loop should do after a command.
+enum Action {
+    Prompt,
+    Quit,
+    Halted(Result<(), String>),
+}
+
+/// An interactive debugger for the guest. The prompt appears on start, whenever the guest
+/// reaches a breakpoint or executes `ebreak`, and on Ctrl-C while it is running.
+pub struct Monitor {
+    breakpoints: BTreeSet<u64>,
+    symbols: HashMap<String, u64>,
+    /// Set when the guest stopped on its own `ebreak`, which is stepped over on resume.
+    stopped_at_ebreak: bool,
+}
+
+impl Monitor {
+    pub fn new(symbols: HashMap<String, u64>) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            symbols,
+            stopped_at_ebreak: false,
+        }
+    }
+
+    /// Runs the monitor on stdin/stdout until the guest halts or the user quits. Returns
+    /// `None` if the user quit.
+    pub fn run(&mut self, vm: &mut VM) -> Option<Result<(), String>> {
+        install_interrupt_handler();
+        let stdin = io::stdin();
+        self.run_with(vm, stdin.lock(), io::stdout())
+    }
+
+    pub fn run_with(
+        &mut self,
+        vm: &mut VM,
+        mut input: impl BufRead,
+        mut output: impl Write,
+    ) -> Option<Result<(), String>> {
+        vm.debug.halt_on_ebreak = true;
+        let _ = writeln!(output, "Monitor: type 'help' for commands.");
+        self.show_location(vm, &mut output);
+
+        loop {
+            let _ = write!(output, "(vm) ");
+            let _ = output.flush();
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            match self.execute_command(vm, line.trim(), &mut output) {
+                Action::Prompt => {}
+                Action::Quit => return None,
+                Action::Halted(result) => {
+                    vm.debug.halt_on_ebreak = false;
+                    return Some(result);
+                }
+            }
+        }
+    }
+
+    fn execute_command(&mut self, vm: &mut VM, line: &str, out: &mut impl Write) -> Action {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Action::Prompt;
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "step" | "s" => match args.first().map(|n| n.parse::<u64>()) {
+                None => Ok(self.resume(vm, Some(1), out)),
+                Some(Ok(count)) => Ok(self.resume(vm, Some(count), out)),
+                Some(Err(_)) => Err(format!("invalid count '{}'", args[0])),
+            },
+            "continue" | "c" => Ok(self.resume(vm, None, out)),
+            "break" | "b" => self.parse_address(vm, &args).map(|addr| {
+                self.breakpoints.insert(addr);
+                let _ = writeln!(out, "Breakpoint at {:#x}", addr);
+                Action::Prompt
+            }),
+            "delete" | "d" => {
+                if args.is_empty() {
+                    self.breakpoints.clear();
+                    Ok(Action::Prompt)
+                } else {
+                    self.parse_address(vm, &args).and_then(|addr| {
+                        if self.breakpoints.remove(&addr) {
+                            Ok(Action::Prompt)
+                        } else {
+                            Err(format!("no breakpoint at {:#x}", addr))
+                        }
+                    })
+                }
+            }
+            "regs" => {
+                let _ = vm.write_state(out);
+                Ok(Action::Prompt)
+            }
+            "csr" => self.show_csr(vm, &args, out),
+            "disas" => self.disassemble(vm, &args, out),
+            "set" => self.set_register(vm, &args),
+            "pagewalk" => self.show_page_walk(vm, &args, out),
+            "help" | "h" => {
+                let _ = writeln!(out, "{}", HELP);
+                Ok(Action::Prompt)
+            }
+            "quit" | "q" => Ok(Action::Quit),
+            _ if command.starts_with("x/") => self.examine(vm, &command[2..], &args, out),
+            _ => Err(format!("unknown command '{}'; try 'help'", command)),
+        };
+
+        match result {
+            Ok(action) => action,
+            Err(message) => {
+                let _ = writeln!(out, "error: {}", message);
+                Action::Prompt
+            }
+        }
+    }
+
+    /// Parses a number (hex with `0x`, otherwise decimal), a symbol, or `pc`.
+    fn parse_value(&self, vm: &VM, text: &str) -> Result<u64, String> {
+        if text == "pc" {
+            return Ok(vm.pc);
+        }
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u64::from_str_radix(hex, 16).map_err(|_| format!("invalid number '{}'", text));
+        }
+        if let Ok(value) = text.parse::<i64>() {
+            return Ok(value as u64);
+        }
+        self.symbols
+            .get(text)
+            .copied()
+            .ok_or_else(|| format!("unknown symbol '{}'", text))
+    }
+
+    fn parse_address(&self, vm: &VM, args: &[&str]) -> Result<u64, String> {
+        match args.first() {
+            Some(text) => self.parse_value(vm, text),
+            None => Err("missing address".to_string()),
+        }
+    }
+
+    /// Runs `count` steps, or until something stops the guest if `count` is `None`.
+    fn resume(&mut self, vm: &mut VM, count: Option<u64>, out: &mut impl Write) -> Action {
+        if self.stopped_at_ebreak {
+            // Move past the guest's own ebreak rather than stopping on it again.
+            vm.pc = vm.pc.wrapping_add(vm.instruction_length_at(vm.pc));
+            self.stopped_at_ebreak = false;
+        }
+        INTERRUPTED.store(false, Ordering::SeqCst);
+
+        let mut steps = 0;
+        loop {
+            if let Some(result) = vm.step() {
+                return Action::Halted(result);
+            }
+            steps += 1;
+
+            if let Some(stop) = vm.debug.stop.take() {
+                match stop {
+                    DebugStop::Breakpoint => {
+                        self.stopped_at_ebreak = true;
+                        let _ = writeln!(out, "ebreak at {:#x}", vm.pc);
+                    }
+                    DebugStop::Watchpoint { kind, addr } => {
+                        let _ = writeln!(out, "{:?} watchpoint hit at {:#x}", kind, addr);
+                    }
+                }
+                break;
+            }
+            if count.is_some_and(|count| steps >= count) {
+                break;
+            }
+            if count.is_none() && self.breakpoints.contains(&vm.pc) {
+                let _ = writeln!(out, "Breakpoint at {:#x}", vm.pc);
+                break;
+            }
+            if INTERRUPTED.swap(false, Ordering::SeqCst) {
+                let _ = writeln!(out, "Interrupted");
+                break;
+            }
+        }
+        self.show_location(vm, out);
+        Action::Prompt
+    }
+
+    fn show_location(&self, vm: &mut VM, out: &mut impl Write) {
+        let mut word = [0u8; 4];
+        let text = if vm.debug_read(vm.pc, &mut word) == 4 {
+            disassemble(u32::from_le_bytes(word), vm.pc)
+        } else {
+            "<unmapped>".to_string()
+        };
+        let _ = writeln!(out, "{:#018x}: {}", vm.pc, text);
+    }
+
+    fn show_csr(&self, vm: &VM, args: &[&str], out: &mut impl Write) -> Result<Action, String> {
+        let name = args.first().ok_or("missing CSR name")?;
+        let addr = parse_csr(name).map_err(|e| e.to_string())?;
+        match vm.csrs.read(addr, 3) {
+            Some(value) => {
+                let _ = writeln!(out, "{} ({:#05x}) = {:#018x}", name, addr, value);
+                Ok(Action::Prompt)
+            }
+            None => Err(format!("CSR {:#05x} is not implemented", addr)),
+        }
+    }
+
+    /// `x/N[f][u] <addr>`: N units of size u (b, h, w or g) in hex.
+    fn examine(
+        &self,
+        vm: &mut VM,
+        spec: &str,
+        args: &[&str],
+        out: &mut impl Write,
+    ) -> Result<Action, String> {
+        let digits_end = spec
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len());
+        let count = if digits_end == 0 {
+            1
+        } else {
+            spec[..digits_end]
+                .parse::<u64>()
+                .map_err(|_| format!("invalid count in 'x/{}'", spec))?
+        };
+        let mut unit = 8;
+        for c in spec[digits_end..].chars() {
+            unit = match c {
+                'x' => unit,
+                'b' => 1,
+                'h' => 2,
+                'w' => 4,
+                'g' => 8,
+                _ => return Err(format!("unsupported format '{}'", c)),
+            };
+        }
+        let addr = self.parse_address(vm, args)?;
+
+        let per_line = 16 / unit;
+        for i in 0..count {
+            let unit_addr = addr.wrapping_add(i * unit);
+            if i % per_line == 0 {
+                if i != 0 {
+                    let _ = writeln!(out);
+                }
+                let _ = write!(out, "{:#018x}:", unit_addr);
+            }
+            let mut bytes = [0u8; 8];
+            if vm.debug_read(unit_addr, &mut bytes[..unit as usize]) != unit as usize {
+                let _ = writeln!(out);
+                return Err(format!("cannot access memory at {:#x}", unit_addr));
+            }
+            let value = u64::from_le_bytes(bytes);
+            let _ = write!(out, " {:#0width$x}", value, width = 2 + 2 * unit as usize);
+        }
+        let _ = writeln!(out);
+        Ok(Action::Prompt)
+    }
+
+    fn disassemble(
+        &self,
+        vm: &mut VM,
+        args: &[&str],
+        out: &mut impl Write,
+    ) -> Result<Action, String> {
+        let addr = self.parse_address(vm, args)?;
+        let count = match args.get(1) {
+            Some(n) => n
+                .parse::<u64>()
+                .map_err(|_| format!("invalid count '{}'", n))?,
+            None => 8,
+        };
+        let mut pc = addr;
+        for _ in 0..count {
+            let length = vm.instruction_length_at(pc) as usize;
+            let mut bytes = [0u8; 4];
+            if vm.debug_read(pc, &mut bytes[..length]) != length {
+                return Err(format!("cannot access memory at {:#x}", pc));
+            }
+            let word = u32::from_le_bytes(bytes);
+            let marker = if pc == vm.pc { "=>" } else { "  " };
+            let encoding = if length == 2 {
+                format!("{:04x}    ", word)
+            } else {
+                format!("{:08x}", word)
+            };
+            let _ = writeln!(
+                out,
+                "{} {:#018x}: {}  {}",
+                marker,
+                pc,
+                encoding,
+                disassemble(word, pc)
+            );
+            pc = pc.wrapping_add(length as u64);
+        }
+        Ok(Action::Prompt)
+    }
+
+    fn set_register(&self, vm: &mut VM, args: &[&str]) -> Result<Action, String> {
+        let [name, value] = args else {
+            return Err("usage: set <reg|pc> <value>".to_string());
+        };
+        let value = self.parse_value(vm, value)?;
+        let xlen = vm.config.isa.xlen();
+        if *name == "pc" {
+            vm.pc = value & xlen.mask();
+        } else {
+            let reg = parse_register(name).map_err(|e| e.to_string())?;
+            // RV32 keeps its registers sign-extended, as `retire_rv32` leaves them.
+            if reg != 0 {
+                vm.registers[reg as usize] = match xlen {
+                    Xlen::Rv32 => value as i32 as i64 as u64,
+                    Xlen::Rv64 => value,
+                };
+            }
+        }
+        Ok(Action::Prompt)
+    }
+
+    fn show_page_walk(
+        &self,
+        vm: &mut VM,
+        args: &[&str],
+        out: &mut impl Write,
+    ) -> Result<Action, String> {
+        let vaddr = self.parse_address(vm, args)?;
+        let (steps, paddr) = vm.page_walk(vaddr);
+        if steps.is_empty() {
+            let _ = writeln!(out, "paging is off (bare mode)");
+        }
+        for step in &steps {
+            let flags: String = "VRWXUGAD"
+                .chars()
+                .enumerate()
+                .map(|(bit, flag)| {
+                    if (step.pte >> bit) & 1 == 1 {
+                        flag
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+            let _ = writeln!(
+                out,
+                "level {}: pte @ {:#018x} = {:#018x} [{}]",
+                step.level, step.pte_addr, step.pte, flags
+            );
+        }
+        match paddr {
+            Some(paddr) => {
+                let _ = writeln!(out, "{:#x} -> {:#x}", vaddr, paddr);
+            }
+            None => {
+                let _ = writeln!(out, "{:#x} is not mapped", vaddr);
+            }
+        }
+        Ok(Action::Prompt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+
+    fn run_script(vm: &mut VM, monitor: &mut Monitor, script: &str) -> String {
+        let mut output = Vec::new();
+        monitor.run_with(vm, script.as_bytes(), &mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step_break_and_continue() {
+        let mut vm = VM::new();
+        for i in 0..4 {
+            vm.bus.write(BASE_ADDRESS + 4 * i, 4, 0x0015_0513); // addi a0, a0, 1
+        }
+        let symbols = HashMap::from([("target".to_string(), BASE_ADDRESS + 12)]);
+        let mut monitor = Monitor::new(symbols);
+
+        let output = run_script(&mut vm, &mut monitor, "step 2\nbreak target\ncontinue\n");
+        assert!(output.contains("0x0000000080000008: addi a0, a0, 1"));
+        assert!(output.contains("Breakpoint at 0x8000000c"));
+        assert_eq!(vm.pc, BASE_ADDRESS + 12);
+        assert_eq!(vm.registers[10], 3);
+    }
+
+    #[test]
+    fn test_ebreak_stops_and_is_stepped_over() {
+        let mut vm = VM::new();
+        vm.bus.write(BASE_ADDRESS, 4, 0x0010_0073); // ebreak
+        vm.bus.write(BASE_ADDRESS + 4, 4, 0x0015_0513); // addi a0, a0, 1
+        let mut monitor = Monitor::new(HashMap::new());
+
+        let output = run_script(&mut vm, &mut monitor, "continue\nstep\n");
+        assert!(output.contains("ebreak at 0x80000000"));
+        assert_eq!(vm.pc, BASE_ADDRESS + 8);
+        assert_eq!(vm.registers[10], 1);
+    }
+
+    #[test]
+    fn test_inspection_commands() {
+        let mut vm = VM::new();
+        vm.bus.write(BASE_ADDRESS + 0x100, 8, 0x1122_3344_5566_7788);
+        let mut monitor = Monitor::new(HashMap::new());
+
+        let output = run_script(
+            &mut vm,
+            &mut monitor,
+            "set a1 0x42\nx/2xg 0x80000100\ncsr mscratch\npagewalk 0x80000000\nbogus\n",
+        );
+        assert_eq!(vm.registers[11], 0x42);
+        assert!(output.contains("0x0000000080000100: 0x1122334455667788 0x0000000000000000"));
+        assert!(output.contains("mscratch (0x340) = 0x0000000000000000"));
+        assert!(output.contains("paging is off"));
+        assert!(output.contains("error: unknown command 'bogus'"));
+    }
+
+    #[test]
+    fn test_set_on_rv32_sign_extends_and_masks_pc() {
+        let mut vm = VM::new_config(VmConfig {
+            isa: "rv32im".parse().unwrap(),
+            ..VmConfig::default()
+        });
+        let mut monitor = Monitor::new(HashMap::new());
+
+        run_script(
+            &mut vm,
+            &mut monitor,
+            "set a0 0x80000000
+set pc 0x180001000
+",
+        );
+        assert_eq!(vm.registers[10], 0xFFFF_FFFF_8000_0000);
+        assert_eq!(vm.pc, 0x8000_1000);
+    }
+
+    #[test]
+    fn test_regs_writes_to_the_monitor_output() {
+        let mut vm = VM::new();
+        vm.registers[11] = 0x42;
+        vm.csrs.mscratch = 0x1234;
+        let mut monitor = Monitor::new(HashMap::new());
+
+        let output = run_script(&mut vm, &mut monitor, "regs\n");
+        assert!(output.contains("x11   a1      0x0000000000000042"));
+        assert!(output.contains("0x340    mscratch   0x0000000000001234"));
+        assert!(output.contains("PC: 0x0000000080000000"));
+        assert!(output.contains("Privilege Level: Machine"));
+    }
+}
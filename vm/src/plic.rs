@@ -0,0 +1,258 @@
+use crate::bus::Device;
+use bincode::{Decode, Encode};
+use riscv_core::cause;
+
+pub const PLIC_BASE_ADDRESS: u64 = 0x0C00_0000;
+pub const PLIC_SIZE: u64 = 0x0400_0000;
+
+/// Interrupt sources 1..64; source 0 is reserved to mean "no interrupt".
+pub const PLIC_NUM_SOURCES: usize = 64;
+
+/// Context 0 is hart 0 in M-mode and context 1 is hart 0 in S-mode, as on QEMU's virt machine.
+pub const PLIC_CONTEXT_MACHINE: usize = 0;
+pub const PLIC_CONTEXT_SUPERVISOR: usize = 1;
+const PLIC_NUM_CONTEXTS: usize = 2;
+
+const PRIORITY_OFFSET: u64 = 0x0000;
+const PENDING_OFFSET: u64 = 0x1000;
+const ENABLE_OFFSET: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_OFFSET: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// Priorities are WARL; this PLIC implements levels 0 (never interrupt) through 7.
+const PRIORITY_MASK: u32 = 0x7;
+
+pub const MIP_SEIP: u64 = 1 << (cause::SUPERVISOR_EXTERNAL_INTERRUPT & !cause::INTERRUPT_BIT);
+pub const MIP_MEIP: u64 = 1 << (cause::MACHINE_EXTERNAL_INTERRUPT & !cause::INTERRUPT_BIT);
+
+/// Platform-level interrupt controller. Devices drive their source's level with `set_level`,
+/// and each context claims and completes interrupts through its claim/complete register.
+#[derive(Clone, Encode, Decode)]
+pub struct Plic {
+    pub priority: [u32; PLIC_NUM_SOURCES],
+    pub pending: u64,
+    pub enable: [u64; PLIC_NUM_CONTEXTS],
+    pub threshold: [u32; PLIC_NUM_CONTEXTS],
+    /// Sources that have been claimed but not yet completed.
+    pub in_service: u64,
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Self {
+            priority: [0; PLIC_NUM_SOURCES],
+            pending: 0,
+            enable: [0; PLIC_NUM_CONTEXTS],
+            threshold: [0; PLIC_NUM_CONTEXTS],
+            in_service: 0,
+        }
+    }
+
+    /// Updates the interrupt line of `source`. The gateway latches an asserted line as
+    /// pending; a deasserted line withdraws a request that has not been claimed yet.
+    pub fn set_level(&mut self, source: u32, asserted: bool) {
+        if source == 0 || source as usize >= PLIC_NUM_SOURCES {
+            return;
+        }
+        if asserted {
+            self.pending |= 1 << source;
+        } else {
+            self.pending &= !(1 << source);
+        }
+    }
+
+    /// The MEIP and SEIP bits this device is currently driving into `mip`.
+    pub fn mip_bits(&self) -> u64 {
+        let mut bits = 0;
+        if self.best_pending(PLIC_CONTEXT_MACHINE) != 0 {
+            bits |= MIP_MEIP;
+        }
+        if self.best_pending(PLIC_CONTEXT_SUPERVISOR) != 0 {
+            bits |= MIP_SEIP;
+        }
+        bits
+    }
+
+    /// The highest-priority source that `context` could claim, or 0 if there is none.
+    /// Ties go to the lowest source ID.
+    fn best_pending(&self, context: usize) -> u32 {
+        let candidates = self.pending & self.enable[context] & !self.in_service;
+        let mut best = 0;
+        let mut best_priority = self.threshold[context];
+        for source in 1..PLIC_NUM_SOURCES {
+            if (candidates >> source) & 1 == 1 && self.priority[source] > best_priority {
+                best = source as u32;
+                best_priority = self.priority[source];
+            }
+        }
+        best
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        let source = self.best_pending(context);
+        if source != 0 {
+            self.pending &= !(1 << source);
+            self.in_service |= 1 << source;
+        }
+        source
+    }
+
+    fn complete(&mut self, context: usize, source: u32) {
+        if (source as usize) < PLIC_NUM_SOURCES && (self.enable[context] >> source) & 1 == 1 {
+            self.in_service &= !(1 << source);
+        }
+    }
+}
+
+impl Device for Plic {
+    fn read(&mut self, offset: u64, size: u64) -> Option<u64> {
+        if size != 4 {
+            return None;
+        }
+        let value = match offset {
+            PRIORITY_OFFSET..PENDING_OFFSET => {
+                let source = (offset / 4) as usize;
+                self.priority.get(source).copied().unwrap_or(0)
+            }
+            PENDING_OFFSET..ENABLE_OFFSET => word_of(self.pending, offset - PENDING_OFFSET),
+            ENABLE_OFFSET..CONTEXT_OFFSET => {
+                let context = ((offset - ENABLE_OFFSET) / ENABLE_STRIDE) as usize;
+                let word_offset = (offset - ENABLE_OFFSET) % ENABLE_STRIDE;
+                match self.enable.get(context) {
+                    Some(&enable) => word_of(enable, word_offset),
+                    None => 0,
+                }
+            }
+            _ => {
+                let context = ((offset - CONTEXT_OFFSET) / CONTEXT_STRIDE) as usize;
+                if context >= PLIC_NUM_CONTEXTS {
+                    return Some(0);
+                }
+                match (offset - CONTEXT_OFFSET) % CONTEXT_STRIDE {
+                    0 => self.threshold[context],
+                    4 => self.claim(context),
+                    _ => 0,
+                }
+            }
+        };
+        Some(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, size: u64, value: u64) -> bool {
+        if size != 4 {
+            return false;
+        }
+        let value = value as u32;
+        match offset {
+            PRIORITY_OFFSET..PENDING_OFFSET => {
+                let source = (offset / 4) as usize;
+                if source != 0 && source < PLIC_NUM_SOURCES {
+                    self.priority[source] = value & PRIORITY_MASK;
+                }
+            }
+            // The pending array is read-only.
+            PENDING_OFFSET..ENABLE_OFFSET => {}
+            ENABLE_OFFSET..CONTEXT_OFFSET => {
+                let context = ((offset - ENABLE_OFFSET) / ENABLE_STRIDE) as usize;
+                let word_offset = (offset - ENABLE_OFFSET) % ENABLE_STRIDE;
+                if let Some(enable) = self.enable.get_mut(context) {
+                    // Source 0 does not exist, so its enable bit is hardwired to zero.
+                    *enable = with_word(*enable, word_offset, value) & !1;
+                }
+            }
+            _ => {
+                let context = ((offset - CONTEXT_OFFSET) / CONTEXT_STRIDE) as usize;
+                if context < PLIC_NUM_CONTEXTS {
+                    match (offset - CONTEXT_OFFSET) % CONTEXT_STRIDE {
+                        0 => self.threshold[context] = value & PRIORITY_MASK,
+                        4 => self.complete(context, value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Extracts the 32-bit word at `byte_offset` of a 64-bit bitfield.
+fn word_of(bits: u64, byte_offset: u64) -> u32 {
+    match byte_offset {
+        0 => bits as u32,
+        4 => (bits >> 32) as u32,
+        _ => 0,
+    }
+}
+
+/// Replaces the 32-bit word at `byte_offset` of a 64-bit bitfield.
+fn with_word(bits: u64, byte_offset: u64, word: u32) -> u64 {
+    match byte_offset {
+        0 => (bits & !0xFFFF_FFFF) | word as u64,
+        4 => (bits & 0xFFFF_FFFF) | ((word as u64) << 32),
+        _ => bits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim_offset(context: u64) -> u64 {
+        CONTEXT_OFFSET + context * CONTEXT_STRIDE + 4
+    }
+
+    #[test]
+    fn test_claim_and_complete() {
+        let mut plic = Plic::new();
+        plic.write(PRIORITY_OFFSET + 4 * 10, 4, 1);
+        plic.write(ENABLE_OFFSET + ENABLE_STRIDE, 4, 1 << 10);
+
+        plic.set_level(10, true);
+        assert_eq!(plic.mip_bits(), MIP_SEIP);
+        assert_eq!(plic.read(PENDING_OFFSET, 4), Some(1 << 10));
+
+        assert_eq!(plic.read(claim_offset(1), 4), Some(10));
+        assert_eq!(plic.mip_bits(), 0);
+
+        // The source stays masked until the claim is completed, even if it is raised again.
+        plic.set_level(10, true);
+        assert_eq!(plic.mip_bits(), 0);
+        assert_eq!(plic.read(claim_offset(1), 4), Some(0));
+
+        plic.write(claim_offset(1), 4, 10);
+        assert_eq!(plic.mip_bits(), MIP_SEIP);
+    }
+
+    #[test]
+    fn test_priority_and_threshold() {
+        let mut plic = Plic::new();
+        plic.write(PRIORITY_OFFSET + 4 * 3, 4, 2);
+        plic.write(PRIORITY_OFFSET + 4 * 5, 4, 6);
+        plic.write(ENABLE_OFFSET, 4, (1 << 3) | (1 << 5));
+        plic.set_level(3, true);
+        plic.set_level(5, true);
+
+        plic.write(CONTEXT_OFFSET, 4, 6);
+        assert_eq!(plic.mip_bits(), 0);
+
+        plic.write(CONTEXT_OFFSET, 4, 1);
+        assert_eq!(plic.mip_bits(), MIP_MEIP);
+        assert_eq!(plic.read(claim_offset(0), 4), Some(5));
+        assert_eq!(plic.read(claim_offset(0), 4), Some(3));
+    }
+
+    #[test]
+    fn test_zero_priority_never_interrupts() {
+        let mut plic = Plic::new();
+        plic.write(ENABLE_OFFSET, 4, 1 << 1);
+        plic.set_level(1, true);
+        assert_eq!(plic.mip_bits(), 0);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::isa::Xlen;
+use crate::VM;
+use bincode::{Decode, Encode};
+
+/// The number of PMP entries a hart implements unless configured otherwise.
+pub const DEFAULT_PMP_ENTRIES: usize = 16;
+/// The entry counts the VM implements: none, or the lowest 16 or all 64 PMP CSRs.
+pub const PMP_ENTRY_COUNTS: [usize; 3] = [0, 16, 64];
+
+// The fields of a pmpNcfg byte.
+pub const PMP_R: u8 = 1 << 0;
+pub const PMP_W: u8 = 1 << 1;
+pub const PMP_X: u8 = 1 << 2;
+const PMP_A_SHIFT: u8 = 3;
+const PMP_A_MASK: u8 = 0b11 << PMP_A_SHIFT;
+pub const PMP_L: u8 = 1 << 7;
+
+// Address-matching modes, the A field.
+pub const PMP_OFF: u8 = 0;
+pub const PMP_TOR: u8 = 1 << PMP_A_SHIFT;
+pub const PMP_NA4: u8 = 2 << PMP_A_SHIFT;
+pub const PMP_NAPOT: u8 = 3 << PMP_A_SHIFT;
+
+/// The kind of access a PMP entry must permit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+impl AccessType {
+    fn permission(self) -> u8 {
+        match self {
+            AccessType::Read => PMP_R,
+            AccessType::Write => PMP_W,
+            AccessType::Execute => PMP_X,
+        }
+    }
+}
+
+/// The physical memory protection entries: a `pmpNcfg` byte and a `pmpaddrN` register
+/// each. The grain is 4 bytes, so every matching mode is available and pmpaddr reads back
+/// as written.
+#[derive(Clone, Default, Encode, Decode)]
+pub struct Pmp {
+    cfg: Vec<u8>,
+    addr: Vec<u64>,
+}
+
+impl Pmp {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            cfg: vec![0; entries],
+            addr: vec![0; entries],
+        }
+    }
+
+    pub fn entries(&self) -> usize {
+        self.cfg.len()
+    }
+
+    fn locked(&self, entry: usize) -> bool {
+        self.cfg.get(entry).is_some_and(|&cfg| cfg & PMP_L != 0)
+    }
+
+    /// Reads `pmpcfg{index}`, which packs the configuration of four entries on RV32 and
+    /// eight on RV64. Entries the hart lacks read as zero.
+    pub fn read_cfg(&self, index: usize, xlen: Xlen) -> u64 {
+        (0..xlen.bits() as usize / 8).fold(0, |value, byte| {
+            let cfg = self.cfg.get(index * 4 + byte).copied().unwrap_or(0);
+            value | (cfg as u64) << (8 * byte)
+        })
+    }
+
+    /// Writes `pmpcfg{index}`, skipping locked entries. The reserved bits are hardwired to
+    /// zero, and W is dropped without R since that combination is reserved.
+    pub fn write_cfg(&mut self, index: usize, value: u64, xlen: Xlen) {
+        for byte in 0..xlen.bits() as usize / 8 {
+            let entry = index * 4 + byte;
+            if entry >= self.entries() || self.locked(entry) {
+                continue;
+            }
+            let mut cfg =
+                (value >> (8 * byte)) as u8 & (PMP_L | PMP_A_MASK | PMP_X | PMP_W | PMP_R);
+            if cfg & PMP_R == 0 {
+                cfg &= !PMP_W;
+            }
+            self.cfg[entry] = cfg;
+        }
+    }
+
+    pub fn read_addr(&self, entry: usize) -> u64 {
+        self.addr.get(entry).copied().unwrap_or(0)
+    }
+
+    /// Writes `pmpaddr{entry}`, which holds bits 55:2 of an address on RV64 and 33:2 on
+    /// RV32. A locked entry's address is fixed, and so is the one below a locked TOR
+    /// entry, since it is that entry's lower bound.
+    pub fn write_addr(&mut self, entry: usize, value: u64, xlen: Xlen) {
+        let top_of_locked_range =
+            self.locked(entry + 1) && self.cfg[entry + 1] & PMP_A_MASK == PMP_TOR;
+        if entry >= self.entries() || self.locked(entry) || top_of_locked_range {
+            return;
+        }
+        self.addr[entry] = match xlen {
+            Xlen::Rv32 => value & 0xFFFF_FFFF,
+            Xlen::Rv64 => value & ((1 << 54) - 1),
+        };
+    }
+
+    /// The byte range `[start, end)` that `entry` matches, or `None` if it is off.
+    fn range(&self, entry: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[entry];
+        match self.cfg[entry] & PMP_A_MASK {
+            PMP_TOR => {
+                let start = if entry == 0 {
+                    0
+                } else {
+                    self.addr[entry - 1] << 2
+                };
+                Some((start, addr << 2))
+            }
+            PMP_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PMP_NAPOT => {
+                // The trailing ones of pmpaddr encode a size of 2^(ones + 3) bytes.
+                let ones = addr.trailing_ones();
+                let start = (addr & !((1 << ones) - 1)) << 2;
+                Some((start, start + (1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether an access of `size` bytes at `paddr` made at `privilege` is allowed. The
+    /// lowest-numbered entry that matches any of its bytes decides, and must match them
+    /// all. M-mode is only held to locked entries and may access what no entry matches;
+    /// S-mode and U-mode need an entry granting the access, unless the hart has no PMP.
+    pub fn check(&self, paddr: u64, size: u64, privilege: u8, access: AccessType) -> bool {
+        if self.entries() == 0 {
+            return true;
+        }
+        let end = paddr.saturating_add(size);
+        for entry in 0..self.entries() {
+            let Some((start, limit)) = self.range(entry) else {
+                continue;
+            };
+            if paddr >= limit || end <= start {
+                continue;
+            }
+            if paddr < start || end > limit {
+                return false;
+            }
+            let cfg = self.cfg[entry];
+            if privilege == 3 && cfg & PMP_L == 0 {
+                return true;
+            }
+            return cfg & access.permission() != 0;
+        }
+        privilege == 3
+    }
+}
+
+impl VM {
+    /// Whether PMP lets the hart make an access of `size` bytes at `paddr`. Loads and
+    /// stores are checked at the privilege they are translated at, so mstatus.MPRV applies.
+    pub(crate) fn pmp_allows(&self, paddr: u64, size: u64, access: AccessType) -> bool {
+        let privilege = self.effective_privilege(access == AccessType::Execute);
+        self.csrs.pmp.check(paddr, size, privilege, access)
+    }
+
+    /// Gives S-mode and U-mode access to all of memory through the last PMP entry, the way
+    /// firmware does before entering the kernel.
+    #[cfg(test)]
+    pub(crate) fn open_pmp(&mut self) {
+        let entries = self.csrs.pmp.entries();
+        if entries == 0 {
+            return;
+        }
+        let last = entries - 1;
+        self.csrs.pmp.write_addr(last, u64::MAX, self.csrs.xlen());
+        let cfg = self.csrs.pmp.read_cfg(last / 4, Xlen::Rv32)
+            | ((PMP_NAPOT | PMP_X | PMP_W | PMP_R) as u64) << (8 * (last % 4));
+        self.csrs.pmp.write_cfg(last / 4, cfg, Xlen::Rv32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BASE_ADDRESS;
+    use riscv_core::{cause, csr};
+
+    const RAM: u64 = 0x8000_0000;
+
+    fn pmp(entries: &[(u8, u64)]) -> Pmp {
+        let mut pmp = Pmp::new(16);
+        for (index, &(cfg, addr)) in entries.iter().enumerate() {
+            pmp.write_addr(index, addr, Xlen::Rv64);
+            let packed = pmp.read_cfg(index / 4, Xlen::Rv32) | (cfg as u64) << (8 * (index % 4));
+            pmp.write_cfg(index / 4, packed, Xlen::Rv32);
+        }
+        pmp
+    }
+
+    #[test]
+    fn test_matching_modes_and_priority() {
+        // A read-only NA4 word, inside a read-write 4 KiB NAPOT page, inside an executable
+        // TOR range covering the first 64 KiB of RAM.
+        let pmp = pmp(&[
+            (PMP_NA4 | PMP_R, (RAM + 0x100) >> 2),
+            (PMP_NAPOT | PMP_R | PMP_W, (RAM >> 2) | 0x1FF),
+            (PMP_OFF, RAM >> 2),
+            (PMP_TOR | PMP_X, (RAM + 0x10000) >> 2),
+        ]);
+        assert!(pmp.check(RAM + 0x100, 4, 1, AccessType::Read));
+        assert!(!pmp.check(RAM + 0x100, 4, 1, AccessType::Write));
+        assert!(pmp.check(RAM + 0x104, 4, 1, AccessType::Write));
+        assert!(!pmp.check(RAM + 0x104, 4, 1, AccessType::Execute));
+        assert!(pmp.check(RAM + 0x1000, 4, 0, AccessType::Execute));
+        assert!(!pmp.check(RAM + 0x1000, 4, 0, AccessType::Read));
+        // No entry matches: S-mode and U-mode fail, M-mode succeeds.
+        assert!(!pmp.check(RAM + 0x10000, 4, 1, AccessType::Read));
+        assert!(pmp.check(RAM + 0x10000, 4, 3, AccessType::Read));
+        // An access straddling two entries matches neither fully.
+        assert!(!pmp.check(RAM + 0xFFC, 8, 1, AccessType::Read));
+    }
+
+    #[test]
+    fn test_locked_entries_bind_m_mode_and_ignore_writes() {
+        let mut pmp = pmp(&[
+            (PMP_NAPOT | PMP_R, (RAM >> 2) | 0x1FF),
+            (PMP_TOR | PMP_L | PMP_R, (RAM + 0x4000) >> 2),
+        ]);
+        assert!(pmp.check(RAM, 8, 3, AccessType::Write));
+        assert!(!pmp.check(RAM + 0x2000, 8, 3, AccessType::Write));
+
+        // Entry 1 is locked, and entry 0's address is its lower bound.
+        pmp.write_cfg(0, 0, Xlen::Rv64);
+        pmp.write_addr(0, 0, Xlen::Rv64);
+        pmp.write_addr(1, 0, Xlen::Rv64);
+        assert_eq!(
+            pmp.read_cfg(0, Xlen::Rv64),
+            ((PMP_TOR | PMP_L | PMP_R) as u64) << 8
+        );
+        assert_eq!(pmp.read_addr(0), (RAM >> 2) | 0x1FF);
+        assert_eq!(pmp.read_addr(1), (RAM + 0x4000) >> 2);
+
+        // W without R is reserved and entries past the implemented ones read as zero.
+        pmp.write_cfg(2, (PMP_NA4 | PMP_W) as u64, Xlen::Rv64);
+        assert_eq!(pmp.read_cfg(2, Xlen::Rv64), PMP_NA4 as u64);
+        assert_eq!(pmp.read_cfg(4, Xlen::Rv64), 0);
+    }
+
+    #[test]
+    fn test_bootloader_sandbox_traps_supervisor_accesses() {
+        const LD_A0_A1: u32 = 0x0005_b503; // ld a0, 0(a1)
+        const SD_A0_A1: u32 = 0x00a5_b023; // sd a0, 0(a1)
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        // The BIOS's layout: no access to its own 64 KiB, everything else open.
+        vm.csrs
+            .write(csr::PMPADDR0, (BASE_ADDRESS >> 2) | 0x1FFF, 3);
+        vm.csrs.write(csr::PMPADDR1, u64::MAX, 3);
+        vm.csrs.write(csr::PMPCFG0, 0x1F18, 3);
+        assert_eq!(vm.csrs.read(csr::PMPCFG0, 3), Some(0x1F18));
+        assert_eq!(vm.csrs.read(csr::PMPCFG1, 3), None);
+
+        vm.privilege_level = 1;
+        vm.registers[11] = BASE_ADDRESS + 0x10000;
+        assert!(vm.execute(LD_A0_A1, 4));
+        assert_eq!(vm.privilege_level, 1);
+
+        for (inst, fault) in [
+            (LD_A0_A1, cause::LOAD_ACCESS_FAULT),
+            (SD_A0_A1, cause::STORE_AMO_ACCESS_FAULT),
+        ] {
+            vm.privilege_level = 1;
+            vm.registers[11] = BASE_ADDRESS + 0x100;
+            vm.execute(inst, 4);
+            assert_eq!(vm.privilege_level, 3);
+            assert_eq!(vm.csrs.read(csr::MCAUSE, 3), Some(fault));
+            assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(BASE_ADDRESS + 0x100));
+        }
+
+        // The unlocked entry does not bind M-mode; once locked, it does.
+        assert!(vm.execute(SD_A0_A1, 4));
+        vm.csrs.write(csr::PMPCFG0, 0x1F98, 3);
+        vm.execute(LD_A0_A1, 4);
+        assert_eq!(vm.csrs.read(csr::MCAUSE, 3), Some(cause::LOAD_ACCESS_FAULT));
+    }
+}
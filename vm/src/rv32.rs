@@ -0,0 +1,228 @@
+//! RV32 support. An RV32 hart keeps every integer register sign-extended from bit 31, the
+//! same form the RV64 `*W` instructions produce. Most RV32 instructions then behave exactly
+//! like an RV64 instruction: `add` like `addw`, `srl` like `srlw`, `divu` like `divuw` and
+//! so on. The executor rewrites them to that instruction and, once it has run, re-extends
+//! the destination register and truncates the pc to 32 bits.
+
+use crate::VM;
+use riscv_core::{csr, funct3, funct7, opcodes};
+
+impl VM {
+    /// Rewrites the RV32 instruction `inst` as the RV64 instruction with the same effect on
+    /// sign-extended registers. Returns `None` for instructions RV32 does not have: the
+    /// `*W` forms, `ld`, `lwu`, `sd`, shifts by 32 or more, and M-extension instructions
+    /// when M is not implemented.
+    pub(crate) fn rv32_equivalent(&self, inst: u32) -> Option<u32> {
+        let opcode = inst & 0x7F;
+        let funct3 = (inst >> 12) & 0x7;
+        let funct7 = inst >> 25;
+        let as_word_op = |word_opcode: u32| Some((inst & !0x7F) | word_opcode);
+
+        match opcode {
+            opcodes::OP_IMM_32 | opcodes::OP_REG_32 => None,
+            opcodes::OP_LOAD if matches!(funct3, funct3::LD | funct3::LWU) => None,
+            opcodes::OP_STORE if funct3 == funct3::SD => None,
+            opcodes::OP_IMM => match funct3 {
+                funct3::ADD_SUB => as_word_op(opcodes::OP_IMM_32),
+                // Bit 25 is shamt[5], which RV32 reserves.
+                funct3::SLL | funct3::SRL_SRA if funct7 & 1 == 1 => None,
+                funct3::SLL if funct7 == funct7::DEFAULT => as_word_op(opcodes::OP_IMM_32),
+                funct3::SRL_SRA if matches!(funct7, funct7::DEFAULT | funct7::SRA) => {
+                    as_word_op(opcodes::OP_IMM_32)
+                }
+                _ => Some(inst),
+            },
+            opcodes::OP_REG if funct7 == funct7::MULDIV => {
+                if !self.config.isa.has_base('m') {
+                    return None;
+                }
+                match funct3 {
+                    // The high-half multiplies have no W form; they use XLEN themselves.
+                    funct3::MULH | funct3::MULHSU | funct3::MULHU => Some(inst),
+                    _ => as_word_op(opcodes::OP_REG_32),
+                }
+            }
+            opcodes::OP_REG => match (funct3, funct7) {
+                (funct3::ADD_SUB, funct7::DEFAULT | funct7::SUB)
+                | (funct3::SLL, funct7::DEFAULT)
+                | (funct3::SRL_SRA, funct7::DEFAULT | funct7::SRA) => {
+                    as_word_op(opcodes::OP_REG_32)
+                }
+                _ => Some(inst),
+            },
+            _ => Some(inst),
+        }
+    }
+
+    /// Restores the RV32 register invariants after `inst` has executed: its destination is
+    /// sign-extended from bit 31 and the pc is truncated to 32 bits. Instructions without a
+    /// destination leave an already extended register in bits 11:7, which this leaves as is.
+    pub(crate) fn retire_rv32(&mut self, inst: u32) {
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        self.registers[rd] = self.registers[rd] as i32 as i64 as u64;
+        self.pc &= 0xFFFF_FFFF;
+    }
+}
+
+/// Whether bit 63 of `addr` moves to bit 31 on RV32: the SD bit of mstatus and sstatus and
+/// the interrupt bit of mcause and scause.
+fn has_top_bit(addr: u32) -> bool {
+    matches!(
+        addr,
+        csr::MSTATUS | csr::SSTATUS | csr::MCAUSE | csr::SCAUSE
+    )
+}
+
+/// The RV32 view of the CSR value `value`, read from `addr`.
+pub(crate) fn narrow_csr(addr: u32, value: u64) -> u64 {
+    let top = if has_top_bit(addr) {
+        (value >> 32) & (1 << 31)
+    } else {
+        0
+    };
+    (value & 0xFFFF_FFFF) | top
+}
+
+/// The value to store in the CSR at `addr` when RV32 code writes `value` to it.
+pub(crate) fn widen_csr(addr: u32, value: u64) -> u64 {
+    let value = value & 0xFFFF_FFFF;
+    if has_top_bit(addr) {
+        (value & !(1 << 31)) | ((value >> 31) << 63)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::SATP32_MODE_SV32;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+    use riscv_core::cause;
+
+    fn rv32_vm(isa: &str, program: &[u32]) -> VM {
+        let mut vm = VM::new_config(VmConfig {
+            isa: isa.parse().unwrap(),
+            ..VmConfig::default()
+        });
+        for (i, &inst) in program.iter().enumerate() {
+            vm.bus.write(BASE_ADDRESS + 4 * i as u64, 4, inst as u64);
+        }
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        vm
+    }
+
+    #[test]
+    fn test_arithmetic_wraps_at_32_bits() {
+        let mut vm = rv32_vm(
+            "rv32im",
+            &[
+                0x00b50633, // add a2, a0, a1
+                0x00b556b3, // srl a3, a0, a1
+                0x00b51733, // sll a4, a0, a1
+                0x02b537b3, // mulhu a5, a0, a1
+                0x02b55833, // divu a6, a0, a1
+                0x00f00893, // li a7, 15
+                0x01f51913, // slli s2, a0, 31
+            ],
+        );
+        vm.registers[10] = 0xFFFF_FFFF_8000_0000; // a0 = 0x80000000
+        vm.registers[11] = 33; // a shift by 33 is a shift by 1
+
+        for _ in 0..7 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!(vm.registers[12], 0xFFFF_FFFF_8000_0021);
+        assert_eq!(vm.registers[13], 0x4000_0000);
+        assert_eq!(vm.registers[14], 0);
+        assert_eq!(vm.registers[15], 16);
+        assert_eq!(vm.registers[16], 0x8000_0000 / 33);
+        assert_eq!(vm.registers[17], 15);
+        assert_eq!(vm.registers[18], 0);
+        assert_eq!(vm.pc, BASE_ADDRESS + 28);
+    }
+
+    #[test]
+    fn test_rv64_only_instructions_are_illegal() {
+        for inst in [
+            0x00b5053b, // addw a0, a0, a1
+            0x0005b503, // ld a0, 0(a1)
+            0x02051513, // slli a0, a0, 32
+        ] {
+            let mut vm = rv32_vm("rv32im", &[inst]);
+            assert_eq!(vm.step(), None);
+            assert_eq!(
+                vm.csrs.read(csr::MCAUSE, 3),
+                Some(cause::ILLEGAL_INSTRUCTION)
+            );
+            assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(inst as u64));
+        }
+
+        let mut vm = rv32_vm("rv32i", &[0x02b50533]); // mul a0, a0, a1
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x200);
+    }
+
+    #[test]
+    fn test_csrs_use_the_rv32_layout() {
+        let mut vm = rv32_vm(
+            "rv32im",
+            &[
+                0x30102573, // csrr a0, misa
+                0x342025f3, // csrr a1, mcause
+                0xb8061073, // csrw mcycleh, a2
+                0xc8002673, // csrr a2, cycleh
+                0x310026f3, // csrr a3, mstatush
+            ],
+        );
+        vm.csrs.mcause = cause::MACHINE_TIMER_INTERRUPT;
+        vm.registers[12] = 5;
+
+        for _ in 0..5 {
+            assert_eq!(vm.step(), None);
+        }
+        assert_eq!((vm.registers[10] >> 30) & 0b11, 0b01);
+        assert_eq!(vm.registers[11], 0xFFFF_FFFF_8000_0007);
+        // The low half kept counting: two cycles before the write and two after it.
+        assert_eq!(vm.csrs.read(csr::MCYCLE, 3), Some((5 << 32) | 4));
+        assert_eq!(vm.registers[12], 5);
+        assert_eq!(vm.registers[13], 0);
+
+        // RV64 has no upper-half CSRs.
+        let mut vm = VM::new();
+        vm.bus.write(BASE_ADDRESS, 4, 0xc8002673);
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x200, 3);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.pc, BASE_ADDRESS + 0x200);
+    }
+
+    #[test]
+    fn test_sv32_translates_through_a_megapage_and_a_page() {
+        const ROOT: u64 = BASE_ADDRESS + 0x10000;
+        const PTE_RWX: u64 = 0xCF;
+        let mut vm = rv32_vm("rv32im", &[]);
+        let pte = |addr: u64, flags: u64| ((addr >> 12) << 10) | flags;
+        // A 4 MiB megapage at 0x80000000, identity mapped.
+        vm.bus
+            .write(ROOT + 4 * 0x200, 4, pte(BASE_ADDRESS, PTE_RWX));
+        // Virtual 0x00401000 is the page at BASE_ADDRESS + 0x7000.
+        vm.bus.write(ROOT + 4, 4, pte(ROOT + 0x1000, 1));
+        vm.bus
+            .write(ROOT + 0x1000 + 4, 4, pte(BASE_ADDRESS + 0x7000, PTE_RWX));
+        vm.csrs.write(csr::SATP, SATP32_MODE_SV32 | (ROOT >> 12), 3);
+        vm.privilege_level = 1;
+        vm.open_pmp();
+
+        assert_eq!(
+            vm.translate(0x0040_1234, false, false),
+            Ok(BASE_ADDRESS + 0x7234)
+        );
+        // A sign-extended register holding 0x80000010 reaches the megapage.
+        assert_eq!(
+            vm.translate(0xFFFF_FFFF_8000_0010, true, false),
+            Ok(BASE_ADDRESS + 0x10)
+        );
+        assert!(vm.translate(0x0080_0000, false, false).is_err());
+    }
+}
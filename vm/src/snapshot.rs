@@ -0,0 +1,317 @@
+use crate::clint::ClintState;
+use crate::csr::CsrFile;
+use crate::plic::Plic;
+use crate::uart::UartState;
+use crate::virtio::VirtioBlkState;
+use crate::VM;
+use bincode::{Decode, Encode};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SNAPSHOT_MAGIC: [u8; 4] = *b"RVSN";
+
+/// Bumped whenever the layout of `Snapshot` changes; older files are rejected.
+pub const SNAPSHOT_VERSION: u32 = 11;
+
+/// RAM is stored page by page, skipping pages that are entirely zero.
+const SNAPSHOT_PAGE_SIZE: usize = 4096;
+
+#[derive(Encode, Decode)]
+struct SnapshotHeader {
+    magic: [u8; 4],
+    version: u32,
+    isa: String,
+}
+
+#[derive(Encode, Decode)]
+struct Snapshot {
+    registers: [u64; 32],
+    fregs: [u64; 32],
+    vregs: Vec<u8>,
+    pc: u64,
+    privilege_level: u8,
+    csrs: CsrFile,
+    ram_size: u64,
+    ram_pages: Vec<(u32, Vec<u8>)>,
+    clint: ClintState,
+    plic: Plic,
+    uart: UartState,
+    disk: Option<VirtioBlkState>,
+    entropy: u64,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl VM {
+    /// Writes the full machine state to `path`: hart registers, CSRs, RAM, device registers
+    /// and the contents of the attached disk.
+    pub fn save_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        let ram_pages = self
+            .bus
+            .ram
+            .chunks(SNAPSHOT_PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|&byte| byte != 0))
+            .map(|(index, page)| (index as u32, page.to_vec()))
+            .collect();
+        let disk = match &self.virtio_blk {
+            Some(disk) => Some(disk.borrow_mut().save_state()?),
+            None => None,
+        };
+
+        let header = SnapshotHeader {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION,
+            isa: self.config.isa.to_string(),
+        };
+        let snapshot = Snapshot {
+            registers: self.registers,
+            fregs: self.fregs,
+            vregs: self.vregs.clone(),
+            pc: self.pc,
+            privilege_level: self.privilege_level,
+            csrs: self.csrs.clone(),
+            ram_size: self.bus.ram.len() as u64,
+            ram_pages,
+            clint: self.clint.borrow().save_state(),
+            plic: self.plic.borrow().clone(),
+            uart: self.uart.borrow().save_state(),
+            disk,
+            entropy: self.entropy.save_state(),
+        };
+
+        let config = bincode::config::standard();
+        let mut bytes = bincode::encode_to_vec(&header, config).map_err(io::Error::other)?;
+        bytes.extend(bincode::encode_to_vec(&snapshot, config).map_err(io::Error::other)?);
+        fs::write(path, bytes)
+    }
+
+    /// Replaces the machine state with the snapshot at `path`. The VM must have the same ISA
+    /// and RAM size as the one that saved it, and a disk attached exactly when that one had.
+    /// The attached disk's contents are overwritten with the saved ones.
+    pub fn load_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let config = bincode::config::standard();
+
+        let (header, header_len): (SnapshotHeader, usize) =
+            bincode::decode_from_slice(&bytes, config).map_err(invalid_data_from)?;
+        if header.magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a VM snapshot"));
+        }
+        if header.version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "snapshot version {} is not supported (expected {})",
+                header.version, SNAPSHOT_VERSION
+            )));
+        }
+        let isa = self.config.isa.to_string();
+        if header.isa != isa {
+            return Err(invalid_data(format!(
+                "snapshot is of an {} hart but this VM is {}",
+                header.isa, isa
+            )));
+        }
+
+        let (snapshot, _): (Snapshot, usize) =
+            bincode::decode_from_slice(&bytes[header_len..], config).map_err(invalid_data_from)?;
+        if snapshot.ram_size != self.bus.ram.len() as u64 {
+            return Err(invalid_data(format!(
+                "snapshot has {} bytes of RAM but this VM has {}",
+                snapshot.ram_size,
+                self.bus.ram.len()
+            )));
+        }
+
+        if snapshot.vregs.len() != self.vregs.len() {
+            return Err(invalid_data(format!(
+                "snapshot has a VLEN of {} bits but this VM has {}",
+                snapshot.vregs.len() / 32 * 8,
+                self.vregs.len() / 32 * 8
+            )));
+        }
+
+        // Check everything that can be rejected before touching the disk or RAM, so a bad
+        // snapshot leaves the machine as it was.
+        for (index, page) in &snapshot.ram_pages {
+            let start = *index as usize * SNAPSHOT_PAGE_SIZE;
+            if start + page.len() > self.bus.ram.len() {
+                return Err(invalid_data("snapshot RAM page out of range"));
+            }
+        }
+
+        match (snapshot.disk, &self.virtio_blk) {
+            (Some(state), Some(disk)) => disk.borrow_mut().restore_state(state)?,
+            (Some(_), None) => {
+                return Err(invalid_data(
+                    "snapshot includes a disk but none is attached",
+                ));
+            }
+            (None, Some(_)) => {
+                return Err(invalid_data("snapshot has no disk but one is attached"));
+            }
+            (None, None) => {}
+        }
+
+        self.bus.ram.fill(0);
+        for (index, page) in snapshot.ram_pages {
+            let start = index as usize * SNAPSHOT_PAGE_SIZE;
+            self.bus.ram[start..start + page.len()].copy_from_slice(&page);
+        }
+
+        self.registers = snapshot.registers;
+        self.fregs = snapshot.fregs;
+        self.vregs = snapshot.vregs;
+        self.pc = snapshot.pc;
+        self.privilege_level = snapshot.privilege_level;
+        self.csrs = snapshot.csrs;
+        self.clint.borrow_mut().restore_state(snapshot.clint);
+        *self.plic.borrow_mut() = snapshot.plic;
+        self.uart.borrow_mut().restore_state(snapshot.uart);
+        self.entropy.restore_state(snapshot.entropy);
+        self.tlb.clear();
+        self.reservation = None;
+        Ok(())
+    }
+}
+
+fn invalid_data_from(error: bincode::error::DecodeError) -> io::Error {
+    invalid_data(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::Isa;
+    use crate::memory::BASE_ADDRESS;
+    use crate::VmConfig;
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vm-snapshot-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_round_trip_restores_machine_state() {
+        let path = snapshot_path("round-trip");
+        let mut vm = VM::new();
+        vm.registers[10] = 0xdead_beef;
+        vm.pc = BASE_ADDRESS + 0x40;
+        vm.privilege_level = 1;
+        vm.csrs.mscratch = 0x1234;
+        vm.csrs.write(riscv_core::csr::MEDELEG, 0xB1FF, 3);
+        vm.bus
+            .write(BASE_ADDRESS + 0x2000, 8, 0x0102_0304_0506_0708);
+        vm.clint.borrow_mut().mtimecmp = 500;
+        vm.plic.borrow_mut().priority[10] = 3;
+        vm.save_snapshot(&path).unwrap();
+
+        let mut restored = VM::new();
+        restored.bus.write(BASE_ADDRESS + 0x5000, 8, 0xFFFF);
+        restored.load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.registers[10], 0xdead_beef);
+        assert_eq!(restored.pc, BASE_ADDRESS + 0x40);
+        assert_eq!(restored.privilege_level, 1);
+        assert_eq!(restored.csrs.mscratch, 0x1234);
+        assert_eq!(
+            restored.csrs.read(riscv_core::csr::MEDELEG, 3),
+            Some(0xB1FF)
+        );
+        assert_eq!(
+            restored.bus.read(BASE_ADDRESS + 0x2000, 8),
+            Some(0x0102_0304_0506_0708)
+        );
+        assert_eq!(restored.bus.read(BASE_ADDRESS + 0x5000, 8), Some(0));
+        assert_eq!(restored.clint.borrow().mtimecmp, 500);
+        assert_eq!(restored.plic.borrow().priority[10], 3);
+    }
+
+    #[test]
+    fn test_seeded_entropy_resumes_after_restore() {
+        let path = snapshot_path("entropy");
+        let mut vm = VM::new();
+        vm.entropy.poll();
+        vm.save_snapshot(&path).unwrap();
+        let next = vm.entropy.poll();
+
+        let mut restored = VM::new();
+        restored.load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.entropy.poll(), next);
+    }
+
+    #[test]
+    fn test_bad_ram_page_leaves_the_disk_untouched() {
+        let path = snapshot_path("bad-page");
+        let disk = snapshot_path("bad-page-disk");
+        std::fs::write(&disk, [0xAA; 512]).unwrap();
+        let mut vm = VM::new();
+        vm.attach_disk(&disk, false).unwrap();
+        vm.save_snapshot(&path).unwrap();
+
+        // Point a page past the end of RAM, and change the disk so a restore would show.
+        let bytes = std::fs::read(&path).unwrap();
+        let config = bincode::config::standard();
+        let (header, header_len): (SnapshotHeader, usize) =
+            bincode::decode_from_slice(&bytes, config).unwrap();
+        let (mut snapshot, _): (Snapshot, usize) =
+            bincode::decode_from_slice(&bytes[header_len..], config).unwrap();
+        let pages = (snapshot.ram_size as usize / SNAPSHOT_PAGE_SIZE) as u32;
+        snapshot.ram_pages = vec![(pages, vec![1; SNAPSHOT_PAGE_SIZE])];
+        let mut bytes = bincode::encode_to_vec(&header, config).unwrap();
+        bytes.extend(bincode::encode_to_vec(&snapshot, config).unwrap());
+        std::fs::write(&path, bytes).unwrap();
+        std::fs::write(&disk, [0xBB; 512]).unwrap();
+
+        let error = vm.load_snapshot(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&disk).unwrap(), [0xBB; 512]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&disk).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_another_isa_or_disk_layout() {
+        let path = snapshot_path("mismatch");
+        let disk = snapshot_path("mismatch-disk");
+        std::fs::write(&disk, [0; 512]).unwrap();
+        VM::new().save_snapshot(&path).unwrap();
+
+        let mut other_isa = VM::new_config(VmConfig {
+            isa: "rv64gc_svadu".parse().unwrap(),
+            ..VmConfig::default()
+        });
+        let error = other_isa.load_snapshot(&path).unwrap_err();
+        assert!(error.to_string().contains("svadu"));
+
+        let mut with_disk = VM::new();
+        with_disk.attach_disk(&disk, false).unwrap();
+        let error = with_disk.load_snapshot(&path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "snapshot has no disk but one is attached"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&disk).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let path = snapshot_path("version");
+        let header = SnapshotHeader {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION + 1,
+            isa: Isa::default().to_string(),
+        };
+        let bytes = bincode::encode_to_vec(&header, bincode::config::standard()).unwrap();
+        std::fs::write(&path, bytes).unwrap();
+
+        let error = VM::new().load_snapshot(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,832 @@
+//! IEEE 754 binary32/binary64 arithmetic done with integers, so results, rounding and
+//! exception flags match the F and D extensions exactly whatever the host FPU does.
+
+/// Inexact.
+pub const FLAG_NX: u64 = 1 << 0;
+/// Underflow.
+pub const FLAG_UF: u64 = 1 << 1;
+/// Overflow.
+pub const FLAG_OF: u64 = 1 << 2;
+/// Divide by zero.
+pub const FLAG_DZ: u64 = 1 << 3;
+/// Invalid operation.
+pub const FLAG_NV: u64 = 1 << 4;
+
+/// The rounding modes, numbered as in `frm` and the instruction `rm` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Self::NearestEven),
+            1 => Some(Self::TowardZero),
+            2 => Some(Self::Down),
+            3 => Some(Self::Up),
+            4 => Some(Self::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// A binary interchange format, described by the widths of its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const F32: Format = Format {
+    exp_bits: 8,
+    frac_bits: 23,
+};
+pub const F64: Format = Format {
+    exp_bits: 11,
+    frac_bits: 52,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn max_exp_field(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn pack(self, sign: bool, exp_field: u64, frac: u64) -> u64 {
+        ((sign as u64) << (self.exp_bits + self.frac_bits)) | (exp_field << self.frac_bits) | frac
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        self.pack(false, self.max_exp_field(), 1 << (self.frac_bits - 1))
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.pack(sign, self.max_exp_field(), 0)
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        self.pack(sign, 0, 0)
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.pack(sign, self.max_exp_field() - 1, self.frac_mask())
+    }
+
+    fn is_nan(self, bits: u64) -> bool {
+        (bits >> self.frac_bits) & self.max_exp_field() == self.max_exp_field()
+            && bits & self.frac_mask() != 0
+    }
+
+    fn is_signaling_nan(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & (1 << (self.frac_bits - 1)) == 0
+    }
+
+    fn unpack(self, bits: u64) -> Value {
+        let sign = bits & self.sign_bit() != 0;
+        let exp_field = (bits >> self.frac_bits) & self.max_exp_field();
+        let frac = bits & self.frac_mask();
+        let min_exp = 1 - self.bias() - self.frac_bits as i32;
+        if exp_field == self.max_exp_field() {
+            if frac == 0 {
+                Value::Infinity { sign }
+            } else {
+                Value::NaN
+            }
+        } else if exp_field == 0 {
+            Value::Finite {
+                sign,
+                exp: min_exp,
+                sig: frac as u128,
+            }
+        } else {
+            Value::Finite {
+                sign,
+                exp: min_exp + exp_field as i32 - 1,
+                sig: (frac | (1 << self.frac_bits)) as u128,
+            }
+        }
+    }
+
+    /// Whether `a < b`, for operands that are not NaN.
+    fn less(self, a: u64, b: u64) -> bool {
+        let sign_a = a & self.sign_bit() != 0;
+        let sign_b = b & self.sign_bit() != 0;
+        let magnitude_a = a & !self.sign_bit();
+        let magnitude_b = b & !self.sign_bit();
+        if magnitude_a == 0 && magnitude_b == 0 {
+            false
+        } else if sign_a != sign_b {
+            sign_a
+        } else if sign_a {
+            magnitude_a > magnitude_b
+        } else {
+            magnitude_a < magnitude_b
+        }
+    }
+
+    /// The `fclass` bit mask for `bits`.
+    pub fn classify(self, bits: u64) -> u64 {
+        let sign = bits & self.sign_bit() != 0;
+        let exp_field = (bits >> self.frac_bits) & self.max_exp_field();
+        let frac = bits & self.frac_mask();
+        let class = if self.is_signaling_nan(bits) {
+            8
+        } else if self.is_nan(bits) {
+            9
+        } else if exp_field == self.max_exp_field() {
+            if sign {
+                0
+            } else {
+                7
+            }
+        } else if exp_field == 0 && frac == 0 {
+            if sign {
+                3
+            } else {
+                4
+            }
+        } else if exp_field == 0 {
+            if sign {
+                2
+            } else {
+                5
+            }
+        } else if sign {
+            1
+        } else {
+            6
+        };
+        1 << class
+    }
+}
+
+/// An unpacked operand. A finite value is `sig * 2^exp`; zero has `sig == 0`.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    NaN,
+    Infinity { sign: bool },
+    Finite { sign: bool, exp: i32, sig: u128 },
+}
+
+/// Index of the most significant set bit of a non-zero `value`.
+fn msb(value: u128) -> i32 {
+    127 - value.leading_zeros() as i32
+}
+
+/// Shifts `value` right, ORing any bits shifted out into bit 0. As long as the result keeps
+/// at least two bits below the rounding position, it rounds exactly like the unshifted value.
+fn shift_right_jam(value: u128, shift: i32) -> u128 {
+    if shift <= 0 {
+        value
+    } else if shift >= 128 {
+        (value != 0) as u128
+    } else {
+        (value >> shift) | ((value & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+/// Floor square root and whether it was inexact.
+fn isqrt(value: u128) -> (u128, bool) {
+    let mut remainder = value;
+    let mut root = 0u128;
+    let mut bit = 1u128 << 126;
+    while bit > remainder {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, remainder != 0)
+}
+
+/// Arithmetic in one format and rounding mode, accumulating exception flags.
+pub struct SoftFloat {
+    fmt: Format,
+    rm: RoundingMode,
+    pub flags: u64,
+}
+
+impl SoftFloat {
+    pub fn new(fmt: Format, rm: RoundingMode) -> Self {
+        Self { fmt, rm, flags: 0 }
+    }
+
+    /// Rounds `sig` shifted right by `shift` bits to an integer, returning it and whether
+    /// any non-zero bits were discarded.
+    fn round_shift(&self, sign: bool, sig: u128, shift: i32) -> (u128, bool) {
+        if shift <= 0 {
+            return (sig << -shift, false);
+        }
+        let (kept, remainder, half) = if shift >= 128 {
+            // Everything is below the rounding position and less than half of it.
+            (0, sig, u128::MAX)
+        } else {
+            (sig >> shift, sig & ((1 << shift) - 1), 1 << (shift - 1))
+        };
+        let inexact = remainder != 0;
+        let increment = match self.rm {
+            RoundingMode::NearestEven => remainder > half || (remainder == half && kept & 1 == 1),
+            RoundingMode::NearestMaxMagnitude => remainder >= half,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign && inexact,
+            RoundingMode::Up => !sign && inexact,
+        };
+        (kept + increment as u128, inexact)
+    }
+
+    fn overflow(&mut self, sign: bool) -> u64 {
+        self.flags |= FLAG_OF | FLAG_NX;
+        let to_infinity = match self.rm {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign,
+            RoundingMode::Up => !sign,
+        };
+        if to_infinity {
+            self.fmt.infinity(sign)
+        } else {
+            self.fmt.max_finite(sign)
+        }
+    }
+
+    /// Rounds `sig * 2^exp` to the format, raising inexact, underflow and overflow.
+    /// Underflow is detected after rounding, as RISC-V requires.
+    fn round_pack(&mut self, sign: bool, exp: i32, sig: u128) -> u64 {
+        if sig == 0 {
+            return self.fmt.zero(sign);
+        }
+        let precision = self.fmt.frac_bits as i32;
+        let min_exp = 1 - self.fmt.bias();
+        let leading_exp = exp + msb(sig);
+        let mut lsb_exp = leading_exp.max(min_exp) - precision;
+
+        let (mut kept, inexact) = self.round_shift(sign, sig, lsb_exp - exp);
+        if kept >> (precision + 1) != 0 {
+            kept >>= 1;
+            lsb_exp += 1;
+        }
+
+        if inexact {
+            self.flags |= FLAG_NX;
+            // Tiny if, rounded with an unbounded exponent range, the result is still below
+            // the smallest normal number.
+            let tiny = leading_exp < min_exp - 1
+                || (leading_exp == min_exp - 1 && {
+                    let (unbounded, _) = self.round_shift(sign, sig, leading_exp - precision - exp);
+                    unbounded >> (precision + 1) == 0
+                });
+            if tiny {
+                self.flags |= FLAG_UF;
+            }
+        }
+
+        let exp_field = if kept >> precision != 0 {
+            (lsb_exp + precision + self.fmt.bias()) as u64
+        } else {
+            0
+        };
+        if exp_field >= self.fmt.max_exp_field() {
+            return self.overflow(sign);
+        }
+        self.fmt
+            .pack(sign, exp_field, kept as u64 & self.fmt.frac_mask())
+    }
+
+    /// Returns the canonical NaN, raising invalid if any operand is a signaling NaN.
+    fn propagate_nan(&mut self, operands: &[u64]) -> u64 {
+        if operands.iter().any(|&bits| self.fmt.is_signaling_nan(bits)) {
+            self.flags |= FLAG_NV;
+        }
+        self.fmt.canonical_nan()
+    }
+
+    fn invalid(&mut self) -> u64 {
+        self.flags |= FLAG_NV;
+        self.fmt.canonical_nan()
+    }
+
+    /// Adds two finite values exactly and rounds the sum once.
+    fn add_finite(
+        &mut self,
+        (sign_a, exp_a, sig_a): (bool, i32, u128),
+        (sign_b, exp_b, sig_b): (bool, i32, u128),
+    ) -> u64 {
+        if sig_a == 0 && sig_b == 0 {
+            let sign = if sign_a == sign_b {
+                sign_a
+            } else {
+                self.rm == RoundingMode::Down
+            };
+            return self.fmt.zero(sign);
+        }
+        if sig_a == 0 {
+            return self.round_pack(sign_b, exp_b, sig_b);
+        }
+        if sig_b == 0 {
+            return self.round_pack(sign_a, exp_a, sig_a);
+        }
+
+        // Line both up with their leading bit at 125, leaving room for a carry and enough
+        // guard bits that jamming the smaller operand cannot change the rounding.
+        let normalize = |exp: i32, sig: u128| {
+            let shift = 125 - msb(sig);
+            (exp - shift, sig << shift)
+        };
+        let (mut exp_a, mut sig_a) = normalize(exp_a, sig_a);
+        let (mut exp_b, mut sig_b) = normalize(exp_b, sig_b);
+        let (mut sign_a, mut sign_b) = (sign_a, sign_b);
+        if exp_a < exp_b {
+            (exp_a, exp_b) = (exp_b, exp_a);
+            (sig_a, sig_b) = (sig_b, sig_a);
+            (sign_a, sign_b) = (sign_b, sign_a);
+        }
+        let sig_b = shift_right_jam(sig_b, exp_a - exp_b);
+
+        if sign_a == sign_b {
+            self.round_pack(sign_a, exp_a, sig_a + sig_b)
+        } else if sig_a > sig_b {
+            self.round_pack(sign_a, exp_a, sig_a - sig_b)
+        } else if sig_b > sig_a {
+            self.round_pack(sign_b, exp_a, sig_b - sig_a)
+        } else {
+            self.fmt.zero(self.rm == RoundingMode::Down)
+        }
+    }
+
+    pub fn add(&mut self, a: u64, b: u64) -> u64 {
+        match (self.fmt.unpack(a), self.fmt.unpack(b)) {
+            (Value::NaN, _) | (_, Value::NaN) => self.propagate_nan(&[a, b]),
+            (Value::Infinity { sign: sign_a }, Value::Infinity { sign: sign_b }) => {
+                if sign_a == sign_b {
+                    self.fmt.infinity(sign_a)
+                } else {
+                    self.invalid()
+                }
+            }
+            (Value::Infinity { sign }, _) | (_, Value::Infinity { sign }) => {
+                self.fmt.infinity(sign)
+            }
+            (
+                Value::Finite {
+                    sign: sign_a,
+                    exp: exp_a,
+                    sig: sig_a,
+                },
+                Value::Finite {
+                    sign: sign_b,
+                    exp: exp_b,
+                    sig: sig_b,
+                },
+            ) => self.add_finite((sign_a, exp_a, sig_a), (sign_b, exp_b, sig_b)),
+        }
+    }
+
+    pub fn sub(&mut self, a: u64, b: u64) -> u64 {
+        self.add(a, b ^ self.fmt.sign_bit())
+    }
+
+    pub fn mul(&mut self, a: u64, b: u64) -> u64 {
+        let sign = (a ^ b) & self.fmt.sign_bit() != 0;
+        match (self.fmt.unpack(a), self.fmt.unpack(b)) {
+            (Value::NaN, _) | (_, Value::NaN) => self.propagate_nan(&[a, b]),
+            (Value::Infinity { .. }, Value::Finite { sig: 0, .. })
+            | (Value::Finite { sig: 0, .. }, Value::Infinity { .. }) => self.invalid(),
+            (Value::Infinity { .. }, _) | (_, Value::Infinity { .. }) => self.fmt.infinity(sign),
+            (
+                Value::Finite {
+                    exp: exp_a,
+                    sig: sig_a,
+                    ..
+                },
+                Value::Finite {
+                    exp: exp_b,
+                    sig: sig_b,
+                    ..
+                },
+            ) => self.round_pack(sign, exp_a + exp_b, sig_a * sig_b),
+        }
+    }
+
+    pub fn div(&mut self, a: u64, b: u64) -> u64 {
+        let sign = (a ^ b) & self.fmt.sign_bit() != 0;
+        match (self.fmt.unpack(a), self.fmt.unpack(b)) {
+            (Value::NaN, _) | (_, Value::NaN) => self.propagate_nan(&[a, b]),
+            (Value::Infinity { .. }, Value::Infinity { .. }) => self.invalid(),
+            (Value::Infinity { .. }, _) => self.fmt.infinity(sign),
+            (_, Value::Infinity { .. }) => self.fmt.zero(sign),
+            (Value::Finite { sig: 0, .. }, Value::Finite { sig: 0, .. }) => self.invalid(),
+            (_, Value::Finite { sig: 0, .. }) => {
+                self.flags |= FLAG_DZ;
+                self.fmt.infinity(sign)
+            }
+            (Value::Finite { sig: 0, .. }, _) => self.fmt.zero(sign),
+            (
+                Value::Finite {
+                    exp: exp_a,
+                    sig: sig_a,
+                    ..
+                },
+                Value::Finite {
+                    exp: exp_b,
+                    sig: sig_b,
+                    ..
+                },
+            ) => {
+                // A 127-bit dividend over a 64-bit divisor leaves at least 63 quotient bits.
+                let shift_a = 126 - msb(sig_a);
+                let shift_b = 63 - msb(sig_b);
+                let dividend = sig_a << shift_a;
+                let divisor = sig_b << shift_b;
+                let quotient = dividend / divisor;
+                let sticky = !dividend.is_multiple_of(divisor) as u128;
+                self.round_pack(sign, exp_a - shift_a - (exp_b - shift_b), quotient | sticky)
+            }
+        }
+    }
+
+    pub fn sqrt(&mut self, a: u64) -> u64 {
+        match self.fmt.unpack(a) {
+            Value::NaN => self.propagate_nan(&[a]),
+            Value::Finite { sig: 0, .. } | Value::Infinity { sign: false } => a,
+            Value::Infinity { sign: true } | Value::Finite { sign: true, .. } => self.invalid(),
+            Value::Finite {
+                sign: false,
+                exp,
+                sig,
+            } => {
+                let shift = 125 - msb(sig);
+                let (mut exp, mut sig) = (exp - shift, sig << shift);
+                if exp % 2 != 0 {
+                    exp -= 1;
+                    sig <<= 1;
+                }
+                let (root, inexact) = isqrt(sig);
+                self.round_pack(false, exp / 2, root | inexact as u128)
+            }
+        }
+    }
+
+    /// `a * b + c` with a single rounding.
+    pub fn mul_add(&mut self, a: u64, b: u64, c: u64) -> u64 {
+        let product_sign = (a ^ b) & self.fmt.sign_bit() != 0;
+        let (value_a, value_b, value_c) =
+            (self.fmt.unpack(a), self.fmt.unpack(b), self.fmt.unpack(c));
+        // Infinity times zero is invalid even when the addend is a quiet NaN.
+        if matches!(
+            (value_a, value_b),
+            (Value::Infinity { .. }, Value::Finite { sig: 0, .. })
+                | (Value::Finite { sig: 0, .. }, Value::Infinity { .. })
+        ) {
+            return self.invalid();
+        }
+        match (value_a, value_b, value_c) {
+            (Value::NaN, _, _) | (_, Value::NaN, _) | (_, _, Value::NaN) => {
+                self.propagate_nan(&[a, b, c])
+            }
+            (Value::Infinity { .. }, _, Value::Infinity { sign })
+            | (_, Value::Infinity { .. }, Value::Infinity { sign }) => {
+                if sign == product_sign {
+                    self.fmt.infinity(sign)
+                } else {
+                    self.invalid()
+                }
+            }
+            (Value::Infinity { .. }, _, _) | (_, Value::Infinity { .. }, _) => {
+                self.fmt.infinity(product_sign)
+            }
+            (_, _, Value::Infinity { sign }) => self.fmt.infinity(sign),
+            (
+                Value::Finite {
+                    exp: exp_a,
+                    sig: sig_a,
+                    ..
+                },
+                Value::Finite {
+                    exp: exp_b,
+                    sig: sig_b,
+                    ..
+                },
+                Value::Finite {
+                    sign: sign_c,
+                    exp: exp_c,
+                    sig: sig_c,
+                },
+            ) => self.add_finite(
+                (product_sign, exp_a + exp_b, sig_a * sig_b),
+                (sign_c, exp_c, sig_c),
+            ),
+        }
+    }
+
+    /// `fmin`/`fmax`: a NaN operand yields the other one, and -0 is less than +0.
+    pub fn min_max(&mut self, a: u64, b: u64, is_max: bool) -> u64 {
+        if self.fmt.is_signaling_nan(a) || self.fmt.is_signaling_nan(b) {
+            self.flags |= FLAG_NV;
+        }
+        match (self.fmt.is_nan(a), self.fmt.is_nan(b)) {
+            (true, true) => self.fmt.canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            (false, false) => {
+                let a_first = if a & !self.fmt.sign_bit() == 0 && b & !self.fmt.sign_bit() == 0 {
+                    // Both zero: -0 orders below +0.
+                    a & self.fmt.sign_bit() != 0
+                } else {
+                    self.fmt.less(a, b)
+                };
+                if a_first != is_max {
+                    a
+                } else {
+                    b
+                }
+            }
+        }
+    }
+
+    /// `feq`: a quiet comparison, invalid only for signaling NaNs.
+    pub fn eq(&mut self, a: u64, b: u64) -> bool {
+        if self.fmt.is_nan(a) || self.fmt.is_nan(b) {
+            self.propagate_nan(&[a, b]);
+            return false;
+        }
+        a == b || (a | b) & !self.fmt.sign_bit() == 0
+    }
+
+    /// `flt`/`fle`: signaling comparisons, invalid for any NaN.
+    pub fn lt(&mut self, a: u64, b: u64, or_equal: bool) -> bool {
+        if self.fmt.is_nan(a) || self.fmt.is_nan(b) {
+            self.flags |= FLAG_NV;
+            return false;
+        }
+        self.fmt.less(a, b) || (or_equal && self.eq(a, b))
+    }
+
+    /// Converts to a `width`-bit integer, sign-extended to 64 bits. Out-of-range values and
+    /// NaNs saturate and raise invalid.
+    pub fn to_int(&mut self, a: u64, signed: bool, width: u32) -> u64 {
+        let max = if signed {
+            (1u128 << (width - 1)) - 1
+        } else {
+            (1u128 << width) - 1
+        };
+        let saturate = |negative: bool| -> u64 {
+            let value = match (signed, negative) {
+                (true, true) => (1u64 << (width - 1)).wrapping_neg(),
+                (false, true) => 0,
+                (_, false) => max as u64,
+            };
+            sign_extend(value, width)
+        };
+
+        let (sign, exp, sig) = match self.fmt.unpack(a) {
+            Value::NaN => {
+                self.flags |= FLAG_NV;
+                return saturate(false);
+            }
+            Value::Infinity { sign } => {
+                self.flags |= FLAG_NV;
+                return saturate(sign);
+            }
+            Value::Finite { sign, exp, sig } => (sign, exp, sig),
+        };
+        // Anything this large is out of range for every width.
+        if sig != 0 && exp + msb(sig) > 64 {
+            self.flags |= FLAG_NV;
+            return saturate(sign);
+        }
+        let (magnitude, inexact) = self.round_shift(sign, sig, -exp);
+        let in_range = match (signed, sign) {
+            (true, true) => magnitude <= max + 1,
+            (false, true) => magnitude == 0,
+            (_, false) => magnitude <= max,
+        };
+        if !in_range {
+            self.flags |= FLAG_NV;
+            return saturate(sign);
+        }
+        if inexact {
+            self.flags |= FLAG_NX;
+        }
+        let value = if sign {
+            (magnitude as u64).wrapping_neg()
+        } else {
+            magnitude as u64
+        };
+        sign_extend(value, width)
+    }
+
+    /// Converts the low `width` bits of `value`, as a signed or unsigned integer.
+    pub fn from_int(&mut self, value: u64, signed: bool, width: u32) -> u64 {
+        let value = if width == 32 {
+            if signed {
+                value as i32 as i64 as u64
+            } else {
+                value as u32 as u64
+            }
+        } else {
+            value
+        };
+        let negative = signed && (value as i64) < 0;
+        let magnitude = if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        };
+        self.round_pack(negative, 0, magnitude as u128)
+    }
+
+    /// Converts `a` from format `from` into this one.
+    pub fn convert(&mut self, a: u64, from: Format) -> u64 {
+        match from.unpack(a) {
+            Value::NaN => {
+                if from.is_signaling_nan(a) {
+                    self.flags |= FLAG_NV;
+                }
+                self.fmt.canonical_nan()
+            }
+            Value::Infinity { sign } => self.fmt.infinity(sign),
+            Value::Finite { sign, exp, sig } => self.round_pack(sign, exp, sig),
+        }
+    }
+}
+
+fn sign_extend(value: u64, width: u32) -> u64 {
+    if width == 32 {
+        value as i32 as i64 as u64
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f64_op(rm: RoundingMode, op: impl FnOnce(&mut SoftFloat) -> u64) -> (f64, u64) {
+        let mut sf = SoftFloat::new(F64, rm);
+        let bits = op(&mut sf);
+        (f64::from_bits(bits), sf.flags)
+    }
+
+    #[test]
+    fn test_arithmetic_matches_host_in_nearest_even() {
+        let values = [
+            1.0f64,
+            -2.5,
+            0.1,
+            3.0e-310,
+            1.7e308,
+            -0.0,
+            123456.789,
+            f64::MIN_POSITIVE,
+        ];
+        for &a in &values {
+            for &b in &values {
+                let mut sf = SoftFloat::new(F64, RoundingMode::NearestEven);
+                assert_eq!(sf.add(a.to_bits(), b.to_bits()), (a + b).to_bits());
+                assert_eq!(sf.sub(a.to_bits(), b.to_bits()), (a - b).to_bits());
+                assert_eq!(sf.mul(a.to_bits(), b.to_bits()), (a * b).to_bits());
+                if b != 0.0 {
+                    assert_eq!(sf.div(a.to_bits(), b.to_bits()), (a / b).to_bits());
+                }
+                assert_eq!(
+                    sf.mul_add(a.to_bits(), b.to_bits(), 0.3f64.to_bits()),
+                    a.mul_add(b, 0.3).to_bits()
+                );
+            }
+            let mut sf = SoftFloat::new(F64, RoundingMode::NearestEven);
+            let root = sf.sqrt(a.abs().to_bits());
+            assert_eq!(root, a.abs().sqrt().to_bits());
+        }
+    }
+
+    #[test]
+    fn test_directed_rounding_and_flags() {
+        let one_third_down = f64_op(RoundingMode::Down, |sf| {
+            sf.div(1.0f64.to_bits(), 3.0f64.to_bits())
+        });
+        let one_third_up = f64_op(RoundingMode::Up, |sf| {
+            sf.div(1.0f64.to_bits(), 3.0f64.to_bits())
+        });
+        assert_eq!(one_third_down.1, FLAG_NX);
+        assert_eq!(one_third_up.0, one_third_down.0.next_up());
+
+        let (value, flags) = f64_op(RoundingMode::TowardZero, |sf| {
+            sf.mul(f64::MAX.to_bits(), 2.0f64.to_bits())
+        });
+        assert_eq!(value, f64::MAX);
+        assert_eq!(flags, FLAG_OF | FLAG_NX);
+
+        let (value, flags) = f64_op(RoundingMode::NearestEven, |sf| {
+            sf.mul(f64::MIN_POSITIVE.to_bits(), 0.75f64.to_bits())
+        });
+        assert_eq!(value, f64::MIN_POSITIVE * 0.75);
+        assert_eq!(flags, 0);
+
+        let (_, flags) = f64_op(RoundingMode::NearestEven, |sf| {
+            sf.div(1.0f64.to_bits(), 0.0f64.to_bits())
+        });
+        assert_eq!(flags, FLAG_DZ);
+
+        let (value, flags) = f64_op(RoundingMode::Down, |sf| {
+            sf.add(1.0f64.to_bits(), (-1.0f64).to_bits())
+        });
+        assert_eq!(value.to_bits(), (-0.0f64).to_bits());
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn test_tininess_is_detected_after_rounding() {
+        // Just below the smallest normal; it rounds up to it, so only inexact is raised.
+        let just_below = f64::MIN_POSITIVE.to_bits() - 1;
+        let (value, flags) = f64_op(RoundingMode::NearestEven, |sf| {
+            sf.mul(just_below, (1.0f64 + f64::EPSILON).to_bits())
+        });
+        assert_eq!(value, f64::MIN_POSITIVE);
+        assert_eq!(flags, FLAG_NX);
+
+        let (_, flags) = f64_op(RoundingMode::TowardZero, |sf| {
+            sf.mul(just_below, (1.0f64 + f64::EPSILON).to_bits())
+        });
+        assert_eq!(flags, FLAG_NX | FLAG_UF);
+    }
+
+    #[test]
+    fn test_nan_and_comparison_semantics() {
+        let snan = 0x7FA0_0000u64;
+        let mut sf = SoftFloat::new(F32, RoundingMode::NearestEven);
+        assert_eq!(sf.add(snan, 1.0f32.to_bits() as u64), 0x7FC0_0000);
+        assert_eq!(sf.flags, FLAG_NV);
+
+        let mut sf = SoftFloat::new(F32, RoundingMode::NearestEven);
+        let neg_zero = (-0.0f32).to_bits() as u64;
+        assert_eq!(sf.min_max(0, neg_zero, false), neg_zero);
+        assert_eq!(
+            sf.min_max(0x7FC0_0000, 2.0f32.to_bits() as u64, true),
+            2.0f32.to_bits() as u64
+        );
+        assert!(sf.eq(0, neg_zero));
+        assert_eq!(sf.flags, 0);
+        assert!(!sf.lt(0x7FC0_0000, 0, false));
+        assert_eq!(sf.flags, FLAG_NV);
+
+        assert_eq!(F32.classify(snan), 1 << 8);
+        assert_eq!(F64.classify((-0.0f64).to_bits()), 1 << 3);
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        let mut sf = SoftFloat::new(F64, RoundingMode::NearestEven);
+        assert_eq!(sf.to_int((-2.5f64).to_bits(), true, 32), (-2i64) as u64);
+        assert_eq!(sf.flags, FLAG_NX);
+
+        let mut sf = SoftFloat::new(F64, RoundingMode::NearestMaxMagnitude);
+        assert_eq!(sf.to_int(2.5f64.to_bits(), true, 64), 3);
+
+        let mut sf = SoftFloat::new(F64, RoundingMode::TowardZero);
+        assert_eq!(sf.to_int(1e20f64.to_bits(), true, 32), i32::MAX as u64);
+        assert_eq!(sf.to_int((-1.0f64).to_bits(), false, 64), 0);
+        assert_eq!(sf.to_int(f64::NAN.to_bits(), false, 32), u64::MAX);
+        assert_eq!(sf.flags, FLAG_NV);
+
+        let mut sf = SoftFloat::new(F32, RoundingMode::NearestEven);
+        assert_eq!(
+            sf.from_int(u64::MAX, false, 64),
+            1.8446744e19f32.to_bits() as u64
+        );
+        assert_eq!(sf.flags, FLAG_NX);
+        assert_eq!(
+            sf.from_int(0xFFFF_FFFF, true, 32),
+            (-1.0f32).to_bits() as u64
+        );
+
+        let mut sf = SoftFloat::new(F32, RoundingMode::NearestEven);
+        assert_eq!(
+            sf.convert(0.1f64.to_bits(), F64),
+            (0.1f64 as f32).to_bits() as u64
+        );
+        assert_eq!(sf.flags, FLAG_NX);
+    }
+}
@@ -1,31 +1,136 @@
-use crate::VM;
+use crate::{
+    csr::{
+        MIP_LCOFIP, MSTATUS_MIE, MSTATUS_MPIE, MSTATUS_MPP, MSTATUS_MPP_SHIFT, MSTATUS_SIE,
+        MSTATUS_SPIE, MSTATUS_SPP, TVEC_MODE_MASK, TVEC_MODE_VECTORED,
+    },
+    hpm::HpmEvent,
+    VM,
+};
 use assembler::disassemble;
 use riscv_core::{abi, cause, csr};
-use std::io::{self, Write};
+
+/// Interrupt causes in the order the privileged spec says simultaneous interrupts are taken.
+const INTERRUPT_PRIORITY: [u64; 7] = [
+    cause::MACHINE_EXTERNAL_INTERRUPT,
+    cause::MACHINE_SOFTWARE_INTERRUPT,
+    cause::MACHINE_TIMER_INTERRUPT,
+    cause::SUPERVISOR_EXTERNAL_INTERRUPT,
+    cause::SUPERVISOR_SOFTWARE_INTERRUPT,
+    cause::SUPERVISOR_TIMER_INTERRUPT,
+    cause::COUNTER_OVERFLOW_INTERRUPT,
+];
 
 impl VM {
+    /// Takes a trap for `cause`. The target privilege level is chosen from `medeleg`/`mideleg`
+    /// and, if the guest has installed a handler in the target's `xtvec`, the hart enters it
+    /// the way the privileged spec describes. Without a guest handler the VM falls back to
+    /// handling the event itself. Returns `false` if execution should halt.
     pub(crate) fn handle_trap(&mut self, cause: u64, tval: u64) -> bool {
-        let is_interrupt = (cause >> 63) & 1 == 1;
-        let return_pc = if is_interrupt { self.pc } else { self.pc + 4 };
+        let is_interrupt = cause & cause::INTERRUPT_BIT != 0;
+        let code = cause & !cause::INTERRUPT_BIT;
+        let target_level = self.trap_target_level(is_interrupt, code);
+        self.trap_level = target_level;
+        self.count_event(HpmEvent::Trap);
+        if !is_interrupt {
+            self.trapped = true;
+            self.csrs.hold_instret();
+        }
+
+        let (tvec_addr, epc_addr, cause_addr, tval_addr) = if target_level == 3 {
+            (csr::MTVEC, csr::MEPC, csr::MCAUSE, csr::MTVAL)
+        } else {
+            (csr::STVEC, csr::SEPC, csr::SCAUSE, csr::STVAL)
+        };
+
+        self.csrs.write(epc_addr, self.pc, 3);
+        self.csrs.write(cause_addr, cause, 3);
+        self.csrs.write(tval_addr, tval, 3);
+
+        let tvec = self.csrs.read(tvec_addr, 3).unwrap_or(0);
+        let tvec_base = tvec & !TVEC_MODE_MASK;
+        if tvec_base == 0 {
+            return if is_interrupt {
+                self.handle_interrupt(cause)
+            } else {
+                self.handle_exception(cause, tval)
+            };
+        }
 
-        match self.privilege_level {
-            1 | 0 => {
-                self.csrs.write(csr::SEPC, return_pc, self.privilege_level);
-                self.csrs.write(csr::SCAUSE, cause, self.privilege_level);
-                self.csrs.write(csr::STVAL, tval, self.privilege_level);
+        let mut mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+        if target_level == 3 {
+            let mie = (mstatus & MSTATUS_MIE) != 0;
+            mstatus &= !(MSTATUS_MPIE | MSTATUS_MIE | MSTATUS_MPP);
+            if mie {
+                mstatus |= MSTATUS_MPIE;
             }
-            3 => {
-                self.csrs.write(csr::MEPC, return_pc, self.privilege_level);
-                self.csrs.write(csr::MCAUSE, cause, self.privilege_level);
-                self.csrs.write(csr::MTVAL, tval, self.privilege_level);
+            mstatus |= (self.privilege_level as u64) << MSTATUS_MPP_SHIFT;
+        } else {
+            let sie = (mstatus & MSTATUS_SIE) != 0;
+            mstatus &= !(MSTATUS_SPIE | MSTATUS_SIE | MSTATUS_SPP);
+            if sie {
+                mstatus |= MSTATUS_SPIE;
+            }
+            if self.privilege_level == 1 {
+                mstatus |= MSTATUS_SPP;
             }
-            _ => unreachable!(),
         }
+        self.csrs.write(csr::MSTATUS, mstatus, 3);
+        self.privilege_level = target_level;
 
-        if is_interrupt {
-            self.handle_interrupt(cause)
+        self.pc = if is_interrupt && (tvec & TVEC_MODE_MASK) == TVEC_MODE_VECTORED {
+            tvec_base.wrapping_add(4 * code)
+        } else {
+            tvec_base
+        };
+        true
+    }
+
+    /// Returns the cause of the highest-priority interrupt that is pending, enabled in `mie`
+    /// and not masked by the global enables. Interrupts destined for a more-privileged mode
+    /// are always taken, ones for the current mode are gated by `mstatus.xIE`, and ones for a
+    /// less-privileged mode are never taken.
+    pub(crate) fn pending_interrupt(&self) -> Option<u64> {
+        let mip = self.csrs.read(csr::MIP, 3).unwrap_or(0);
+        let mie = self.csrs.read(csr::MIE, 3).unwrap_or(0);
+        let pending = mip & mie;
+        if pending == 0 {
+            return None;
+        }
+
+        let mstatus = self.csrs.read(csr::MSTATUS, 3).unwrap_or(0);
+        let mideleg = self.csrs.read(csr::MIDELEG, 3).unwrap_or(0);
+
+        let m_enabled = self.privilege_level < 3 || (mstatus & MSTATUS_MIE) != 0;
+        let s_enabled =
+            self.privilege_level < 1 || (self.privilege_level == 1 && (mstatus & MSTATUS_SIE) != 0);
+
+        let m_pending = if m_enabled { pending & !mideleg } else { 0 };
+        let s_pending = if s_enabled { pending & mideleg } else { 0 };
+
+        [m_pending, s_pending].into_iter().find_map(|candidates| {
+            INTERRUPT_PRIORITY
+                .iter()
+                .copied()
+                .find(|&cause| (candidates >> (cause & !cause::INTERRUPT_BIT)) & 1 == 1)
+        })
+    }
+
+    /// Traps never move to a less-privileged mode. Anything raised below M-mode goes to
+    /// S-mode when the matching `medeleg`/`mideleg` bit is set.
+    fn trap_target_level(&self, is_interrupt: bool, code: u64) -> u8 {
+        if self.privilege_level == 3 || code >= 64 {
+            return 3;
+        }
+        let deleg_addr = if is_interrupt {
+            csr::MIDELEG
         } else {
-            self.handle_exception(cause, tval)
+            csr::MEDELEG
+        };
+        let deleg = self.csrs.read(deleg_addr, 3).unwrap_or(0);
+        if (deleg >> code) & 1 == 1 {
+            1
+        } else {
+            3
         }
     }
 
@@ -38,12 +143,12 @@ impl VM {
                         let exit_code = self.registers[abi::A0 as usize];
                         println!("\n\n--- ECALL: Exit with code {} --- \n", exit_code as i32);
                         self.print_state();
-                        println!("");
-                        return false;
+                        println!();
+                        false
                     }
                     _ => {
                         println!("--- Unimplemented Syscall: a7={} ---", syscall_num);
-                        return self.handle_exception(cause::ILLEGAL_INSTRUCTION, syscall_num);
+                        self.handle_exception(cause::ILLEGAL_INSTRUCTION, syscall_num)
                     }
                 }
             }
@@ -54,14 +159,14 @@ impl VM {
                 let disassembled_text = disassemble(instruction_word, self.pc);
                 println!("Failing Instruction: '{}'", disassembled_text);
                 println!("Instruction Word (mtval): {:#010x}", instruction_word);
-                return false;
+                false
             }
 
             cause::INSTRUCTION_ADDRESS_MISALIGNED => {
                 println!("\n--- FATAL EXCEPTION: Instruction Address Misaligned ---");
                 println!("PC (mepc): {:#x}", self.pc);
                 println!("Misaligned Address (mtval): {:#x}", tval);
-                return false;
+                false
             }
 
             cause::INSTRUCTION_ACCESS_FAULT
@@ -75,7 +180,7 @@ impl VM {
                 );
                 println!("Faulting Address (mtval): {:#x}", tval);
                 println!("Instruction PC (mepc): {:#x}", self.pc);
-                return false;
+                false
             }
 
             cause::LOAD_ADDRESS_MISALIGNED | cause::STORE_AMO_ADDRESS_MISALIGNED => {
@@ -87,21 +192,16 @@ impl VM {
                 );
                 println!("Misaligned Address (mtval): {:#x}", tval);
                 println!("Instruction PC (mepc): {:#x}", self.pc);
-                return false;
+                false
             }
 
             cause::BREAKPOINT => {
                 println!("\n--- BREAKPOINT ---");
                 println!("Breakpoint at PC: {:#x}", self.pc);
                 self.print_state();
-                self.pc += 4;
-
-                print!("Press Enter to continue...");
-                io::stdout().flush().unwrap();
-                let mut buffer = String::new();
-                io::stdin().read_line(&mut buffer).unwrap();
-
-                return true;
+                // Without a debugger attached there is nothing to stop for; skip the ebreak.
+                self.pc += self.instruction_length_at(self.pc);
+                true
             }
 
             cause::INSTRUCTION_PAGE_FAULT
@@ -115,7 +215,7 @@ impl VM {
                 );
                 println!("Faulting Virtual Address (mtval): {:#x}", tval);
                 println!("Instruction PC (mepc): {:#x}", self.pc);
-                return false;
+                false
             }
 
             _ => {
@@ -126,51 +226,43 @@ impl VM {
                     self.cause_to_string(exception_code)
                 );
                 println!("Trap Value (mtval): {:#x}", tval);
-                return false;
+                false
             }
         }
     }
 
     fn handle_interrupt(&mut self, cause: u64) -> bool {
-        let interrupt_type = cause & 0xfff;
+        let interrupt_type = cause & !cause::INTERRUPT_BIT;
 
-        match interrupt_type {
+        match cause {
+            // The emulated M-mode handler forwards to S-mode the way an SBI implementation
+            // would, by raising the S-mode interrupt in mip directly.
+            // MTIP and MSIP follow the CLINT, so the handler acknowledges them there: it
+            // pushes mtimecmp out of reach and clears msip, as clearing the mip bits alone
+            // would see them raised again on the next step.
             cause::MACHINE_TIMER_INTERRUPT => {
-                let mut mip = self.csrs.read(csr::MIP, self.privilege_level).unwrap();
-                mip |= 1 << (cause::SUPERVISOR_SOFTWARE_INTERRUPT & 0xfff);
-                self.csrs.write(csr::MIP, mip, self.privilege_level);
-
-                mip &= !(1 << (cause::MACHINE_TIMER_INTERRUPT & 0xfff));
-                self.csrs.write(csr::MIP, mip, self.privilege_level);
+                self.csrs.mip |= 1 << (cause::SUPERVISOR_SOFTWARE_INTERRUPT & 0xfff);
+                self.clint.borrow_mut().mtimecmp = u64::MAX;
             }
 
             cause::MACHINE_EXTERNAL_INTERRUPT => {
-                let mut mip = self.csrs.read(csr::MIP, self.privilege_level).unwrap();
-                mip |= 1 << (cause::SUPERVISOR_EXTERNAL_INTERRUPT & 0xfff);
-                self.csrs.write(csr::MIP, mip, self.privilege_level);
+                self.csrs.mip |= 1 << (cause::SUPERVISOR_EXTERNAL_INTERRUPT & 0xfff);
             }
 
             cause::MACHINE_SOFTWARE_INTERRUPT => {
-                let mut mip = self.csrs.read(csr::MIP, self.privilege_level).unwrap();
-                mip &= !(1 << (cause::MACHINE_SOFTWARE_INTERRUPT & 0xfff));
-                mip |= 1 << (cause::SUPERVISOR_SOFTWARE_INTERRUPT & 0xfff);
-                self.csrs.write(csr::MIP, mip, self.privilege_level);
+                self.clint.borrow_mut().msip = 0;
+                self.csrs.mip |= 1 << (cause::SUPERVISOR_SOFTWARE_INTERRUPT & 0xfff);
             }
 
-            cause::SUPERVISOR_TIMER_INTERRUPT => {
-                let mut sip = self.csrs.read(csr::SIP, self.privilege_level).unwrap();
-                sip |= 1 << interrupt_type;
-                self.csrs.write(csr::SIP, sip, self.privilege_level);
+            cause::SUPERVISOR_TIMER_INTERRUPT
+            | cause::SUPERVISOR_SOFTWARE_INTERRUPT
+            | cause::SUPERVISOR_EXTERNAL_INTERRUPT => {
+                self.csrs.mip |= 1 << interrupt_type;
             }
-            cause::SUPERVISOR_SOFTWARE_INTERRUPT => {
-                let mut sip = self.csrs.read(csr::SIP, self.privilege_level).unwrap();
-                sip |= 1 << interrupt_type;
-                self.csrs.write(csr::SIP, sip, self.privilege_level);
-            }
-            cause::SUPERVISOR_EXTERNAL_INTERRUPT => {
-                let mut sip = self.csrs.read(csr::SIP, self.privilege_level).unwrap();
-                sip |= 1 << interrupt_type;
-                self.csrs.write(csr::SIP, sip, self.privilege_level);
+
+            // Nothing samples the counters without a guest handler, so just acknowledge it.
+            cause::COUNTER_OVERFLOW_INTERRUPT => {
+                self.csrs.mip &= !MIP_LCOFIP;
             }
 
             cause::USER_TIMER_INTERRUPT
@@ -195,7 +287,7 @@ impl VM {
         let code = cause & 0xfff;
 
         if is_interrupt {
-            match code {
+            match cause {
                 cause::USER_SOFTWARE_INTERRUPT => "User Software Interrupt",
                 cause::SUPERVISOR_SOFTWARE_INTERRUPT => "Supervisor Software Interrupt",
                 cause::MACHINE_SOFTWARE_INTERRUPT => "Machine Software Interrupt",
@@ -205,6 +297,7 @@ impl VM {
                 cause::USER_EXTERNAL_INTERRUPT => "User External Interrupt",
                 cause::SUPERVISOR_EXTERNAL_INTERRUPT => "Supervisor External Interrupt",
                 cause::MACHINE_EXTERNAL_INTERRUPT => "Machine External Interrupt",
+                cause::COUNTER_OVERFLOW_INTERRUPT => "Counter Overflow Interrupt",
                 _ => "Unknown Interrupt",
             }
         } else {
@@ -228,3 +321,154 @@ impl VM {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plic::MIP_SEIP;
+
+    #[test]
+    fn test_delegated_exception_enters_stvec() {
+        let mut vm = VM::new();
+        vm.csrs
+            .write(csr::MEDELEG, 1 << cause::ECALL_FROM_U_MODE, 3);
+        vm.csrs.write(csr::STVEC, 0x8000_1000, 3);
+        vm.csrs.write(csr::MSTATUS, MSTATUS_SIE, 3);
+        vm.privilege_level = 0;
+        vm.pc = 0x8000_0040;
+
+        assert!(vm.handle_trap(cause::ECALL_FROM_U_MODE, 0));
+
+        let mstatus = vm.csrs.read(csr::MSTATUS, 3).unwrap();
+        assert_eq!(vm.pc, 0x8000_1000);
+        assert_eq!(vm.privilege_level, 1);
+        assert_eq!(vm.csrs.read(csr::SEPC, 3), Some(0x8000_0040));
+        assert_eq!(vm.csrs.read(csr::SCAUSE, 3), Some(cause::ECALL_FROM_U_MODE));
+        assert_eq!(
+            mstatus & (MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP),
+            MSTATUS_SPIE
+        );
+    }
+
+    #[test]
+    fn test_undelegated_interrupt_uses_vectored_mtvec() {
+        let mut vm = VM::new();
+        vm.csrs
+            .write(csr::MTVEC, 0x8000_2000 | TVEC_MODE_VECTORED, 3);
+        vm.csrs.write(csr::MSTATUS, MSTATUS_MIE, 3);
+        vm.privilege_level = 1;
+        vm.pc = 0x8000_0100;
+
+        assert!(vm.handle_trap(cause::MACHINE_TIMER_INTERRUPT, 0));
+
+        let mstatus = vm.csrs.read(csr::MSTATUS, 3).unwrap();
+        assert_eq!(vm.pc, 0x8000_2000 + 4 * 7);
+        assert_eq!(vm.privilege_level, 3);
+        assert_eq!(vm.csrs.read(csr::MEPC, 3), Some(0x8000_0100));
+        assert_eq!(mstatus & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MPIE);
+        assert_eq!((mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT, 1);
+    }
+
+    #[test]
+    fn test_pending_interrupt_priority_and_enables() {
+        let mut vm = VM::new();
+        let mtip = 1 << (cause::MACHINE_TIMER_INTERRUPT & !cause::INTERRUPT_BIT);
+        let msip = 1 << (cause::MACHINE_SOFTWARE_INTERRUPT & !cause::INTERRUPT_BIT);
+        let stip = 1 << (cause::SUPERVISOR_TIMER_INTERRUPT & !cause::INTERRUPT_BIT);
+        vm.csrs.write(csr::MIE, mtip | msip | stip, 3);
+        vm.csrs.mip = mtip | msip | stip;
+        vm.csrs.write(csr::MIDELEG, stip, 3);
+
+        // M-mode with MIE clear masks everything.
+        assert_eq!(vm.pending_interrupt(), None);
+
+        vm.csrs.write(csr::MSTATUS, MSTATUS_MIE, 3);
+        assert_eq!(
+            vm.pending_interrupt(),
+            Some(cause::MACHINE_SOFTWARE_INTERRUPT)
+        );
+
+        // Interrupts to M-mode are always enabled from S-mode, and win over S-mode ones.
+        vm.csrs.write(csr::MSTATUS, 0, 3);
+        vm.privilege_level = 1;
+        assert_eq!(
+            vm.pending_interrupt(),
+            Some(cause::MACHINE_SOFTWARE_INTERRUPT)
+        );
+
+        vm.csrs.mip = stip;
+        assert_eq!(vm.pending_interrupt(), None);
+        vm.csrs.write(csr::MSTATUS, MSTATUS_SIE, 3);
+        assert_eq!(
+            vm.pending_interrupt(),
+            Some(cause::SUPERVISOR_TIMER_INTERRUPT)
+        );
+
+        vm.csrs.write(csr::MSTATUS, 0, 3);
+        vm.privilege_level = 0;
+        assert_eq!(
+            vm.pending_interrupt(),
+            Some(cause::SUPERVISOR_TIMER_INTERRUPT)
+        );
+    }
+
+    #[test]
+    fn test_exceptions_from_m_mode_are_never_delegated() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MEDELEG, u64::MAX, 3);
+        vm.csrs.write(csr::MTVEC, 0x8000_3000, 3);
+        vm.csrs.write(csr::STVEC, 0x8000_4000, 3);
+        vm.pc = 0x8000_0200;
+
+        assert!(vm.handle_trap(cause::ILLEGAL_INSTRUCTION, 0xdead));
+
+        assert_eq!(vm.pc, 0x8000_3000);
+        assert_eq!(vm.privilege_level, 3);
+        assert_eq!(vm.csrs.read(csr::MTVAL, 3), Some(0xdead));
+    }
+
+    #[test]
+    fn test_halt_reports_the_cause_of_the_mode_trapped_into() {
+        let mut vm = VM::new();
+        // A stale scause from an earlier exit ecall must not turn an M-mode trap into a
+        // clean exit.
+        vm.csrs.write(csr::SCAUSE, cause::ECALL_FROM_U_MODE, 3);
+        vm.registers[abi::A7 as usize] = 93;
+
+        assert!(!vm.handle_trap(cause::ILLEGAL_INSTRUCTION, 0));
+        assert_eq!(vm.trap_level, 3);
+        assert!(vm
+            .halt_result()
+            .unwrap_err()
+            .contains("Illegal Instruction"));
+    }
+
+    #[test]
+    fn test_emulated_timer_handler_acknowledges_the_clint() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MIE, 1 << 7, 3);
+        vm.csrs.write(csr::MSTATUS, MSTATUS_MIE, 3);
+        vm.clint.borrow_mut().mtimecmp = 0;
+
+        vm.tick_devices();
+        let cause = vm.pending_interrupt().unwrap();
+        assert!(vm.handle_trap(cause, 0));
+        assert_eq!(vm.clint.borrow().mtimecmp, u64::MAX);
+        vm.tick_devices();
+        assert_eq!(vm.pending_interrupt(), None);
+    }
+
+    #[test]
+    fn test_software_seip_survives_device_ticks() {
+        let mut vm = VM::new();
+        vm.csrs.write(csr::MIP, MIP_SEIP, 3);
+        vm.tick_devices();
+        assert_eq!(vm.csrs.read(csr::MIP, 3), Some(MIP_SEIP));
+
+        // Clearing the software bit leaves whatever the PLIC drives.
+        vm.csrs.write(csr::MIP, 0, 3);
+        vm.csrs.seip_line = true;
+        assert_eq!(vm.csrs.read(csr::MIP, 3), Some(MIP_SEIP));
+        assert_eq!(vm.csrs.mip & MIP_SEIP, 0);
+    }
+}
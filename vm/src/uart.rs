@@ -0,0 +1,393 @@
+use crate::bus::Device;
+use bincode::{Decode, Encode};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+pub const UART_BASE_ADDRESS: u64 = 0x1000_0000;
+pub const UART_SIZE: u64 = 0x100;
+
+/// PLIC source wired to the UART, as on QEMU's virt machine.
+pub const UART_IRQ: u32 = 10;
+
+const RBR_THR_DLL: u64 = 0;
+const IER_DLM: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_RX_AVAILABLE: u8 = 0x01;
+const IER_THR_EMPTY: u8 = 0x02;
+const IER_MASK: u8 = 0x0F;
+
+const IIR_NO_INTERRUPT: u8 = 0x01;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+const FCR_FIFO_ENABLE: u8 = 0x01;
+const FCR_CLEAR_RX: u8 = 0x02;
+
+const LCR_DLAB: u8 = 0x80;
+
+const MCR_LOOPBACK: u8 = 0x10;
+const MCR_MASK: u8 = 0x1F;
+
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_OVERRUN: u8 = 0x02;
+const LSR_THR_EMPTY: u8 = 0x20;
+const LSR_TRANSMITTER_EMPTY: u8 = 0x40;
+
+/// Depth of the receive FIFO when FIFOs are enabled through FCR.
+const FIFO_DEPTH: usize = 16;
+
+/// The register and FIFO state of a `Uart`, as saved in a snapshot. The host input and
+/// output are not part of it.
+#[derive(Encode, Decode)]
+pub struct UartState {
+    rx_fifo: Vec<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr_errors: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    thr_empty_pending: bool,
+}
+
+/// An NS16550A-compatible UART. Transmitted bytes are written straight to the host output,
+/// so the transmitter is always empty, and received bytes come from an optional host input
+/// channel that is drained without blocking.
+pub struct Uart {
+    rx_fifo: VecDeque<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr_errors: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    thr_empty_pending: bool,
+    input: Option<Receiver<u8>>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+}
+
+impl Uart {
+    pub fn new(output: Box<dyn Write + Send>) -> Self {
+        Self {
+            rx_fifo: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            lsr_errors: 0,
+            scr: 0,
+            dll: 0,
+            dlm: 0,
+            thr_empty_pending: false,
+            input: None,
+            output,
+        }
+    }
+
+    /// Feeds host stdin to the receiver. A background thread does the blocking reads so the
+    /// guest never stalls waiting for input.
+    pub fn connect_stdin(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut byte = [0u8; 1];
+            while let Ok(1) = stdin.read(&mut byte) {
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+        self.input = Some(receiver);
+    }
+
+    pub fn save_state(&self) -> UartState {
+        UartState {
+            rx_fifo: self.rx_fifo.iter().copied().collect(),
+            ier: self.ier,
+            fcr: self.fcr,
+            lcr: self.lcr,
+            mcr: self.mcr,
+            lsr_errors: self.lsr_errors,
+            scr: self.scr,
+            dll: self.dll,
+            dlm: self.dlm,
+            thr_empty_pending: self.thr_empty_pending,
+        }
+    }
+
+    pub fn restore_state(&mut self, state: UartState) {
+        self.rx_fifo = state.rx_fifo.into();
+        self.ier = state.ier;
+        self.fcr = state.fcr;
+        self.lcr = state.lcr;
+        self.mcr = state.mcr;
+        self.lsr_errors = state.lsr_errors;
+        self.scr = state.scr;
+        self.dll = state.dll;
+        self.dlm = state.dlm;
+        self.thr_empty_pending = state.thr_empty_pending;
+    }
+
+    /// Queues a byte as if it had arrived on the serial line.
+    pub fn receive(&mut self, byte: u8) {
+        if self.rx_fifo.len() >= self.rx_capacity() {
+            self.lsr_errors |= LSR_OVERRUN;
+            return;
+        }
+        self.rx_fifo.push_back(byte);
+    }
+
+    /// How many bytes the receiver holds: the whole FIFO, or a single holding register while
+    /// the FIFO is disabled.
+    fn rx_capacity(&self) -> usize {
+        if self.fcr & FCR_FIFO_ENABLE != 0 {
+            FIFO_DEPTH
+        } else {
+            1
+        }
+    }
+
+    /// Moves any host input that has arrived into the receiver. Bytes that do not fit stay in
+    /// the channel until the guest has read what is already there.
+    fn poll_input(&mut self) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            return;
+        }
+        let Some(input) = &self.input else {
+            return;
+        };
+        let mut received = Vec::new();
+        let capacity = self.rx_capacity().saturating_sub(self.rx_fifo.len());
+        while received.len() < capacity {
+            match input.try_recv() {
+                Ok(byte) => received.push(byte),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.input = None;
+                    break;
+                }
+            }
+        }
+        for byte in received {
+            self.receive(byte);
+        }
+    }
+
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RX_AVAILABLE != 0 && !self.rx_fifo.is_empty() {
+            IIR_RX_AVAILABLE
+        } else if self.ier & IER_THR_EMPTY != 0 && self.thr_empty_pending {
+            IIR_THR_EMPTY
+        } else {
+            IIR_NO_INTERRUPT
+        }
+    }
+
+    fn lsr(&self) -> u8 {
+        let mut lsr = LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY | self.lsr_errors;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LSR_DATA_READY;
+        }
+        lsr
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            self.receive(byte);
+        } else {
+            let _ = self.output.write_all(&[byte]);
+            let _ = self.output.flush();
+        }
+        self.thr_empty_pending = true;
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64, _size: u64) -> Option<u64> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            RBR_THR_DLL if dlab => self.dll,
+            RBR_THR_DLL => self.rx_fifo.pop_front().unwrap_or(0),
+            IER_DLM if dlab => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => {
+                let id = self.interrupt_id();
+                if id == IIR_THR_EMPTY {
+                    self.thr_empty_pending = false;
+                }
+                let fifo_bits = if self.fcr & FCR_FIFO_ENABLE != 0 {
+                    IIR_FIFO_ENABLED
+                } else {
+                    0
+                };
+                id | fifo_bits
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let lsr = self.lsr();
+                self.lsr_errors = 0;
+                lsr
+            }
+            MSR => 0,
+            SCR => self.scr,
+            _ => 0,
+        };
+        Some(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, _size: u64, value: u64) -> bool {
+        let value = value as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            RBR_THR_DLL if dlab => self.dll = value,
+            RBR_THR_DLL => self.transmit(value),
+            IER_DLM if dlab => self.dlm = value,
+            IER_DLM => {
+                let enabling_thr_empty =
+                    value & IER_THR_EMPTY != 0 && self.ier & IER_THR_EMPTY == 0;
+                self.ier = value & IER_MASK;
+                if enabling_thr_empty {
+                    self.thr_empty_pending = true;
+                }
+            }
+            IIR_FCR => {
+                if value & FCR_CLEAR_RX != 0 {
+                    self.rx_fifo.clear();
+                }
+                self.fcr = value & FCR_FIFO_ENABLE;
+            }
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & MCR_MASK,
+            SCR => self.scr = value,
+            _ => {}
+        }
+        true
+    }
+
+    fn tick(&mut self, _ram: &mut [u8]) {
+        self.poll_input();
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.interrupt_id() != IIR_NO_INTERRUPT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transmit_and_divisor_latch() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut uart = Uart::new(Box::new(SharedOutput(output.clone())));
+
+        uart.write(LCR, 1, LCR_DLAB as u64);
+        uart.write(RBR_THR_DLL, 1, 0x03);
+        uart.write(IER_DLM, 1, 0x00);
+        uart.write(LCR, 1, 0x03);
+        uart.write(RBR_THR_DLL, 1, b'h' as u64);
+        uart.write(RBR_THR_DLL, 1, b'i' as u64);
+
+        assert_eq!(output.lock().unwrap().as_slice(), b"hi");
+        assert_eq!(uart.dll, 0x03);
+        assert_eq!(
+            uart.read(LSR, 1),
+            Some((LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY) as u64)
+        );
+    }
+
+    #[test]
+    fn test_receive_raises_interrupt_until_drained() {
+        let mut uart = Uart::new(Box::new(io::sink()));
+        uart.write(IIR_FCR, 1, FCR_FIFO_ENABLE as u64);
+        uart.write(IER_DLM, 1, IER_RX_AVAILABLE as u64);
+        assert!(!uart.interrupt_pending());
+
+        uart.receive(b'a');
+        uart.receive(b'b');
+        assert!(uart.interrupt_pending());
+        assert_eq!(
+            uart.read(IIR_FCR, 1),
+            Some((IIR_RX_AVAILABLE | IIR_FIFO_ENABLED) as u64)
+        );
+        assert_eq!(
+            uart.read(LSR, 1).unwrap() as u8 & LSR_DATA_READY,
+            LSR_DATA_READY
+        );
+
+        assert_eq!(uart.read(RBR_THR_DLL, 1), Some(b'a' as u64));
+        assert_eq!(uart.read(RBR_THR_DLL, 1), Some(b'b' as u64));
+        assert!(!uart.interrupt_pending());
+    }
+
+    #[test]
+    fn test_host_input_waits_while_the_fifo_is_disabled() {
+        let mut uart = Uart::new(Box::new(io::sink()));
+        let (sender, receiver) = mpsc::channel();
+        uart.input = Some(receiver);
+        for &byte in b"abc" {
+            sender.send(byte).unwrap();
+        }
+
+        for &byte in b"abc" {
+            uart.poll_input();
+            assert_eq!(uart.read(RBR_THR_DLL, 1), Some(byte as u64));
+        }
+        assert_eq!(uart.read(LSR, 1).unwrap() as u8 & LSR_OVERRUN, 0);
+    }
+
+    #[test]
+    fn test_thr_empty_interrupt_clears_on_iir_read() {
+        let mut uart = Uart::new(Box::new(io::sink()));
+        uart.write(IER_DLM, 1, IER_THR_EMPTY as u64);
+        assert!(uart.interrupt_pending());
+        assert_eq!(uart.read(IIR_FCR, 1), Some(IIR_THR_EMPTY as u64));
+        assert!(!uart.interrupt_pending());
+
+        uart.write(RBR_THR_DLL, 1, b'x' as u64);
+        assert!(uart.interrupt_pending());
+    }
+
+    #[test]
+    fn test_loopback_echoes_transmitted_bytes() {
+        let mut uart = Uart::new(Box::new(io::sink()));
+        uart.write(MCR, 1, MCR_LOOPBACK as u64);
+        uart.write(RBR_THR_DLL, 1, b'z' as u64);
+        assert_eq!(uart.read(RBR_THR_DLL, 1), Some(b'z' as u64));
+    }
+}
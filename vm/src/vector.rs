@@ -0,0 +1,766 @@
+use crate::isa::Extension;
+use crate::mmu::TranslateFault;
+use crate::pmp::AccessType;
+use crate::VM;
+use riscv_core::vector::{self, *};
+use riscv_core::{cause, opcodes};
+
+/// The vector register width used unless configured otherwise, in bits.
+pub const DEFAULT_VLEN: u64 = 128;
+/// The bounds on VLEN: the V extension needs at least 128 bits, and the spec allows at most
+/// 65536.
+pub const MIN_VLEN: u64 = 128;
+pub const MAX_VLEN: u64 = 65536;
+/// The widest element the vector unit supports, in bits.
+const ELEN: u64 = 64;
+
+/// Whether `vlen` is a vector register width the VM can be configured with.
+pub fn valid_vlen(vlen: u64) -> bool {
+    vlen.is_power_of_two() && (MIN_VLEN..=MAX_VLEN).contains(&vlen)
+}
+
+/// Whether a LOAD-FP or STORE-FP instruction is a vector access rather than a scalar FP one.
+pub fn is_vector_access(inst: u32) -> bool {
+    matches!(
+        (inst >> 12) & 0x7,
+        vector::WIDTH_8 | vector::WIDTH_16 | vector::WIDTH_32 | vector::WIDTH_64
+    )
+}
+
+/// The element width and register grouping selected by a supported `vtype`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct VType {
+    /// SEW, in bits.
+    sew: u64,
+    /// log2 of LMUL, from -3 for mf8 to 3 for m8.
+    lmul_log2: i64,
+}
+
+impl VType {
+    /// Decodes `vtype`, or returns `None` if it sets `vill`, a reserved bit, or a setting
+    /// the hart does not support.
+    fn decode(vtype: u64) -> Option<Self> {
+        if vtype & !(VTYPE_VLMUL | VTYPE_VSEW | VTYPE_VTA | VTYPE_VMA) != 0 {
+            return None;
+        }
+        let sew = 8 << ((vtype & VTYPE_VSEW) >> VTYPE_VSEW_SHIFT);
+        let lmul_log2 = match vtype & VTYPE_VLMUL {
+            lmul @ 0..=3 => lmul as i64,
+            5 => -3,
+            6 => -2,
+            7 => -1,
+            _ => return None,
+        };
+        // A fractional group must still hold at least one element of every width up to
+        // ELEN * LMUL.
+        if sew > ELEN || (lmul_log2 < 0 && sew > ELEN >> -lmul_log2) {
+            return None;
+        }
+        Some(Self { sew, lmul_log2 })
+    }
+
+    /// VLMAX: the number of elements in a register group.
+    fn vlmax(self, vlen: u64) -> u64 {
+        let elements_per_register = vlen / self.sew;
+        if self.lmul_log2 >= 0 {
+            elements_per_register << self.lmul_log2
+        } else {
+            elements_per_register >> -self.lmul_log2
+        }
+    }
+}
+
+/// The fields shared by every `OP_V` arithmetic instruction.
+struct VectorOp {
+    funct3: u32,
+    funct6: u32,
+    /// Whether the instruction only writes elements whose bit in `v0` is set.
+    masked: bool,
+    vd: usize,
+    /// vs1, rs1 or the 5-bit immediate, depending on `funct3`.
+    vs1: usize,
+    vs2: usize,
+    vtype: VType,
+}
+
+impl VectorOp {
+    fn decode(inst: u32, vtype: VType) -> Self {
+        Self {
+            funct3: (inst >> 12) & 0x7,
+            funct6: inst >> 26,
+            masked: (inst >> 25) & 1 == 0,
+            vd: ((inst >> 7) & 0x1F) as usize,
+            vs1: ((inst >> 15) & 0x1F) as usize,
+            vs2: ((inst >> 20) & 0x1F) as usize,
+            vtype,
+        }
+    }
+
+    fn sew(&self) -> u64 {
+        self.vtype.sew
+    }
+
+    /// Whether `reg` can start a register group at the current LMUL.
+    fn aligned(&self, reg: usize) -> bool {
+        group_aligned(reg, self.vtype.lmul_log2)
+    }
+}
+
+/// Whether `reg` can start a group of 2^`emul_log2` registers. A fractional group always
+/// fits in one register.
+fn group_aligned(reg: usize, emul_log2: i64) -> bool {
+    emul_log2 <= 0 || reg.is_multiple_of(1 << emul_log2)
+}
+
+fn truncate(value: u64, sew: u64) -> u64 {
+    if sew == 64 {
+        value
+    } else {
+        value & ((1 << sew) - 1)
+    }
+}
+
+fn sext(value: u64, sew: u64) -> i64 {
+    ((value << (64 - sew)) as i64) >> (64 - sew)
+}
+
+/// Whether the OPI operation `funct6` has a form with operand category `funct3`.
+fn opi_form_exists(funct6: u32, funct3: u32) -> bool {
+    match funct6 {
+        VADD | VAND | VOR | VXOR | VMERGE_VMV | VMSEQ | VMSNE | VMSLEU | VMSLE | VSLL | VSRL
+        | VSRA => true,
+        VSUB | VMINU | VMIN | VMAXU | VMAX | VMSLTU | VMSLT => funct3 != OPIVI,
+        VRSUB | VMSGTU | VMSGT => funct3 != OPIVV,
+        _ => false,
+    }
+}
+
+fn is_compare(funct6: u32) -> bool {
+    (VMSEQ..=VMSGT).contains(&funct6)
+}
+
+/// The result of the element-wise OPI operation `funct6` on `a` (from vs2) and `b`.
+fn opi_result(funct6: u32, a: u64, b: u64, sew: u64) -> u64 {
+    let shamt = b & (sew - 1);
+    let value = match funct6 {
+        VADD => a.wrapping_add(b),
+        VSUB => a.wrapping_sub(b),
+        VRSUB => b.wrapping_sub(a),
+        VMINU => a.min(b),
+        VMIN if sext(a, sew) <= sext(b, sew) => a,
+        VMAXU => a.max(b),
+        VMAX if sext(a, sew) >= sext(b, sew) => a,
+        VMIN | VMAX => b,
+        VAND => a & b,
+        VOR => a | b,
+        VXOR => a ^ b,
+        VSLL => a << shamt,
+        VSRL => a >> shamt,
+        VSRA => (sext(a, sew) >> shamt) as u64,
+        _ => unreachable!(),
+    };
+    truncate(value, sew)
+}
+
+fn compare(funct6: u32, a: u64, b: u64, sew: u64) -> bool {
+    let (signed_a, signed_b) = (sext(a, sew), sext(b, sew));
+    match funct6 {
+        VMSEQ => a == b,
+        VMSNE => a != b,
+        VMSLTU => a < b,
+        VMSLT => signed_a < signed_b,
+        VMSLEU => a <= b,
+        VMSLE => signed_a <= signed_b,
+        VMSGTU => a > b,
+        VMSGT => signed_a > signed_b,
+        _ => unreachable!(),
+    }
+}
+
+fn is_multiply_add(funct6: u32) -> bool {
+    matches!(funct6, VMADD | VNMSUB | VMACC | VNMSAC)
+}
+
+/// The result of a multiply, divide or multiply-add on `a` (from vs2), `b` (from vs1 or
+/// rs1) and, for multiply-adds, `d` (from vd).
+fn multiply_divide_result(funct6: u32, a: u64, b: u64, d: u64, sew: u64) -> u64 {
+    let (signed_a, signed_b) = (sext(a, sew) as i128, sext(b, sew) as i128);
+    let value = match funct6 {
+        VMUL => a.wrapping_mul(b),
+        VMULH => ((signed_a * signed_b) >> sew) as u64,
+        VMULHU => ((a as u128 * b as u128) >> sew) as u64,
+        VMULHSU => ((signed_a * b as i128) >> sew) as u64,
+        VDIVU | VDIV if b == 0 => u64::MAX,
+        VREMU | VREM if b == 0 => a,
+        VDIVU => a / b,
+        VREMU => a % b,
+        VDIV => sext(a, sew).wrapping_div(sext(b, sew)) as u64,
+        VREM => sext(a, sew).wrapping_rem(sext(b, sew)) as u64,
+        VMACC => d.wrapping_add(b.wrapping_mul(a)),
+        VNMSAC => d.wrapping_sub(b.wrapping_mul(a)),
+        VMADD => b.wrapping_mul(d).wrapping_add(a),
+        VNMSUB => a.wrapping_sub(b.wrapping_mul(d)),
+        _ => unreachable!(),
+    };
+    truncate(value, sew)
+}
+
+impl VM {
+    fn vlenb(&self) -> usize {
+        (self.config.vlen / 8) as usize
+    }
+
+    /// Reads element `index` of a register group of `eew`-bit elements starting at `reg`.
+    fn read_element(&self, reg: usize, eew: u64, index: u64) -> u64 {
+        let size = (eew / 8) as usize;
+        let start = reg * self.vlenb() + index as usize * size;
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.vregs[start..start + size]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn write_element(&mut self, reg: usize, eew: u64, index: u64, value: u64) {
+        let size = (eew / 8) as usize;
+        let start = reg * self.vlenb() + index as usize * size;
+        self.vregs[start..start + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    fn mask_bit(&self, reg: usize, index: u64) -> bool {
+        let byte = self.vregs[reg * self.vlenb() + (index / 8) as usize];
+        (byte >> (index % 8)) & 1 != 0
+    }
+
+    fn set_mask_bit(&mut self, reg: usize, index: u64, bit: bool) {
+        let offset = reg * self.vlenb() + (index / 8) as usize;
+        let mask = 1 << (index % 8);
+        if bit {
+            self.vregs[offset] |= mask;
+        } else {
+            self.vregs[offset] &= !mask;
+        }
+    }
+
+    /// Whether element `index` takes part in an instruction: it is unmasked, or its bit in
+    /// `v0` is set. Inactive elements are left undisturbed.
+    fn element_active(&self, masked: bool, index: u64) -> bool {
+        !masked || self.mask_bit(0, index)
+    }
+
+    fn write_int_register(&mut self, rd: usize, value: u64) {
+        if rd > 0 {
+            self.registers[rd] = value;
+        }
+    }
+
+    /// The current `vtype`, if vector instructions other than `vset{i}vl{i}` may execute:
+    /// V is enabled and `vtype` is not `vill`.
+    fn current_vtype(&self) -> Option<VType> {
+        if !self.extension_enabled(Extension::V) || !self.csrs.vector_enabled() {
+            return None;
+        }
+        VType::decode(self.csrs.vtype)
+    }
+
+    /// Executes a vector load or store. Unit-stride and strided accesses move `vl` elements
+    /// (or fewer when masked); `vlm.v` and `vsm.v` move the first `vl` bits of a mask
+    /// register. A fault leaves the element's index in `vstart` so the access can resume
+    /// from there.
+    pub(crate) fn execute_vector_access(&mut self, inst: u32, next_pc: u64) -> bool {
+        let Some(vtype) = self.current_vtype() else {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        };
+        let is_store = inst & 0x7F == opcodes::OP_STORE_FP;
+        let vd = ((inst >> 7) & 0x1F) as usize;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = (inst >> 20) & 0x1F;
+        let masked = (inst >> 25) & 1 == 0;
+        let mop = (inst >> 26) & 0b11;
+        // Segment accesses (nf) and the reserved mew bit are not supported.
+        let nf_mew = inst >> 28;
+
+        let eew: u64 = match (inst >> 12) & 0x7 {
+            vector::WIDTH_8 => 8,
+            vector::WIDTH_16 => 16,
+            vector::WIDTH_32 => 32,
+            _ => 64,
+        };
+        let emul_log2 =
+            eew.trailing_zeros() as i64 - vtype.sew.trailing_zeros() as i64 + vtype.lmul_log2;
+        let vl = self.csrs.vl;
+        let (stride, count, emul_log2) = match (mop, rs2) {
+            (MOP_UNIT_STRIDE, 0) => (eew / 8, vl, emul_log2),
+            (MOP_UNIT_STRIDE, UMOP_MASK) if eew == 8 && !masked => (1, vl.div_ceil(8), 0),
+            (MOP_STRIDED, _) => (self.registers[rs2 as usize], vl, emul_log2),
+            _ => return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64),
+        };
+        if nf_mew != 0
+            || !(-3..=3).contains(&emul_log2)
+            || !group_aligned(vd, emul_log2)
+            || (masked && !is_store && vd == 0)
+        {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+
+        let access = if is_store {
+            AccessType::Write
+        } else {
+            AccessType::Read
+        };
+        let size = eew / 8;
+        let base = self.registers[rs1];
+        for index in self.csrs.vstart..count {
+            if !self.element_active(masked, index) {
+                continue;
+            }
+            self.csrs.vstart = index;
+            let vaddr = base.wrapping_add(index.wrapping_mul(stride));
+            if self.debug.check_watchpoints(vaddr, size, is_store) {
+                return true;
+            }
+            let paddr = match self.translate_access(vaddr, size, access) {
+                Ok(addr) => addr,
+                Err(fault) => return self.handle_trap(fault.cause(access), fault.vaddr()),
+            };
+
+            if is_store {
+                let value = self.read_element(vd, eew, index);
+                if !self.write_physical(paddr, size, value) {
+                    return self
+                        .handle_trap(TranslateFault::AccessFault(vaddr).cause(access), vaddr);
+                }
+                self.invalidate_reservation(paddr, size);
+            } else {
+                let Some(value) = self.read_physical(paddr, size, AccessType::Read) else {
+                    return self
+                        .handle_trap(TranslateFault::AccessFault(vaddr).cause(access), vaddr);
+                };
+                self.write_element(vd, eew, index, value);
+            }
+        }
+
+        self.csrs.vstart = 0;
+        self.csrs.mark_vector_dirty();
+        self.pc = next_pc;
+        true
+    }
+
+    /// Executes an `OP_V` instruction: `vset{i}vl{i}` or an integer arithmetic, compare,
+    /// mask or reduction instruction.
+    pub(crate) fn execute_vector(&mut self, inst: u32, next_pc: u64) -> bool {
+        if (inst >> 12) & 0x7 == OPCFG {
+            return self.execute_vsetvl(inst, next_pc);
+        }
+        // The spec lets arithmetic instructions reject a non-zero vstart, which only a
+        // debugger or a trap handler writing vstart directly can leave behind.
+        let Some(vtype) = self.current_vtype().filter(|_| self.csrs.vstart == 0) else {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        };
+
+        let op = VectorOp::decode(inst, vtype);
+        let executed = match op.funct3 {
+            OPIVV | OPIVX | OPIVI => self.execute_opi(&op),
+            OPMVV | OPMVX => self.execute_opm(&op),
+            _ => false,
+        };
+        if !executed {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+        self.csrs.mark_vector_dirty();
+        self.pc = next_pc;
+        true
+    }
+
+    /// Executes `vsetvli`, `vsetivli` or `vsetvl`, which set `vtype` and pick `vl` from the
+    /// application vector length (AVL). An unsupported `vtype` sets `vill` and a `vl` of zero.
+    fn execute_vsetvl(&mut self, inst: u32, next_pc: u64) -> bool {
+        if !self.extension_enabled(Extension::V) || !self.csrs.vector_enabled() {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        }
+        let rd = ((inst >> 7) & 0x1F) as usize;
+        let rs1 = ((inst >> 15) & 0x1F) as usize;
+        let rs2 = ((inst >> 20) & 0x1F) as usize;
+
+        let (vtype, avl) = if inst >> 31 == 0 {
+            ((inst >> 20) as u64 & 0x7FF, None)
+        } else if inst >> 30 == 0b11 {
+            ((inst >> 20) as u64 & 0x3FF, Some(rs1 as u64))
+        } else if (inst >> 25) & 0x3F == 0 {
+            (self.registers[rs2], None)
+        } else {
+            return self.handle_trap(cause::ILLEGAL_INSTRUCTION, inst as u64);
+        };
+        // With rs1 = x0 the AVL is VLMAX if rd is written, or else the current vl.
+        let avl = match avl {
+            Some(avl) => avl,
+            None if rs1 != 0 => self.registers[rs1],
+            None if rd != 0 => u64::MAX,
+            None => self.csrs.vl,
+        };
+
+        match VType::decode(vtype) {
+            Some(decoded) => {
+                self.csrs.vtype = vtype;
+                self.csrs.vl = avl.min(decoded.vlmax(self.config.vlen));
+            }
+            None => {
+                self.csrs.vtype = VTYPE_VILL;
+                self.csrs.vl = 0;
+            }
+        }
+        self.csrs.vstart = 0;
+        self.csrs.mark_vector_dirty();
+        self.write_int_register(rd, self.csrs.vl);
+        self.pc = next_pc;
+        true
+    }
+
+    /// The second operand of element `index`: from vs1, rs1, or the sign-extended immediate.
+    fn operand(&self, op: &VectorOp, index: u64) -> u64 {
+        let sew = op.sew();
+        match op.funct3 {
+            OPIVV | OPMVV => self.read_element(op.vs1, sew, index),
+            OPIVX | OPMVX => truncate(self.registers[op.vs1], sew),
+            // Shifts take their immediate unsigned.
+            _ if matches!(op.funct6, VSLL | VSRL | VSRA) => op.vs1 as u64,
+            _ => truncate(sext(op.vs1 as u64, 5) as u64, sew),
+        }
+    }
+
+    /// Executes an OPIVV, OPIVX or OPIVI instruction. Returns `false` if it is not a valid one.
+    fn execute_opi(&mut self, op: &VectorOp) -> bool {
+        let compare_op = is_compare(op.funct6);
+        if !opi_form_exists(op.funct6, op.funct3)
+            || !op.aligned(op.vs2)
+            || (op.funct3 == OPIVV && !op.aligned(op.vs1))
+            || (!compare_op && !op.aligned(op.vd))
+            // Only a mask result may overwrite the mask it is computed under.
+            || (op.masked && !compare_op && op.vd == 0)
+        {
+            return false;
+        }
+        let sew = op.sew();
+        let vl = self.csrs.vl;
+
+        if op.funct6 == VMERGE_VMV {
+            // Unmasked, this is vmv.v.*, which has no vs2.
+            if !op.masked && op.vs2 != 0 {
+                return false;
+            }
+            for index in 0..vl {
+                let value = if self.element_active(op.masked, index) {
+                    self.operand(op, index)
+                } else {
+                    self.read_element(op.vs2, sew, index)
+                };
+                self.write_element(op.vd, sew, index, value);
+            }
+            return true;
+        }
+
+        for index in 0..vl {
+            if !self.element_active(op.masked, index) {
+                continue;
+            }
+            let a = self.read_element(op.vs2, sew, index);
+            let b = self.operand(op, index);
+            if compare_op {
+                self.set_mask_bit(op.vd, index, compare(op.funct6, a, b, sew));
+            } else {
+                self.write_element(op.vd, sew, index, opi_result(op.funct6, a, b, sew));
+            }
+        }
+        true
+    }
+
+    /// Executes an OPMVV or OPMVX instruction. Returns `false` if it is not a valid one.
+    fn execute_opm(&mut self, op: &VectorOp) -> bool {
+        let is_vv = op.funct3 == OPMVV;
+        match op.funct6 {
+            VREDSUM..=VREDMAX if is_vv => self.execute_reduction(op),
+            VWXUNARY0 => self.execute_wxunary(op),
+            VMUNARY0 if is_vv => self.execute_munary(op),
+            VMANDN..=VMXNOR if is_vv && !op.masked => {
+                for index in 0..self.csrs.vl {
+                    let (a, b) = (self.mask_bit(op.vs2, index), self.mask_bit(op.vs1, index));
+                    let bit = match op.funct6 {
+                        VMANDN => a & !b,
+                        VMAND => a & b,
+                        VMOR => a | b,
+                        VMXOR => a ^ b,
+                        VMORN => a | !b,
+                        VMNAND => !(a & b),
+                        VMNOR => !(a | b),
+                        _ => !(a ^ b),
+                    };
+                    self.set_mask_bit(op.vd, index, bit);
+                }
+                true
+            }
+            VDIVU..=VMULH | VMADD | VNMSUB | VMACC | VNMSAC => {
+                if !op.aligned(op.vd)
+                    || !op.aligned(op.vs2)
+                    || (is_vv && !op.aligned(op.vs1))
+                    || (op.masked && op.vd == 0)
+                {
+                    return false;
+                }
+                let sew = op.sew();
+                for index in 0..self.csrs.vl {
+                    if !self.element_active(op.masked, index) {
+                        continue;
+                    }
+                    let a = self.read_element(op.vs2, sew, index);
+                    let b = self.operand(op, index);
+                    let d = if is_multiply_add(op.funct6) {
+                        self.read_element(op.vd, sew, index)
+                    } else {
+                        0
+                    };
+                    let value = multiply_divide_result(op.funct6, a, b, d, sew);
+                    self.write_element(op.vd, sew, index, value);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Executes `vred*.vs`: folds the active elements of vs2 into element 0 of vs1 and
+    /// writes the result to element 0 of vd.
+    fn execute_reduction(&mut self, op: &VectorOp) -> bool {
+        if !op.aligned(op.vs2) {
+            return false;
+        }
+        // The min and max reductions share their funct6 with vmin, vmax and friends.
+        let opi_funct6 = match op.funct6 {
+            VREDSUM => VADD,
+            VREDAND => VAND,
+            VREDOR => VOR,
+            VREDXOR => VXOR,
+            min_max => min_max,
+        };
+        let sew = op.sew();
+        let vl = self.csrs.vl;
+        if vl == 0 {
+            return true;
+        }
+        let mut accumulator = self.read_element(op.vs1, sew, 0);
+        for index in 0..vl {
+            if self.element_active(op.masked, index) {
+                let element = self.read_element(op.vs2, sew, index);
+                accumulator = opi_result(opi_funct6, accumulator, element, sew);
+            }
+        }
+        self.write_element(op.vd, sew, 0, accumulator);
+        true
+    }
+
+    /// Executes `vmv.x.s`, `vcpop.m` and `vfirst.m` (OPMVV) or `vmv.s.x` (OPMVX).
+    fn execute_wxunary(&mut self, op: &VectorOp) -> bool {
+        let sew = op.sew();
+        let vl = self.csrs.vl;
+        if op.funct3 == OPMVX {
+            if op.vs2 != 0 || op.masked {
+                return false;
+            }
+            if vl > 0 {
+                let value = truncate(self.registers[op.vs1], sew);
+                self.write_element(op.vd, sew, 0, value);
+            }
+            return true;
+        }
+
+        let value = match op.vs1 as u32 {
+            VS1_VMV_X_S if !op.masked => sext(self.read_element(op.vs2, sew, 0), sew) as u64,
+            VS1_VCPOP => (0..vl)
+                .filter(|&index| {
+                    self.element_active(op.masked, index) && self.mask_bit(op.vs2, index)
+                })
+                .count() as u64,
+            VS1_VFIRST => (0..vl)
+                .find(|&index| {
+                    self.element_active(op.masked, index) && self.mask_bit(op.vs2, index)
+                })
+                .unwrap_or(u64::MAX),
+            _ => return false,
+        };
+        self.write_int_register(op.vd, value);
+        true
+    }
+
+    /// Executes `vmsbf.m`, `vmsif.m`, `vmsof.m`, `viota.m` and `vid.v`.
+    fn execute_munary(&mut self, op: &VectorOp) -> bool {
+        let sew = op.sew();
+        let vl = self.csrs.vl;
+        let vs1 = op.vs1 as u32;
+        if (op.masked && op.vd == 0) || (vs1 != VS1_VID && op.vd == op.vs2) {
+            return false;
+        }
+
+        match vs1 {
+            VS1_VMSBF | VS1_VMSIF | VS1_VMSOF => {
+                let mut found = false;
+                for index in 0..vl {
+                    if !self.element_active(op.masked, index) {
+                        continue;
+                    }
+                    let set = self.mask_bit(op.vs2, index);
+                    let bit = match vs1 {
+                        VS1_VMSBF => !found && !set,
+                        VS1_VMSIF => !found,
+                        _ => !found && set,
+                    };
+                    self.set_mask_bit(op.vd, index, bit);
+                    found |= set;
+                }
+            }
+            VS1_VIOTA | VS1_VID => {
+                if !op.aligned(op.vd) || (vs1 == VS1_VID && op.vs2 != 0) {
+                    return false;
+                }
+                let mut count = 0;
+                for index in 0..vl {
+                    if !self.element_active(op.masked, index) {
+                        continue;
+                    }
+                    if vs1 == VS1_VID {
+                        self.write_element(op.vd, sew, index, index);
+                    } else {
+                        self.write_element(op.vd, sew, index, count);
+                        count += self.mask_bit(op.vs2, index) as u64;
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::MSTATUS_VS_SHIFT;
+    use crate::memory::BASE_ADDRESS;
+    use riscv_core::csr;
+
+    const DATA: u64 = BASE_ADDRESS + 0x1000;
+
+    fn vector_vm(program: &[u32]) -> VM {
+        let mut vm = VM::new();
+        for (i, &inst) in program.iter().enumerate() {
+            vm.bus.write(BASE_ADDRESS + 4 * i as u64, 4, inst as u64);
+        }
+        vm.csrs.mstatus |= 1 << MSTATUS_VS_SHIFT;
+        vm.csrs.write(csr::MTVEC, BASE_ADDRESS + 0x800, 3);
+        vm
+    }
+
+    fn run(vm: &mut VM, steps: usize) {
+        for _ in 0..steps {
+            assert_eq!(vm.step(), None);
+        }
+    }
+
+    #[test]
+    fn test_vsetvli_picks_vl_from_avl_and_lmul() {
+        // vsetvli a0, a1, e32, m2, ta, ma
+        // vsetvli a0, zero, e8, mf8, ta, ma
+        // vsetvli a0, zero, e64, mf2, ta, ma  (SEW > ELEN * LMUL sets vill)
+        let mut vm = vector_vm(&[0x0d15f557, 0x0c507557, 0x0df07557]);
+        vm.registers[11] = 100;
+        run(&mut vm, 1);
+        assert_eq!(vm.registers[10], 8);
+        assert_eq!(vm.csrs.read(csr::VTYPE, 0), Some(0xd1));
+        run(&mut vm, 1);
+        assert_eq!(vm.registers[10], 2);
+        run(&mut vm, 1);
+        assert_eq!(vm.registers[10], 0);
+        assert_eq!(vm.csrs.vtype, VTYPE_VILL);
+        assert!(!vm.csrs.write(csr::VL, 4, 3));
+    }
+
+    #[test]
+    fn test_strided_load_add_and_unit_stride_store() {
+        let mut vm = vector_vm(&[
+            0x0d05f557, // vsetvli a0, a1, e32, m1, ta, ma
+            0x0ad66087, // vlse32.v v1, (a2), a3
+            0x0212b157, // vadd.vi v2, v1, 5
+            0x02076127, // vse32.v v2, (a4)
+        ]);
+        vm.registers[11] = 3;
+        vm.registers[12] = DATA;
+        vm.registers[13] = 8;
+        vm.registers[14] = DATA + 0x100;
+        for i in 0..4 {
+            vm.bus.write(DATA + 8 * i, 4, 10 * i + 1);
+        }
+        run(&mut vm, 4);
+        assert_eq!(vm.registers[10], 3);
+        assert_eq!(vm.bus.read(DATA + 0x100, 4), Some(6));
+        assert_eq!(vm.bus.read(DATA + 0x104, 4), Some(16));
+        assert_eq!(vm.bus.read(DATA + 0x108, 4), Some(26));
+        // Elements past vl are not stored.
+        assert_eq!(vm.bus.read(DATA + 0x10C, 4), Some(0));
+    }
+
+    #[test]
+    fn test_compare_and_mask_instructions_find_a_zero_byte() {
+        let mut vm = vector_vm(&[
+            0x0c05f557, // vsetvli a0, a1, e8, m1, ta, ma
+            0x02060087, // vle8.v v1, (a2)
+            0x62103057, // vmseq.vi v0, v1, 0
+            0x4208a6d7, // vfirst.m a3, v0
+            0x5201a157, // vmsif.m v2, v0
+            0x42282757, // vcpop.m a4, v2
+        ]);
+        vm.registers[11] = 16;
+        vm.registers[12] = DATA;
+        vm.bus.write(DATA, 8, u64::from_le_bytes(*b"hello\0xy"));
+        run(&mut vm, 6);
+        assert_eq!(vm.registers[13], 5);
+        assert_eq!(vm.registers[14], 6);
+    }
+
+    #[test]
+    fn test_reduction_and_multiply_add() {
+        let mut vm = vector_vm(&[
+            0x0d85f557, // vsetvli a0, a1, e64, m1, ta, ma
+            0x5e0640d7, // vmv.v.x v1, a2
+            0x5208a157, // vid.v v2
+            0xb626e0d7, // vmacc.vx v1, a3, v2
+            0x420061d7, // vmv.s.x v3, zero
+            0x0211a1d7, // vredsum.vs v3, v1, v3
+            0x42302757, // vmv.x.s a4, v3
+        ]);
+        vm.registers[11] = 2;
+        vm.registers[12] = 7;
+        vm.registers[13] = 3;
+        run(&mut vm, 7);
+        // v1 is [7 + 3 * 0, 7 + 3 * 1].
+        assert_eq!(vm.registers[14], 17);
+    }
+
+    #[test]
+    fn test_vector_instructions_are_illegal_while_vs_is_off() {
+        let mut vm = vector_vm(&[0x0d05f557]);
+        vm.csrs.mstatus &= !crate::csr::MSTATUS_VS;
+        run(&mut vm, 1);
+        assert_eq!(vm.csrs.mcause, cause::ILLEGAL_INSTRUCTION);
+        assert_eq!(vm.csrs.read(csr::VLENB, 3), None);
+    }
+
+    #[test]
+    fn test_load_fault_records_vstart() {
+        let mut vm = vector_vm(&[
+            0x0d05f557, // vsetvli a0, a1, e32, m1, ta, ma
+            0x02066087, // vle32.v v1, (a2)
+        ]);
+        vm.registers[11] = 4;
+        // The third element lies past the end of RAM.
+        vm.registers[12] = BASE_ADDRESS + vm.bus.ram.len() as u64 - 8;
+        run(&mut vm, 2);
+        assert_eq!(vm.csrs.mcause, cause::LOAD_ACCESS_FAULT);
+        assert_eq!(vm.csrs.vstart, 2);
+    }
+}
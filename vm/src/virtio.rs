@@ -0,0 +1,690 @@
+use crate::bus::Device;
+use crate::memory::BASE_ADDRESS;
+use bincode::{Decode, Encode};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const VIRTIO_BLK_BASE_ADDRESS: u64 = 0x1000_1000;
+pub const VIRTIO_MMIO_SIZE: u64 = 0x1000;
+
+/// PLIC source wired to the first virtio-mmio slot, as on QEMU's virt machine.
+pub const VIRTIO_BLK_IRQ: u32 = 1;
+
+// virtio-mmio register offsets (virtio 1.1, section 4.2.2).
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00c;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+const CONFIG: u64 = 0x100;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const MMIO_VERSION: u32 = 2;
+const DEVICE_ID_BLOCK: u32 = 2;
+const VENDOR: u32 = 0x554d_4551; // "QEMU"
+
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_DEVICE_NEEDS_RESET: u32 = 64;
+
+const INTERRUPT_USED_BUFFER: u32 = 1;
+const INTERRUPT_CONFIG_CHANGE: u32 = 2;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const QUEUE_SIZE_MAX: u32 = 128;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const SECTOR_SIZE: u64 = 512;
+const REQUEST_HEADER_SIZE: usize = 16;
+const DEVICE_ID_STRING: &[u8] = b"vm-virtio-blk";
+const DEVICE_ID_LEN: usize = 20;
+
+/// One split virtqueue as configured by the driver. Addresses are guest-physical.
+#[derive(Default, Clone, Encode, Decode)]
+struct Virtqueue {
+    num: u32,
+    ready: bool,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    last_avail_idx: u16,
+}
+
+/// A buffer from a descriptor chain.
+struct Buffer {
+    addr: u64,
+    len: u32,
+    writable: bool,
+}
+
+/// The transport state and disk contents of a `VirtioBlk`, as saved in a snapshot.
+#[derive(Encode, Decode)]
+pub struct VirtioBlkState {
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queue: Virtqueue,
+    interrupt_status: u32,
+    notified: bool,
+    contents: Vec<u8>,
+}
+
+/// A virtio-blk device behind a virtio-mmio (version 2) transport, backed by a host file.
+/// Requests are picked up from the single request queue on the tick after the driver
+/// notifies it, and completion is signalled with a used-buffer interrupt.
+pub struct VirtioBlk {
+    backing: File,
+    read_only: bool,
+    capacity: u64,
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queue: Virtqueue,
+    interrupt_status: u32,
+    notified: bool,
+}
+
+impl VirtioBlk {
+    /// Opens `path` as the disk image. Its length is rounded down to whole sectors.
+    pub fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        let backing = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let capacity = backing.metadata()?.len() / SECTOR_SIZE;
+        Ok(Self {
+            backing,
+            read_only,
+            capacity,
+            status: 0,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queue: Virtqueue::default(),
+            interrupt_status: 0,
+            notified: false,
+        })
+    }
+
+    pub fn save_state(&mut self) -> io::Result<VirtioBlkState> {
+        let mut contents = Vec::new();
+        self.backing.seek(SeekFrom::Start(0))?;
+        self.backing.read_to_end(&mut contents)?;
+        Ok(VirtioBlkState {
+            status: self.status,
+            device_features_sel: self.device_features_sel,
+            driver_features: self.driver_features,
+            driver_features_sel: self.driver_features_sel,
+            queue_sel: self.queue_sel,
+            queue: self.queue.clone(),
+            interrupt_status: self.interrupt_status,
+            notified: self.notified,
+            contents,
+        })
+    }
+
+    /// Restores the transport state and writes the saved contents back to the backing file.
+    /// A read-only disk must already hold the same contents.
+    pub fn restore_state(&mut self, state: VirtioBlkState) -> io::Result<()> {
+        if self.read_only {
+            let mut contents = Vec::new();
+            self.backing.seek(SeekFrom::Start(0))?;
+            self.backing.read_to_end(&mut contents)?;
+            if contents != state.contents {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "read-only disk image differs from the snapshot",
+                ));
+            }
+        } else {
+            self.backing.set_len(0)?;
+            self.backing.seek(SeekFrom::Start(0))?;
+            self.backing.write_all(&state.contents)?;
+        }
+        self.capacity = state.contents.len() as u64 / SECTOR_SIZE;
+        self.status = state.status;
+        self.device_features_sel = state.device_features_sel;
+        self.driver_features = state.driver_features;
+        self.driver_features_sel = state.driver_features_sel;
+        self.queue_sel = state.queue_sel;
+        self.queue = state.queue;
+        self.interrupt_status = state.interrupt_status;
+        self.notified = state.notified;
+        Ok(())
+    }
+
+    fn device_features(&self) -> u64 {
+        let mut features = VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_FLUSH;
+        if self.read_only {
+            features |= VIRTIO_BLK_F_RO;
+        }
+        features
+    }
+
+    fn reset(&mut self) {
+        self.status = 0;
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queue = Virtqueue::default();
+        self.interrupt_status = 0;
+        self.notified = false;
+    }
+
+    fn read_config(&self, offset: u64, size: u64) -> u64 {
+        let config = self.capacity.to_le_bytes();
+        let mut value = [0u8; 8];
+        for (i, byte) in value.iter_mut().take(size as usize).enumerate() {
+            *byte = config.get(offset as usize + i).copied().unwrap_or(0);
+        }
+        u64::from_le_bytes(value)
+    }
+
+    /// Services every request the driver has made available since the last call.
+    fn process_queue(&mut self, ram: &mut [u8]) {
+        if !self.queue.ready || self.queue.num == 0 || self.status & STATUS_DEVICE_NEEDS_RESET != 0
+        {
+            return;
+        }
+        if self.drain_available(ram).is_none() {
+            // The driver handed us addresses outside RAM or a malformed chain.
+            self.status |= STATUS_DEVICE_NEEDS_RESET;
+            self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+        }
+    }
+
+    fn drain_available(&mut self, ram: &mut [u8]) -> Option<()> {
+        let num = self.queue.num as u64;
+        let avail_flags = read_u16(ram, self.queue.driver)?;
+        let avail_idx = read_u16(ram, self.queue.driver + 2)?;
+        let mut completed = false;
+
+        while self.queue.last_avail_idx != avail_idx {
+            let slot = self.queue.last_avail_idx as u64 % num;
+            let head = read_u16(ram, self.queue.driver + 4 + 2 * slot)?;
+            let written = self.process_request(ram, head)?;
+
+            let used_idx = read_u16(ram, self.queue.device + 2)?;
+            let elem = self.queue.device + 4 + 8 * (used_idx as u64 % num);
+            write_bytes(ram, elem, &(head as u32).to_le_bytes())?;
+            write_bytes(ram, elem + 4, &written.to_le_bytes())?;
+            write_bytes(
+                ram,
+                self.queue.device + 2,
+                &used_idx.wrapping_add(1).to_le_bytes(),
+            )?;
+
+            self.queue.last_avail_idx = self.queue.last_avail_idx.wrapping_add(1);
+            completed = true;
+        }
+
+        if completed && avail_flags & VIRTQ_AVAIL_F_NO_INTERRUPT == 0 {
+            self.interrupt_status |= INTERRUPT_USED_BUFFER;
+        }
+        Some(())
+    }
+
+    /// Follows the descriptor chain starting at `head`.
+    fn descriptor_chain(&self, ram: &[u8], head: u16) -> Option<Vec<Buffer>> {
+        let mut buffers = Vec::new();
+        let mut index = head;
+        loop {
+            if index as u32 >= self.queue.num || buffers.len() >= self.queue.num as usize {
+                return None;
+            }
+            let desc = self.queue.desc + 16 * index as u64;
+            let addr = u64::from_le_bytes(read_bytes(ram, desc, 8)?.try_into().ok()?);
+            let len = u32::from_le_bytes(read_bytes(ram, desc + 8, 4)?.try_into().ok()?);
+            let flags = read_u16(ram, desc + 12)?;
+            let next = read_u16(ram, desc + 14)?;
+            buffers.push(Buffer {
+                addr,
+                len,
+                writable: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Some(buffers);
+            }
+            index = next;
+        }
+    }
+
+    /// Executes one block request and returns the number of bytes written into the chain.
+    fn process_request(&mut self, ram: &mut [u8], head: u16) -> Option<u32> {
+        let chain = self.descriptor_chain(ram, head)?;
+
+        let mut request = Vec::new();
+        for buffer in chain.iter().filter(|b| !b.writable) {
+            request.extend_from_slice(read_bytes(ram, buffer.addr, buffer.len as u64)?);
+        }
+        // The response is sized from the writable buffers, so they must all lie in RAM, and
+        // the last one needs room for the status byte.
+        let writable: Vec<&Buffer> = chain.iter().filter(|b| b.writable).collect();
+        if request.len() < REQUEST_HEADER_SIZE
+            || writable.last()?.len == 0
+            || writable
+                .iter()
+                .any(|b| read_bytes(ram, b.addr, b.len as u64).is_none())
+        {
+            return None;
+        }
+        let writable_len: usize = writable.iter().map(|b| b.len as usize).sum();
+
+        let request_type = u32::from_le_bytes(request[0..4].try_into().ok()?);
+        let sector = u64::from_le_bytes(request[8..16].try_into().ok()?);
+        let payload = &request[REQUEST_HEADER_SIZE..];
+
+        // Everything but the trailing status byte is the data area.
+        let mut response = vec![0u8; writable_len - 1];
+        let (status, data_len) = match request_type {
+            VIRTIO_BLK_T_IN => match self.read_sectors(sector, &mut response) {
+                Ok(()) => (VIRTIO_BLK_S_OK, response.len()),
+                Err(_) => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_OUT if self.read_only => (VIRTIO_BLK_S_IOERR, 0),
+            VIRTIO_BLK_T_OUT => match self.write_sectors(sector, payload) {
+                Ok(()) => (VIRTIO_BLK_S_OK, 0),
+                Err(_) => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_FLUSH => match self.backing.sync_all() {
+                Ok(()) => (VIRTIO_BLK_S_OK, 0),
+                Err(_) => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_GET_ID => {
+                let len = response.len().min(DEVICE_ID_LEN);
+                let id_len = len.min(DEVICE_ID_STRING.len());
+                response[..id_len].copy_from_slice(&DEVICE_ID_STRING[..id_len]);
+                (VIRTIO_BLK_S_OK, len)
+            }
+            _ => (VIRTIO_BLK_S_UNSUPP, 0),
+        };
+        response.truncate(data_len);
+
+        let mut remaining = response.as_slice();
+        for buffer in &writable {
+            let n = remaining.len().min(buffer.len as usize);
+            write_bytes(ram, buffer.addr, &remaining[..n])?;
+            remaining = &remaining[n..];
+        }
+        let status_buffer = writable.last()?;
+        write_bytes(
+            ram,
+            status_buffer.addr + status_buffer.len as u64 - 1,
+            &[status],
+        )?;
+
+        Some(data_len as u32 + 1)
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> io::Result<u64> {
+        let end = sector
+            .checked_mul(SECTOR_SIZE)
+            .and_then(|start| start.checked_add(len as u64));
+        match end {
+            Some(end) if end <= self.capacity * SECTOR_SIZE => Ok(sector * SECTOR_SIZE),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "request beyond end of disk",
+            )),
+        }
+    }
+
+    fn read_sectors(&mut self, sector: u64, data: &mut [u8]) -> io::Result<()> {
+        let offset = self.check_range(sector, data.len())?;
+        self.backing.seek(SeekFrom::Start(offset))?;
+        self.backing.read_exact(data)
+    }
+
+    fn write_sectors(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        let offset = self.check_range(sector, data.len())?;
+        self.backing.seek(SeekFrom::Start(offset))?;
+        self.backing.write_all(data)
+    }
+}
+
+impl Device for VirtioBlk {
+    fn read(&mut self, offset: u64, size: u64) -> Option<u64> {
+        if offset >= CONFIG {
+            return Some(self.read_config(offset - CONFIG, size));
+        }
+        if size != 4 {
+            return None;
+        }
+        let value = match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION => MMIO_VERSION,
+            DEVICE_ID => DEVICE_ID_BLOCK,
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.device_features() as u32,
+                1 => (self.device_features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX if self.queue_sel == 0 => QUEUE_SIZE_MAX,
+            QUEUE_READY if self.queue_sel == 0 => self.queue.ready as u32,
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        };
+        Some(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, size: u64, value: u64) -> bool {
+        if offset >= CONFIG {
+            // The block device's configuration space is read-only.
+            return true;
+        }
+        if size != 4 {
+            return false;
+        }
+        let value = value as u32;
+        let queue_selected = self.queue_sel == 0;
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = value,
+            DRIVER_FEATURES => match self.driver_features_sel {
+                0 => self.driver_features = (self.driver_features & !0xFFFF_FFFF) | value as u64,
+                1 => {
+                    self.driver_features =
+                        (self.driver_features & 0xFFFF_FFFF) | ((value as u64) << 32)
+                }
+                _ => {}
+            },
+            DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+            QUEUE_SEL => self.queue_sel = value,
+            QUEUE_NUM if queue_selected => self.queue.num = value.min(QUEUE_SIZE_MAX),
+            QUEUE_READY if queue_selected => self.queue.ready = value & 1 != 0,
+            QUEUE_NOTIFY => self.notified |= value == 0,
+            INTERRUPT_ACK => self.interrupt_status &= !value,
+            STATUS => {
+                if value == 0 {
+                    self.reset();
+                } else {
+                    let mut status = value;
+                    if status & STATUS_FEATURES_OK != 0
+                        && self.driver_features & !self.device_features() != 0
+                    {
+                        status &= !STATUS_FEATURES_OK;
+                    }
+                    self.status = status;
+                }
+            }
+            QUEUE_DESC_LOW if queue_selected => self.queue.desc = with_low(self.queue.desc, value),
+            QUEUE_DESC_HIGH if queue_selected => {
+                self.queue.desc = with_high(self.queue.desc, value)
+            }
+            QUEUE_DRIVER_LOW if queue_selected => {
+                self.queue.driver = with_low(self.queue.driver, value)
+            }
+            QUEUE_DRIVER_HIGH if queue_selected => {
+                self.queue.driver = with_high(self.queue.driver, value)
+            }
+            QUEUE_DEVICE_LOW if queue_selected => {
+                self.queue.device = with_low(self.queue.device, value)
+            }
+            QUEUE_DEVICE_HIGH if queue_selected => {
+                self.queue.device = with_high(self.queue.device, value)
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn tick(&mut self, ram: &mut [u8]) {
+        if self.notified {
+            self.notified = false;
+            self.process_queue(ram);
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.interrupt_status != 0
+    }
+}
+
+fn with_low(current: u64, low: u32) -> u64 {
+    (current & !0xFFFF_FFFF) | low as u64
+}
+
+fn with_high(current: u64, high: u32) -> u64 {
+    (current & 0xFFFF_FFFF) | ((high as u64) << 32)
+}
+
+/// The bytes of guest-physical `[addr, addr + len)`, if they all lie in RAM.
+fn read_bytes(ram: &[u8], addr: u64, len: u64) -> Option<&[u8]> {
+    let start = addr.checked_sub(BASE_ADDRESS)? as usize;
+    let end = start.checked_add(len as usize)?;
+    ram.get(start..end)
+}
+
+fn read_u16(ram: &[u8], addr: u64) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        read_bytes(ram, addr, 2)?.try_into().ok()?,
+    ))
+}
+
+fn write_bytes(ram: &mut [u8], addr: u64, bytes: &[u8]) -> Option<()> {
+    let start = addr.checked_sub(BASE_ADDRESS)? as usize;
+    let end = start.checked_add(bytes.len())?;
+    ram.get_mut(start..end)?.copy_from_slice(bytes);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESC: u64 = BASE_ADDRESS;
+    const AVAIL: u64 = BASE_ADDRESS + 0x100;
+    const USED: u64 = BASE_ADDRESS + 0x200;
+    const HEADER: u64 = BASE_ADDRESS + 0x300;
+    const DATA: u64 = BASE_ADDRESS + 0x400;
+    const STATUS_BYTE: u64 = BASE_ADDRESS + 0x800;
+
+    fn disk_image(name: &str, sectors: u64) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("vm-virtio-{}-{}", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; (sectors * SECTOR_SIZE) as usize]).unwrap();
+        path
+    }
+
+    fn set_up_queue(blk: &mut VirtioBlk) {
+        blk.write(STATUS, 4, 1 | 2);
+        blk.write(DRIVER_FEATURES, 4, 0);
+        blk.write(STATUS, 4, 1 | 2 | STATUS_FEATURES_OK as u64);
+        blk.write(QUEUE_SEL, 4, 0);
+        blk.write(QUEUE_NUM, 4, 8);
+        blk.write(QUEUE_DESC_LOW, 4, DESC & 0xFFFF_FFFF);
+        blk.write(QUEUE_DRIVER_LOW, 4, AVAIL & 0xFFFF_FFFF);
+        blk.write(QUEUE_DEVICE_LOW, 4, USED & 0xFFFF_FFFF);
+        blk.write(QUEUE_READY, 4, 1);
+        blk.write(STATUS, 4, 1 | 2 | STATUS_FEATURES_OK as u64 | 4);
+    }
+
+    fn write_desc(ram: &mut [u8], index: u64, addr: u64, len: u32, flags: u16, next: u16) {
+        let desc = DESC + 16 * index;
+        write_bytes(ram, desc, &addr.to_le_bytes()).unwrap();
+        write_bytes(ram, desc + 8, &len.to_le_bytes()).unwrap();
+        write_bytes(ram, desc + 12, &flags.to_le_bytes()).unwrap();
+        write_bytes(ram, desc + 14, &next.to_le_bytes()).unwrap();
+    }
+
+    /// Submits a three-descriptor request (header, data, status) and runs the device.
+    fn submit(blk: &mut VirtioBlk, ram: &mut [u8], request_type: u32, sector: u64) -> u8 {
+        let data_flags = if request_type == VIRTIO_BLK_T_IN {
+            VIRTQ_DESC_F_WRITE
+        } else {
+            0
+        };
+        write_bytes(ram, HEADER, &request_type.to_le_bytes()).unwrap();
+        write_bytes(ram, HEADER + 8, &sector.to_le_bytes()).unwrap();
+        write_desc(ram, 0, HEADER, 16, VIRTQ_DESC_F_NEXT, 1);
+        write_desc(ram, 1, DATA, 512, data_flags | VIRTQ_DESC_F_NEXT, 2);
+        write_desc(ram, 2, STATUS_BYTE, 1, VIRTQ_DESC_F_WRITE, 0);
+
+        let avail_idx = read_u16(ram, AVAIL + 2).unwrap();
+        write_bytes(
+            ram,
+            AVAIL + 4 + 2 * (avail_idx as u64 % 8),
+            &0u16.to_le_bytes(),
+        )
+        .unwrap();
+        write_bytes(ram, AVAIL + 2, &avail_idx.wrapping_add(1).to_le_bytes()).unwrap();
+
+        blk.write(QUEUE_NOTIFY, 4, 0);
+        blk.tick(ram);
+        read_bytes(ram, STATUS_BYTE, 1).unwrap()[0]
+    }
+
+    #[test]
+    fn test_identifies_as_virtio_blk() {
+        let path = disk_image("identify", 4);
+        let mut blk = VirtioBlk::open(&path, false).unwrap();
+        assert_eq!(blk.read(MAGIC_VALUE, 4), Some(MAGIC as u64));
+        assert_eq!(blk.read(VERSION, 4), Some(2));
+        assert_eq!(blk.read(DEVICE_ID, 4), Some(2));
+        assert_eq!(blk.read(CONFIG, 8), Some(4));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_then_read_back() {
+        let path = disk_image("rw", 4);
+        let mut blk = VirtioBlk::open(&path, false).unwrap();
+        let mut ram = vec![0u8; 0x1000];
+        set_up_queue(&mut blk);
+
+        write_bytes(&mut ram, DATA, &[0xAB; 512]).unwrap();
+        assert_eq!(
+            submit(&mut blk, &mut ram, VIRTIO_BLK_T_OUT, 2),
+            VIRTIO_BLK_S_OK
+        );
+        assert!(blk.interrupt_pending());
+        blk.write(INTERRUPT_ACK, 4, INTERRUPT_USED_BUFFER as u64);
+        assert!(!blk.interrupt_pending());
+
+        write_bytes(&mut ram, DATA, &[0; 512]).unwrap();
+        assert_eq!(
+            submit(&mut blk, &mut ram, VIRTIO_BLK_T_IN, 2),
+            VIRTIO_BLK_S_OK
+        );
+        assert_eq!(read_bytes(&ram, DATA, 512).unwrap(), &[0xAB; 512]);
+        assert_eq!(read_u16(&ram, USED + 2), Some(2));
+        assert_eq!(
+            read_bytes(&ram, USED + 16, 4).unwrap(),
+            &513u32.to_le_bytes()
+        );
+
+        assert_eq!(
+            submit(&mut blk, &mut ram, VIRTIO_BLK_T_IN, 4),
+            VIRTIO_BLK_S_IOERR
+        );
+
+        let on_disk = std::fs::read(&path).unwrap();
+        assert_eq!(&on_disk[1024..1536], &[0xAB; 512]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_buffers_outside_ram_need_a_reset() {
+        let path = disk_image("bounds", 4);
+        let mut blk = VirtioBlk::open(&path, false).unwrap();
+        let mut ram = vec![0u8; 0x1000];
+        set_up_queue(&mut blk);
+
+        // A data buffer running past the end of RAM.
+        write_desc(
+            &mut ram,
+            1,
+            DATA,
+            0x1000,
+            VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+            2,
+        );
+        write_bytes(&mut ram, HEADER, &VIRTIO_BLK_T_IN.to_le_bytes()).unwrap();
+        write_desc(&mut ram, 0, HEADER, 16, VIRTQ_DESC_F_NEXT, 1);
+        write_desc(&mut ram, 2, STATUS_BYTE, 1, VIRTQ_DESC_F_WRITE, 0);
+        write_bytes(&mut ram, AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+        blk.write(QUEUE_NOTIFY, 4, 0);
+        blk.tick(&mut ram);
+        assert_ne!(
+            blk.read(STATUS, 4).unwrap() as u32 & STATUS_DEVICE_NEEDS_RESET,
+            0
+        );
+
+        // After a reset, a status buffer with no room for the status byte.
+        blk.write(STATUS, 4, 0);
+        set_up_queue(&mut blk);
+        write_desc(
+            &mut ram,
+            1,
+            DATA,
+            512,
+            VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+            2,
+        );
+        write_desc(&mut ram, 2, BASE_ADDRESS, 0, VIRTQ_DESC_F_WRITE, 0);
+        write_bytes(&mut ram, AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+        blk.write(QUEUE_NOTIFY, 4, 0);
+        blk.tick(&mut ram);
+        assert_ne!(
+            blk.read(STATUS, 4).unwrap() as u32 & STATUS_DEVICE_NEEDS_RESET,
+            0
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_disk_rejects_writes() {
+        let path = disk_image("ro", 1);
+        let mut blk = VirtioBlk::open(&path, true).unwrap();
+        let mut ram = vec![0u8; 0x1000];
+        assert_ne!(blk.read(DEVICE_FEATURES, 4).unwrap() & VIRTIO_BLK_F_RO, 0);
+        set_up_queue(&mut blk);
+
+        assert_eq!(
+            submit(&mut blk, &mut ram, VIRTIO_BLK_T_OUT, 0),
+            VIRTIO_BLK_S_IOERR
+        );
+        assert_eq!(
+            submit(&mut blk, &mut ram, VIRTIO_BLK_T_FLUSH, 0),
+            VIRTIO_BLK_S_OK
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}